@@ -4,6 +4,7 @@ module flexemu::challenge_script {
     use trie::hash_value;
     use trie::rlp;
     use flexemu::mips_emulator;
+    use flexemu::preimage_oracle;
     use StarcoinFramework::Signer;
 
     public(script) fun declare_state(signer: signer, final_state: vector<u8>) {
@@ -18,9 +19,10 @@ module flexemu::challenge_script {
         signer: signer,
         proposer_address: address,
         final_system_state: vector<u8>,
-        step_count: u64
+        step_count: u64,
+        little_endian: bool
     ) {
-        SimpleChallenge::create_challenge(&signer, proposer_address, hash_value::new(final_system_state), step_count);
+        SimpleChallenge::create_challenge(&signer, proposer_address, hash_value::new(final_system_state), step_count, little_endian);
     }
 
     public(script) fun assert_state(
@@ -40,7 +42,8 @@ module flexemu::challenge_script {
         sender: signer,
         proposer: address,
         challenge_id: u64,
-        state_data: vector<u8>
+        state_data: vector<u8>,
+        preimages: vector<u8>
     ) {
         {
             let access_nodes = {
@@ -52,6 +55,12 @@ module flexemu::challenge_script {
             let emu_addr = Signer::address_of(&sender);
 
             mips_emulator::batch_add_trie_data(emu_addr, access_nodes);
+            // pre-images the step may read, each one is only kept under its hash.
+            preimage_oracle::create_if_not_exists(&sender);
+            preimage_oracle::batch_add_preimages(emu_addr, {
+                let r = rlp::new(preimages);
+                rlp::as_valuelist(&r)
+            });
         };
         SimpleChallenge::confirm_state_transition(&sender, proposer, challenge_id);
     }
@@ -60,7 +69,8 @@ module flexemu::challenge_script {
         sender: signer,
         proposer_address: address,
         challenge_id: u64,
-        state_data: vector<u8>
+        state_data: vector<u8>,
+        preimages: vector<u8>
     ) {
         {
             let access_nodes = {
@@ -72,6 +82,12 @@ module flexemu::challenge_script {
             let emu_addr = Signer::address_of(&sender);
 
             mips_emulator::batch_add_trie_data(emu_addr, access_nodes);
+            // pre-images the step may read, each one is only kept under its hash.
+            preimage_oracle::create_if_not_exists(&sender);
+            preimage_oracle::batch_add_preimages(emu_addr, {
+                let r = rlp::new(preimages);
+                rlp::as_valuelist(&r)
+            });
         };
         SimpleChallenge::deny_state_transition(&sender, proposer_address, challenge_id);
     }
@@ -13,14 +13,14 @@ module flexemu::memory {
     use trie::rlp;
     use trie::rlp_stream;
     use StarcoinFramework::Signer;
+    #[test_only]
+    use trie::hash_value;
 
     const MISSING_REG_DATA: u64 = 200;
     const MEM_ACCESS_MUTST_BE_ALIGNED_TO_4BYTES: u64 = 401;
     const BITS_LEN_OUT_OF_BOUND: u64 = 601;
     const ELEM_NOT_FOUND: u64 = 701;
 
-    const REG_FAKE_ADDRESS_START: u64 = 0xffffffff + 1;
-
     /// Wrapper around trie db to store memory slots
     struct MemoryStorage has key, store {
         data: Option::Option<trie::TrieDB>,
@@ -40,6 +40,8 @@ module flexemu::memory {
         data: trie::TrieDB,
         registers: vector<Register>,
         root: HashValue,
+        /// words of the memory are little-endian, for mipsel.
+        little_endian: bool,
     }
 
     /// Create a new memory storage.
@@ -68,13 +70,29 @@ module flexemu::memory {
             };
         };
     }
+    #[test_only]
+    /// Root of an empty trie, keccak(rlp("")).
+    const EMPTY_TRIE_ROOT: vector<u8> = x"56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
+
+    #[test_only]
+    /// Write a state without memory and registers to the storage, and return its root.
+    public fun new_state(mem_addr: address): HashValue acquires MemoryStorage {
+        let db = Option::borrow_mut(&mut borrow_global_mut<MemoryStorage>(mem_addr).data);
+        trie::update(db, hash_value::new(EMPTY_TRIE_ROOT), REG_KEY, serialize_registers(&Vector::empty()))
+    }
+
     /// Get a one-shot Memory struct from underlying storage at some state root.
     public fun get_mem(memory_addr: address, state_root: HashValue): Memory acquires MemoryStorage {
+        get_mem_with_endian(memory_addr, state_root, false)
+    }
+    /// Same as `get_mem`, but words of the memory are read and written as little-endian if `little_endian`.
+    public fun get_mem_with_endian(memory_addr: address, state_root: HashValue, little_endian: bool): Memory acquires MemoryStorage {
          let mem = Memory {
              storage_handle: memory_addr,
              data: Option::extract(&mut borrow_global_mut<MemoryStorage>(memory_addr).data),
              registers: Vector::empty(),
              root: state_root,
+             little_endian,
          };
         recover_registers(&mut mem);
         StarcoinFramework::Debug::print(&mem.registers);
@@ -121,7 +139,7 @@ module flexemu::memory {
 
     public fun return_mem(mem: Memory): HashValue
     acquires MemoryStorage {
-        let Memory {data, storage_handle, registers, root} = mem;
+        let Memory {data, storage_handle, registers, root, little_endian: _} = mem;
         let ser_regs = serialize_registers(&registers);
         StarcoinFramework::Debug::print(&ser_regs);
         let root = trie::update(&mut data, root, REG_KEY, ser_regs);
@@ -176,25 +194,34 @@ module flexemu::memory {
 
 
 
-    /// Read memory in four-bytes and convert it to u32 as big-endian representation.
+    /// Whether words of the memory are little-endian.
+    public fun is_little_endian(mem: &Memory): bool {
+        mem.little_endian
+    }
+
+    /// Address holding the storage of the memory.
+    public fun storage_handle(mem: &Memory): address {
+        mem.storage_handle
+    }
+
+    /// Read memory in four-bytes and convert it to u32 in the endianness of the memory.
     public fun read_memory(mem: &Memory, state_hash: HashValue, addr: u64): u64 {
         assert!(addr & 3 == 0, Errors::invalid_argument(MEM_ACCESS_MUTST_BE_ALIGNED_TO_4BYTES));
         let key = to_be_bytes(addr >> 2);
         let v = trie::get(&mem.data, state_hash, &key);
+        // the trie nodes of the path prove the key is absent, zero words aren't stored.
         if (Option::is_none(&v)) {
-            if (addr < REG_FAKE_ADDRESS_START) {
-                abort Errors::internal(addr)
-            } else {
-                0
-            }
+            0
+        } else if (mem.little_endian) {
+            from_le_bytes(&Option::destroy_some(v))
         } else {
             from_be_bytes(&Option::destroy_some(v))
         }
     }
-    /// Write a u32 `value` as big-endian representation to memory addr start from `addr`
+    /// Write a u32 `value` in the endianness of the memory to memory addr start from `addr`
     public fun write_memory(mem: &mut Memory, state_hash: HashValue, addr: u64, value: u64): HashValue {
         assert!(addr & 3 == 0, Errors::invalid_argument(MEM_ACCESS_MUTST_BE_ALIGNED_TO_4BYTES));
-        let value = to_be_bytes(value);
+        let value = if (mem.little_endian) { to_le_bytes(value) } else { to_be_bytes(value) };
         let key = to_be_bytes(addr >> 2);
         let new_root = trie::update(&mut mem.data, state_hash, key, value);
         mem.root = new_root;
@@ -249,6 +276,29 @@ module flexemu::memory {
         ret
     }
 
+    /// to le bytes
+    public fun to_le_bytes(v: u64): vector<u8> {
+        assert!(v <= U32_MAX, 1001);
+        // le bytes
+        let ret = BCS::to_bytes(&v);
+        Vector::pop_back(&mut ret);
+        Vector::pop_back(&mut ret);
+        Vector::pop_back(&mut ret);
+        Vector::pop_back(&mut ret);
+        ret
+    }
+
+    /// from le_bytes
+    public fun from_le_bytes(dat: &vector<u8>): u64 {
+        let ret = 0;
+        let i = 0;
+        while (i < Vector::length(dat) && i < 8) {
+            ret = ret | (*Vector::borrow(dat, i) as u64) << ((i * 8) as u8);
+            i = i + 1;
+        };
+        ret
+    }
+
     /// from be_bytes
     public fun from_be_bytes(dat: &vector<u8>): u64 {
         from_be_bytes_offset(dat, 0)
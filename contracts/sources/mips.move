@@ -5,7 +5,9 @@ module flexemu::mips {
     use signed_integer::i64;
     use trie::hash_value::HashValue;
     use trie::hash_value;
-    use flexemu::memory::{Memory, read_reg, write_reg, read_reg_bits, write_reg_bits, read_memory, write_memory};
+    use flexemu::memory::{Memory, read_reg, write_reg, read_reg_bits, write_reg_bits, read_memory, write_memory, is_little_endian, storage_handle};
+    use flexemu::preimage_oracle;
+    use StarcoinFramework::Vector;
 
 
     const REG_ZERO: u64 = 2;
@@ -21,6 +23,14 @@ module flexemu::mips {
     const REG_R4: u64 = 6;
     const REG_V0: u64 = 4;
     const REG_A0: u64 = 6;
+
+    /// the preimage syscall of flexemu, whose pre-images are verified by `preimage_oracle`.
+    const SYS_PREIMAGE: u64 = 4990;
+    const PREIMAGE_HINT: u64 = 0;
+    const PREIMAGE_SIZE: u64 = 1;
+    const PREIMAGE_READ: u64 = 2;
+    const PREIMAGE_KEY_SIZE: u64 = 32;
+    const EINVAL: u64 = 22;
     /// vector[REG_A0, REG_A0 + 1, REG_A0 + 2, REG_A0 + 3];
     const ARG_REGS: vector<u64> = vector[6, 7,8,9];
 
@@ -111,7 +121,7 @@ module flexemu::mips {
         } else if (funct == 12) {
             // syscall
             // TODO: handle syscall
-            let exit = handle_syscall(mem, state_hash);
+            let (exit, state_hash) = handle_syscall(mem, state_hash);
             if (exit) {
                 next_pc = 0x5ead0000;
             };
@@ -301,7 +311,7 @@ module flexemu::mips {
             let mem_addr = (bits::data(&se(imm, 32)) + bits::data(&rs_value)) & 0xffffffff;
             let memory_data_4b = bits::from_u64(read_memory(mem, state_hash, mem_addr & 0xfffffffc), 32);
 
-            let mem_1b = bits::slice(memory_data_4b, (31 - (mem_addr & 0x3) * 8 as u8), (32 - (mem_addr & 0x3) * 8 - 8 as u8));
+            let mem_1b = bits::slice(memory_data_4b, lane_high(mem, mem_addr, 1), lane_low(mem, mem_addr, 1));
             set_gpr_bits(mem, state_hash, rt, se(mem_1b, 32))
         } else if (opcode == 33) {
             // lh
@@ -309,7 +319,7 @@ module flexemu::mips {
             // must be a mutiple 0f 2
             assert!(mem_addr & 0x1 == 0, 10000);
             let memory_data_4b = bits::from_u64(read_memory(mem, state_hash, mem_addr & 0xfffffffc), 32);
-            let mem_2b = bits::slice(memory_data_4b, (31 - (mem_addr & 0x3) * 8 as u8), (32 - (mem_addr & 0x3) * 8 - 16 as u8));
+            let mem_2b = bits::slice(memory_data_4b, lane_high(mem, mem_addr, 2), lane_low(mem, mem_addr, 2));
             set_gpr_bits(mem, state_hash, rt, se(mem_2b, 32))
         }
         // else if (opcode == 34) { // lwl
@@ -327,7 +337,7 @@ module flexemu::mips {
             let mem_addr = (bits::data(&se(imm, 32)) + bits::data(&rs_value)) & 0xffffffff;
             let memory_data_4b = bits::from_u64(read_memory(mem, state_hash, mem_addr & 0xfffffffc), 32);
 
-            let mem_1b = bits::slice(memory_data_4b, (31 - (mem_addr & 0x3) * 8 as u8), (32 - (mem_addr & 0x3) * 8 - 8 as u8));
+            let mem_1b = bits::slice(memory_data_4b, lane_high(mem, mem_addr, 1), lane_low(mem, mem_addr, 1));
             set_gpr_bits(mem, state_hash, rt, bits::ze(mem_1b, 32))
         } else if (opcode == 37) {
             // lhu
@@ -335,7 +345,7 @@ module flexemu::mips {
             // must be a mutiple 0f 2
             assert!(mem_addr & 0x1 == 0, 10000);
             let memory_data_4b = bits::from_u64(read_memory(mem, state_hash, mem_addr & 0xfffffffc), 32);
-            let mem_2b = bits::slice(memory_data_4b, (31 - (mem_addr & 0x3) * 8 as u8), (32 - (mem_addr & 0x3) * 8 - 16 as u8));
+            let mem_2b = bits::slice(memory_data_4b, lane_high(mem, mem_addr, 2), lane_low(mem, mem_addr, 2));
             set_gpr_bits(mem, state_hash, rt, bits::ze(mem_2b, 32))
         } else if (opcode == 40) {
             // sb
@@ -345,7 +355,7 @@ module flexemu::mips {
 
             let write_back = bits::write_range(
                 memory_data_4b,
-                (31 - (mem_addr & 0x3) * 8 as u8), (32 - (mem_addr & 0x3) * 8 - 8 as u8),
+                lane_high(mem, mem_addr, 1), lane_low(mem, mem_addr, 1),
                 bits::slice(rt_value, 7, 0)
             );
             write_memory(mem, state_hash, read_addr, bits::data(&write_back))
@@ -358,7 +368,7 @@ module flexemu::mips {
                 let memory_data_4b = bits::from_u64(read_memory(mem, state_hash, read_addr), 32);
                 bits::write_range(
                     memory_data_4b,
-                    (31 - (mem_addr & 0x3) * 8 as u8), (32 - (mem_addr & 0x3) * 8 - 16 as u8),
+                    lane_high(mem, mem_addr, 2), lane_low(mem, mem_addr, 2),
                     bits::slice(rt_value, 15, 0)
                 )
             };
@@ -377,6 +387,20 @@ module flexemu::mips {
         return set_pc(mem, state_hash, next_pc)
     }
 
+    /// highest bit of the `size` bytes at `addr` in the word read from the aligned address.
+    fun lane_high(mem: &Memory, addr: u64, size: u64): u8 {
+        if (is_little_endian(mem)) {
+            ((addr & 0x3) * 8 + size * 8 - 1 as u8)
+        } else {
+            (31 - (addr & 0x3) * 8 as u8)
+        }
+    }
+
+    /// lowest bit of the `size` bytes at `addr` in the word read from the aligned address.
+    fun lane_low(mem: &Memory, addr: u64, size: u64): u8 {
+        lane_high(mem, addr, size) + 1 - (size * 8 as u8)
+    }
+
     fun step_pc(mem: &mut Memory, state_hash: HashValue, pc: u64, next_pc: u64): HashValue {
         pc = pc & 0xffffffff;
         next_pc = next_pc & 0xffffffff;
@@ -443,8 +467,17 @@ module flexemu::mips {
         write_reg(mem, hash_value::zero(), REG_A0 + 3, 0);
     }
 
-    fun handle_syscall(mem: &mut Memory, state_hash: HashValue): bool {
+    fun set_errno(mem: &mut Memory, errno: u64) {
+        write_reg(mem, hash_value::zero(), REG_V0,  errno);
+        write_reg(mem, hash_value::zero(), REG_A0 + 3, 1);
+    }
+
+    /// Handle the syscall, return whether the program exited and the new state hash.
+    fun handle_syscall(mem: &mut Memory, state_hash: HashValue): (bool, HashValue) {
         let sysno = read_reg(mem, state_hash, REG_V0);
+        if (sysno == SYS_PREIMAGE) {
+            return (false, preimage(mem, state_hash))
+        };
 
         let ret_value = if (sysno == 4000) { // syscall
             0
@@ -486,7 +519,61 @@ module flexemu::mips {
         }
         else {0};
         set_retvalue(mem, ret_value);
-        false
+        (false, state_hash)
+    }
+
+    /// The preimage syscall, it aborts if the pre-image of the key wasn't given to `preimage_oracle`,
+    /// which only keeps pre-images matching their key.
+    fun preimage(mem: &mut Memory, state_hash: HashValue): HashValue {
+        let op = get_param(mem, state_hash, 0);
+        if (op == PREIMAGE_HINT) {
+            set_retvalue(mem, 0);
+            return state_hash
+        };
+        if (op != PREIMAGE_SIZE && op != PREIMAGE_READ) {
+            set_errno(mem, EINVAL);
+            return state_hash
+        };
+
+        let key_addr = get_param(mem, state_hash, 1);
+        let key = Vector::empty();
+        let i = 0;
+        while (i < PREIMAGE_KEY_SIZE) {
+            Vector::push_back(&mut key, read_byte(mem, state_hash, key_addr + i));
+            i = i + 1;
+        };
+        let data = preimage_oracle::get(storage_handle(mem), key);
+        let len = Vector::length(&data);
+        if (op == PREIMAGE_SIZE) {
+            set_retvalue(mem, len);
+            return state_hash
+        };
+
+        let buf = get_param(mem, state_hash, 2);
+        let count = get_param(mem, state_hash, 3);
+        let offset = get_param(mem, state_hash, 4);
+        let copied = 0;
+        while (offset + copied < len && copied < count) {
+            state_hash = write_byte(mem, state_hash, buf + copied, *Vector::borrow(&data, offset + copied));
+            copied = copied + 1;
+        };
+        set_retvalue(mem, copied);
+        state_hash
+    }
+
+    fun read_byte(mem: &Memory, state_hash: HashValue, addr: u64): u8 {
+        let word = bits::from_u64(read_memory(mem, state_hash, addr & 0xfffffffc), 32);
+        (bits::data(&bits::slice(word, lane_high(mem, addr, 1), lane_low(mem, addr, 1))) as u8)
+    }
+
+    fun write_byte(mem: &mut Memory, state_hash: HashValue, addr: u64, value: u8): HashValue {
+        let read_addr = addr & 0xfffffffc;
+        let word = bits::write_range(
+            bits::from_u64(read_memory(mem, state_hash, read_addr), 32),
+            lane_high(mem, addr, 1), lane_low(mem, addr, 1),
+            bits::from_u64((value as u64), 8)
+        );
+        write_memory(mem, state_hash, read_addr, bits::data(&word))
     }
 
     fun set_thread_area(mem: &mut Memory, u_info_addr: u64): u64 {
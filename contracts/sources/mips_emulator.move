@@ -41,4 +41,97 @@ module flexemu::mips_emulator {
         let new_root = memory::return_mem(mem);
         hash_value::to_bytes(new_root)
     }
-}
\ No newline at end of file
+
+    /// Same as `run`, for mipsel programs, whose words are little-endian in memory.
+    public fun run_little_endian(emulator: address, state_root: vector<u8>): vector<u8> {
+        let mem = memory::get_mem_with_endian(emulator, hash_value::new(state_root), true);
+        let root = hash_value::new(state_root);
+        mips::step(&mut mem, root);
+        let new_root = memory::return_mem(mem);
+        hash_value::to_bytes(new_root)
+    }
+
+    /// `run_little_endian` if `little_endian`, `run` otherwise.
+    public fun run_with_endian(emulator: address, state_root: vector<u8>, little_endian: bool): vector<u8> {
+        if (little_endian) {
+            run_little_endian(emulator, state_root)
+        } else {
+            run(emulator, state_root)
+        }
+    }
+
+    #[test_only]
+    use StarcoinFramework::Signer;
+    #[test_only]
+    use StarcoinFramework::Vector;
+    #[test_only]
+    use StarcoinFramework::Hash::keccak_256 as keccak256;
+    #[test_only]
+    use flexemu::preimage_oracle;
+
+    #[test(account = @0x42)]
+    fun test_run_little_endian(account: signer) {
+        create(&account);
+        let emu_addr = Signer::address_of(&account);
+
+        // addiu $v0, $zero, 1 at 0x1000, stored little-endian.
+        let root = memory::new_state(emu_addr);
+        let mem = memory::get_mem_with_endian(emu_addr, root, true);
+        let root = memory::write_memory(&mut mem, root, 0x1000, 0x24020001);
+        memory::write_reg(&mut mem, root, 1, 0x1000);
+        let root = memory::return_mem(mem);
+
+        // the same word is an `and` if it's read big-endian.
+        let mem = memory::get_mem(emu_addr, root);
+        assert!(memory::read_memory(&mem, root, 0x1000) == 0x01000224, 1);
+        memory::return_mem(mem);
+
+        let new_root = hash_value::new(run_with_endian(emu_addr, hash_value::to_bytes(root), true));
+        let mem = memory::get_mem_with_endian(emu_addr, new_root, true);
+        assert!(mips::gpr(&mem, new_root, 2) == 1, 2);
+        assert!(memory::get_register(&mem, 1) == 0x1004, 3);
+        memory::return_mem(mem);
+    }
+
+    #[test(account = @0x42)]
+    fun test_run_preimage_read(account: signer) {
+        create(&account);
+        preimage_oracle::create_if_not_exists(&account);
+        let emu_addr = Signer::address_of(&account);
+        let block = b"l2 block";
+        let key = keccak256(copy block);
+        preimage_oracle::batch_add_preimages(emu_addr, Vector::singleton(block));
+
+        // syscall at 0x1000, preimage(PREIMAGE_READ, 0x2000, 0x3000, 4, 2) with the key at 0x2000.
+        let root = memory::new_state(emu_addr);
+        let mem = memory::get_mem(emu_addr, root);
+        let root = memory::write_memory(&mut mem, root, 0x1000, 0xc);
+        let i = 0;
+        while (i < 32) {
+            let word = 0;
+            let j = 0;
+            while (j < 4) {
+                word = (word << 8) | (*Vector::borrow(&key, i + j) as u64);
+                j = j + 1;
+            };
+            root = memory::write_memory(&mut mem, root, 0x2000 + i, word);
+            i = i + 4;
+        };
+        // the offset is the 5th argument, on the stack.
+        let root = memory::write_memory(&mut mem, root, 0x4010, 2);
+        memory::write_reg(&mut mem, root, 1, 0x1000);
+        memory::write_reg(&mut mem, root, 4, 4990);
+        memory::write_reg(&mut mem, root, 6, 2);
+        memory::write_reg(&mut mem, root, 7, 0x2000);
+        memory::write_reg(&mut mem, root, 8, 0x3000);
+        memory::write_reg(&mut mem, root, 9, 4);
+        memory::write_reg(&mut mem, root, 31, 0x4000);
+        let root = memory::return_mem(mem);
+
+        let new_root = hash_value::new(run(emu_addr, hash_value::to_bytes(root)));
+        let mem = memory::get_mem(emu_addr, new_root);
+        assert!(mips::gpr(&mem, new_root, 2) == 4, 1);
+        assert!(memory::read_memory(&mem, new_root, 0x3000) == 0x3220626c, 2);
+        memory::return_mem(mem);
+    }
+}
@@ -0,0 +1,80 @@
+/// Pre-images the preimage syscall of flexemu reads, verified against their keys.
+/// A pre-image is only stored under its keccak256 hash, so a step reading oracle data
+/// can only see the pre-image of the key the program asked for.
+module flexemu::preimage_oracle {
+    use StarcoinFramework::Hash::keccak_256 as keccak256;
+    use StarcoinFramework::Table;
+    use StarcoinFramework::Signer;
+    use StarcoinFramework::Vector;
+    use StarcoinFramework::Errors;
+
+    const ERR_PREIMAGE_KEY_MISMATCH: u64 = 801;
+    const ERR_PREIMAGE_MISSING: u64 = 802;
+
+    struct Preimages has key, store {
+        data: Table::Table<vector<u8>, vector<u8>>,
+    }
+
+    /// Create the pre-image storage if it doesn't exist.
+    public fun create_if_not_exists(signer: &signer) {
+        if (!exists<Preimages>(Signer::address_of(signer))) {
+            move_to(signer, Preimages {
+                data: Table::new(),
+            })
+        }
+    }
+
+    /// Whether `preimage` is the pre-image of `key`.
+    public fun verify(key: &vector<u8>, preimage: &vector<u8>): bool {
+        keccak256(*preimage) == *key
+    }
+
+    /// Add `preimage` under `key`, abort if it isn't its pre-image.
+    public fun add_preimage(oracle_addr: address, key: vector<u8>, preimage: vector<u8>) acquires Preimages {
+        assert!(verify(&key, &preimage), Errors::invalid_argument(ERR_PREIMAGE_KEY_MISMATCH));
+        let preimages = borrow_global_mut<Preimages>(oracle_addr);
+        if (!Table::contains(&preimages.data, key)) {
+            Table::add(&mut preimages.data, key, preimage);
+        }
+    }
+
+    /// Add each of `preimages` under its hash.
+    public fun batch_add_preimages(oracle_addr: address, preimages: vector<vector<u8>>) acquires Preimages {
+        let i = Vector::length(&preimages);
+        while (i != 0) {
+            let preimage = Vector::pop_back(&mut preimages);
+            add_preimage(oracle_addr, keccak256(copy preimage), preimage);
+            i = i - 1;
+        };
+    }
+
+    /// Whether the pre-image of `key` was added.
+    public fun contains(oracle_addr: address, key: vector<u8>): bool acquires Preimages {
+        exists<Preimages>(oracle_addr) && Table::contains(&borrow_global<Preimages>(oracle_addr).data, key)
+    }
+
+    /// The pre-image of `key`, abort if it wasn't added, the step can't be judged without it.
+    public fun get(oracle_addr: address, key: vector<u8>): vector<u8> acquires Preimages {
+        assert!(contains(oracle_addr, copy key), Errors::not_published(ERR_PREIMAGE_MISSING));
+        let preimage = *Table::borrow(&borrow_global<Preimages>(oracle_addr).data, copy key);
+        assert!(verify(&key, &preimage), Errors::invalid_state(ERR_PREIMAGE_KEY_MISMATCH));
+        preimage
+    }
+
+    #[test(account = @0x42)]
+    fun test_add_preimage(account: signer) acquires Preimages {
+        create_if_not_exists(&account);
+        let addr = Signer::address_of(&account);
+        let block = b"l2 block";
+        batch_add_preimages(addr, Vector::singleton(copy block));
+        assert!(get(addr, keccak256(copy block)) == block, 1);
+        assert!(!contains(addr, keccak256(b"forged")), 2);
+    }
+
+    #[test(account = @0x42)]
+    #[expected_failure(abort_code = 205063)]
+    fun test_forged_preimage(account: signer) acquires Preimages {
+        create_if_not_exists(&account);
+        add_preimage(Signer::address_of(&account), keccak256(b"forged"), b"not the pre-image");
+    }
+}
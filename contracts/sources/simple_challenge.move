@@ -37,6 +37,8 @@ module flexemu::SimpleChallenge {
         /// 1: success
         /// 2: failure
         success: u8,
+        /// the program is mipsel, its steps are run with little-endian memory.
+        little_endian: bool,
     }
 
     struct Challenges has key,store {
@@ -89,7 +91,7 @@ module flexemu::SimpleChallenge {
         }
     }
 
-    public fun create_challenge(signer: &signer, proposer_address: address, final_system_state: HashValue, step_count: u64): u64
+    public fun create_challenge(signer: &signer, proposer_address: address, final_system_state: HashValue, step_count: u64, little_endian: bool): u64
     acquires Global, Challenges {
         //let challenge_id = next_challenge_id(proposer_address);
         let glo = borrow_global<Global>(proposer_address);
@@ -103,6 +105,7 @@ module flexemu::SimpleChallenge {
             defended_state: Table::new(),
             challenger: Signer::address_of(signer),
             success: 0,
+            little_endian,
         };
         Table::add(&mut challenge_data.defended_state, step_count, final_system_state);
 
@@ -219,7 +222,7 @@ module flexemu::SimpleChallenge {
         assert!(!is_searching_(c), ERR_BINARY_SEARCH_NOT_FINISHED);
 
         let asserted_state = Table::borrow(&c.asserted_state, c.l);
-        let step_state = mips_emulator::run(Signer::address_of(sender), hash_value::to_bytes(*asserted_state));
+        let step_state = mips_emulator::run_with_endian(Signer::address_of(sender), hash_value::to_bytes(*asserted_state), c.little_endian);
 
         let right_asserted_state = Table::borrow(&c.asserted_state, c.r);
         assert!(step_state == hash_value::to_bytes(*right_asserted_state), ERR_WRONG_ASSERTED_STATE_FOR_CHALLENGER);
@@ -235,7 +238,7 @@ module flexemu::SimpleChallenge {
         assert!(!is_searching_(c), ERR_BINARY_SEARCH_NOT_FINISHED);
 
         let defended_state = Table::borrow(&c.defended_state, c.l);
-        let step_state = mips_emulator::run(Signer::address_of(sender), hash_value::to_bytes(*defended_state));
+        let step_state = mips_emulator::run_with_endian(Signer::address_of(sender), hash_value::to_bytes(*defended_state), c.little_endian);
 
         let right_defended_state = Table::borrow(&c.defended_state, c.r);
         assert!(step_state == hash_value::to_bytes(*right_defended_state), ERR_WRONG_ASSERTED_STATE_FOR_DEFENDER);
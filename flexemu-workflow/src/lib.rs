@@ -10,10 +10,16 @@ use crate::{
 };
 use log::{error, info};
 use flexemu::{
-    arch::mips::{MipsProfile, MIPS},
+    arch::{
+        mips::{MipsProfile, MIPS},
+        Profile,
+    },
     config::FlexEmuConfig,
     emulator::{Emulator, EmulatorState, StateChange},
-    os::linux::LinuxRunner,
+    os::linux::{
+        preimage::{encode_preimages, PreimageKey},
+        LinuxRunner,
+    },
     step_proof::generate_step_proof,
 };
 use starcoin_crypto::{ed25519::Ed25519PrivateKey, HashValue, PrivateKey};
@@ -27,7 +33,7 @@ use starcoin_types::{
     },
 };
 use starcoin_vm_types::state_view::StateReaderExt;
-use std::{thread::sleep, time::Duration};
+use std::{collections::BTreeMap, thread::sleep, time::Duration};
 
 pub struct SharedData {
     client: RpcClient,
@@ -119,7 +125,7 @@ impl Proposer {
 
         // already stopped
         if c.l + 1 == c.r {
-            let state_change = run_mips_state_change(
+            let (state_change, preimages) = run_mips_state_change(
                 self.inner.flexemu_config.clone(),
                 self.inner.program.binary.clone(),
                 self.inner.program.argv.clone(),
@@ -132,6 +138,7 @@ impl Proposer {
                 me,
                 cid,
                 state_proof.access_nodes,
+                encode_preimages(&preimages),
             ))?;
             let txn_info = self.inner.send_and_wait_txn(txn)?;
             info!(
@@ -309,6 +316,7 @@ impl Challenger {
                         self.proposer_address,
                         my_state_root,
                         emu_state.steps,
+                        emu_state.little_endian,
                     ))?;
                     let txn_info = self.inner.send_and_wait_txn(txn)?;
                     if txn_info.status == TransactionStatusView::Executed {
@@ -347,7 +355,7 @@ impl Challenger {
     fn handle_challenge(&self, cid: u64, c: ChallengeData) -> anyhow::Result<()> {
         let proposer_address = self.proposer_address;
         if c.l + 1 == c.r {
-            let state_change = run_mips_state_change(
+            let (state_change, preimages) = run_mips_state_change(
                 self.inner.flexemu_config.clone(),
                 self.inner.program.binary.clone(),
                 self.inner.program.argv.clone(),
@@ -364,6 +372,7 @@ impl Challenger {
                 proposer_address,
                 cid,
                 state_proof.access_nodes,
+                encode_preimages(&preimages),
             ))?;
             let txn_info = self.inner.send_and_wait_txn(txn)?;
             if txn_info.status == TransactionStatusView::Executed {
@@ -415,6 +424,16 @@ impl Challenger {
     }
 }
 
+/// Profile of the program, the challenge contracts only step mips programs.
+fn mips_profile(binary: &[u8]) -> anyhow::Result<MipsProfile> {
+    match Profile::detect(binary).unwrap_or_default() {
+        Profile::Mips(profile) => Ok(profile),
+        Profile::Riscv(_) => {
+            anyhow::bail!("riscv programs can't be challenged, the contracts only step mips")
+        }
+    }
+}
+
 fn run_mips(
     config: FlexEmuConfig,
     binary: Vec<u8>,
@@ -422,32 +441,34 @@ fn run_mips(
     env: Vec<(String, String)>,
     until: Option<usize>, // stop step
 ) -> anyhow::Result<EmulatorState> {
-    let mips_profile = MipsProfile::default();
+    let mips_profile = mips_profile(&binary)?;
     let arch = MIPS::new(mips_profile.pointer_size());
-    let runner = LinuxRunner::new(config.os.mmap_address);
+    let runner = LinuxRunner::from_config(&config);
     let mut emu = Emulator::<_, LinuxRunner>::new(config, arch, mips_profile.mode(), runner)?;
 
     let load_info = emu.load(&binary, argv, env)?;
 
     let _total_steps = emu.run(load_info.entrypoint, None, None, until)?;
+    emu.ensure_provable()?;
     let emu_state = emu.save()?;
     Ok(emu_state)
 }
 
+/// State change of step `until`, with the pre-images the program fetched, which the judge needs to step it.
 fn run_mips_state_change(
     config: FlexEmuConfig,
     binary: Vec<u8>,
     argv: Vec<String>,
     env: Vec<(String, String)>,
     until: usize, // stop step
-) -> anyhow::Result<StateChange> {
-    let mips_profile = MipsProfile::default();
+) -> anyhow::Result<(StateChange, BTreeMap<PreimageKey, Vec<u8>>)> {
+    let mips_profile = mips_profile(&binary)?;
     let arch = MIPS::new(mips_profile.pointer_size());
-    let runner = LinuxRunner::new(config.os.mmap_address);
+    let runner = LinuxRunner::from_config(&config);
     let mut emu = Emulator::<_, LinuxRunner>::new(config, arch, mips_profile.mode(), runner)?;
 
     let load_info = emu.load(&binary, argv, env)?;
 
     let state_change = emu.run_until(load_info.entrypoint, None, None, until)?;
-    Ok(state_change)
+    Ok((state_change, emu.runner().preimages()))
 }
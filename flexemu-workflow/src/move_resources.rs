@@ -12,6 +12,7 @@ pub struct ChallengeData {
     pub defended_state: MoveTable,
     pub challenger: AccountAddress,
     pub success: u8,
+    pub little_endian: bool,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Global {
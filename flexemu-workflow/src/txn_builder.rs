@@ -36,6 +36,7 @@ pub fn create_challenge(
     proposer_address: AccountAddress,
     final_system_state: HashValue,
     step_count: u64,
+    little_endian: bool,
 ) -> TransactionPayload {
     TransactionPayload::ScriptFunction(ScriptFunction::new(
         CHALLENGE_ENTRYPOINT.clone(),
@@ -45,6 +46,7 @@ pub fn create_challenge(
             bcs_ext::to_bytes(&proposer_address).unwrap(),
             bcs_ext::to_bytes(&final_system_state.to_vec()).unwrap(),
             bcs_ext::to_bytes(&step_count).unwrap(),
+            bcs_ext::to_bytes(&little_endian).unwrap(),
         ],
     ))
 }
@@ -82,6 +84,7 @@ pub fn confirm_state_transition(
     proposer: AccountAddress,
     challenge_id: u64,
     state_data: Vec<u8>,
+    preimages: Vec<u8>,
 ) -> TransactionPayload {
     TransactionPayload::ScriptFunction(ScriptFunction::new(
         CHALLENGE_ENTRYPOINT.clone(),
@@ -91,6 +94,7 @@ pub fn confirm_state_transition(
             bcs_ext::to_bytes(&proposer).unwrap(),
             bcs_ext::to_bytes(&challenge_id).unwrap(),
             bcs_ext::to_bytes(&state_data).unwrap(),
+            bcs_ext::to_bytes(&preimages).unwrap(),
         ],
     ))
 }
@@ -99,6 +103,7 @@ pub fn deny_state_transition(
     proposer: AccountAddress,
     challenge_id: u64,
     state_data: Vec<u8>,
+    preimages: Vec<u8>,
 ) -> TransactionPayload {
     TransactionPayload::ScriptFunction(ScriptFunction::new(
         CHALLENGE_ENTRYPOINT.clone(),
@@ -108,6 +113,7 @@ pub fn deny_state_transition(
             bcs_ext::to_bytes(&proposer).unwrap(),
             bcs_ext::to_bytes(&challenge_id).unwrap(),
             bcs_ext::to_bytes(&state_data).unwrap(),
+            bcs_ext::to_bytes(&preimages).unwrap(),
         ],
     ))
 }
@@ -6,7 +6,14 @@ use crate::{
     memory::PointerSizeT,
     registers::Registers,
 };
-use goblin::container::Endian;
+use goblin::{
+    container::Endian,
+    elf::header::{
+        EI_CLASS, EI_DATA, ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB, ELFMAG, EM_MIPS,
+        SELFMAG,
+    },
+};
+use std::str::FromStr;
 use unicorn_engine::{
     unicorn_const::{Arch, Mode},
     RegisterMIPS,
@@ -26,7 +33,51 @@ impl Default for MipsProfile {
     }
 }
 
+impl FromStr for MipsProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mips" => Ok(Self::default()),
+            "mipsel" => Ok(Self::new(true, Endian::Little)),
+            "mips64" => Ok(Self::new(false, Endian::Big)),
+            "mips64el" => Ok(Self::new(false, Endian::Little)),
+            _ => Err(format!(
+                "unknown arch {}, expect mips, mipsel, mips64 or mips64el",
+                s
+            )),
+        }
+    }
+}
+
 impl MipsProfile {
+    pub fn new(mode32: bool, endian: Endian) -> Self {
+        Self { mode32, endian }
+    }
+
+    /// Profile of the mips program in `binary`, from its elf header.
+    /// `None` if it isn't a mips elf. 64 bits elfs are taken as n64 programs.
+    pub fn detect(binary: &[u8]) -> Option<Self> {
+        if binary.len() < 20 || &binary[..SELFMAG] != ELFMAG {
+            return None;
+        }
+        let mode32 = match binary[EI_CLASS] {
+            ELFCLASS32 => true,
+            ELFCLASS64 => false,
+            _ => return None,
+        };
+        let (endian, machine) = match binary[EI_DATA] {
+            ELFDATA2MSB => (Endian::Big, u16::from_be_bytes([binary[18], binary[19]])),
+            ELFDATA2LSB => (Endian::Little, u16::from_le_bytes([binary[18], binary[19]])),
+            _ => return None,
+        };
+        (machine == EM_MIPS).then_some(Self::new(mode32, endian))
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
     pub fn mode(&self) -> Mode {
         let mut mode = if self.mode32 {
             Mode::MODE_32
@@ -79,18 +130,29 @@ impl MIPS {
     //     .into_iter()
     //     .collect();
 
+    /// The o32 abi with 4 bytes pointers, n64 with 8 bytes ones.
     pub fn new(pointer_size: PointerSizeT) -> Self {
+        let inner = if pointer_size == 8 {
+            CallingConventionCommon::new(
+                MipsCC::RET_REG,
+                MipsCC::N64_ARG_REGS.to_vec(),
+                MipsCC::N64_ARG_ON_STACK,
+                0,
+                MipsCC::RET_ADDR_ON_STACK,
+                pointer_size,
+            )
+        } else {
+            CallingConventionCommon::new(
+                MipsCC::RET_REG,
+                MipsCC::ARG_REGS.to_vec(),
+                MipsCC::ARG_ON_STACK,
+                MipsCC::SHADOW,
+                MipsCC::RET_ADDR_ON_STACK,
+                pointer_size,
+            )
+        };
         Self {
-            cc: MipsCC {
-                inner: CallingConventionCommon::new(
-                    MipsCC::RET_REG,
-                    MipsCC::ARG_REGS.to_vec(),
-                    MipsCC::ARG_ON_STACK,
-                    MipsCC::SHADOW,
-                    MipsCC::RET_ADDR_ON_STACK,
-                    pointer_size,
-                ),
-            },
+            cc: MipsCC { inner },
             registers: (RegisterMIPS::PC as i32..=RegisterMIPS::ENDING as i32)
                 .into_iter()
                 .collect(),
@@ -113,6 +175,18 @@ impl MipsCC {
     ];
     const ARG_ON_STACK: usize = 12;
     const SHADOW: usize = 4;
+    /// n64 passes 8 arguments in registers, $8 to $11 are named t0 to t3 after o32.
+    const N64_ARG_REGS: [i32; 8] = [
+        RegisterMIPS::A0 as i32,
+        RegisterMIPS::A1 as i32,
+        RegisterMIPS::A2 as i32,
+        RegisterMIPS::A3 as i32,
+        RegisterMIPS::T0 as i32,
+        RegisterMIPS::T1 as i32,
+        RegisterMIPS::T2 as i32,
+        RegisterMIPS::T3 as i32,
+    ];
+    const N64_ARG_ON_STACK: usize = 8;
     const RET_ADDR_ON_STACK: bool = false;
 }
 
@@ -152,6 +226,13 @@ impl CallingConvention for MipsCC {
         Ok(())
     }
 
+    /// mips reports failures with `a3 = 1` and the positive errno in `v0`.
+    fn set_syscall_error(&self, mach: &mut impl Mach, errno: u64) -> crate::errors::Result<()> {
+        self.inner.set_return_value(mach, errno)?;
+        Registers::write(mach, RegisterMIPS::A3, 1)?;
+        Ok(())
+    }
+
     fn set_return_address(&self, _mach: &mut impl Mach, _addr: u64) -> crate::errors::Result<()> {
         unreachable!()
     }
@@ -165,3 +246,36 @@ impl CallingConvention for MipsCC {
         Ok(Registers::read(mach, RegisterMIPS::RA)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use goblin::container::Endian;
+
+    use super::MipsProfile;
+
+    #[test]
+    fn test_detect() {
+        let mut header = vec![0u8; 52];
+        header[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        header[18] = 8;
+        assert_eq!(
+            MipsProfile::detect(&header),
+            Some(MipsProfile::new(true, Endian::Little))
+        );
+        header[5] = 2;
+        header[18..20].copy_from_slice(&[0, 8]);
+        assert_eq!(MipsProfile::detect(&header), Some(MipsProfile::default()));
+        // x86_64
+        header[4] = 2;
+        header[19] = 62;
+        assert_eq!(MipsProfile::detect(&header), None);
+        assert_eq!("mipsel".parse(), Ok(MipsProfile::new(true, Endian::Little)));
+
+        // mips64 n64
+        header[4] = 2;
+        header[18..20].copy_from_slice(&[0, 8]);
+        let profile = MipsProfile::detect(&header).unwrap();
+        assert_eq!(profile, "mips64".parse().unwrap());
+        assert_eq!(profile.pointer_size(), 8);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod mips;
+pub mod riscv;
 
 use crate::{cc::CallingConvention, memory::PointerSizeT};
 use goblin::container::Endian;
+use mips::MipsProfile;
+use riscv::RiscvProfile;
+use std::str::FromStr;
 use unicorn_engine::{
     unicorn_const::{Arch, Mode, Query},
     Unicorn,
@@ -16,6 +20,44 @@ pub trait ArchT {
     fn registers(&self) -> &[i32];
 }
 
+/// The arch a program runs on, and its abi.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Profile {
+    Mips(MipsProfile),
+    Riscv(RiscvProfile),
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::Mips(MipsProfile::default())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Self::Mips)
+            .or_else(|_| s.parse().map(Self::Riscv))
+            .map_err(|_| {
+                format!(
+                    "unknown arch {}, expect mips, mipsel, mips64, mips64el, riscv32 or riscv64",
+                    s
+                )
+            })
+    }
+}
+
+impl Profile {
+    /// Profile of the program in `binary`, from its elf header.
+    pub fn detect(binary: &[u8]) -> Option<Self> {
+        MipsProfile::detect(binary)
+            .map(Self::Mips)
+            .or_else(|| RiscvProfile::detect(binary).map(Self::Riscv))
+    }
+}
+
 pub trait ArchInfo {
     fn endian(&self) -> Endian;
     fn pointer_size(&self) -> PointerSizeT;
@@ -0,0 +1,203 @@
+use crate::{
+    arch::ArchT,
+    cc::{CallingConvention, CallingConventionCommon},
+    engine::Mach,
+    errors::EmulatorError,
+    memory::PointerSizeT,
+    registers::Registers,
+};
+use goblin::elf::header::{
+    EI_CLASS, EI_DATA, ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFMAG, EM_RISCV, SELFMAG,
+};
+use std::str::FromStr;
+use unicorn_engine::{
+    unicorn_const::{Arch, Mode},
+    RegisterRISCV,
+};
+
+/// riscv32 or riscv64, always little endian.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RiscvProfile {
+    mode32: bool,
+}
+
+impl FromStr for RiscvProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "riscv32" => Ok(Self::new(true)),
+            "riscv64" => Ok(Self::new(false)),
+            _ => Err(format!("unknown arch {}, expect riscv32 or riscv64", s)),
+        }
+    }
+}
+
+impl RiscvProfile {
+    pub fn new(mode32: bool) -> Self {
+        Self { mode32 }
+    }
+
+    /// Profile of the riscv program in `binary`, from its elf header.
+    /// `None` if it isn't a riscv elf.
+    pub fn detect(binary: &[u8]) -> Option<Self> {
+        if binary.len() < 20 || &binary[..SELFMAG] != ELFMAG || binary[EI_DATA] != ELFDATA2LSB {
+            return None;
+        }
+        let mode32 = match binary[EI_CLASS] {
+            ELFCLASS32 => true,
+            ELFCLASS64 => false,
+            _ => return None,
+        };
+        let machine = u16::from_le_bytes([binary[18], binary[19]]);
+        (machine == EM_RISCV).then_some(Self::new(mode32))
+    }
+
+    pub fn mode(&self) -> Mode {
+        if self.mode32 {
+            Mode::RISCV32
+        } else {
+            Mode::RISCV64
+        }
+    }
+    pub fn pointer_size(&self) -> u8 {
+        if self.mode32 {
+            4
+        } else {
+            8
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RISCV {
+    cc: RiscvCC,
+    registers: Vec<i32>,
+}
+impl ArchT for RISCV {
+    type CC = RiscvCC;
+    const T: Arch = Arch::RISCV;
+    const PC: i32 = RegisterRISCV::PC as i32;
+    const SP: i32 = RegisterRISCV::SP as i32;
+
+    #[inline]
+    fn cc(&self) -> Self::CC {
+        self.cc.clone()
+    }
+
+    #[inline]
+    fn registers(&self) -> &[i32] {
+        &self.registers
+    }
+}
+
+impl RISCV {
+    /// The ilp32 abi with 4 bytes pointers, lp64 with 8 bytes ones.
+    /// Registers are the integer ones but x0, the float ones and fcsr, then pc.
+    pub fn new(pointer_size: PointerSizeT) -> Self {
+        let inner = CallingConventionCommon::new(
+            RiscvCC::RET_REG,
+            RiscvCC::ARG_REGS.to_vec(),
+            0,
+            0,
+            false,
+            pointer_size,
+        );
+        Self {
+            cc: RiscvCC { inner },
+            registers: (RegisterRISCV::X1 as i32..=RegisterRISCV::X31 as i32)
+                .chain(RegisterRISCV::F0 as i32..=RegisterRISCV::F31 as i32)
+                .chain([RegisterRISCV::FCSR as i32, RegisterRISCV::PC as i32])
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RiscvCC {
+    inner: CallingConventionCommon,
+}
+
+impl RiscvCC {
+    const RET_REG: i32 = RegisterRISCV::A0 as i32;
+    const ARG_REGS: [i32; 8] = [
+        RegisterRISCV::A0 as i32,
+        RegisterRISCV::A1 as i32,
+        RegisterRISCV::A2 as i32,
+        RegisterRISCV::A3 as i32,
+        RegisterRISCV::A4 as i32,
+        RegisterRISCV::A5 as i32,
+        RegisterRISCV::A6 as i32,
+        RegisterRISCV::A7 as i32,
+    ];
+}
+
+/// syscalls are made by `ecall`, with the number in a7, the arguments in a0 to a5,
+/// and `-errno` returned in a0 on failures.
+impl CallingConvention for RiscvCC {
+    #[inline]
+    fn get_num_slots(_argbits: u64) -> u64 {
+        1
+    }
+
+    fn get_raw_param(
+        &self,
+        mach: &mut impl Mach,
+        slot: u64,
+        argbits: Option<u64>,
+    ) -> crate::errors::Result<u64> {
+        self.inner.get_ram_param(mach, slot as usize, argbits)
+    }
+
+    fn set_raw_param(
+        &self,
+        mach: &mut impl Mach,
+        slot: u64,
+        value: u64,
+        argbits: Option<u64>,
+    ) -> crate::errors::Result<()> {
+        self.inner
+            .set_raw_param(mach, slot as usize, value, argbits)
+    }
+
+    fn get_return_value(&self, mach: &mut impl Mach) -> crate::errors::Result<u64> {
+        self.inner.get_return_value(mach)
+    }
+
+    fn set_return_value(&self, mach: &mut impl Mach, val: u64) -> crate::errors::Result<()> {
+        self.inner.set_return_value(mach, val)
+    }
+
+    fn set_return_address(&self, mach: &mut impl Mach, addr: u64) -> crate::errors::Result<()> {
+        Ok(Registers::write(mach, RegisterRISCV::RA, addr)?)
+    }
+
+    fn reserve(&self, mach: &mut impl Mach, nslots: u64) -> crate::errors::Result<()> {
+        self.inner.reserve(mach, nslots as usize)
+    }
+
+    fn unwind(&self, mach: &mut impl Mach, _nslots: u64) -> Result<u64, EmulatorError> {
+        Ok(Registers::read(mach, RegisterRISCV::RA)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RiscvProfile;
+
+    #[test]
+    fn test_detect() {
+        let mut header = vec![0u8; 64];
+        header[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        header[18] = 243;
+        let profile = RiscvProfile::detect(&header).unwrap();
+        assert_eq!(profile, "riscv64".parse().unwrap());
+        assert_eq!(profile.pointer_size(), 8);
+        header[4] = 1;
+        assert_eq!(RiscvProfile::detect(&header), Some(RiscvProfile::new(true)));
+        // big endian mips
+        header[5] = 2;
+        header[18..20].copy_from_slice(&[0, 8]);
+        assert_eq!(RiscvProfile::detect(&header), None);
+    }
+}
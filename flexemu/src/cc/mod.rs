@@ -32,6 +32,11 @@ pub trait CallingConvention {
     fn get_return_value(&self, mach: &mut impl Mach) -> Result<u64>;
     // TODO: handle negative value?
     fn set_return_value(&self, mach: &mut impl Mach, val: u64) -> Result<()>;
+    /// Report a failed syscall with a positive `errno`.
+    /// Most archs return `-errno` in the return register.
+    fn set_syscall_error(&self, mach: &mut impl Mach, errno: u64) -> Result<()> {
+        self.set_return_value(mach, (errno as i64).wrapping_neg() as u64)
+    }
     fn set_return_address(&self, mach: &mut impl Mach, addr: u64) -> Result<()>;
     fn reserve(&self, mach: &mut impl Mach, nslots: u64) -> Result<()>;
 
@@ -1,8 +1,193 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
+use unicorn_engine::unicorn_const::Arch;
 
 use crate::loader::Config;
 
-#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
 pub struct FlexEmuConfig {
     pub os: Config,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub preimage: PreimageConfig,
+    #[serde(default)]
+    pub machine: MachineConfig,
+}
+
+impl FlexEmuConfig {
+    /// `[machine]`, with the soft limit of the stack being the size of the stack mapped by the loader
+    /// if it isn't set.
+    pub fn machine_config(&self) -> MachineConfig {
+        let mut machine = self.machine.clone();
+        machine
+            .rlimits
+            .entry("stack".to_string())
+            .or_insert(self.os.stack_size);
+        machine
+    }
+}
+
+/// Identity of the emulated machine, as seen by the program through uname, sysinfo,
+/// getrlimit, the auxv and the files of `/proc`.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(default)]
+pub struct MachineConfig {
+    pub hostname: String,
+    pub kernel_release: String,
+    pub kernel_version: String,
+    /// total ram, in bytes.
+    pub ram: u64,
+    pub uid: u32,
+    pub gid: u32,
+    /// soft limits of resources, keyed by name like `stack` or `nofile`.
+    /// Hard limits, and soft limits of the resources not listed, are unlimited,
+    /// but the stack which defaults to `os.stack_size`.
+    pub rlimits: BTreeMap<String, u64>,
+    /// what the kernel reports in the auxv, the one of the architecture if not set.
+    pub auxv: Option<AuxvProfile>,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            hostname: "flexemu".to_string(),
+            kernel_release: "5.10.0".to_string(),
+            kernel_version: "#1 SMP".to_string(),
+            ram: 256 << 20,
+            uid: 1000,
+            gid: 1000,
+            rlimits: BTreeMap::from([("nofile".to_string(), 1024)]),
+            auxv: None,
+        }
+    }
+}
+
+/// What linux reports in the auxv and lays out on the initial stack of programs,
+/// which depends on the architecture, the cpu and the configuration of the kernel.
+/// There's no vdso, so `AT_SYSINFO_EHDR` is never reported.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct AuxvProfile {
+    /// `AT_HWCAP`, the features of the cpu.
+    pub hwcap: u64,
+    /// `AT_HWCAP2`, only reported by architectures which define it.
+    #[serde(default)]
+    pub hwcap2: Option<u64>,
+    /// `AT_PLATFORM`, only reported by some cpus.
+    #[serde(default)]
+    pub platform: Option<String>,
+    /// `AT_BASE_PLATFORM`, the isa of the cpu on mips.
+    #[serde(default)]
+    pub base_platform: Option<String>,
+    /// `AT_MINSIGSTKSZ`, only reported by architectures whose signal frames vary in size.
+    #[serde(default)]
+    pub minsigstksz: Option<u64>,
+    /// alignment of the stack below the argument strings, `ALMASK` of the kernel.
+    pub stack_align: u64,
+    /// bytes `AT_RANDOM` points to, fixed so that runs are reproducible.
+    #[serde(with = "hex")]
+    pub random: [u8; 16],
+}
+
+impl AuxvProfile {
+    /// The auxv of a 32 bits kernel on a mips32r2 cpu without dsp, like the 24Kc, for o32 programs.
+    pub fn mips_o32() -> Self {
+        Self {
+            hwcap: 0,
+            hwcap2: None,
+            platform: None,
+            base_platform: Some("mips32r2".to_string()),
+            minsigstksz: None,
+            stack_align: 8,
+            random: [10; 16],
+        }
+    }
+
+    /// What a 64 bits mips kernel reports to n64 programs, the stack is aligned to 16 bytes.
+    pub fn mips_n64() -> Self {
+        Self {
+            base_platform: Some("mips64r2".to_string()),
+            stack_align: 16,
+            ..Self::mips_o32()
+        }
+    }
+
+    /// What linux reports on a rv32gc or rv64gc cpu, hwcap has a bit per single letter extension: imafdc.
+    pub fn riscv() -> Self {
+        Self {
+            hwcap: 0x112d,
+            base_platform: None,
+            stack_align: 16,
+            ..Self::mips_o32()
+        }
+    }
+
+    /// The auxv of the architecture emulated with pointers of `pointer_size` bytes.
+    pub fn for_arch(arch: Arch, pointer_size: u8) -> Self {
+        match (arch, pointer_size) {
+            (Arch::MIPS, 4) => Self::mips_o32(),
+            (Arch::MIPS, 8) => Self::mips_n64(),
+            (Arch::RISCV, _) => Self::riscv(),
+            // nothing specific to the cpu, and the stack aligned like the 64 bits kernels do.
+            _ => Self {
+                base_platform: None,
+                stack_align: 16,
+                ..Self::mips_o32()
+            },
+        }
+    }
+}
+
+/// Where the program fetches pre-images from, the preimage syscall is only available if set,
+/// and only to mips o32 programs.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct PreimageConfig {
+    /// directory of pre-images, in files named after the hex encoded keccak256 hash of their content.
+    pub dir: Option<PathBuf>,
+}
+
+/// What the emulated program may do on the host, everything is permitted by default.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// names of the syscalls the program may call, all syscalls if not set.
+    pub allow: Option<Vec<String>>,
+    /// names of the syscalls the program may not call, even if they are allowed.
+    pub deny: Vec<String>,
+    /// whether syscalls may reach the host, like opening files or ioctl.
+    /// Without passthrough, the program can only use its stdin, stdout and stderr.
+    pub host_passthrough: bool,
+    /// host paths the program may access, along with everything under them. all paths if not set.
+    pub path_prefixes: Option<Vec<PathBuf>>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            allow: None,
+            deny: vec![],
+            host_passthrough: true,
+            path_prefixes: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlexEmuConfig;
+
+    #[test]
+    fn test_stack_rlimit() {
+        let os = "[os]\nstack_address = 0x7ff00000\nstack_size = 0x100000\nload_address = 0x400000\nmmap_address = 0x90000000\n";
+        let config: FlexEmuConfig = toml::from_str(os).unwrap();
+        assert_eq!(config.machine_config().rlimits["stack"], 0x100000);
+        assert_eq!(config.machine_config().rlimits["nofile"], 1024);
+
+        let config: FlexEmuConfig =
+            toml::from_str(&format!("{}[machine.rlimits]\nstack = 0x8000\n", os)).unwrap();
+        assert_eq!(config.machine_config().rlimits["stack"], 0x8000);
+        assert!(!config.machine_config().rlimits.contains_key("nofile"));
+    }
 }
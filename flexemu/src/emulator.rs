@@ -1,12 +1,20 @@
 //use crate::arch::Core;
 
-use std::{cell::RefCell, collections::BTreeMap, fs::create_dir_all, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
+use anyhow::anyhow;
+use goblin::container::Endian;
 use log::{debug, info, trace};
 use num_traits::Zero;
 use serde::{Deserialize, Serialize};
-use trie_db::{NodeCodec, TrieMut};
-use unicorn_engine::unicorn_const::{HookType, MemType, Mode};
+use trie_db::NodeCodec;
+use unicorn_engine::unicorn_const::{Arch, HookType, MemType, Mode};
 
 use ethtrie_codec::{EthTrieLayout, KeccakHasher, RlpNodeCodec};
 
@@ -15,9 +23,13 @@ use crate::{
     config::FlexEmuConfig,
     engine::{Engine, Machine, MemoryState},
     errors::EmulatorError,
-    loader::{ElfLoader, LoadInfo},
-    os::Runner,
+    loader::{ElfLoader, LoadInfo, Loader},
+    memory::PointerSizeT,
+    os::{RunOutcome, Runner},
     registers::{RegisterState, Registers},
+    step_proof::insert_state,
+    symbolizer::Symbolizer,
+    utils::Packer,
 };
 
 pub struct Emulator<'a, A, Os> {
@@ -45,7 +57,7 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
             HookType::MEM_WRITE | HookType::MEM_READ_AFTER,
             0,
             //align_up((conf.os.stack_address + conf.os.stack_size) as u32, 32) as u64,
-            u32::MAX as u64,
+            u64::MAX,
             {
                 |uc, mem_type, addr, size, value| {
                     trace!("{:?} -> ({},{}), v: {}", mem_type, addr, size, value);
@@ -55,14 +67,15 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
                                 uc.mem_read_as_vec(addr, size).unwrap(),
                                 uc.get_data().state.memory.read_bytes(addr, size)
                             );
+                            let endian = uc.endian();
                             uc.get_data_mut()
                                 .state
                                 .memory
-                                .write_value(addr, size, value);
+                                .write_value(addr, size, value, endian);
                         }
                         MemType::READ_AFTER => {
                             debug_assert_eq!(
-                                &(value as u32).to_be_bytes().as_slice()[(4 - size)..],
+                                Packer::new(uc.endian(), size as u8).pack(value as u64),
                                 uc.get_data().state.memory.read_bytes(addr, size)
                             );
                         }
@@ -73,7 +86,7 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
             },
         )?;
 
-        machine.add_code_hook(0, u32::MAX as u64, {
+        machine.add_code_hook(0, u64::MAX, {
             |uc, addr, size| {
                 uc.get_data_mut().state.steps += 1;
                 debug!(
@@ -81,7 +94,7 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
                     uc.get_data().state.steps,
                     addr,
                     size,
-                    uc.pc_read().unwrap()
+                    uc.get_data().symbolizer.describe(uc.pc_read().unwrap())
                 );
             }
         })?;
@@ -102,19 +115,43 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
         binary: impl AsRef<[u8]>,
         argv: Vec<String>,
         env: Vec<(String, String)>,
+    ) -> Result<LoadInfo, EmulatorError> {
+        self.load_with(&ElfLoader, binary, argv, env)
+    }
+
+    /// Report `path` to the program as the path it's executed with, argv[0] is reported otherwise.
+    pub fn with_execfn(mut self, path: impl Into<String>) -> Self {
+        self.config.os.execfn = Some(path.into());
+        self
+    }
+
+    /// Load the program with `loader`, which lays out its memory and initial stack.
+    pub fn load_with(
+        &mut self,
+        loader: &impl Loader,
+        binary: impl AsRef<[u8]>,
+        argv: Vec<String>,
+        env: Vec<(String, String)>,
     ) -> Result<LoadInfo, EmulatorError> {
         let binary = binary.as_ref();
-        let load_result = ElfLoader::load(
+        let load_result = loader.load(
             &self.config.os,
+            &self.config.machine,
             binary,
             argv,
             env.into_iter().collect::<BTreeMap<_, _>>(),
             &mut self.core,
         )?;
+        self.core.get_data_mut().symbolizer = loader.symbolizer(binary, &load_result);
         self.os.on_load(&mut self.core, load_result)?;
         Ok(load_result)
     }
 
+    /// Names of the addresses of the loaded program.
+    pub fn symbolizer(&self) -> &Symbolizer {
+        self.core.get_data().symbolizer()
+    }
+
     pub fn run(
         &mut self,
         entrypoint: u64,
@@ -123,7 +160,7 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
         count: Option<usize>,
     ) -> Result<u64, EmulatorError> {
         let exitpoint = exitpoint.unwrap_or_else(|| default_exitpoint(self.core.pointer_size()));
-        self.core.emu_start(
+        self.emu_start(
             entrypoint,
             exitpoint,
             timeout.unwrap_or_default(),
@@ -141,36 +178,31 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
     ) -> Result<StateChange, EmulatorError> {
         let exitpoint = exitpoint.unwrap_or_else(|| default_exitpoint(self.core.pointer_size()));
 
-        info!("pc: {}", self.core.pc()?);
+        info!("pc: {}", self.symbolizer().describe(self.core.pc()?));
 
         let state_before = if count.is_zero() {
             self.save()?
         } else {
-            self.core
-                .emu_start(entrypoint, exitpoint, timeout.unwrap_or_default(), count)?;
+            self.emu_start(entrypoint, exitpoint, timeout.unwrap_or_default(), count)?;
             self.save()?
         };
+        self.ensure_provable()?;
 
         let mem_access_sequence = Rc::new(RefCell::new(vec![]));
+        let pc = self.core.pc_read()?;
+        let size = self.instruction_size(pc)?;
         mem_access_sequence.borrow_mut().push(MemAccess {
             write: false,
-            addr: self.core.pc_read()?,
-            size: 4,
-            value: u32::from_be_bytes(
-                *self
-                    .core
-                    .mem_read_as_vec(self.core.pc_read()?, 4)?
-                    .as_chunks()
-                    .0
-                    .first()
-                    .unwrap(),
-            ) as i64,
+            addr: pc,
+            size,
+            value: Packer::new(self.core.endian(), size as u8)
+                .unpack(self.core.mem_read_as_vec(pc, size)?) as i64,
         });
 
         let handle = self.core.add_mem_hook(
             HookType::MEM_READ_AFTER | HookType::MEM_WRITE | HookType::MEM_VALID,
             0,
-            u32::MAX as u64,
+            u64::MAX,
             {
                 let mem_access = mem_access_sequence.clone();
                 move |_uc, mem_type, addr, size, value| {
@@ -197,14 +229,20 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
                 }
             },
         )?;
-        self.core
-            .emu_start(self.core.pc()?, exitpoint, timeout.unwrap_or_default(), 1)?;
+        // syscalls access the memory through the runner, out of sight of the hook.
+        self.core.get_data_mut().state.accesses = Some(mem_access_sequence.clone());
+        let pc = self.core.pc()?;
+        let result = self.emu_start(pc, exitpoint, timeout.unwrap_or_default(), 1);
+        self.core.get_data_mut().state.accesses = None;
         self.core.remove_hook(handle)?;
+        result?;
+        self.ensure_provable()?;
         let state_after = self.save()?;
         Ok(StateChange {
             state_after,
             state_before,
             step: (count + 1) as u64,
+            location: self.symbolizer().locate(pc).map(|l| l.to_string()),
             access: {
                 let x = mem_access_sequence.borrow();
                 x.to_vec()
@@ -212,6 +250,95 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
         })
     }
 
+    /// Size of the instruction at `pc`, riscv instructions whose low bits aren't `0b11` are compressed ones.
+    fn instruction_size(&self, pc: u64) -> Result<usize, EmulatorError> {
+        if self.core.get_arch() != Arch::RISCV {
+            return Ok(4);
+        }
+        let low = self.core.mem_read_as_vec(pc, 1)?;
+        Ok(if low[0] & 3 == 3 { 4 } else { 2 })
+    }
+
+    /// Start the engine, and surface the error which stopped the runner, if any.
+    /// If the runner redirected the control flow, the engine is restarted at the new address until
+    /// `count` instructions are executed.
+    /// The engine is also stopped at the end of every time slice of the runner, so it can reschedule.
+    fn emu_start(
+        &mut self,
+        mut begin: u64,
+        until: u64,
+        timeout: u64,
+        mut count: usize,
+    ) -> Result<(), EmulatorError> {
+        loop {
+            let steps_before = self.core.get_data().state.steps;
+            let slice = self
+                .os
+                .time_slice(steps_before)
+                .filter(|slice| count == 0 || *slice < count);
+            let result = self
+                .core
+                .emu_start(begin, until, timeout, slice.unwrap_or(count));
+            if let Some(e) = self.os.take_error() {
+                return Err(e);
+            }
+            if let Err(error) = result {
+                let pc = self.core.pc()?;
+                return Err(EmulatorError::Fault {
+                    pc,
+                    location: self.symbolizer().locate(pc).map(Box::new),
+                    error,
+                });
+            }
+
+            let executed = (self.core.get_data().state.steps - steps_before) as usize;
+            let resume_address = match self.os.take_resume_address() {
+                Some(addr) => addr,
+                None => match slice {
+                    Some(slice)
+                        if executed >= slice
+                            && self.os.outcome().is_none()
+                            && self.core.pc()? != until =>
+                    {
+                        self.os.on_slice_end(&mut self.core)?;
+                        if let Some(e) = self.os.take_error() {
+                            return Err(e);
+                        }
+                        match self.os.take_resume_address() {
+                            Some(addr) => addr,
+                            None => self.core.pc()?,
+                        }
+                    }
+                    _ => return Ok(()),
+                },
+            };
+            self.core.set_pc(resume_address)?;
+            if count != 0 {
+                if executed >= count {
+                    return Ok(());
+                }
+                count -= executed;
+            }
+            begin = resume_address;
+        }
+    }
+
+    pub fn outcome(&self) -> Option<RunOutcome> {
+        self.os.outcome()
+    }
+
+    /// Fail if the steps of the program can't be proven from the saved state.
+    /// Only memory and registers are saved, not the state of the runner. Once a second thread
+    /// exists, any step may be a switch of threads, which the judge can't replay.
+    pub fn ensure_provable(&self) -> Result<(), EmulatorError> {
+        if self.os.multithreaded() {
+            Err(anyhow!(
+                "the program ran a second thread, its steps can't be proven as the scheduler isn't part of the state"
+            ))?;
+        }
+        Ok(())
+    }
+
     pub fn save(&self) -> Result<EmulatorState, EmulatorError> {
         let register_vals = self.core.save_registers()?;
         let memory = self.core.get_data().state.snapshot();
@@ -219,6 +346,8 @@ impl<'a, A: ArchT, O: Runner> Emulator<'a, A, O> {
             regs: register_vals,
             memories: memory.memory,
             steps: memory.steps,
+            little_endian: self.core.endian() == Endian::Little,
+            pointer_size: self.core.pointer_size(),
         })
     }
 }
@@ -238,6 +367,9 @@ pub struct StateChange {
     pub state_after: EmulatorState,
     pub step: u64,
     pub access: Vec<MemAccess>,
+    /// function and source line of the executed instruction.
+    #[serde(default)]
+    pub location: Option<String>,
 }
 
 impl StateChange {
@@ -273,6 +405,36 @@ impl StateChange {
             &self.access,
         )
         .unwrap();
+        serde_json::to_writer_pretty(
+            std::fs::File::options()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output_dir.join("location.json"))
+                .unwrap(),
+            &self.location,
+        )
+        .unwrap();
+    }
+
+    /// Read back the state change written by `output_to`, the step isn't kept.
+    pub fn read_from(output_dir: &Path) -> Self {
+        let read = |name: &str| {
+            std::fs::File::options()
+                .read(true)
+                .open(output_dir.join(name))
+        };
+        Self {
+            step: 0,
+            state_before: serde_json::from_reader(read("before_state.json").unwrap()).unwrap(),
+            state_after: serde_json::from_reader(read("after_state.json").unwrap()).unwrap(),
+            access: serde_json::from_reader(read("mem_access.json").unwrap()).unwrap(),
+            // step dirs written before the location was kept don't have it.
+            location: read("location.json")
+                .ok()
+                .and_then(|f| serde_json::from_reader::<_, Option<String>>(f).ok())
+                .flatten(),
+        }
     }
 }
 
@@ -290,35 +452,67 @@ pub struct EmulatorState {
     pub regs: RegisterState,
     pub memories: MemoryState,
     pub steps: u64,
+    /// values of the memory accesses are little endian, for mipsel.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub little_endian: bool,
+    /// size of the addresses and registers, 8 for mips64.
+    #[serde(default = "default_pointer_size")]
+    pub pointer_size: PointerSizeT,
+}
+
+fn default_pointer_size() -> PointerSizeT {
+    4
 }
 
 impl EmulatorState {
     pub fn state_root(&self) -> [u8; 32] {
         let mut root = Default::default();
-        let mem: BTreeMap<_, _> = self.memories.clone().into();
         let mut db = memory_db::MemoryDB::<KeccakHasher, memory_db::HashKey<KeccakHasher>, _>::new(
             RlpNodeCodec::empty_node(),
         );
         let mut trie = trie_db::TrieDBMutBuilder::<EthTrieLayout>::new(&mut db, &mut root).build();
-        for (addr, v) in mem {
-            let shortend_addr = (addr >> 2) as u32;
-            trie.insert(&shortend_addr.to_be_bytes(), v.as_slice())
-                .unwrap();
-        }
-
-        // insert registers as a leaf with key [0,0,0,0]
-        let regs = {
-            let mut encoder = rlp::RlpStream::new_list(self.regs.len());
-            for (reg_id, v) in self.regs.clone() {
-                let encoded_register = ((reg_id as u64) << 32) + v;
-                encoder.append_iter(encoded_register.to_be_bytes());
-            }
-            encoder.out().to_vec()
-        };
-        trie.insert(&0u32.to_be_bytes(), &regs).unwrap();
-
+        insert_state(&mut trie, self);
         trie.commit();
         drop(trie);
         root
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{EmulatorState, MemAccess, StateChange};
+
+    #[test]
+    fn test_state_change_files() {
+        let state = || EmulatorState {
+            regs: [(1, 0x400180)].into(),
+            memories: Default::default(),
+            steps: 7,
+            little_endian: false,
+            pointer_size: 4,
+        };
+        let change = StateChange {
+            state_before: state(),
+            state_after: state(),
+            step: 8,
+            access: vec![MemAccess {
+                write: false,
+                addr: 0x400180,
+                size: 4,
+                value: 0x24020001,
+            }],
+            location: Some("main at main.rs:3".to_string()),
+        };
+        let dir = std::env::temp_dir().join(format!("flexemu-step-{}", std::process::id()));
+        change.output_to(dir.clone());
+        let read = StateChange::read_from(&dir);
+        assert_eq!(read.location, change.location);
+        assert_eq!(read.access.len(), 1);
+        assert_eq!(read.state_after.steps, 7);
+
+        // steps written without their location.
+        std::fs::remove_file(dir.join("location.json")).unwrap();
+        assert_eq!(StateChange::read_from(&dir).location, None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
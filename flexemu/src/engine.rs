@@ -1,17 +1,22 @@
 use crate::{
     arch::{ArchInfo, ArchT},
+    emulator::MemAccess,
     memory::{Memory, MemoryManager},
     registers::{Registers, StackRegister},
     stack::Stack,
-    utils::align,
+    symbolizer::Symbolizer,
+    utils::{align, Packer},
 };
+use goblin::container::Endian;
 use hex::ToHex;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
+    cell::RefCell,
     collections::{btree_map::Entry, BTreeMap},
     fmt::{Display, Formatter},
     ops::{Deref, DerefMut},
+    rc::Rc,
 };
 use unicorn_engine::{unicorn_const::Mode, Unicorn};
 pub type Engine<'a, A> = Unicorn<'a, Machine<A>>;
@@ -20,6 +25,8 @@ pub type Engine<'a, A> = Unicorn<'a, Machine<A>>;
 pub struct Machine<A> {
     pub(crate) memories: MemoryManager,
     pub(crate) state: MachineState,
+    /// names of the addresses of the loaded program.
+    pub(crate) symbolizer: Symbolizer,
     arch: A,
 }
 
@@ -27,6 +34,9 @@ impl<A> Machine<A> {
     pub fn env(&self) -> &A {
         &self.arch
     }
+    pub fn symbolizer(&self) -> &Symbolizer {
+        &self.symbolizer
+    }
 }
 
 impl<A: ArchT> Machine<A> {
@@ -35,6 +45,7 @@ impl<A: ArchT> Machine<A> {
             memories: MemoryManager::default(),
             arch: at,
             state: Default::default(),
+            symbolizer: Default::default(),
         };
         let uc = Unicorn::new_with_data(A::T, mode, data).unwrap();
         uc
@@ -49,6 +60,9 @@ impl<T> Mach for T where T: Stack + Registers + Memory + StackRegister + ArchInf
 pub struct MachineState {
     pub steps: u64,
     pub memory: MemoryState,
+    /// accesses of the runner to the memory, like syscalls reading and writing buffers, while a step
+    /// is proven. hooks of unicorn only see the accesses of instructions.
+    pub accesses: Option<Rc<RefCell<Vec<MemAccess>>>>,
 }
 
 impl MachineState {
@@ -57,6 +71,25 @@ impl MachineState {
         s.memory.shrink();
         s
     }
+
+    /// Record an access of the runner to `bytes` at `addr`, one per word it touches.
+    pub fn record(&self, write: bool, mut addr: u64, mut bytes: &[u8], endian: Endian) {
+        let accesses = match &self.accesses {
+            Some(accesses) => accesses,
+            None => return,
+        };
+        while !bytes.is_empty() {
+            let size = ((align(addr, 4u8) + 4 - addr) as usize).min(bytes.len());
+            accesses.borrow_mut().push(MemAccess {
+                write,
+                addr,
+                size,
+                value: Packer::new(endian, size as u8).unpack(bytes[..size].to_vec()) as i64,
+            });
+            addr += size as u64;
+            bytes = &bytes[size..];
+        }
+    }
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -136,9 +169,9 @@ impl MemoryState {
             }
         }
     }
-    pub fn write_value(&mut self, addr: u64, size: usize, value: i64) {
-        let value_bytes = (value as u32).to_be_bytes();
-        self.write_bytes(addr, &value_bytes[(4 - size)..])
+    /// Write the low `size` bytes of `value` in `endian` order, as the guest stores it.
+    pub fn write_value(&mut self, addr: u64, size: usize, value: i64, endian: Endian) {
+        self.write_bytes(addr, &Packer::new(endian, size as u8).pack(value as u64))
     }
 
     fn index_chunk(&self, addr: u64) -> Cow<[u8]> {
@@ -170,15 +203,53 @@ impl MemoryState {
 
 #[cfg(test)]
 mod test {
-    use crate::engine::MemoryState;
+    use std::{cell::RefCell, rc::Rc};
+
+    use goblin::container::Endian;
+
+    use crate::engine::{MachineState, MemoryState};
 
     #[test]
     fn test_memory_state() {
         let mut state = MemoryState::default();
-        state.write_value(2146684216, 4, 4772032);
+        state.write_value(2146684216, 4, 4772032, Endian::Big);
         let data = state.read_bytes(2146684216, 4);
         let value = u32::from_be_bytes(data[0..4].try_into().unwrap());
 
         assert_eq!(value, 4772032);
     }
+
+    #[test]
+    fn test_memory_state_little_endian() {
+        let mut state = MemoryState::default();
+        state.write_value(0x1000, 4, 0x11223344, Endian::Little);
+        state.write_value(0x1005, 2, 0x5566, Endian::Little);
+        assert_eq!(
+            state.read_bytes(0x1000, 8),
+            vec![0x44, 0x33, 0x22, 0x11, 0, 0x66, 0x55, 0]
+        );
+    }
+
+    #[test]
+    fn test_record_accesses() {
+        let mut state = MachineState::default();
+        state.record(true, 0x1000, &[1], Endian::Big);
+        let accesses = Rc::new(RefCell::new(vec![]));
+        state.accesses = Some(accesses.clone());
+        // split at word boundaries, so that proofs cover each word.
+        state.record(true, 0x1002, &[1, 2, 3, 4, 5, 6, 7], Endian::Big);
+        let accesses = accesses
+            .borrow()
+            .iter()
+            .map(|a| (a.write, a.addr, a.size, a.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            accesses,
+            vec![
+                (true, 0x1002, 2, 0x0102),
+                (true, 0x1004, 4, 0x03040506),
+                (true, 0x1008, 1, 0x07)
+            ]
+        );
+    }
 }
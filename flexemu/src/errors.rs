@@ -3,6 +3,8 @@ use std::{io, io::Error};
 use thiserror::Error;
 use unicorn_engine::unicorn_const::uc_error;
 
+use crate::{loader::LayoutRegion, symbolizer::Location};
+
 #[derive(Error, Debug)]
 pub enum EmulatorError {
     #[error("unicorn error {0:?}")]
@@ -13,6 +15,33 @@ pub enum EmulatorError {
     IOError(#[from] io::Error),
     #[error("custom error {0}")]
     Custom(#[from] anyhow::Error),
+    #[error("syscall {name}({}) at pc {pc:#x}{} failed: {source}", .args.iter().map(|a| format!("{:#x}", a)).collect::<Vec<_>>().join(", "), in_location(.location))]
+    SyscallError {
+        name: String,
+        args: Vec<u64>,
+        pc: u64,
+        location: Option<Box<Location>>,
+        #[source]
+        source: Box<EmulatorError>,
+    },
+    #[error("unicorn error {error:?} at pc {pc:#x}{}", in_location(.location))]
+    Fault {
+        pc: u64,
+        location: Option<Box<Location>>,
+        error: uc_error,
+    },
+    #[error("memory layout conflict, {first} and {second} overlap")]
+    Layout {
+        first: LayoutRegion,
+        second: LayoutRegion,
+    },
+}
+
+fn in_location(location: &Option<Box<Location>>) -> String {
+    location
+        .as_ref()
+        .map(|l| format!(" in {}", l))
+        .unwrap_or_default()
 }
 
 pub fn from_raw_syscall_ret(ret: i64) -> EmulatorError {
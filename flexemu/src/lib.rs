@@ -19,6 +19,7 @@ pub mod os;
 pub mod rand;
 pub mod registers;
 pub mod stack;
+pub mod symbolizer;
 pub mod utils;
 
 pub const PAGE_SIZE: u32 = 0x1000;
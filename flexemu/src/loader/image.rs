@@ -0,0 +1,497 @@
+//! Loader of memory images, the pages of a program already laid out in memory, as shipped by
+//! fault proof stacks which snapshot the program once it's loaded.
+//!
+//! An image is either the `state.json` of Cannon, whose pages and registers are imported:
+//!
+//! ```json
+//! {"memory": [{"index": 1024, "data": "eJzs..."}], "pc": 4194304, "nextPC": 4194308, "lo": 0, "hi": 0, "heap": 536870912, "registers": [0, ...]}
+//! ```
+//!
+//! json of flexemu, whose registers start like for elf, except the stack pointer:
+//!
+//! ```json
+//! {"entry": 4194304, "stack_pointer": 2147483632, "pages": [{"address": 4194304, "data": "3c1c0042..."}]}
+//! ```
+//!
+//! or binary: the big endian `u64` entry, followed by pages, each one a big endian `u64` address
+//! and [`PAGE_SIZE`] bytes.
+
+use std::{collections::BTreeMap, io::Read};
+
+use anyhow::anyhow;
+use flate2::read::ZlibDecoder;
+use goblin::container::Endian;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use unicorn_engine::{
+    unicorn_const::{Arch, MemRegion, Permission},
+    RegisterMIPS,
+};
+
+use crate::{
+    config::MachineConfig,
+    engine::Mach,
+    errors::EmulatorError,
+    loader::{
+        check_layout, config_regions, setup_stack, Config, LayoutRegion, LoadInfo, Loader,
+        HOOK_MEM_SIZE,
+    },
+    memory::Memory,
+    registers::Registers,
+    utils::align,
+    PAGE_SIZE,
+};
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct Page {
+    pub address: u64,
+    /// at most a page, the rest of the page is zero.
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct PageImage {
+    pub entry: u64,
+    /// the stack is part of the pages when it's set, otherwise a stack is set up like for elf.
+    #[serde(default)]
+    pub stack_pointer: Option<u64>,
+    pub pages: Vec<Page>,
+    /// registers of the program when the image is a Cannon state.
+    #[serde(skip)]
+    pub cannon: Option<CannonRegisters>,
+}
+
+/// Registers of a mips32 program imported from a Cannon state, and where it maps memory next.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct CannonRegisters {
+    pub gpr: [u32; 32],
+    pub hi: u32,
+    pub lo: u32,
+    pub heap: u32,
+}
+
+/// log2 of the size of the pages of Cannon.
+const CANNON_PAGE_ADDR_SIZE: u32 = 12;
+
+/// The `state.json` of Cannon, written by `cannon load-elf` and `cannon run`.
+/// The pre-image key and offset aren't imported, the pre-image oracle of flexemu is a syscall.
+#[derive(Deserialize, Clone, Eq, PartialEq, Debug)]
+struct CannonState {
+    /// null when no page is set.
+    memory: Option<Vec<CannonPage>>,
+    pc: u32,
+    #[serde(rename = "nextPC")]
+    next_pc: u32,
+    lo: u32,
+    hi: u32,
+    heap: u32,
+    #[serde(default)]
+    exited: bool,
+    #[serde(default, rename = "exit")]
+    exit_code: u8,
+    registers: [u32; 32],
+}
+
+#[derive(Deserialize, Clone, Eq, PartialEq, Debug)]
+struct CannonPage {
+    index: u32,
+    /// base64 of the zlib compressed page.
+    data: String,
+}
+
+impl CannonPage {
+    fn decode(&self) -> Result<Page, EmulatorError> {
+        let compressed = base64::decode(&self.data)
+            .map_err(|e| anyhow!("invalid cannon page {}: {}", self.index, e))?;
+        let mut data = vec![];
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut data)
+            .map_err(|e| anyhow!("invalid cannon page {}: {}", self.index, e))?;
+        if data.len() != 1 << CANNON_PAGE_ADDR_SIZE {
+            Err(anyhow!(
+                "cannon page {} is {} bytes",
+                self.index,
+                data.len()
+            ))?;
+        }
+        Ok(Page {
+            address: (self.index as u64) << CANNON_PAGE_ADDR_SIZE,
+            data,
+        })
+    }
+}
+
+impl CannonState {
+    fn into_image(self) -> Result<PageImage, EmulatorError> {
+        if self.exited {
+            Err(anyhow!("cannon state exited with {}", self.exit_code))?;
+        }
+        // unicorn can't resume between a branch and its delay slot.
+        if self.next_pc != self.pc.wrapping_add(4) {
+            Err(anyhow!(
+                "cannon state at {:#x} is in the delay slot of a branch to {:#x}",
+                self.pc,
+                self.next_pc
+            ))?;
+        }
+        let pages = self
+            .memory
+            .unwrap_or_default()
+            .iter()
+            .map(CannonPage::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PageImage {
+            entry: self.pc as u64,
+            stack_pointer: Some(self.registers[29] as u64),
+            pages,
+            cannon: Some(CannonRegisters {
+                gpr: self.registers,
+                hi: self.hi,
+                lo: self.lo,
+                heap: self.heap,
+            }),
+        })
+    }
+}
+
+impl PageImage {
+    /// Parse an image, json if it starts with `{`, a Cannon state if it has `memory`,
+    /// binary otherwise.
+    pub fn parse(image: &[u8]) -> Result<Self, EmulatorError> {
+        let image = if image.first() == Some(&b'{') {
+            let json: serde_json::Value =
+                serde_json::from_slice(image).map_err(|e| anyhow!("invalid json image: {}", e))?;
+            if json.get("memory").is_some() {
+                serde_json::from_value::<CannonState>(json)
+                    .map_err(|e| anyhow!("invalid cannon state: {}", e))?
+                    .into_image()?
+            } else {
+                serde_json::from_value(json).map_err(|e| anyhow!("invalid json image: {}", e))?
+            }
+        } else {
+            Self::parse_binary(image)?
+        };
+        image.validate()?;
+        Ok(image)
+    }
+
+    fn parse_binary(image: &[u8]) -> Result<Self, EmulatorError> {
+        let page_size = PAGE_SIZE as usize;
+        let chunks = image
+            .get(8..)
+            .map(|pages| pages.chunks_exact(8 + page_size));
+        let chunks = match chunks {
+            Some(chunks) if chunks.remainder().is_empty() => chunks,
+            _ => Err(anyhow!("invalid binary image of {} bytes", image.len()))?,
+        };
+        let read_u64 = |b: &[u8]| u64::from_be_bytes(b[..8].try_into().unwrap());
+        let pages = chunks
+            .map(|chunk| Page {
+                address: read_u64(chunk),
+                data: chunk[8..].to_vec(),
+            })
+            .collect();
+        Ok(Self {
+            entry: read_u64(image),
+            stack_pointer: None,
+            pages,
+            cannon: None,
+        })
+    }
+
+    fn validate(&self) -> Result<(), EmulatorError> {
+        let page_size = PAGE_SIZE as u64;
+        let mut addresses = BTreeMap::new();
+        for page in &self.pages {
+            if align(page.address, page_size) != page.address || page.data.len() as u64 > page_size
+            {
+                Err(anyhow!("invalid image page at {:#x}", page.address))?;
+            }
+            if addresses.insert(page.address, ()).is_some() {
+                Err(anyhow!("image page at {:#x} is duplicated", page.address))?;
+            }
+        }
+        let entry_page = align(self.entry, page_size);
+        if !addresses.contains_key(&entry_page) {
+            Err(anyhow!(
+                "entry {:#x} of image is not in its pages",
+                self.entry
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Pages of the image merged into contiguous regions.
+    fn regions(&self) -> Vec<(u64, u64)> {
+        let page_size = PAGE_SIZE as u64;
+        let mut addresses = self.pages.iter().map(|p| p.address).collect::<Vec<_>>();
+        addresses.sort_unstable();
+        let mut regions: Vec<(u64, u64)> = vec![];
+        for address in addresses {
+            match regions.last_mut() {
+                Some((_, end)) if *end == address => *end += page_size,
+                _ => regions.push((address, address + page_size)),
+            }
+        }
+        regions
+    }
+}
+
+/// Loads a [`PageImage`], its pages are mapped with all permissions.
+/// The registers of Cannon states are set once the pages are loaded.
+pub struct ImageLoader;
+
+impl Loader for ImageLoader {
+    fn load(
+        &self,
+        config: &Config,
+        machine: &MachineConfig,
+        binary: &[u8],
+        argv: Vec<String>,
+        env: BTreeMap<String, String>,
+        uc: &mut impl Mach,
+    ) -> Result<LoadInfo, EmulatorError> {
+        let image = PageImage::parse(binary)?;
+        if image.cannon.is_some()
+            && (uc.arch() != Arch::MIPS || uc.pointer_size() != 4 || uc.endian() != Endian::Big)
+        {
+            Err(anyhow!(
+                "cannon states are of big endian mips32 programs, run them with --arch mips"
+            ))?;
+        }
+        // a Cannon program maps memory from its heap.
+        let config = &match &image.cannon {
+            Some(cannon) => Config {
+                mmap_address: cannon.heap as u64,
+                ..config.clone()
+            },
+            None => config.clone(),
+        };
+        let regions = image.regions();
+        let (mem_start, mem_end) = (regions[0].0, regions[regions.len() - 1].1);
+        let pages = regions
+            .iter()
+            .map(|(begin, end)| LayoutRegion::new("image pages", *begin, *end))
+            .collect::<Vec<_>>();
+        let mut reserved = config_regions(config, mem_end + HOOK_MEM_SIZE);
+        // the stack is one of the pages then.
+        if image.stack_pointer.is_some() {
+            reserved.retain(|r| r.name != "stack");
+        }
+        check_layout(&pages, &reserved)?;
+
+        for (begin, end) in &regions {
+            debug!("mmap image pages {:#x} - {:#x}", begin, end);
+            uc.mem_map(
+                MemRegion {
+                    begin: *begin,
+                    end: *end,
+                    perms: Permission::ALL,
+                },
+                None,
+            )?;
+        }
+        for page in &image.pages {
+            Memory::write(uc, page.address, &page.data)?;
+        }
+
+        let mut load_result = LoadInfo {
+            entrypoint: image.entry,
+            elf_mem_start: mem_start,
+            elf_entry: image.entry,
+            brk_address: mem_end + HOOK_MEM_SIZE,
+            mmap_address: config.mmap_address,
+            load_address: mem_start,
+            ..Default::default()
+        };
+        match image.stack_pointer {
+            Some(sp) => {
+                uc.set_sp(sp)?;
+                load_result.init_stack_address = sp;
+            }
+            None => setup_stack(
+                config,
+                machine,
+                uc,
+                Permission::ALL,
+                None,
+                &mut load_result,
+                argv,
+                env,
+            )?,
+        }
+        if let Some(cannon) = &image.cannon {
+            // skip $zero
+            for (i, value) in cannon.gpr.iter().enumerate().skip(1) {
+                Registers::write(uc, RegisterMIPS::ZERO as i32 + i as i32, *value as u64)?;
+            }
+            Registers::write(uc, RegisterMIPS::HI, cannon.hi as u64)?;
+            Registers::write(uc, RegisterMIPS::LO, cannon.lo as u64)?;
+        }
+        Ok(load_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Write};
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use unicorn_engine::RegisterMIPS;
+
+    use super::{ImageLoader, Page, PageImage};
+    use crate::{
+        arch::mips::{MipsProfile, MIPS},
+        config::MachineConfig,
+        engine::{Engine, Machine},
+        errors::EmulatorError,
+        loader::{Config, LoadInfo, Loader},
+        registers::Registers,
+        PAGE_SIZE,
+    };
+
+    fn config() -> Config {
+        Config {
+            stack_address: 0x7ff0d000,
+            stack_size: 0x30000,
+            load_address: 0x400000,
+            mmap_address: 0x90000000,
+            heap_size: None,
+            sysroot: None,
+            relocate: false,
+            execfn: None,
+            raw: None,
+        }
+    }
+
+    /// Load `image` in a mips engine.
+    fn load<'a>(image: &str) -> Result<(LoadInfo, Engine<'a, MIPS>), EmulatorError> {
+        let profile = MipsProfile::default();
+        let mut core = Machine::create(MIPS::new(profile.pointer_size()), profile.mode());
+        let info = ImageLoader.load(
+            &config(),
+            &MachineConfig::default(),
+            image.as_bytes(),
+            vec![],
+            BTreeMap::new(),
+            &mut core,
+        )?;
+        Ok((info, core))
+    }
+
+    #[test]
+    fn test_parse_image() {
+        let json = br#"{"entry": 8196, "pages": [
+            {"address": 8192, "data": "0000000c"},
+            {"address": 4096, "data": ""}
+        ]}"#;
+        let image = PageImage::parse(json).unwrap();
+        assert_eq!(image.entry, 0x2004);
+        assert_eq!(image.stack_pointer, None);
+        assert_eq!(
+            image.pages[0],
+            Page {
+                address: 0x2000,
+                data: vec![0, 0, 0, 0xc]
+            }
+        );
+        assert_eq!(image.regions(), vec![(0x1000, 0x3000)]);
+
+        let mut binary = 0x5004u64.to_be_bytes().to_vec();
+        binary.extend(0x5000u64.to_be_bytes());
+        binary.extend(vec![0xff; PAGE_SIZE as usize]);
+        let image = PageImage::parse(&binary).unwrap();
+        assert_eq!(image.entry, 0x5004);
+        assert_eq!(image.pages[0].data.len(), PAGE_SIZE as usize);
+
+        // the entry must be loaded, and pages be whole.
+        binary[..8].copy_from_slice(&0x6000u64.to_be_bytes());
+        assert!(PageImage::parse(&binary).is_err());
+        assert!(PageImage::parse(&binary[..100]).is_err());
+    }
+
+    #[test]
+    fn test_load_image() {
+        let config = config();
+        let image = |stack_pointer: Option<u64>| {
+            let stack = match stack_pointer {
+                Some(sp) => format!(r#", {{"address": {}, "data": ""}}"#, sp & !0xfff),
+                None => String::new(),
+            };
+            let stack_pointer = stack_pointer.map_or("null".to_string(), |sp| sp.to_string());
+            format!(
+                r#"{{"entry": 4194304, "stack_pointer": {}, "pages": [{{"address": 4194304, "data": "0000000c"}}{}]}}"#,
+                stack_pointer, stack
+            )
+        };
+
+        let (info, core) = load(&image(None)).unwrap();
+        assert_eq!(info.entrypoint, 0x400000);
+        let sp = core.read(RegisterMIPS::SP).unwrap();
+        assert!((config.stack_address..config.stack_address + config.stack_size).contains(&sp));
+        // the stack of the image may be where the config puts the stack.
+        let (_, core) = load(&image(Some(0x7ff3cff0))).unwrap();
+        assert_eq!(core.read(RegisterMIPS::SP).unwrap(), 0x7ff3cff0);
+
+        // but the other pages must be out of it.
+        match load(&image(None).replace("4194304", "2146492416")) {
+            Err(EmulatorError::Layout { first, second }) => {
+                assert_eq!(first.name, "image pages");
+                assert_eq!(second.name, "stack");
+            }
+            r => panic!("unexpected {:?}", r.map(|(info, _)| info)),
+        }
+    }
+
+    #[test]
+    fn test_load_cannon_state() {
+        // the json of a Go `[]byte`, base64 of the zlib compressed page.
+        let page = |words: &[u32]| {
+            let mut data = words
+                .iter()
+                .flat_map(|w| w.to_be_bytes())
+                .collect::<Vec<_>>();
+            data.resize(PAGE_SIZE as usize, 0);
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(&data).unwrap();
+            base64::encode(&encoder.finish().unwrap())
+        };
+        let mut registers = [0u32; 32];
+        registers[2] = 4246;
+        registers[4] = 1;
+        registers[29] = 0x7fffcff0;
+        let state = |pc: u32, next_pc: u32, exited: bool| {
+            format!(
+                r#"{{"memory": [{{"index": 1024, "data": "{}"}}, {{"index": 524284, "data": "{}"}}],
+                "preimageKey": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "preimageOffset": 0, "pc": {}, "nextPC": {}, "lo": 6, "hi": 5, "heap": 536870912,
+                "exit": 0, "exited": {}, "step": 42, "registers": {:?}}}"#,
+                page(&[0x2402_1096, 0xc]),
+                page(&[]),
+                pc,
+                next_pc,
+                exited,
+                registers
+            )
+        };
+
+        let (info, core) = load(&state(0x400004, 0x400008, false)).unwrap();
+        assert_eq!(info.entrypoint, 0x400004);
+        assert_eq!(info.mmap_address, 0x20000000);
+        assert_eq!(info.init_stack_address, 0x7fffcff0);
+        assert_eq!(
+            core.mem_read_as_vec(0x400004, 4).unwrap(),
+            vec![0, 0, 0, 0xc]
+        );
+        assert_eq!(core.read(RegisterMIPS::SP).unwrap(), 0x7fffcff0);
+        assert_eq!(core.read(RegisterMIPS::V0).unwrap(), 4246);
+        assert_eq!(core.read(RegisterMIPS::A0).unwrap(), 1);
+        assert_eq!(core.read(RegisterMIPS::HI).unwrap(), 5);
+        assert_eq!(core.read(RegisterMIPS::LO).unwrap(), 6);
+
+        // unicorn can't resume in a delay slot, nor is there anything to run once exited.
+        assert!(load(&state(0x400004, 0x400100, false)).is_err());
+        assert!(load(&state(0x400004, 0x400008, true)).is_err());
+    }
+}
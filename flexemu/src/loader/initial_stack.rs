@@ -0,0 +1,261 @@
+//! Initial stack of a program, laid out like `create_elf_tables` of linux.
+//!
+//! From the top of the stack down, there are:
+//! - a null pointer,
+//! - the strings of argv and envp, then the path of the program, argv[0] being the lowest,
+//! - aligned down to `stack_align` of the profile, the `AT_PLATFORM` and `AT_BASE_PLATFORM`
+//!   strings and the `AT_RANDOM` bytes,
+//! - aligned down to 16 bytes, argc, argv, envp and the auxv, where the stack pointer starts.
+
+use goblin::container::Endian;
+
+use crate::{
+    config::AuxvProfile,
+    loader::AUXV,
+    memory::PointerSizeT,
+    utils::{align, Packer},
+};
+
+/// clock ticks per second reported by `AT_CLKTCK`, `USER_HZ` of linux.
+const CLOCK_TICKS: u64 = 100;
+
+/// Values of the auxv which don't point into the stack.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct AuxvValues {
+    pub pagesz: u64,
+    pub phdr: u64,
+    pub phent: u64,
+    pub phnum: u64,
+    /// load address of the interpreter.
+    pub base: u64,
+    /// entry of the program, not of its interpreter.
+    pub entry: u64,
+    pub uid: u64,
+    pub gid: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct InitialStack {
+    /// initial stack pointer.
+    pub sp: u64,
+    /// content of the stack, from `sp` up to the top.
+    pub data: Vec<u8>,
+}
+
+/// Where the content of the stack goes, allocated downward from the top.
+struct Layout {
+    p: u64,
+    writes: Vec<(u64, Vec<u8>)>,
+}
+
+impl Layout {
+    fn alloc(&mut self, bytes: Vec<u8>) -> u64 {
+        self.p -= bytes.len() as u64;
+        self.writes.push((self.p, bytes));
+        self.p
+    }
+
+    fn alloc_str(&mut self, s: &str) -> u64 {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.push(0);
+        self.alloc(bytes)
+    }
+}
+
+/// Lay out the initial stack below `top` for a program started with `argv` and `envp`,
+/// `execfn` is the path the program was executed with.
+#[allow(clippy::too_many_arguments)]
+pub fn initial_stack(
+    top: u64,
+    endian: Endian,
+    pointer_size: PointerSizeT,
+    profile: &AuxvProfile,
+    argv: &[String],
+    envp: &[String],
+    execfn: &str,
+    values: &AuxvValues,
+) -> InitialStack {
+    let packer = Packer::new(endian, pointer_size);
+    let ps = pointer_size as u64;
+    let mut layout = Layout {
+        p: top - ps,
+        writes: vec![],
+    };
+
+    // strings are copied from the last one, so that they're in order in memory.
+    let execfn = layout.alloc_str(execfn);
+    let mut env_addrs = envp
+        .iter()
+        .rev()
+        .map(|s| layout.alloc_str(s))
+        .collect::<Vec<_>>();
+    env_addrs.reverse();
+    let mut arg_addrs = argv
+        .iter()
+        .rev()
+        .map(|s| layout.alloc_str(s))
+        .collect::<Vec<_>>();
+    arg_addrs.reverse();
+
+    layout.p = align(layout.p, profile.stack_align);
+    let platform = profile.platform.as_ref().map(|s| layout.alloc_str(s));
+    let base_platform = profile.base_platform.as_ref().map(|s| layout.alloc_str(s));
+    let random = layout.alloc(profile.random.to_vec());
+
+    let mut auxv = vec![];
+    if let Some(minsigstksz) = profile.minsigstksz {
+        auxv.push((AUXV::AT_MINSIGSTKSZ, minsigstksz));
+    }
+    auxv.extend([
+        (AUXV::AT_HWCAP, profile.hwcap),
+        (AUXV::AT_PAGESZ, values.pagesz),
+        (AUXV::AT_CLKTCK, CLOCK_TICKS),
+        (AUXV::AT_PHDR, values.phdr),
+        (AUXV::AT_PHENT, values.phent),
+        (AUXV::AT_PHNUM, values.phnum),
+        (AUXV::AT_BASE, values.base),
+        (AUXV::AT_FLAGS, 0),
+        (AUXV::AT_ENTRY, values.entry),
+        (AUXV::AT_UID, values.uid),
+        (AUXV::AT_EUID, values.uid),
+        (AUXV::AT_GID, values.gid),
+        (AUXV::AT_EGID, values.gid),
+        (AUXV::AT_SECURE, 0),
+        (AUXV::AT_RANDOM, random),
+    ]);
+    if let Some(hwcap2) = profile.hwcap2 {
+        auxv.push((AUXV::AT_HWCAP2, hwcap2));
+    }
+    auxv.push((AUXV::AT_EXECFN, execfn));
+    if let Some(platform) = platform {
+        auxv.push((AUXV::AT_PLATFORM, platform));
+    }
+    if let Some(base_platform) = base_platform {
+        auxv.push((AUXV::AT_BASE_PLATFORM, base_platform));
+    }
+    auxv.push((AUXV::AT_NULL, 0));
+
+    let mut table = packer.pack(argv.len() as u64);
+    for addr in arg_addrs.into_iter().chain([0]) {
+        table.extend(packer.pack(addr));
+    }
+    for addr in env_addrs.into_iter().chain([0]) {
+        table.extend(packer.pack(addr));
+    }
+    for (k, v) in auxv {
+        table.extend(packer.pack(k as u64));
+        table.extend(packer.pack(v));
+    }
+    let sp = align(layout.p - table.len() as u64, 16u64);
+    layout.writes.push((sp, table));
+
+    let mut data = vec![0; (top - sp) as usize];
+    for (addr, bytes) in layout.writes {
+        let offset = (addr - sp) as usize;
+        data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    InitialStack { sp, data }
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::container::Endian;
+
+    use super::{initial_stack, AuxvValues};
+    use crate::config::AuxvProfile;
+
+    #[test]
+    fn test_initial_stack_mips_o32() {
+        let profile = AuxvProfile {
+            random: *b"0123456789abcdef",
+            ..AuxvProfile::mips_o32()
+        };
+        let values = AuxvValues {
+            pagesz: 0x1000,
+            phdr: 0x400034,
+            phent: 32,
+            phnum: 7,
+            base: 0,
+            entry: 0x400180,
+            uid: 1000,
+            gid: 1000,
+        };
+        let stack = initial_stack(
+            0x7ff3d000,
+            Endian::Big,
+            4,
+            &profile,
+            &["./hello".to_string(), "-v".to_string()],
+            &["HOME=/root".to_string(), "PATH=/bin".to_string()],
+            "./hello",
+            &values,
+        );
+        // worked out by hand after `create_elf_tables` of linux 5.10 for an o32 program on a 24Kc,
+        // minus the AT_SYSINFO_EHDR of the vdso, which isn't mapped. it's not a capture of a kernel yet,
+        // replace it with the output of `stack-dump-example` of rust-mips-example, see its README.
+        let golden = hex::decode(concat!(
+            // argc, argv, envp
+            "00000002",
+            "7ff3cfd4",
+            "7ff3cfdc",
+            "00000000",
+            "7ff3cfdf",
+            "7ff3cfea",
+            "00000000",
+            // auxv
+            "00000010",
+            "00000000",
+            "00000006",
+            "00001000",
+            "00000011",
+            "00000064",
+            "00000003",
+            "00400034",
+            "00000004",
+            "00000020",
+            "00000005",
+            "00000007",
+            "00000007",
+            "00000000",
+            "00000008",
+            "00000000",
+            "00000009",
+            "00400180",
+            "0000000b",
+            "000003e8",
+            "0000000c",
+            "000003e8",
+            "0000000d",
+            "000003e8",
+            "0000000e",
+            "000003e8",
+            "00000017",
+            "00000000",
+            "00000019",
+            "7ff3cfb7",
+            "0000001f",
+            "7ff3cff4",
+            "00000018",
+            "7ff3cfc7",
+            "00000000",
+            "00000000",
+            // padding of the table to 16 bytes
+            "0000000000000000000000",
+            // AT_RANDOM and AT_BASE_PLATFORM
+            "30313233343536373839616263646566",
+            "6d6970733332723200",
+            // padding of the strings to 8 bytes
+            "00000000",
+            // argv, envp and execfn strings, then the null pointer at the top.
+            "2e2f68656c6c6f00",
+            "2d7600",
+            "484f4d453d2f726f6f7400",
+            "504154483d2f62696e00",
+            "2e2f68656c6c6f00",
+            "00000000",
+        ))
+        .unwrap();
+        assert_eq!(stack.sp, 0x7ff3cf00);
+        assert_eq!(hex::encode(&stack.data), hex::encode(&golden));
+    }
+}
@@ -0,0 +1,148 @@
+//! Checks that the regions laid out by the loaders don't clash, before anything is mapped,
+//! so that a bad `[os]` config is reported by name rather than by an error of unicorn.
+
+use std::fmt::{Display, Formatter};
+
+use goblin::elf::{program_header::PT_LOAD, Elf};
+
+use crate::{
+    errors::EmulatorError,
+    loader::Config,
+    utils::{align, align_up},
+    PAGE_SIZE,
+};
+
+/// A named range of addresses, empty for places where an area starts, like the mmap base.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LayoutRegion {
+    pub name: String,
+    pub begin: u64,
+    pub end: u64,
+}
+
+impl Display for LayoutRegion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.begin == self.end {
+            write!(f, "{} at {:#x}", self.name, self.begin)
+        } else {
+            write!(f, "{} [{:#x}, {:#x})", self.name, self.begin, self.end)
+        }
+    }
+}
+
+impl LayoutRegion {
+    pub fn new(name: impl Into<String>, begin: u64, end: u64) -> Self {
+        Self {
+            name: name.into(),
+            begin,
+            end,
+        }
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        self.begin <= addr && addr < self.end
+    }
+
+    /// Whether the regions share an address, an empty region clashes with the region it's in.
+    fn overlaps(&self, other: &LayoutRegion) -> bool {
+        match (self.begin == self.end, other.begin == other.end) {
+            (true, true) => false,
+            (true, false) => other.contains(self.begin),
+            (false, true) => self.contains(other.begin),
+            (false, false) => self.begin < other.end && other.begin < self.end,
+        }
+    }
+}
+
+/// Pages of the loadable segments of `elf` loaded at `load_address`, named after `image`.
+pub fn segment_regions(image: &str, elf: &Elf, load_address: u64) -> Vec<LayoutRegion> {
+    let page_size = PAGE_SIZE as u64;
+    elf.program_headers
+        .iter()
+        .filter(|h| h.p_type == PT_LOAD)
+        .enumerate()
+        .map(|(i, h)| {
+            let begin = load_address + h.p_vaddr;
+            LayoutRegion::new(
+                format!("segment {} of the {}", i, image),
+                align(begin, page_size),
+                align_up(begin + h.p_memsz, page_size),
+            )
+        })
+        .collect()
+}
+
+/// The stack, the heap starting at `brk_start`, and the mmap base of `config`.
+pub fn config_regions(config: &Config, brk_start: u64) -> Vec<LayoutRegion> {
+    let brk_start = align_up(brk_start, PAGE_SIZE as u64);
+    vec![
+        LayoutRegion::new(
+            "stack",
+            config.stack_address,
+            config.stack_address.saturating_add(config.stack_size),
+        ),
+        LayoutRegion::new(
+            "heap",
+            brk_start,
+            brk_start.saturating_add(config.heap_size.unwrap_or_default()),
+        ),
+        LayoutRegion::new("mmap base", config.mmap_address, config.mmap_address),
+    ]
+}
+
+/// Check that none of `regions` clash with each other or with `others`.
+/// `others` are not checked against each other.
+pub fn check_layout(
+    regions: &[LayoutRegion],
+    others: &[LayoutRegion],
+) -> Result<(), EmulatorError> {
+    for (i, region) in regions.iter().enumerate() {
+        for other in regions[i + 1..].iter().chain(others) {
+            if region.overlaps(other) {
+                return Err(EmulatorError::Layout {
+                    first: region.clone(),
+                    second: other.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_layout, LayoutRegion};
+    use crate::errors::EmulatorError;
+
+    #[test]
+    fn test_check_layout() {
+        let segments = [
+            LayoutRegion::new("segment 0 of the program", 0x400000, 0x402000),
+            // segments may share a page.
+            LayoutRegion::new("segment 1 of the program", 0x401000, 0x403000),
+        ];
+        let config = [
+            LayoutRegion::new("stack", 0x7ff0d000, 0x7ff3d000),
+            LayoutRegion::new("heap", 0x405000, 0x405000),
+            LayoutRegion::new("mmap base", 0x90000000, 0x90000000),
+        ];
+        check_layout(&config, &segments).unwrap();
+
+        let config = [
+            LayoutRegion::new("stack", 0x402000, 0x432000),
+            LayoutRegion::new("mmap base", 0x401000, 0x401000),
+        ];
+        match check_layout(&config, &segments) {
+            Err(EmulatorError::Layout { first, second }) => {
+                assert_eq!(first.name, "stack");
+                assert_eq!(second.name, "segment 1 of the program");
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+        let err = check_layout(&config[1..], &segments).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "memory layout conflict, mmap base at 0x401000 and segment 0 of the program [0x400000, 0x402000) overlap"
+        );
+    }
+}
@@ -1,22 +1,49 @@
 use crate::{
+    config::{AuxvProfile, MachineConfig},
     engine::Mach,
-    errors,
     errors::EmulatorError,
     memory::Memory,
-    stack::Stack,
+    symbolizer::Symbolizer,
     utils::{align, align_up, seg_perm_to_uc_prot, Packer},
     PAGE_SIZE,
 };
 use anyhow::anyhow;
-use bytes::{BufMut, BytesMut};
-use goblin::{
-    container::Endian,
-    elf::{program_header::PT_LOAD, Elf},
+use goblin::elf::{
+    header::{EM_MIPS, EM_RISCV, ET_DYN, ET_EXEC},
+    program_header::{PT_GNU_RELRO, PT_GNU_STACK, PT_LOAD},
+    reloc::{
+        r_to_str, R_MIPS_NONE, R_MIPS_REL32, R_RISCV_32, R_RISCV_64, R_RISCV_NONE, R_RISCV_RELATIVE,
+    },
+    Elf,
 };
 use log::debug;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use unicorn_engine::unicorn_const::{uc_error, MemRegion, Permission};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+use unicorn_engine::unicorn_const::{MemRegion, Permission};
+
+pub use image::{CannonRegisters, ImageLoader, Page, PageImage};
+pub use initial_stack::{initial_stack, AuxvValues, InitialStack};
+pub use layout::{check_layout, config_regions, segment_regions, LayoutRegion};
+pub use raw::RawLoader;
+
+mod image;
+mod initial_stack;
+mod layout;
+mod raw;
+
+/// gap between the end of the program and the start of its heap, the size of `[hook_mem]`.
+const HOOK_MEM_SIZE: u64 = 0x2000;
+
+/// dynamic tags of mips which describe the GOT.
+const DT_MIPS_LOCAL_GOTNO: u64 = 0x7000_000a;
+const DT_MIPS_SYMTABNO: u64 = 0x7000_0011;
+const DT_MIPS_GOTSYM: u64 = 0x7000_0013;
 
 /// auxiliary vector types
 /// see: https://man7.org/linux/man-pages/man3/getauxval.3.html
@@ -47,25 +74,117 @@ pub enum AUXV {
     AT_RANDOM = 25,
     AT_HWCAP2 = 26,
     AT_EXECFN = 31,
+    AT_SYSINFO_EHDR = 33,
+    AT_MINSIGSTKSZ = 51,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
 pub struct Config {
     pub stack_address: u64,
     pub stack_size: u64,
     pub load_address: u64,
     pub mmap_address: u64,
+    /// largest size of the heap grown by brk, in bytes. unlimited if not set.
+    #[serde(default)]
+    pub heap_size: Option<u64>,
+    /// host directory standing for the root of the program, where the interpreter of dynamically
+    /// linked programs and the libraries they open are found.
+    #[serde(default)]
+    pub sysroot: Option<PathBuf>,
+    /// relocate position independent programs without an interpreter, and protect their relro.
+    /// off by default, static-PIE programs relocate themselves at startup, and write their relro then.
+    #[serde(default)]
+    pub relocate: bool,
+    /// path the program is executed with, reported by `AT_EXECFN`, argv[0] if not set.
+    /// it's set by the command line rather than by config files.
+    #[serde(skip)]
+    pub execfn: Option<String>,
+    /// where raw binaries are loaded.
+    #[serde(default)]
+    pub raw: Option<RawConfig>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RawConfig {
+    /// address of the first byte of the binary.
+    pub base: u64,
+    /// where the program starts, `base` if not set.
+    #[serde(default)]
+    pub entry: Option<u64>,
+}
+
+/// Puts a program into the memory of the engine, with its initial stack, ready to run.
+pub trait Loader {
+    fn load(
+        &self,
+        config: &Config,
+        machine: &MachineConfig,
+        binary: &[u8],
+        argv: Vec<String>,
+        env: BTreeMap<String, String>,
+        uc: &mut impl Mach,
+    ) -> Result<LoadInfo, EmulatorError>;
+
+    /// Names of the addresses of the program loaded as `load_info`, none by default.
+    fn symbolizer(&self, _binary: &[u8], _load_info: &LoadInfo) -> Symbolizer {
+        Symbolizer::default()
+    }
+}
+
+/// Format of the programs to load, each one has its loader.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum BinaryFormat {
+    #[default]
+    Elf,
+    Raw,
+    Image,
+}
+
+impl FromStr for BinaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "elf" => Ok(BinaryFormat::Elf),
+            "raw" => Ok(BinaryFormat::Raw),
+            "image" => Ok(BinaryFormat::Image),
+            _ => Err(format!(
+                "unknown binary format {}, expected elf, raw or image",
+                s
+            )),
+        }
+    }
+}
+
+impl Loader for BinaryFormat {
+    fn load(
+        &self,
+        config: &Config,
+        machine: &MachineConfig,
+        binary: &[u8],
+        argv: Vec<String>,
+        env: BTreeMap<String, String>,
+        uc: &mut impl Mach,
+    ) -> Result<LoadInfo, EmulatorError> {
+        match self {
+            BinaryFormat::Elf => ElfLoader.load(config, machine, binary, argv, env, uc),
+            BinaryFormat::Raw => RawLoader.load(config, machine, binary, argv, env, uc),
+            BinaryFormat::Image => ImageLoader.load(config, machine, binary, argv, env, uc),
+        }
+    }
+
+    fn symbolizer(&self, binary: &[u8], load_info: &LoadInfo) -> Symbolizer {
+        match self {
+            BinaryFormat::Elf => ElfLoader.symbolizer(binary, load_info),
+            BinaryFormat::Raw => RawLoader.symbolizer(binary, load_info),
+            BinaryFormat::Image => ImageLoader.symbolizer(binary, load_info),
+        }
+    }
 }
 
 /// Elf binary loader.
 /// See [How programs get run: ELF binaries](https://lwn.net/Articles/631631/).
-pub struct ElfLoader {
-    config: Config,
-    stack_address: u32,
-    entrypoint: u32,
-    elf_mem_start: u32,
-    elf_entry: u32,
-}
+pub struct ElfLoader;
 
 #[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
 pub struct LoadInfo {
@@ -76,43 +195,50 @@ pub struct LoadInfo {
     pub brk_address: u64,
     pub mmap_address: u64,
     pub load_address: u64,
+    /// load address of the interpreter, 0 for static programs.
+    pub interp_address: u64,
     pub init_stack_address: u64,
 }
 
-impl ElfLoader {
-    pub fn load(
+impl Loader for ElfLoader {
+    fn load(
+        &self,
         config: &Config,
-        binary: impl AsRef<[u8]>,
+        machine: &MachineConfig,
+        binary: &[u8],
         argv: Vec<String>,
         env: BTreeMap<String, String>,
         uc: &mut impl Mach,
-    ) -> Result<LoadInfo, errors::EmulatorError> {
-        let stack_address = config.stack_address;
-        let stack_size = config.stack_size;
-
-        uc.mem_map(
-            MemRegion {
-                begin: stack_address,
-                end: stack_address + stack_size,
-                perms: Permission::ALL,
-            },
-            Some("[stack]".to_string()),
-        )?;
-
-        let b = binary.as_ref();
+    ) -> Result<LoadInfo, EmulatorError> {
+        let b = binary;
         let elf = Elf::parse(b)?;
 
-        if elf.header.e_type != goblin::elf::header::ET_EXEC {
-            return Err(anyhow!("binary not exec"))?;
-        }
-        // anyhow::ensure!(
-        //     elf.header.e_type == goblin::elf::header::ET_EXEC,
-        //
-        // )?;
+        // the stack is executable unless PT_GNU_STACK says otherwise, which is the default of mips.
+        let stack_perms = match elf
+            .program_headers
+            .iter()
+            .find(|h| h.p_type == PT_GNU_STACK)
+        {
+            Some(h) => seg_perm_to_uc_prot(h.p_flags) | Permission::READ | Permission::WRITE,
+            None => Permission::ALL,
+        };
+
+        // position independent executables are loaded at the configured address, others where they're linked.
+        let load_address = match elf.header.e_type {
+            ET_EXEC => 0,
+            ET_DYN => align(config.load_address, PAGE_SIZE),
+            e_type => return Err(anyhow!("binary not exec, e_type {}", e_type))?,
+        };
 
-        let load_address = 0;
+        let segments = segment_regions("program", &elf, load_address);
+        let brk_start = segments.iter().map(|r| r.end).max().unwrap_or_default() + HOOK_MEM_SIZE;
+        let reserved = config_regions(config, brk_start);
+        check_layout(&reserved, &segments)?;
 
-        let (mem_start, mem_end) = Self::load_elf_segments(uc, b, &elf, load_address)?;
+        // programs are relocated, and their relro protected, by their interpreter or by themselves,
+        // unless the loader is asked to.
+        let relocate = elf.interpreter.is_none() && config.relocate;
+        let (mem_start, mem_end) = Self::load_elf_segments(uc, b, &elf, load_address, relocate)?;
         debug!("mem_start: {}, mem_end: {}", mem_start, mem_end);
 
         let mut load_result = LoadInfo::default();
@@ -121,123 +247,328 @@ impl ElfLoader {
 
         load_result.load_address = load_address;
         load_result.entrypoint = entrypoint;
+        match elf.interpreter {
+            // the interpreter relocates the program, and jumps to its entry once it's done.
+            Some(interp) => {
+                // the interpreter is loaded at the mmap base, it must not clash with the rest.
+                let reserved = segments
+                    .into_iter()
+                    .chain(reserved.into_iter().filter(|r| r.name != "mmap base"))
+                    .collect::<Vec<_>>();
+                let (interp_address, interp_entry) =
+                    Self::load_interp(config, uc, interp, &reserved)?;
+                load_result.interp_address = interp_address;
+                load_result.entrypoint = interp_entry;
+            }
+            None if relocate && elf.header.e_type == ET_DYN => {
+                Self::relocate(uc, &elf, load_address)?
+            }
+            None => {}
+        }
 
         load_result.elf_entry = entrypoint;
         load_result.elf_mem_start = mem_start;
-        // note: 0x2000 is the size of [hook_mem]
-        load_result.brk_address = mem_end + 0x2000;
+        load_result.brk_address = mem_end + HOOK_MEM_SIZE;
 
         // set info to be used by gdb
         load_result.mmap_address = config.mmap_address;
-        load_result.init_stack_address = uc.sp()?;
-
-        // init stack address
-        uc.set_sp(stack_address + stack_size)?;
-        // set elf table
-        Self::load_elf_table(uc, &elf, &load_result, argv, env)?;
+        setup_stack(
+            config,
+            machine,
+            uc,
+            stack_perms,
+            Some(&elf),
+            &mut load_result,
+            argv,
+            env,
+        )?;
         Ok(load_result)
     }
-    fn load_elf_table(
+
+    /// The symbols and line tables of the program, those of the interpreter are not loaded.
+    fn symbolizer(&self, binary: &[u8], load_info: &LoadInfo) -> Symbolizer {
+        match Elf::parse(binary) {
+            Ok(elf) => Symbolizer::from_elf(binary, &elf, load_info.load_address),
+            Err(_) => Symbolizer::default(),
+        }
+    }
+}
+
+/// Map the stack of the program, and lay out its arguments, environment and auxv like linux does.
+/// `elf` is the program whose headers are reported in the auxv, if it's an elf.
+#[allow(clippy::too_many_arguments)]
+fn setup_stack(
+    config: &Config,
+    machine: &MachineConfig,
+    uc: &mut impl Mach,
+    perms: Permission,
+    elf: Option<&Elf>,
+    load_result: &mut LoadInfo,
+    argv: Vec<String>,
+    env: BTreeMap<String, String>,
+) -> Result<(), EmulatorError> {
+    let stack_address = config.stack_address;
+    let stack_size = config.stack_size;
+    uc.mem_map(
+        MemRegion {
+            begin: stack_address,
+            end: stack_address + stack_size,
+            perms,
+        },
+        Some("[stack]".to_string()),
+    )?;
+
+    let profile = machine
+        .auxv
+        .clone()
+        .unwrap_or_else(|| AuxvProfile::for_arch(uc.arch(), uc.pointer_size()));
+    let values = auxv_values(uc.pagesize(), machine, elf, load_result);
+    let envp = env
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>();
+    // argv[0] stands for the path the program was executed with if it's not known.
+    let execfn = config
+        .execfn
+        .as_deref()
+        .or(argv.first().map(|s| s.as_str()))
+        .unwrap_or("main");
+    let stack = initial_stack(
+        stack_address + stack_size,
+        uc.endian(),
+        uc.pointer_size(),
+        &profile,
+        &argv,
+        &envp,
+        execfn,
+        &values,
+    );
+    Memory::write(uc, stack.sp, &stack.data)?;
+    uc.set_sp(stack.sp)?;
+    load_result.init_stack_address = stack.sp;
+    Ok(())
+}
+
+/// Values of the auxv of the program `elf`, loaded as `load_result` says.
+fn auxv_values(
+    pagesz: u64,
+    machine: &MachineConfig,
+    elf: Option<&Elf>,
+    load_result: &LoadInfo,
+) -> AuxvValues {
+    // the program headers are found through the first loadable segment, as linux does.
+    let phdr = elf
+        .and_then(|elf| {
+            let first = elf.program_headers.iter().find(|h| h.p_type == PT_LOAD)?;
+            Some(load_result.load_address + first.p_vaddr - first.p_offset + elf.header.e_phoff)
+        })
+        .unwrap_or_default();
+    AuxvValues {
+        pagesz,
+        phdr,
+        phent: elf.map_or(0, |elf| elf.header.e_phentsize as u64),
+        phnum: elf.map_or(0, |elf| elf.header.e_phnum as u64),
+        base: load_result.interp_address,
+        entry: load_result.elf_entry,
+        uid: machine.uid as u64,
+        gid: machine.gid as u64,
+    }
+}
+
+impl ElfLoader {
+    /// Load the interpreter at `path` of the sysroot where the runner places mappings,
+    /// return its load address and entry point.
+    fn load_interp(
+        config: &Config,
         uc: &mut impl Mach,
-        elf: &Elf,
-        load_result: &LoadInfo,
-        argv: Vec<String>, // argv.len must >0
-        envs: BTreeMap<String, String>,
-    ) -> Result<(), uc_error> {
-        let packer = Packer::new(uc.endian(), uc.pointer_size());
-        let mut elf_table = BytesMut::new();
-        // write argc
-        elf_table.put_slice(&packer.pack(argv.len() as u64));
-        // write argv
-        for s in &argv {
-            Stack::aligned_push_str(uc, s)?;
-            let stack_addr = uc.sp()?;
-            elf_table.put_slice(&packer.pack(stack_addr));
+        path: &str,
+        reserved: &[LayoutRegion],
+    ) -> Result<(u64, u64), EmulatorError> {
+        let (binary, interp_address) = Self::find_interp(config, path, reserved)?;
+        let elf = Elf::parse(&binary)?;
+        // the interpreter protects its own relro once it relocated itself.
+        Self::load_elf_segments(uc, &binary, &elf, interp_address, false)?;
+        Ok((interp_address, interp_address + elf.header.e_entry))
+    }
+
+    /// Read the interpreter at `path` of the sysroot, return it with its load address,
+    /// where it doesn't clash with `reserved`.
+    fn find_interp(
+        config: &Config,
+        path: &str,
+        reserved: &[LayoutRegion],
+    ) -> Result<(Vec<u8>, u64), EmulatorError> {
+        let sysroot = config.sysroot.as_ref().ok_or_else(|| {
+            anyhow!(
+                "the program is dynamically linked with {}, but no sysroot is configured",
+                path
+            )
+        })?;
+        let host_path = sysroot_path(sysroot, path)
+            .ok_or_else(|| anyhow!("no interpreter {} in {}", path, sysroot.display()))?;
+        debug!("load interpreter {}", host_path.display());
+        let binary = fs::read(&host_path)?;
+        let elf = Elf::parse(&binary)?;
+        if elf.header.e_type != ET_DYN {
+            return Err(anyhow!("interpreter {} is not position independent", path))?;
         }
-        // add a nullptr sentinel
-        elf_table.put_slice(&packer.pack(0));
+        let interp_address = align(config.mmap_address, PAGE_SIZE);
+        check_layout(
+            &segment_regions("interpreter", &elf, interp_address),
+            reserved,
+        )?;
+        Ok((binary, interp_address))
+    }
 
-        // write env
-        for (k, v) in &envs {
-            Stack::aligned_push_str(uc, &format!("{}={}", k, v))?;
-            elf_table.put_slice(&packer.pack(uc.sp()?));
+    /// Relocate a position independent executable loaded at `load_address`, as the dynamic linker would.
+    fn relocate(uc: &mut impl Mach, elf: &Elf, load_address: u64) -> Result<(), EmulatorError> {
+        match elf.header.e_machine {
+            EM_MIPS => Self::relocate_mips(uc, elf, load_address),
+            EM_RISCV => Self::relocate_riscv(uc, elf, load_address),
+            machine => Err(anyhow!("relocation of machine {} not supported", machine))?,
         }
-        // add a nullptr sentinel
-        elf_table.put_slice(&packer.pack(0));
-
-        let execfn = {
-            Stack::aligned_push_str(
-                uc,
-                argv.first().map(|s| s.as_str()).unwrap_or_else(|| "main"),
-            )?;
-            uc.sp()?
-        };
-        let randdata_addr = {
-            Stack::aligned_push_bytes(uc, [10u8; 16], None)?;
-            uc.sp()?
+    }
+
+    /// The local entries of the GOT are moved by the load address and the global ones are
+    /// set to their symbols, then `R_MIPS_REL32` relocations add the address of their symbol,
+    /// or the load address without a symbol.
+    fn relocate_mips(
+        uc: &mut impl Mach,
+        elf: &Elf,
+        load_address: u64,
+    ) -> Result<(), EmulatorError> {
+        let dynamic = match &elf.dynamic {
+            Some(dynamic) => dynamic,
+            None => return Ok(()),
         };
-        let cpustr_addr = {
-            Stack::aligned_push_str(uc, &format!("{:?}", uc.arch()))?;
-            uc.sp()?
+        let tag = |tag: u64| {
+            dynamic
+                .dyns
+                .iter()
+                .find(|d| d.d_tag == tag)
+                .map(|d| d.d_val)
         };
-        let aux_entries = vec![
-            (
-                AUXV::AT_HWCAP,
-                if uc.pointer_size() == 8 {
-                    0x078bfbfd
-                } else if uc.pointer_size() == 4 {
-                    if uc.endian() == Endian::Big {
-                        // FIXME: considering this is a 32 bits value, it is not a big-endian version of the
-                        // value above like it is meant to be, since the one above has an implied leading zero
-                        // byte (i.e. 0x001fb8d7) which the EB value didn't take into account
-                        0xd7b81f
-                    } else {
-                        0x1fb8d7
-                    }
+        let word_size = uc.pointer_size() as u64;
+        let packer = Packer::new(uc.endian(), uc.pointer_size());
+
+        let local_gotno = tag(DT_MIPS_LOCAL_GOTNO).unwrap_or_default();
+        let gotsym = tag(DT_MIPS_GOTSYM).unwrap_or_default();
+        let symtabno = tag(DT_MIPS_SYMTABNO).unwrap_or(elf.dynsyms.len() as u64);
+        let got = dynamic.info.pltgot.map(|got| load_address + got);
+        if let Some(got) = got {
+            // got[0] is reserved for the lazy resolver, got[1] too if its top bit is set.
+            let got1 = packer.unpack(Memory::read(uc, got + word_size, word_size as usize)?);
+            let first = if got1 & (1 << (word_size * 8 - 1)) != 0 {
+                2
+            } else {
+                1
+            };
+            for i in first..local_gotno {
+                let addr = got + i * word_size;
+                let value = packer.unpack(Memory::read(uc, addr, word_size as usize)?);
+                Memory::write(uc, addr, packer.pack(value.wrapping_add(load_address)))?;
+            }
+            for i in gotsym..symtabno {
+                let sym = elf
+                    .dynsyms
+                    .get(i as usize)
+                    .ok_or_else(|| anyhow!("no dynamic symbol {} for the got", i))?;
+                let value = if sym.st_shndx == 0 {
+                    0
                 } else {
-                    unimplemented!()
-                },
-            ),
-            (AUXV::AT_PAGESZ, uc.pagesize()),
-            (AUXV::AT_CLKTCK, 100),
-            // following three: store aux vector data for gdb use
-            (
-                AUXV::AT_PHDR,
-                elf.header.e_phoff + load_result.elf_mem_start,
-            ),
-            (AUXV::AT_PHENT, elf.header.e_phentsize as u64),
-            (AUXV::AT_PHNUM, elf.header.e_phnum as u64),
-            (AUXV::AT_BASE, 0),
-            (AUXV::AT_FLAGS, 0),
-            (AUXV::AT_ENTRY, load_result.elf_entry),
-            (AUXV::AT_UID, 1000),
-            (AUXV::AT_EUID, 1000),
-            (AUXV::AT_GID, 1000),
-            (AUXV::AT_EGID, 1000),
-            (AUXV::AT_SECURE, 0),
-            (AUXV::AT_RANDOM, randdata_addr),
-            (AUXV::AT_HWCAP2, 0),
-            (AUXV::AT_EXECFN, execfn),
-            (AUXV::AT_PLATFORM, cpustr_addr),
-            (AUXV::AT_NULL, 0),
-        ];
-        for (k, v) in aux_entries {
-            elf_table.extend_from_slice(&packer.pack(k as u64));
-            elf_table.extend_from_slice(&packer.pack(v));
+                    sym.st_value + load_address
+                };
+                let addr = got + (local_gotno + i - gotsym) * word_size;
+                Memory::write(uc, addr, packer.pack(value))?;
+            }
         }
 
-        // write elf table
-        Stack::aligned_push_bytes(uc, elf_table.as_ref(), Some(0x10))?;
+        for reloc in elf.dynrels.iter() {
+            match reloc.r_type {
+                R_MIPS_NONE => continue,
+                R_MIPS_REL32 => {}
+                r_type => {
+                    return Err(anyhow!(
+                        "unsupported relocation {} at {:#x}",
+                        r_to_str(r_type, elf.header.e_machine),
+                        reloc.r_offset
+                    ))?
+                }
+            }
+            let symbol = reloc.r_sym as u64;
+            let delta = if symbol == 0 {
+                load_address
+            } else if symbol < gotsym {
+                let sym = elf
+                    .dynsyms
+                    .get(symbol as usize)
+                    .ok_or_else(|| anyhow!("no dynamic symbol {} to relocate", symbol))?;
+                sym.st_value + load_address
+            } else {
+                // global symbols are resolved through their got entry.
+                let got =
+                    got.ok_or_else(|| anyhow!("relocation of a global symbol without got"))?;
+                let entry = got + (local_gotno + symbol - gotsym) * word_size;
+                packer.unpack(Memory::read(uc, entry, word_size as usize)?)
+            };
+            let addr = load_address + reloc.r_offset;
+            let value = packer.unpack(Memory::read(uc, addr, word_size as usize)?);
+            Memory::write(uc, addr, packer.pack(value.wrapping_add(delta)))?;
+        }
+        Ok(())
+    }
 
+    /// riscv uses rela relocations: `R_RISCV_RELATIVE` is the load address plus the addend,
+    /// `R_RISCV_32` and `R_RISCV_64` the address of their symbol plus the addend,
+    /// undefined symbols are weak ones resolved to 0.
+    fn relocate_riscv(
+        uc: &mut impl Mach,
+        elf: &Elf,
+        load_address: u64,
+    ) -> Result<(), EmulatorError> {
+        for reloc in elf.dynrelas.iter() {
+            let addend = reloc.r_addend.unwrap_or_default() as u64;
+            let (size, value) = match reloc.r_type {
+                R_RISCV_NONE => continue,
+                R_RISCV_RELATIVE => (uc.pointer_size(), load_address.wrapping_add(addend)),
+                r_type @ (R_RISCV_32 | R_RISCV_64) => {
+                    let sym = elf
+                        .dynsyms
+                        .get(reloc.r_sym)
+                        .ok_or_else(|| anyhow!("no dynamic symbol {} to relocate", reloc.r_sym))?;
+                    let base = if sym.st_shndx == 0 {
+                        0
+                    } else {
+                        sym.st_value + load_address
+                    };
+                    let size = if r_type == R_RISCV_32 { 4 } else { 8 };
+                    (size, base.wrapping_add(addend))
+                }
+                r_type => {
+                    return Err(anyhow!(
+                        "unsupported relocation {} at {:#x}",
+                        r_to_str(r_type, elf.header.e_machine),
+                        reloc.r_offset
+                    ))?
+                }
+            };
+            let packer = Packer::new(uc.endian(), size);
+            Memory::write(uc, load_address + reloc.r_offset, packer.pack(value))?;
+        }
         Ok(())
     }
 
+    /// Map and fill the loadable segments of `elf` at `load_address`, return the bounds of the mapped pages.
+    /// Pages shared by several segments get the permissions of all of them. The pages of `PT_GNU_RELRO`
+    /// are made read only if `relro` is set, which is when neither a dynamic linker nor the program will do it later.
     fn load_elf_segments(
         uc: &mut impl Memory,
         binary: impl AsRef<[u8]>,
         elf: &Elf,
         load_address: u64,
+        relro: bool,
     ) -> Result<(u64, u64), EmulatorError> {
+        let binary = binary.as_ref();
         // get list of loadable segments which will be loaded into memory.
         let load_segments = {
             let mut load_segments = elf
@@ -249,46 +580,47 @@ impl ElfLoader {
             load_segments
         };
 
-        let mut load_regions = Vec::new();
+        let page_size = PAGE_SIZE as u64;
+        let mut segments = vec![];
         for seg in &load_segments {
-            let lbound = align(load_address + seg.p_vaddr, PAGE_SIZE);
-            let ubound = align_up(load_address + seg.p_vaddr + seg.p_memsz, PAGE_SIZE);
-            let perms = seg_perm_to_uc_prot(seg.p_flags);
-            if load_regions.is_empty() {
-                load_regions.push(MemRegion {
-                    begin: lbound as u64,
-                    end: ubound as u64,
-                    perms,
-                });
-            } else {
-                let prev_region = load_regions.last_mut().unwrap();
-
-                if lbound > prev_region.end {
-                    load_regions.push(MemRegion {
-                        begin: lbound as u64,
-                        end: ubound as u64,
-                        perms,
-                    });
-                } else if lbound == prev_region.end {
-                    // new region starts where the previous one ended
-                    // same perms.
-                    if perms == prev_region.perms {
-                        prev_region.end = ubound;
-                    } else {
-                        // different perms. start a new one
-                        load_regions.push(MemRegion {
-                            begin: lbound,
-                            end: ubound,
-                            perms,
-                        });
-                    }
-                } else if lbound < prev_region.end {
-                    Err(goblin::error::Error::Malformed(
-                        "invalid elf file, segment intersect.".to_string(),
-                    ))?;
-                }
+            if seg.p_filesz > seg.p_memsz || seg.p_offset + seg.p_filesz > binary.len() as u64 {
+                Err(goblin::error::Error::Malformed(
+                    "invalid elf file, segment out of range.".to_string(),
+                ))?;
             }
+            let begin = load_address + seg.p_vaddr;
+            if segments
+                .last()
+                .is_some_and(|prev: &MemRegion| begin < prev.end)
+            {
+                Err(goblin::error::Error::Malformed(
+                    "invalid elf file, segment intersect.".to_string(),
+                ))?;
+            }
+            segments.push(MemRegion {
+                begin,
+                end: begin + seg.p_memsz,
+                perms: seg_perm_to_uc_prot(seg.p_flags),
+            });
         }
+        let read_only = elf
+            .program_headers
+            .iter()
+            .filter(|h| relro && h.p_type == PT_GNU_RELRO)
+            .map(|h| {
+                (
+                    load_address + h.p_vaddr,
+                    load_address + h.p_vaddr + h.p_memsz,
+                )
+            })
+            .collect::<Vec<_>>();
+        let load_regions = page_regions(&segments, &read_only);
+        let (mem_start, mem_end) = match (load_regions.first(), load_regions.last()) {
+            (Some(first), Some(last)) => (first.begin, last.end),
+            _ => Err(goblin::error::Error::Malformed(
+                "invalid elf file, no loadable segment.".to_string(),
+            ))?,
+        };
 
         for region in &load_regions {
             debug!("mmap {:?}", region);
@@ -296,19 +628,236 @@ impl ElfLoader {
         }
 
         for seg in &load_segments {
-            let data = &binary.as_ref()[seg.file_range()];
-            Memory::write(uc, load_address + seg.p_vaddr, data)?;
+            let begin = load_address + seg.p_vaddr;
+            let data = &binary[seg.file_range()];
+            Memory::write(uc, begin, data)?;
+            // the bss is zero, explicitly on the page holding the end of the file data,
+            // the pages after it are mapped fresh.
+            let file_end = begin + seg.p_filesz;
+            let zero_end = (begin + seg.p_memsz).min(align_up(file_end, page_size));
+            if zero_end > file_end {
+                Memory::write(uc, file_end, vec![0; (zero_end - file_end) as usize])?;
+            }
             debug!(
-                "load binary segment: {} - {}",
-                load_address + seg.p_vaddr,
-                load_address + seg.p_vaddr + data.len() as u64
+                "load binary segment: {} - {}, bss up to {}",
+                begin,
+                file_end,
+                begin + seg.p_memsz
             );
         }
 
-        let (mem_start, mem_end) = (
-            load_regions.first().unwrap().begin,
-            load_regions.last().unwrap().end,
-        );
         Ok((mem_start, mem_end))
     }
 }
+
+/// Regions of the pages holding `segments`, pages shared by several segments get the permissions of all.
+/// Pages entirely within a `read_only` range lose their write permission.
+fn page_regions(segments: &[MemRegion], read_only: &[(u64, u64)]) -> Vec<MemRegion> {
+    let page_size = PAGE_SIZE as u64;
+    let mut page_perms = BTreeMap::new();
+    for seg in segments {
+        for page in
+            (align(seg.begin, page_size)..align_up(seg.end, page_size)).step_by(PAGE_SIZE as usize)
+        {
+            *page_perms.entry(page).or_insert(Permission::NONE) |= seg.perms;
+        }
+    }
+    for (begin, end) in read_only {
+        // pages partly covered stay writable, like ld.so leaves them.
+        let range = align_up(*begin, page_size)..align(*end, page_size);
+        for (_, perms) in page_perms.range_mut(range) {
+            perms.remove(Permission::WRITE);
+        }
+    }
+
+    // consecutive pages with the same permissions make up a region.
+    let mut regions: Vec<MemRegion> = Vec::new();
+    for (page, perms) in page_perms {
+        match regions.last_mut() {
+            Some(region) if region.end == page && region.perms == perms => region.end += page_size,
+            _ => regions.push(MemRegion {
+                begin: page,
+                end: page + page_size,
+                perms,
+            }),
+        }
+    }
+    regions
+}
+
+/// Host path of the absolute guest `path` in `sysroot`, symlinks are resolved inside the sysroot.
+/// None if there is no such file.
+pub fn sysroot_path(sysroot: &Path, path: &str) -> Option<PathBuf> {
+    // linux gives up after 40 symlinks.
+    const MAX_SYMLINKS: usize = 40;
+    // names to walk through in reverse order, `..` included.
+    let names = |path: &Path| -> Vec<OsString> {
+        let names = path.components().filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_owned()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        });
+        names.rev().collect()
+    };
+    let mut pending = names(Path::new(path));
+    let mut symlinks = 0;
+    let mut resolved = PathBuf::new();
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        resolved.push(name);
+        if let Ok(target) = fs::read_link(sysroot.join(&resolved)) {
+            symlinks += 1;
+            if symlinks > MAX_SYMLINKS {
+                return None;
+            }
+            resolved.pop();
+            if target.is_absolute() {
+                resolved.clear();
+            }
+            pending.extend(names(&target));
+        }
+    }
+    let host_path = sysroot.join(resolved);
+    host_path.exists().then_some(host_path)
+}
+
+/// Host path of the entry at the absolute guest `path` in `sysroot`, which may not exist yet,
+/// a symlink at `path` is not followed. None if its directory doesn't exist.
+pub fn sysroot_entry_path(sysroot: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => Some(sysroot_path(sysroot, dir.to_str()?)?.join(name)),
+        _ => sysroot_path(sysroot, path.to_str()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, os::unix::fs::symlink, path::PathBuf};
+
+    use goblin::elf::Elf;
+    use unicorn_engine::unicorn_const::{MemRegion, Permission};
+
+    use super::{
+        auxv_values, page_regions, sysroot_entry_path, sysroot_path, Config, ElfLoader, LoadInfo,
+    };
+    use crate::config::MachineConfig;
+
+    fn config(sysroot: Option<PathBuf>) -> Config {
+        Config {
+            stack_address: 0x7ff0d000,
+            stack_size: 0x30000,
+            load_address: 0x56555000,
+            mmap_address: 0x90000000,
+            heap_size: None,
+            sysroot,
+            relocate: false,
+            execfn: None,
+            raw: None,
+        }
+    }
+
+    #[test]
+    fn test_page_regions() {
+        let (rx, rw) = (
+            Permission::READ | Permission::EXEC,
+            Permission::READ | Permission::WRITE,
+        );
+        // text and data share the page at 0x2000, the relro covers 0x3000..0x5000 entirely.
+        let segments = [
+            MemRegion {
+                begin: 0x1000,
+                end: 0x2100,
+                perms: rx,
+            },
+            MemRegion {
+                begin: 0x2100,
+                end: 0x6800,
+                perms: rw,
+            },
+        ];
+        let regions = page_regions(&segments, &[(0x2100, 0x5000)]);
+        let regions: Vec<_> = regions.iter().map(|r| (r.begin, r.end, r.perms)).collect();
+        assert_eq!(
+            regions,
+            vec![
+                (0x1000, 0x2000, rx),
+                (0x2000, 0x3000, rx | rw),
+                (0x3000, 0x5000, Permission::READ),
+                (0x5000, 0x7000, rw),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sysroot_path() {
+        let sysroot = env::temp_dir().join(format!("flexemu-sysroot-{}", std::process::id()));
+        fs::create_dir_all(sysroot.join("lib")).unwrap();
+        fs::write(sysroot.join("lib/libc.so"), b"").unwrap();
+        // absolute symlinks point into the sysroot, not to the host.
+        let _ = symlink("/lib/libc.so", sysroot.join("lib/ld-musl-mips.so.1"));
+        let _ = symlink("../lib", sysroot.join("lib/up"));
+
+        let libc = Some(sysroot.join("lib/libc.so"));
+        assert_eq!(sysroot_path(&sysroot, "/lib/ld-musl-mips.so.1"), libc);
+        assert_eq!(sysroot_path(&sysroot, "/lib/up/libc.so"), libc);
+        assert_eq!(sysroot_path(&sysroot, "/../lib/./libc.so"), libc);
+        assert_eq!(sysroot_path(&sysroot, "/lib/libm.so"), None);
+
+        // entries are looked up in the directory they'd be in, symlinks are not followed.
+        assert_eq!(
+            sysroot_entry_path(&sysroot, "/lib/ld-musl-mips.so.1"),
+            Some(sysroot.join("lib/ld-musl-mips.so.1"))
+        );
+        assert_eq!(
+            sysroot_entry_path(&sysroot, "/lib/up/libm.so"),
+            Some(sysroot.join("lib/libm.so"))
+        );
+        assert_eq!(sysroot_entry_path(&sysroot, "/usr/lib/libm.so"), None);
+        fs::remove_dir_all(&sysroot).unwrap();
+    }
+
+    #[test]
+    fn test_find_interp() {
+        // the test binary stands for an interpreter, it's position independent like one.
+        let exe = env::current_exe().unwrap();
+        let exe = exe.to_str().unwrap();
+        let with_sysroot = config(Some(PathBuf::from("/")));
+        let (binary, interp_address) = ElfLoader::find_interp(&with_sysroot, exe, &[]).unwrap();
+        assert_eq!(binary, fs::read(exe).unwrap());
+        assert_eq!(interp_address, with_sysroot.mmap_address);
+
+        assert!(ElfLoader::find_interp(&with_sysroot, "/nonexistent/ld.so", &[]).is_err());
+        assert!(ElfLoader::find_interp(&config(None), exe, &[]).is_err());
+    }
+
+    #[test]
+    fn test_auxv_base() {
+        let binary = fs::read(env::current_exe().unwrap()).unwrap();
+        let elf = Elf::parse(&binary).unwrap();
+        let load_info = LoadInfo {
+            load_address: 0x56555000,
+            elf_entry: 0x56555000 + elf.header.e_entry,
+            interp_address: 0x90000000,
+            entrypoint: 0x90001000,
+            ..Default::default()
+        };
+        let values = auxv_values(0x1000, &MachineConfig::default(), Some(&elf), &load_info);
+        // the interpreter is reported by AT_BASE, the program by AT_ENTRY and AT_PHDR.
+        assert_eq!(values.base, 0x90000000);
+        assert_eq!(values.entry, 0x56555000 + elf.header.e_entry);
+        assert_eq!(values.phnum, elf.header.e_phnum as u64);
+        assert!(values.phdr > 0x56555000);
+
+        let values = auxv_values(
+            0x1000,
+            &MachineConfig::default(),
+            None,
+            &LoadInfo::default(),
+        );
+        assert_eq!(values.base, 0);
+    }
+}
@@ -0,0 +1,92 @@
+//! Loader of flat binaries, copied as is to a fixed address, like firmware or boot images.
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use log::debug;
+use unicorn_engine::unicorn_const::{MemRegion, Permission};
+
+use crate::{
+    config::MachineConfig,
+    engine::Mach,
+    errors::EmulatorError,
+    loader::{
+        check_layout, config_regions, setup_stack, Config, LayoutRegion, LoadInfo, Loader,
+        HOOK_MEM_SIZE,
+    },
+    memory::Memory,
+    utils::{align, align_up},
+    PAGE_SIZE,
+};
+
+/// Loads a raw binary at `base` of the `[os.raw]` config, and starts it at its `entry`.
+pub struct RawLoader;
+
+impl Loader for RawLoader {
+    fn load(
+        &self,
+        config: &Config,
+        machine: &MachineConfig,
+        binary: &[u8],
+        argv: Vec<String>,
+        env: BTreeMap<String, String>,
+        uc: &mut impl Mach,
+    ) -> Result<LoadInfo, EmulatorError> {
+        let raw = config
+            .raw
+            .ok_or_else(|| anyhow!("raw binaries need a base address, set [os.raw] in config"))?;
+        if binary.is_empty() {
+            Err(anyhow!("raw binary is empty"))?;
+        }
+        let page_size = PAGE_SIZE as u64;
+        let begin = align(raw.base, page_size);
+        let end = align_up(raw.base + binary.len() as u64, page_size);
+        let entrypoint = raw.entry.unwrap_or(raw.base);
+        if !(raw.base..raw.base + binary.len() as u64).contains(&entrypoint) {
+            Err(anyhow!(
+                "entry {:#x} of raw binary is out of {:#x} - {:#x}",
+                entrypoint,
+                raw.base,
+                raw.base + binary.len() as u64
+            ))?;
+        }
+
+        check_layout(
+            &[LayoutRegion::new("raw binary", begin, end)],
+            &config_regions(config, end + HOOK_MEM_SIZE),
+        )?;
+
+        // nothing tells which parts are code or data.
+        uc.mem_map(
+            MemRegion {
+                begin,
+                end,
+                perms: Permission::ALL,
+            },
+            None,
+        )?;
+        Memory::write(uc, raw.base, binary)?;
+        debug!("load raw binary: {:#x} - {:#x}", begin, end);
+
+        let mut load_result = LoadInfo {
+            entrypoint,
+            elf_mem_start: begin,
+            elf_entry: entrypoint,
+            brk_address: end + HOOK_MEM_SIZE,
+            mmap_address: config.mmap_address,
+            load_address: begin,
+            ..Default::default()
+        };
+        setup_stack(
+            config,
+            machine,
+            uc,
+            Permission::ALL,
+            None,
+            &mut load_result,
+            argv,
+            env,
+        )?;
+        Ok(load_result)
+    }
+}
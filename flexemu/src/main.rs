@@ -2,19 +2,28 @@ use byteorder::ByteOrder;
 use clap::Parser;
 use log::{info, LevelFilter};
 use flexemu::{
-    arch::mips::{MipsProfile, MIPS},
+    arch::{mips::MIPS, riscv::RISCV, ArchT, Profile},
     config::FlexEmuConfig,
     emulator::{Emulator, StateChange},
     errors::EmulatorError,
-    os::linux::LinuxRunner,
+    loader::BinaryFormat,
+    os::{
+        linux::{
+            preimage::{write_preimages, PreimageKey},
+            strace::StraceFormat,
+            LinuxRunner,
+        },
+        RunOutcome,
+    },
     parse_key_val,
     step_proof::generate_step_proof,
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env,
     error::Error,
-    fs::{create_dir_all, read},
+    fs::{create_dir_all, read, File},
+    io::{self, LineWriter, Write},
     num::NonZeroUsize,
     path::PathBuf,
 };
@@ -36,6 +45,21 @@ enum SubCommands {
         args: Vec<String>,
         #[clap(long = "env", parse(try_from_str=parse_key_val))]
         envs: Vec<(String, String)>,
+        /// format of the exec file: elf, raw (loaded at [os.raw] of config) or image (memory pages).
+        #[clap(long, default_value = "elf")]
+        format: BinaryFormat,
+        /// mips, mipsel, mips64, mips64el, riscv32 or riscv64, from the elf header of the exec file if omitted.
+        #[clap(long)]
+        arch: Option<Profile>,
+        /// trace the syscalls of the program, to stderr unless --strace-output is set.
+        #[clap(long)]
+        strace: bool,
+        /// format of the syscall trace, text or json (one json object per line).
+        #[clap(long, default_value = "text")]
+        strace_format: StraceFormat,
+        /// write the syscall trace to this file.
+        #[clap(long)]
+        strace_output: Option<PathBuf>,
     },
     GenState {
         /// exec file
@@ -43,6 +67,12 @@ enum SubCommands {
         args: Vec<String>,
         #[clap(long = "env", parse(try_from_str=parse_key_val))]
         envs: Vec<(String, String)>,
+        /// format of the exec file: elf, raw (loaded at [os.raw] of config) or image (memory pages).
+        #[clap(long, default_value = "elf")]
+        format: BinaryFormat,
+        /// mips, mipsel, mips64, mips64el, riscv32 or riscv64, from the elf header of the exec file if omitted.
+        #[clap(long)]
+        arch: Option<Profile>,
         /// gen state before/after executing N steps.
         #[clap(short, long)]
         steps: NonZeroUsize,
@@ -67,7 +97,16 @@ fn main() -> Result<(), EmulatorError> {
         toml::from_str(&std::fs::read_to_string(&opts.config_file).unwrap()).unwrap();
 
     match opts.command {
-        SubCommands::Run { exec, args, envs } => {
+        SubCommands::Run {
+            exec,
+            args,
+            envs,
+            format,
+            arch,
+            strace,
+            strace_format,
+            strace_output,
+        } => {
             let binary = read(exec.as_path()).unwrap();
             let argv = {
                 let mut a = args;
@@ -75,23 +114,62 @@ fn main() -> Result<(), EmulatorError> {
                 a
             };
             let env = envs;
+            // AT_EXECFN is the path the program is executed with.
+            let execfn = exec.to_string_lossy().into_owned();
 
-            let mips_profile = MipsProfile::default();
-            let arch = MIPS::new(mips_profile.pointer_size());
-            let runner = LinuxRunner::new(config.os.mmap_address);
-            let mut emu =
-                Emulator::<_, LinuxRunner>::new(config, arch, mips_profile.mode(), runner)?;
-
-            let load_info = emu.load(&binary, argv, env)?;
-            info!("load info: {:?}", &load_info);
-
-            let total_steps = emu.run(load_info.entrypoint, None, None, None)?;
-            info!("steps: {}", total_steps);
+            let profile = arch
+                .or_else(|| Profile::detect(&binary))
+                .unwrap_or_default();
+            let mut runner = LinuxRunner::from_config(&config);
+            if strace || strace_output.is_some() {
+                let out: Box<dyn Write> = match &strace_output {
+                    Some(path) => Box::new(LineWriter::new(File::create(path)?)),
+                    None => Box::new(io::stderr()),
+                };
+                runner = runner.with_strace(strace_format, out);
+            }
+            let outcome = match profile {
+                Profile::Mips(p) => {
+                    let emu = Emulator::new(config, MIPS::new(p.pointer_size()), p.mode(), runner)?
+                        .with_execfn(execfn);
+                    run(emu, &format, &binary, argv, env)?
+                }
+                Profile::Riscv(p) => {
+                    let emu =
+                        Emulator::new(config, RISCV::new(p.pointer_size()), p.mode(), runner)?
+                            .with_execfn(execfn);
+                    run(emu, &format, &binary, argv, env)?
+                }
+            };
+            match outcome {
+                Some(RunOutcome::Exited(code)) => {
+                    info!("exited with code {}", code);
+                    if code != 0 {
+                        std::process::exit(code as i32);
+                    }
+                }
+                Some(RunOutcome::Signaled(sig)) => {
+                    info!("terminated by signal {}", sig);
+                    // same as shells report programs killed by signals.
+                    std::process::exit(128 + sig as i32);
+                }
+                Some(RunOutcome::OutOfMemory { used, requested }) => {
+                    info!(
+                        "out of memory, {} bytes mapped and {} more requested",
+                        used, requested
+                    );
+                    // the oom killer of linux sends SIGKILL.
+                    std::process::exit(128 + 9);
+                }
+                None => {}
+            }
         }
         SubCommands::GenState {
             exec,
             args,
             envs,
+            format,
+            arch,
             output_dir,
             steps,
         } => {
@@ -102,22 +180,35 @@ fn main() -> Result<(), EmulatorError> {
                 a
             };
             let env = envs;
+            // AT_EXECFN is the path the program is executed with.
+            let execfn = exec.to_string_lossy().into_owned();
 
-            let mips_profile = MipsProfile::default();
-            let arch = MIPS::new(mips_profile.pointer_size());
-            let runner = LinuxRunner::new(config.os.mmap_address);
-            let mut emu =
-                Emulator::<_, LinuxRunner>::new(config, arch, mips_profile.mode(), runner)?;
-
-            let load_info = emu.load(&binary, argv, env)?;
-            info!("load info: {:?}", &load_info);
-
-            let state_change = emu.run_until(load_info.entrypoint, None, None, steps.get() - 1)?;
+            let profile = arch
+                .or_else(|| Profile::detect(&binary))
+                .unwrap_or_default();
+            let runner = LinuxRunner::from_config(&config);
+            let (state_change, preimages) = match profile {
+                Profile::Mips(p) => {
+                    let emu = Emulator::new(config, MIPS::new(p.pointer_size()), p.mode(), runner)?
+                        .with_execfn(execfn);
+                    gen_state(emu, &format, &binary, argv, env, steps)?
+                }
+                Profile::Riscv(p) => {
+                    let emu =
+                        Emulator::new(config, RISCV::new(p.pointer_size()), p.mode(), runner)?
+                            .with_execfn(execfn);
+                    gen_state(emu, &format, &binary, argv, env, steps)?
+                }
+            };
 
             let output_dir = output_dir
                 .unwrap_or(env::current_dir().unwrap())
                 .join(format!("step-{}", steps));
             state_change.output_to(output_dir.clone());
+            // the judge is given the fetched pre-images along with the proof, and checks them against their key.
+            if !preimages.is_empty() {
+                write_preimages(&preimages, output_dir.join("preimages"))?;
+            }
             let step_proof = generate_step_proof(state_change);
             serde_json::to_writer_pretty(
                 std::fs::File::options()
@@ -131,30 +222,7 @@ fn main() -> Result<(), EmulatorError> {
             .unwrap();
         }
         SubCommands::GenStepProof { step_dir } => {
-            let change = StateChange {
-                step: 0,
-                state_before: serde_json::from_reader(
-                    std::fs::File::options()
-                        .read(true)
-                        .open(step_dir.join("before_state.json"))
-                        .unwrap(),
-                )
-                .unwrap(),
-                state_after: serde_json::from_reader(
-                    std::fs::File::options()
-                        .read(true)
-                        .open(step_dir.join("after_state.json"))
-                        .unwrap(),
-                )
-                .unwrap(),
-                access: serde_json::from_reader(
-                    std::fs::File::options()
-                        .read(true)
-                        .open(step_dir.join("mem_access.json"))
-                        .unwrap(),
-                )
-                .unwrap(),
-            };
+            let change = StateChange::read_from(&step_dir);
             let step_proof = generate_step_proof(change);
             serde_json::to_writer_pretty(
                 std::fs::File::options()
@@ -177,6 +245,39 @@ const REG_OFFSET: u32 = 0xc0000000;
 const REG_PC: u32 = REG_OFFSET + 0x20 * 4;
 const REG_HEAP: u32 = REG_OFFSET + 0x23 * 4;
 
+/// Run the program to its end, return how it ended.
+fn run<A: ArchT>(
+    mut emu: Emulator<'_, A, LinuxRunner>,
+    format: &BinaryFormat,
+    binary: &[u8],
+    argv: Vec<String>,
+    env: Vec<(String, String)>,
+) -> Result<Option<RunOutcome>, EmulatorError> {
+    let load_info = emu.load_with(format, binary, argv, env)?;
+    info!("load info: {:?}", &load_info);
+
+    let total_steps = emu.run(load_info.entrypoint, None, None, None)?;
+    info!("steps: {}", total_steps);
+    Ok(emu.outcome())
+}
+
+/// Run the program until the step before `steps`, return the state change of step `steps`,
+/// and the pre-images fetched until then.
+fn gen_state<A: ArchT>(
+    mut emu: Emulator<'_, A, LinuxRunner>,
+    format: &BinaryFormat,
+    binary: &[u8],
+    argv: Vec<String>,
+    env: Vec<(String, String)>,
+    steps: NonZeroUsize,
+) -> Result<(StateChange, BTreeMap<PreimageKey, Vec<u8>>), EmulatorError> {
+    let load_info = emu.load_with(format, binary, argv, env)?;
+    info!("load info: {:?}", &load_info);
+
+    let state_change = emu.run_until(load_info.entrypoint, None, None, steps.get() - 1)?;
+    Ok((state_change, emu.runner().preimages()))
+}
+
 /// length of data should be times of 4.
 fn load_data(data: impl AsRef<[u8]>, ram: &mut HashMap<u32, u32>, base: u32) {
     let dat = data.as_ref();
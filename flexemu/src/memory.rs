@@ -37,6 +37,8 @@ pub trait Memory {
     fn mem_map(&mut self, region: MemRegion, info: Option<String>) -> Result<(), uc_error>;
     fn mem_unmap(&mut self, addr: u64, size: usize) -> Result<(), uc_error>;
     fn is_mapped(&self, addr: u64, size: usize) -> Result<bool, uc_error>;
+    /// Mapped regions sorted by address, `end` is exclusive.
+    fn mapped_regions(&self) -> Result<Vec<MemRegion>, uc_error>;
     fn mprotect(&mut self, addr: u64, size: usize, perm: Permission) -> Result<(), uc_error>;
     fn read(&self, addr: u64, size: usize) -> Result<Vec<u8>, uc_error>;
     fn read_ptr(&self, address: u64, pointersize: Option<PointerSizeT>) -> Result<u64, uc_error>;
@@ -81,9 +83,28 @@ impl<'a, A> Memory for Unicorn<'a, Machine<A>> {
         // TODO: manage map_info
         Unicorn::mem_unmap(self, addr, size)
     }
-    fn is_mapped(&self, _addr: u64, _size: usize) -> Result<bool, uc_error> {
-        // FIXME: impl it.
-        Ok(false)
+    fn is_mapped(&self, addr: u64, size: usize) -> Result<bool, uc_error> {
+        // walk the sorted regions which cover the range, until a hole is found.
+        let end = addr + size as u64;
+        let mut cur = addr;
+        for region in self.mapped_regions()? {
+            if cur >= end || region.begin > cur {
+                break;
+            }
+            if region.end > cur {
+                cur = region.end;
+            }
+        }
+        Ok(cur >= end)
+    }
+    fn mapped_regions(&self) -> Result<Vec<MemRegion>, uc_error> {
+        let mut regions = self.mem_regions()?;
+        // unicorn reports the last address of a region, not the end.
+        for region in regions.iter_mut() {
+            region.end += 1;
+        }
+        regions.sort_by_key(|r| r.begin);
+        Ok(regions)
     }
     fn mprotect(&mut self, addr: u64, size: usize, perm: Permission) -> Result<(), uc_error> {
         // TODO: manage map_info
@@ -91,7 +112,11 @@ impl<'a, A> Memory for Unicorn<'a, Machine<A>> {
     }
 
     fn read(&self, addr: u64, len: usize) -> Result<Vec<u8>, uc_error> {
-        self.mem_read_as_vec(addr, len)
+        let data = self.mem_read_as_vec(addr, len)?;
+        self.get_data()
+            .state
+            .record(false, addr, &data, self.endian());
+        Ok(data)
     }
     fn read_ptr(&self, address: u64, pointersize: Option<PointerSizeT>) -> Result<u64, uc_error> {
         let pointersize = pointersize.unwrap_or_else(|| self.pointer_size());
@@ -101,10 +126,10 @@ impl<'a, A> Memory for Unicorn<'a, Machine<A>> {
     }
     fn write(&mut self, address: u64, bytes: impl AsRef<[u8]>) -> Result<(), uc_error> {
         self.mem_write(address, bytes.as_ref())?;
-        self.get_data_mut()
-            .state
-            .memory
-            .write_bytes(address, bytes.as_ref());
+        let endian = self.endian();
+        let state = &mut self.get_data_mut().state;
+        state.memory.write_bytes(address, bytes.as_ref());
+        state.record(true, address, bytes.as_ref(), endian);
         Ok(())
     }
 
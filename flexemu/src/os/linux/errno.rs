@@ -0,0 +1,162 @@
+//! errno values, which differ between the host and the arch of the program.
+//! Errors of host calls are translated explicitly, never passed through as host numbers.
+
+use std::{fmt, io};
+
+use unicorn_engine::unicorn_const::Arch;
+
+/// name, value on the host, value on most linux archs, and value on mips.
+const ERRNOS: [(&str, i32, i64, i64); 68] = [
+    ("EPERM", libc::EPERM, 1, 1),
+    ("ENOENT", libc::ENOENT, 2, 2),
+    ("ESRCH", libc::ESRCH, 3, 3),
+    ("EINTR", libc::EINTR, 4, 4),
+    ("EIO", libc::EIO, 5, 5),
+    ("ENXIO", libc::ENXIO, 6, 6),
+    ("E2BIG", libc::E2BIG, 7, 7),
+    ("ENOEXEC", libc::ENOEXEC, 8, 8),
+    ("EBADF", libc::EBADF, 9, 9),
+    ("ECHILD", libc::ECHILD, 10, 10),
+    ("EAGAIN", libc::EAGAIN, 11, 11),
+    ("ENOMEM", libc::ENOMEM, 12, 12),
+    ("EACCES", libc::EACCES, 13, 13),
+    ("EFAULT", libc::EFAULT, 14, 14),
+    ("ENOTBLK", libc::ENOTBLK, 15, 15),
+    ("EBUSY", libc::EBUSY, 16, 16),
+    ("EEXIST", libc::EEXIST, 17, 17),
+    ("EXDEV", libc::EXDEV, 18, 18),
+    ("ENODEV", libc::ENODEV, 19, 19),
+    ("ENOTDIR", libc::ENOTDIR, 20, 20),
+    ("EISDIR", libc::EISDIR, 21, 21),
+    ("EINVAL", libc::EINVAL, 22, 22),
+    ("ENFILE", libc::ENFILE, 23, 23),
+    ("EMFILE", libc::EMFILE, 24, 24),
+    ("ENOTTY", libc::ENOTTY, 25, 25),
+    ("ETXTBSY", libc::ETXTBSY, 26, 26),
+    ("EFBIG", libc::EFBIG, 27, 27),
+    ("ENOSPC", libc::ENOSPC, 28, 28),
+    ("ESPIPE", libc::ESPIPE, 29, 29),
+    ("EROFS", libc::EROFS, 30, 30),
+    ("EMLINK", libc::EMLINK, 31, 31),
+    ("EPIPE", libc::EPIPE, 32, 32),
+    ("EDOM", libc::EDOM, 33, 33),
+    ("ERANGE", libc::ERANGE, 34, 34),
+    ("EDEADLK", libc::EDEADLK, 35, 45),
+    ("ENAMETOOLONG", libc::ENAMETOOLONG, 36, 78),
+    ("ENOLCK", libc::ENOLCK, 37, 46),
+    ("ENOSYS", libc::ENOSYS, 38, 89),
+    ("ENOTEMPTY", libc::ENOTEMPTY, 39, 93),
+    ("ELOOP", libc::ELOOP, 40, 90),
+    ("ENOMSG", libc::ENOMSG, 42, 35),
+    ("EIDRM", libc::EIDRM, 43, 36),
+    ("ENODATA", libc::ENODATA, 61, 61),
+    ("ETIME", libc::ETIME, 62, 62),
+    ("EOVERFLOW", libc::EOVERFLOW, 75, 79),
+    ("EILSEQ", libc::EILSEQ, 84, 88),
+    ("ENOTSOCK", libc::ENOTSOCK, 88, 95),
+    ("EDESTADDRREQ", libc::EDESTADDRREQ, 89, 96),
+    ("EMSGSIZE", libc::EMSGSIZE, 90, 97),
+    ("EPROTOTYPE", libc::EPROTOTYPE, 91, 98),
+    ("ENOPROTOOPT", libc::ENOPROTOOPT, 92, 99),
+    ("EPROTONOSUPPORT", libc::EPROTONOSUPPORT, 93, 120),
+    ("EOPNOTSUPP", libc::EOPNOTSUPP, 95, 122),
+    ("EAFNOSUPPORT", libc::EAFNOSUPPORT, 97, 124),
+    ("EADDRINUSE", libc::EADDRINUSE, 98, 125),
+    ("EADDRNOTAVAIL", libc::EADDRNOTAVAIL, 99, 126),
+    ("ENETDOWN", libc::ENETDOWN, 100, 127),
+    ("ENETUNREACH", libc::ENETUNREACH, 101, 128),
+    ("ECONNABORTED", libc::ECONNABORTED, 103, 130),
+    ("ECONNRESET", libc::ECONNRESET, 104, 131),
+    ("ENOBUFS", libc::ENOBUFS, 105, 132),
+    ("EISCONN", libc::EISCONN, 106, 133),
+    ("ENOTCONN", libc::ENOTCONN, 107, 134),
+    ("ETIMEDOUT", libc::ETIMEDOUT, 110, 145),
+    ("ECONNREFUSED", libc::ECONNREFUSED, 111, 146),
+    ("EHOSTUNREACH", libc::EHOSTUNREACH, 113, 148),
+    ("EALREADY", libc::EALREADY, 114, 149),
+    ("EINPROGRESS", libc::EINPROGRESS, 115, 150),
+];
+
+/// Value of the errno at `index` of the table on `arch`.
+fn arch_value(arch: Arch, index: usize) -> i64 {
+    let (_, _, generic, mips) = ERRNOS[index];
+    match arch {
+        Arch::MIPS => mips,
+        _ => generic,
+    }
+}
+
+/// Name of `errno` of `arch`.
+pub fn errno_name(arch: Arch, errno: i64) -> String {
+    match (0..ERRNOS.len()).find(|i| arch_value(arch, *i) == errno) {
+        Some(i) => ERRNOS[i].0.to_string(),
+        None => format!("E{}", errno),
+    }
+}
+
+/// errno of a failed host call.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct HostErrno(pub i32);
+
+/// Result of a host call, which fails with the errno of the host.
+pub type HostResult<T = i64> = Result<T, HostErrno>;
+
+impl HostErrno {
+    /// errno left by the last libc call.
+    pub fn last() -> Self {
+        Self(
+            io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO),
+        )
+    }
+
+    /// The errno of `arch` with the same meaning.
+    /// Host errors the program can't know about are reported as `EIO`.
+    pub fn to_guest(self, arch: Arch) -> i64 {
+        match ERRNOS.iter().position(|(_, host, _, _)| *host == self.0) {
+            Some(i) => arch_value(arch, i),
+            None => {
+                log::warn!("no errno of {:?} for host errno {}", arch, self);
+                let eio = ERRNOS.iter().position(|(name, ..)| *name == "EIO");
+                arch_value(arch, eio.unwrap_or_default())
+            }
+        }
+    }
+
+    /// Raw syscall return of the program for this error.
+    pub fn guest_ret(self, arch: Arch) -> i64 {
+        -self.to_guest(arch)
+    }
+}
+
+impl From<io::Error> for HostErrno {
+    fn from(e: io::Error) -> Self {
+        Self(e.raw_os_error().unwrap_or(libc::EIO))
+    }
+}
+
+impl fmt::Display for HostErrno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", io::Error::from_raw_os_error(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::{errno_name, HostErrno};
+
+    #[test]
+    fn test_errno_translation() {
+        assert_eq!(HostErrno(libc::ENOENT).to_guest(Arch::MIPS), 2);
+        assert_eq!(HostErrno(libc::ENOSYS).to_guest(Arch::MIPS), 89);
+        assert_eq!(HostErrno(libc::ENOSYS).to_guest(Arch::RISCV), 38);
+        assert_eq!(HostErrno(libc::ENOTEMPTY).guest_ret(Arch::MIPS), -93);
+        assert_eq!(HostErrno(libc::ETIMEDOUT).to_guest(Arch::MIPS), 145);
+        assert_eq!(errno_name(Arch::MIPS, 145), "ETIMEDOUT");
+        assert_eq!(errno_name(Arch::RISCV, 38), "ENOSYS");
+        assert_eq!(errno_name(Arch::MIPS, 4000), "E4000");
+    }
+}
@@ -0,0 +1,442 @@
+//! Pipes, eventfds and synthetic files of the emulated process.
+//!
+//! They live in the emulator rather than on the host, so that threads talking through them
+//! block and wake up under the deterministic scheduler of [`crate::os::linux::thread`],
+//! and so that files made up for the program, like the ones of `/proc`, never touch the host.
+//! Their fds are placeholders opened on the host: fd numbers are allocated by the host like the
+//! ones of host files, so they never clash, and `close` or `dup2` on the host keep them in sync.
+//!
+//! Errors are errno of the program. `EAGAIN` means the call would block, the runner either
+//! returns it, for non blocking fds, or blocks the thread until the object changes.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use goblin::container::Endian;
+use unicorn_engine::unicorn_const::Arch;
+
+use crate::{
+    os::linux::{EAGAIN, EBADF, EINVAL, EPIPE, ESPIPE},
+    utils::Packer,
+};
+
+/// bytes a pipe holds before writers block, the default of linux.
+pub const PIPE_CAPACITY: usize = 65536;
+/// writes of at most this many bytes are atomic.
+pub const PIPE_BUF: usize = 4096;
+
+pub const POLLIN: u64 = 0x1;
+pub const POLLOUT: u64 = 0x4;
+pub const POLLERR: u64 = 0x8;
+pub const POLLHUP: u64 = 0x10;
+
+/// `O_CLOEXEC` of all archs, which is also `EFD_CLOEXEC`.
+pub const O_CLOEXEC: u64 = 0x80000;
+
+/// `O_NONBLOCK` of `arch`, the only status flag of pipes and eventfds, which is also `EFD_NONBLOCK`.
+pub fn o_nonblock(arch: Arch) -> u64 {
+    match arch {
+        Arch::MIPS => 0x80,
+        _ => 0x800,
+    }
+}
+
+/// largest count of an eventfd.
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+#[derive(Debug)]
+enum Object {
+    Pipe(VecDeque<u8>),
+    EventFd {
+        count: u64,
+        semaphore: bool,
+        endian: Endian,
+    },
+    /// read only file holding its content.
+    File(Vec<u8>),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum End {
+    Read,
+    Write,
+    /// eventfds are read and written through the same fd.
+    Both,
+}
+
+/// An open file of an object, shared by the fds duplicated from the same one.
+#[derive(Debug)]
+struct Description {
+    object: usize,
+    end: End,
+    nonblock: bool,
+    fds: usize,
+    /// position in a file.
+    offset: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct FdTable {
+    objects: BTreeMap<usize, Object>,
+    descriptions: BTreeMap<usize, Description>,
+    /// description of each fd.
+    fds: BTreeMap<u64, usize>,
+    next_id: usize,
+}
+
+impl FdTable {
+    /// Whether `fd` is a pipe, an eventfd or a synthetic file.
+    pub fn contains(&self, fd: u64) -> bool {
+        self.fds.contains_key(&fd)
+    }
+
+    /// Make `read_fd` and `write_fd` the two ends of a new pipe.
+    pub fn pipe(&mut self, read_fd: u64, write_fd: u64, nonblock: bool) {
+        let object = self.insert_object(Object::Pipe(VecDeque::new()));
+        self.insert_fd(read_fd, object, End::Read, nonblock);
+        self.insert_fd(write_fd, object, End::Write, nonblock);
+    }
+
+    /// Make `fd` a new eventfd counting from `count`, its values are `endian` integers.
+    pub fn eventfd(
+        &mut self,
+        fd: u64,
+        count: u64,
+        semaphore: bool,
+        nonblock: bool,
+        endian: Endian,
+    ) {
+        let object = self.insert_object(Object::EventFd {
+            count,
+            semaphore,
+            endian,
+        });
+        self.insert_fd(fd, object, End::Both, nonblock);
+    }
+
+    /// Make `fd` a new read only file holding `content`.
+    pub fn file(&mut self, fd: u64, content: Vec<u8>) {
+        let object = self.insert_object(Object::File(content));
+        self.insert_fd(fd, object, End::Read, false);
+    }
+
+    fn insert_object(&mut self, object: Object) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.objects.insert(id, object);
+        id
+    }
+
+    fn insert_fd(&mut self, fd: u64, object: usize, end: End, nonblock: bool) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.descriptions.insert(
+            id,
+            Description {
+                object,
+                end,
+                nonblock,
+                fds: 1,
+                offset: 0,
+            },
+        );
+        self.fds.insert(fd, id);
+    }
+
+    fn description(&self, fd: u64) -> Option<&Description> {
+        self.fds.get(&fd).and_then(|id| self.descriptions.get(id))
+    }
+
+    /// Whether an open file of `object` can do `end`.
+    fn is_open(&self, object: usize, end: End) -> bool {
+        self.descriptions
+            .values()
+            .any(|d| d.object == object && (d.end == end || d.end == End::Both))
+    }
+
+    /// Make `new_fd` refer to the open file of `old_fd`, `new_fd` must not be in the table.
+    pub fn dup(&mut self, old_fd: u64, new_fd: u64) {
+        if let Some(id) = self.fds.get(&old_fd).copied() {
+            if let Some(d) = self.descriptions.get_mut(&id) {
+                d.fds += 1;
+            }
+            self.fds.insert(new_fd, id);
+        }
+    }
+
+    /// Remove `fd`, the object goes away with its last open file. Return whether `fd` was in the table.
+    pub fn close(&mut self, fd: u64) -> bool {
+        let id = match self.fds.remove(&fd) {
+            Some(id) => id,
+            None => return false,
+        };
+        let object = match self.descriptions.get_mut(&id) {
+            Some(d) if d.fds > 1 => {
+                d.fds -= 1;
+                return true;
+            }
+            Some(d) => d.object,
+            None => return true,
+        };
+        self.descriptions.remove(&id);
+        if !self.descriptions.values().any(|d| d.object == object) {
+            self.objects.remove(&object);
+        }
+        true
+    }
+
+    pub fn nonblock(&self, fd: u64) -> bool {
+        self.description(fd).is_some_and(|d| d.nonblock)
+    }
+
+    /// Access mode and `O_NONBLOCK` of `fd` on `arch`, as reported by F_GETFL.
+    pub fn status_flags(&self, fd: u64, arch: Arch) -> Option<u64> {
+        let d = self.description(fd)?;
+        let mode = match d.end {
+            End::Read => 0,
+            End::Write => 1,
+            End::Both => 2,
+        };
+        Some(if d.nonblock {
+            mode | o_nonblock(arch)
+        } else {
+            mode
+        })
+    }
+
+    /// Set the status flags of `fd` on `arch`, as F_SETFL does, only `O_NONBLOCK` can be changed.
+    pub fn set_status_flags(&mut self, fd: u64, flags: u64, arch: Arch) {
+        if let Some(d) = self
+            .fds
+            .get(&fd)
+            .and_then(|id| self.descriptions.get_mut(id))
+        {
+            d.nonblock = flags & o_nonblock(arch) != 0;
+        }
+    }
+
+    /// Read at most `len` bytes of `fd`, empty at the end of a pipe or a file.
+    pub fn read(&mut self, fd: u64, len: usize) -> Result<Vec<u8>, i64> {
+        let (object, end, offset) = match self.description(fd) {
+            Some(d) => (d.object, d.end, d.offset),
+            None => return Err(EBADF),
+        };
+        if end == End::Write {
+            return Err(EBADF);
+        }
+        let writers = self.is_open(object, End::Write);
+        match self.objects.get_mut(&object) {
+            Some(Object::Pipe(data)) => {
+                if data.is_empty() && len > 0 {
+                    return if writers { Err(EAGAIN) } else { Ok(vec![]) };
+                }
+                let n = len.min(data.len());
+                Ok(data.drain(..n).collect())
+            }
+            Some(Object::EventFd {
+                count,
+                semaphore,
+                endian,
+            }) => {
+                if len < 8 {
+                    return Err(EINVAL);
+                }
+                if *count == 0 {
+                    return Err(EAGAIN);
+                }
+                let value = if *semaphore { 1 } else { *count };
+                *count -= value;
+                Ok(Packer::new(*endian, 8).pack(value))
+            }
+            Some(Object::File(content)) => {
+                let data = read_at(content, len, offset);
+                if let Some(d) = self
+                    .fds
+                    .get(&fd)
+                    .and_then(|id| self.descriptions.get_mut(id))
+                {
+                    d.offset += data.len();
+                }
+                Ok(data)
+            }
+            None => Err(EBADF),
+        }
+    }
+
+    /// Read at most `len` bytes of the file `fd` from `offset`, leaving its position alone.
+    pub fn pread(&self, fd: u64, len: usize, offset: u64) -> Result<Vec<u8>, i64> {
+        let d = self.description(fd).ok_or(EBADF)?;
+        match self.objects.get(&d.object) {
+            Some(Object::File(_)) if (offset as i64) < 0 => Err(EINVAL),
+            Some(Object::File(content)) => Ok(read_at(content, len, offset as usize)),
+            Some(_) => Err(ESPIPE),
+            None => Err(EBADF),
+        }
+    }
+
+    /// Move the position of the file `fd` as lseek does with `whence`, return the new position.
+    pub fn seek(&mut self, fd: u64, offset: i64, whence: u64) -> Result<u64, i64> {
+        const SEEK_SET: u64 = 0;
+        const SEEK_CUR: u64 = 1;
+        const SEEK_END: u64 = 2;
+        let id = *self.fds.get(&fd).ok_or(EBADF)?;
+        let d = self.descriptions.get_mut(&id).ok_or(EBADF)?;
+        let len = match self.objects.get(&d.object) {
+            Some(Object::File(content)) => content.len(),
+            Some(_) => return Err(ESPIPE),
+            None => return Err(EBADF),
+        };
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => d.offset as i64,
+            SEEK_END => len as i64,
+            _ => return Err(EINVAL),
+        };
+        match base.checked_add(offset) {
+            Some(position) if position >= 0 => {
+                d.offset = position as usize;
+                Ok(position as u64)
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    /// Write `data` to `fd`, return the number of bytes written.
+    /// Writes to a pipe are short when it's nearly full, and `EPIPE` when nobody can read it.
+    pub fn write(&mut self, fd: u64, data: &[u8]) -> Result<usize, i64> {
+        let (object, end) = match self.description(fd) {
+            Some(d) => (d.object, d.end),
+            None => return Err(EBADF),
+        };
+        if end == End::Read {
+            return Err(EBADF);
+        }
+        let readers = self.is_open(object, End::Read);
+        match self.objects.get_mut(&object) {
+            Some(Object::Pipe(buf)) => {
+                if !readers {
+                    return Err(EPIPE);
+                }
+                let space = PIPE_CAPACITY - buf.len();
+                // small writes are not split.
+                if space == 0 || (data.len() <= PIPE_BUF && space < data.len()) {
+                    return Err(EAGAIN);
+                }
+                let n = space.min(data.len());
+                buf.extend(&data[..n]);
+                Ok(n)
+            }
+            Some(Object::EventFd { count, endian, .. }) => {
+                if data.len() < 8 {
+                    return Err(EINVAL);
+                }
+                let value = Packer::new(*endian, 8).unpack(data[..8].to_vec());
+                if value == u64::MAX {
+                    return Err(EINVAL);
+                }
+                if value > EVENTFD_MAX - *count {
+                    return Err(EAGAIN);
+                }
+                *count += value;
+                Ok(8)
+            }
+            // files are only opened for reading.
+            Some(Object::File(_)) => Err(EBADF),
+            None => Err(EBADF),
+        }
+    }
+
+    /// Ready events of `fd` among `events`, errors and hang ups are always reported.
+    /// None if `fd` is not in the table.
+    pub fn poll(&self, fd: u64, events: u64) -> Option<u64> {
+        let d = self.description(fd)?;
+        let ready = match self.objects.get(&d.object)? {
+            Object::Pipe(data) => match d.end {
+                End::Write if !self.is_open(d.object, End::Read) => POLLOUT | POLLERR,
+                End::Write if PIPE_CAPACITY - data.len() >= PIPE_BUF => POLLOUT,
+                End::Write => 0,
+                _ if !self.is_open(d.object, End::Write) => POLLIN | POLLHUP,
+                _ if !data.is_empty() => POLLIN,
+                _ => 0,
+            },
+            Object::EventFd { count, .. } => {
+                let readable = if *count > 0 { POLLIN } else { 0 };
+                let writable = if *count < EVENTFD_MAX { POLLOUT } else { 0 };
+                readable | writable
+            }
+            Object::File(_) => POLLIN,
+        };
+        Some(ready & (events | POLLERR | POLLHUP))
+    }
+}
+
+/// At most `len` bytes of `content` from `offset`, empty past the end.
+fn read_at(content: &[u8], len: usize, offset: usize) -> Vec<u8> {
+    let rest = &content[offset.min(content.len())..];
+    rest[..len.min(rest.len())].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::container::Endian;
+
+    use super::{FdTable, PIPE_CAPACITY, POLLHUP, POLLIN, POLLOUT};
+    use crate::os::linux::{EAGAIN, EBADF, EINVAL, EPIPE, ESPIPE};
+
+    #[test]
+    fn test_pipe_and_eventfd() {
+        let mut fds = FdTable::default();
+        fds.pipe(3, 4, false);
+        assert_eq!(fds.read(3, 4), Err(EAGAIN));
+        assert_eq!(fds.write(3, b"x"), Err(EBADF));
+        assert_eq!(fds.poll(4, POLLIN | POLLOUT), Some(POLLOUT));
+        assert_eq!(fds.write(4, b"ping"), Ok(4));
+        assert_eq!(fds.poll(3, POLLIN), Some(POLLIN));
+        assert_eq!(fds.read(3, 2), Ok(b"pi".to_vec()));
+
+        // the pipe stays open through the duplicated write end.
+        fds.dup(4, 5);
+        assert!(fds.close(4));
+        assert_eq!(fds.write(5, &vec![0; PIPE_CAPACITY]), Ok(PIPE_CAPACITY - 2));
+        assert_eq!(fds.write(5, b"x"), Err(EAGAIN));
+        assert!(fds.close(5));
+        assert_eq!(fds.read(3, 2), Ok(b"ng".to_vec()));
+        assert_eq!(fds.read(3, PIPE_CAPACITY).unwrap().len(), PIPE_CAPACITY - 2);
+        assert_eq!(fds.read(3, 4), Ok(vec![]));
+        assert_eq!(fds.poll(3, POLLIN), Some(POLLIN | POLLHUP));
+
+        fds.pipe(6, 7, true);
+        fds.close(6);
+        assert_eq!(fds.write(7, b"x"), Err(EPIPE));
+        assert!(!fds.close(6));
+
+        fds.eventfd(8, 0, true, false, Endian::Big);
+        assert_eq!(fds.read(8, 8), Err(EAGAIN));
+        assert_eq!(fds.write(8, &2u64.to_be_bytes()), Ok(8));
+        // semaphores count down by one.
+        assert_eq!(fds.read(8, 8), Ok(1u64.to_be_bytes().to_vec()));
+        assert_eq!(fds.poll(8, POLLIN), Some(POLLIN));
+        assert_eq!(fds.read(8, 8), Ok(1u64.to_be_bytes().to_vec()));
+        assert_eq!(fds.poll(8, POLLIN), Some(0));
+    }
+
+    #[test]
+    fn test_file() {
+        let mut fds = FdTable::default();
+        fds.file(3, b"MemTotal: 1 kB\n".to_vec());
+        assert_eq!(fds.read(3, 8), Ok(b"MemTotal".to_vec()));
+        assert_eq!(fds.write(3, b"x"), Err(EBADF));
+        assert_eq!(fds.pread(3, 4, 10), Ok(b"1 kB".to_vec()));
+        assert_eq!(fds.read(3, 64), Ok(b": 1 kB\n".to_vec()));
+        assert_eq!(fds.read(3, 64), Ok(vec![]));
+
+        // duplicated fds share the position.
+        fds.dup(3, 4);
+        assert_eq!(fds.seek(4, -3, 2), Ok(12));
+        assert_eq!(fds.read(3, 64), Ok(b"kB\n".to_vec()));
+        assert_eq!(fds.seek(3, -1, 0), Err(EINVAL));
+
+        fds.pipe(5, 6, false);
+        assert_eq!(fds.seek(5, 0, 0), Err(ESPIPE));
+        assert_eq!(fds.pread(5, 1, 0), Err(ESPIPE));
+    }
+}
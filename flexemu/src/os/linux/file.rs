@@ -1,38 +1,76 @@
-use std::{
-    fs::{File, OpenOptions},
-    io::{stderr, stdin, stdout, Read, Write},
-    os::{
-        fd::{AsRawFd, FromRawFd, RawFd},
-        unix::fs::OpenOptionsExt,
-    },
-};
+//! Host side of the file syscalls, on top of libc so it works on any unix host.
+//! Values of the program, like open flags or fcntl commands, are translated to the values
+//! of the host here, and failures are returned as the errno of the host, see [`HostErrno`].
+
+use std::{ffi::CString, mem};
 
-use log::{debug, info};
+use unicorn_engine::unicorn_const::Arch;
 
 use crate::os::linux::{
-    file::FileFlags::{O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY},
-    syscall::*,
+    errno::{HostErrno, HostResult},
+    file::FileFlags::O_ACCMODE,
+    structs::Stat,
 };
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn open(path: &mut String, flags: u64, mode: u64) -> i64 {
-    let flags = flags & 0xffffffff;
-    let open_mode = mode & 0x7fffffff;
-
-    let mut c_path = path.as_bytes().to_vec();
-    c_path.extend_from_slice(b"\x00");
-
-    unsafe {
-        syscall_4(
-            LinuxSysCalls::Open as u64,
-            c_path.as_ptr() as u64,
-            flags,
-            open_mode,
-            0,
-        )
+/// open flags of mips, and their values on the host.
+const MIPS_OPEN_FLAGS: [(u64, i32); 11] = [
+    (0x8, libc::O_APPEND),
+    (0x10, libc::O_DSYNC),
+    (0x80, libc::O_NONBLOCK),
+    (0x100, libc::O_CREAT),
+    (0x200, libc::O_TRUNC),
+    (0x400, libc::O_EXCL),
+    (0x800, libc::O_NOCTTY),
+    (0x4000, libc::O_SYNC),
+    (0x10000, libc::O_DIRECTORY),
+    (0x20000, libc::O_NOFOLLOW),
+    (0x80000, libc::O_CLOEXEC),
+];
+
+/// open flags of the other archs, like riscv, and their values on the host.
+const GENERIC_OPEN_FLAGS: [(u64, i32); 11] = [
+    (0x40, libc::O_CREAT),
+    (0x80, libc::O_EXCL),
+    (0x100, libc::O_NOCTTY),
+    (0x200, libc::O_TRUNC),
+    (0x400, libc::O_APPEND),
+    (0x800, libc::O_NONBLOCK),
+    (0x1000, libc::O_DSYNC),
+    (0x10000, libc::O_DIRECTORY),
+    (0x20000, libc::O_NOFOLLOW),
+    (0x80000, libc::O_CLOEXEC),
+    (0x100000, libc::O_SYNC),
+];
+
+fn open_flags(arch: Arch) -> &'static [(u64, i32)] {
+    match arch {
+        Arch::MIPS => &MIPS_OPEN_FLAGS,
+        _ => &GENERIC_OPEN_FLAGS,
     }
 }
 
+/// access modes of the program, and their values on the host.
+const ACCESS_MODES: [(u64, i32); 3] = [
+    (0x0, libc::O_RDONLY),
+    (0x1, libc::O_WRONLY),
+    (0x2, libc::O_RDWR),
+];
+
+/// `dirfd` of *at syscalls of the program for paths relative to the current directory.
+pub const AT_FDCWD: i32 = -100;
+/// `*at` flags of the program.
+pub const AT_SYMLINK_NOFOLLOW: u64 = 0x100;
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+/// fcntl commands of the program.
+pub const F_DUPFD: u64 = 0;
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+pub const F_GETFL: u64 = 3;
+pub const F_SETFL: u64 = 4;
+pub const F_DUPFD_CLOEXEC: u64 = 1030;
+const FD_CLOEXEC: u64 = 1;
+
 #[repr(u64)]
 pub enum FileFlags {
     O_ACCMODE = 0x3,
@@ -41,183 +79,284 @@ pub enum FileFlags {
     O_RDWR = 0x2,
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn open(path: &mut String, flags: u64, mode: u64) -> i64 {
-    let flags = flags & 0xffffffff;
-    let open_mode = mode & 0x7fffffff;
+/// Translate the open flags of the program on `arch` to the flags of the host.
+/// Unknown flags, and flags without meaning on the host like O_LARGEFILE, are dropped.
+pub fn host_open_flags(arch: Arch, flags: u64) -> i32 {
+    let mode = ACCESS_MODES
+        .iter()
+        .find(|(guest, _)| flags & O_ACCMODE as u64 == *guest)
+        .map_or(libc::O_RDONLY, |(_, host)| *host);
+    open_flags(arch)
+        .iter()
+        .filter(|(guest, _)| flags & guest != 0)
+        .fold(mode, |host, (_, h)| host | h)
+}
 
-    let file = OpenOptions::new()
-        .custom_flags(flags as i32)
-        .read(
-            (flags & O_ACCMODE as u64 == O_RDONLY as u64)
-                || (flags & O_ACCMODE as u64 == O_RDWR as u64),
-        )
-        .write(
-            (flags & O_ACCMODE as u64 == O_WRONLY as u64)
-                || (flags & O_ACCMODE as u64 == O_RDWR as u64),
-        )
-        .mode(open_mode as u32)
-        .open(path)
-        .unwrap();
+/// Translate the open flags of the host, as reported by F_GETFL, to the flags of the program on `arch`.
+pub fn guest_open_flags(arch: Arch, flags: i32) -> u64 {
+    let mode = ACCESS_MODES
+        .iter()
+        .find(|(_, host)| flags & libc::O_ACCMODE == *host)
+        .map_or(0, |(guest, _)| *guest);
+    open_flags(arch)
+        .iter()
+        .filter(|(_, host)| flags & host == *host)
+        .fold(mode, |guest, (g, _)| guest | g)
+}
 
-    file.as_raw_fd() as i64
+/// Result of a libc call, which returns a negative value and sets errno on failure.
+fn check(ret: i64) -> HostResult {
+    if ret < 0 {
+        Err(HostErrno::last())
+    } else {
+        Ok(ret)
+    }
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn read(fd: u64, buf: &mut Vec<u8>, size: u64) -> i64 {
-    let buf = buf.as_ptr();
-    unsafe { syscall_4(LinuxSysCalls::Read as u64, fd, buf as u64, size, 0) }
+fn c_path(path: &str) -> HostResult<CString> {
+    CString::new(path).map_err(|_| HostErrno(libc::EINVAL))
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn read(fd: u64, buf: &mut Vec<u8>, _size: u64) -> i64 {
-    if fd == 0 {
-        return stdin().read(buf).unwrap() as i64;
+/// fd of the program on the host, fds are passed through.
+fn host_fd(fd: u64) -> i32 {
+    fd as i32
+}
+
+/// Directory fd of a `*at` syscall on the host, `AT_FDCWD` differs between systems.
+fn host_dir_fd(dir_fd: u64) -> i32 {
+    match dir_fd as i32 {
+        AT_FDCWD => libc::AT_FDCWD,
+        fd => fd,
     }
+}
 
-    let mut f = unsafe { File::from_raw_fd(fd as RawFd) };
-    f.read(buf).unwrap() as i64
+/// Open `path` with the host `flags`.
+pub fn open(path: &str, flags: i32, mode: u64) -> HostResult {
+    let path = c_path(path)?;
+    let mode = (mode & 0o7777) as libc::c_uint;
+    check(unsafe { libc::open(path.as_ptr(), flags, mode) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn write(fd: u64, mut data: Vec<u8>, len: u64) -> i64 {
-    let data = data.as_ptr();
-    unsafe { syscall_4(LinuxSysCalls::Write as u64, fd, data as u64, len, 0) }
+/// Open `path` relative to `dir_fd` with the host `flags`.
+pub fn openat(dir_fd: u64, path: &str, flags: i32, mode: u64) -> HostResult {
+    let path = c_path(path)?;
+    let mode = (mode & 0o7777) as libc::c_uint;
+    check(unsafe { libc::openat(host_dir_fd(dir_fd), path.as_ptr(), flags, mode) } as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn write(fd: u64, mut data: Vec<u8>, len: u64) -> i64 {
-    if fd == 1 {
-        return stdout().write(&mut *data).unwrap() as i64;
-    }
-    if fd == 2 {
-        return stderr().write(&mut *data).unwrap() as i64;
-    }
+pub fn read(fd: u64, buf: &mut [u8]) -> HostResult {
+    let ptr = buf.as_mut_ptr() as *mut libc::c_void;
+    check(unsafe { libc::read(host_fd(fd), ptr, buf.len()) } as i64)
+}
 
-    let mut f = unsafe { File::from_raw_fd(fd as RawFd) };
-    f.write(&mut *data).unwrap() as i64
+pub fn pread(fd: u64, buf: &mut [u8], offset: u64) -> HostResult {
+    let ptr = buf.as_mut_ptr() as *mut libc::c_void;
+    let offset = offset as libc::off_t;
+    check(unsafe { libc::pread(host_fd(fd), ptr, buf.len(), offset) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn close(fd: u64) -> i64 {
-    unsafe { syscall_4(LinuxSysCalls::Close as u64, fd, 0, 0, 0) }
+pub fn write(fd: u64, data: &[u8]) -> HostResult {
+    let ptr = data.as_ptr() as *const libc::c_void;
+    check(unsafe { libc::write(host_fd(fd), ptr, data.len()) } as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn close(fd: u64) -> i64 {
-    let mut f = unsafe { File::from_raw_fd(fd as RawFd) };
-    drop(f);
-    0
+pub fn pwrite(fd: u64, data: &[u8], offset: u64) -> HostResult {
+    let ptr = data.as_ptr() as *const libc::c_void;
+    let offset = offset as libc::off_t;
+    check(unsafe { libc::pwrite(host_fd(fd), ptr, data.len(), offset) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn lseek(fd: u64, offset: u64, whence: u64) -> i64 {
-    unsafe { syscall_4(LinuxSysCalls::Lseek as u64, fd, offset, whence, 0) }
+pub fn close(fd: u64) -> HostResult {
+    check(unsafe { libc::close(host_fd(fd)) } as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn lseek(fd: u64, offset: u64, whence: u64) -> i64 {
-    0 // TODO
+/// Seek `fd`, `whence` is the one of the program.
+pub fn lseek(fd: u64, offset: i64, whence: u64) -> HostResult {
+    let whence = match whence {
+        0 => libc::SEEK_SET,
+        1 => libc::SEEK_CUR,
+        2 => libc::SEEK_END,
+        _ => return Err(HostErrno(libc::EINVAL)),
+    };
+    check(unsafe { libc::lseek(host_fd(fd), offset as libc::off_t, whence) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn fcntl(fd: u64, cmd: u64, arg: u64) -> i64 {
-    unsafe { syscall_4(LinuxSysCalls::Fcntl as u64, fd, cmd, arg, 0) }
+/// fcntl of the program on `arch`, only the commands on fds and their flags are supported.
+pub fn fcntl(arch: Arch, fd: u64, cmd: u64, arg: u64) -> HostResult {
+    let fd = host_fd(fd);
+    let ret = match cmd {
+        F_DUPFD => unsafe { libc::fcntl(fd, libc::F_DUPFD, arg as libc::c_int) },
+        F_DUPFD_CLOEXEC => unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, arg as libc::c_int) },
+        F_GETFD => {
+            let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFD) } as i64)?;
+            return Ok(if flags as i32 & libc::FD_CLOEXEC != 0 {
+                FD_CLOEXEC as i64
+            } else {
+                0
+            });
+        }
+        F_SETFD => {
+            let flags = if arg & FD_CLOEXEC != 0 {
+                libc::FD_CLOEXEC
+            } else {
+                0
+            };
+            unsafe { libc::fcntl(fd, libc::F_SETFD, flags) }
+        }
+        F_GETFL => {
+            let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFL) } as i64)?;
+            return Ok(guest_open_flags(arch, flags as i32) as i64);
+        }
+        F_SETFL => unsafe { libc::fcntl(fd, libc::F_SETFL, host_open_flags(arch, arg)) },
+        _ => {
+            log::warn!("unsupported fcntl command {}", cmd);
+            return Err(HostErrno(libc::EINVAL));
+        }
+    };
+    check(ret as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn fcntl(fd: u64, cmd: u64, arg: u64) -> i64 {
-    0 // TODO
+pub fn readlink(path: &str, buf: &mut [u8]) -> HostResult {
+    let path = c_path(path)?;
+    let ptr = buf.as_mut_ptr() as *mut libc::c_char;
+    check(unsafe { libc::readlink(path.as_ptr(), ptr, buf.len()) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn readlink(path: *const u8, buf: *mut u8, buf_size: u64) -> i64 {
-    unsafe {
-        syscall_4(
-            LinuxSysCalls::Readlink as u64,
-            path as u64,
-            buf as u64,
-            buf_size,
-            0,
-        )
-    }
+/// Run a libc call filling a host `struct stat`.
+fn host_stat(call: impl FnOnce(*mut libc::stat) -> libc::c_int) -> HostResult<Stat> {
+    let mut st: libc::stat = unsafe { mem::zeroed() };
+    check(call(&mut st) as i64)?;
+    Ok(Stat::from(&st))
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn readlink(path: *const u8, buf: *mut u8, buf_size: u64) -> i64 {
-    0 // TODO
+pub fn stat(path: &str) -> HostResult<Stat> {
+    let path = c_path(path)?;
+    host_stat(|st| unsafe { libc::stat(path.as_ptr(), st) })
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn stat(path: *const u8, stat_buf: *mut StatX8664) -> i64 {
-    unsafe {
-        syscall_4(
-            LinuxSysCalls::Stat as u64,
-            path as u64,
-            stat_buf as u64,
-            0,
-            0,
-        )
+pub fn lstat(path: &str) -> HostResult<Stat> {
+    let path = c_path(path)?;
+    host_stat(|st| unsafe { libc::lstat(path.as_ptr(), st) })
+}
+
+pub fn fstat(fd: u64) -> HostResult<Stat> {
+    host_stat(|st| unsafe { libc::fstat(host_fd(fd), st) })
+}
+
+/// Stat `path` relative to `dir_fd`, `flags` are the `AT_*` flags of the program.
+/// Flags which only tune the sync of network filesystems are ignored.
+pub fn fstatat(dir_fd: u64, path: &str, flags: u64) -> HostResult<Stat> {
+    if path.is_empty() {
+        if flags & AT_EMPTY_PATH == 0 {
+            return Err(HostErrno(libc::ENOENT));
+        }
+        return fstat(dir_fd);
     }
+    let host_flags = if flags & AT_SYMLINK_NOFOLLOW != 0 {
+        libc::AT_SYMLINK_NOFOLLOW
+    } else {
+        0
+    };
+    let path = c_path(path)?;
+    host_stat(|st| unsafe { libc::fstatat(host_dir_fd(dir_fd), path.as_ptr(), st, host_flags) })
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn stat(path: *const u8, stat_buf: *mut StatX8664) -> i64 {
-    0 // TODO
+/// `struct winsize` of the terminal `fd`: rows, columns, width and height.
+pub fn winsize(fd: u64) -> HostResult<[u16; 4]> {
+    let mut ws: libc::winsize = unsafe { mem::zeroed() };
+    check(unsafe { libc::ioctl(host_fd(fd), libc::TIOCGWINSZ as _, &mut ws) } as i64)?;
+    Ok([ws.ws_row, ws.ws_col, ws.ws_xpixel, ws.ws_ypixel])
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn fstat(fd: u64, stat_buf: *mut StatX8664) -> i64 {
-    unsafe { syscall_4(LinuxSysCalls::Fstat as u64, fd, stat_buf as u64, 0, 0) }
+/// Read host `linux_dirent64` records of the directory `fd` into `buf`.
+#[cfg(target_os = "linux")]
+pub fn getdents64(fd: u64, buf: &mut [u8]) -> HostResult {
+    let ptr = buf.as_mut_ptr();
+    check(unsafe { libc::syscall(libc::SYS_getdents64, host_fd(fd), ptr, buf.len()) } as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn fstat(fd: u64, stat_buf: *mut StatX8664) -> i64 {
-    0 // TODO
+/// Other hosts don't have `linux_dirent64` records.
+#[cfg(not(target_os = "linux"))]
+pub fn getdents64(_fd: u64, _buf: &mut [u8]) -> HostResult {
+    Err(HostErrno(libc::ENOSYS))
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn lstat(path: *const u8, stat_buf: *mut StatX8664) -> i64 {
-    unsafe {
-        syscall_4(
-            LinuxSysCalls::Lstat as u64,
-            path as u64,
-            stat_buf as u64,
-            0,
-            0,
-        )
-    }
+pub fn ftruncate(fd: u64, length: i64) -> HostResult {
+    check(unsafe { libc::ftruncate(host_fd(fd), length as libc::off_t) } as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn lstat(path: *const u8, stat_buf: *mut StatX8664) -> i64 {
-    0 // TODO
+pub fn unlink(path: &str) -> HostResult {
+    let path = c_path(path)?;
+    check(unsafe { libc::unlink(path.as_ptr()) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn fstatat64(dir_fd: u64, path: *const u8, stat_buf: *mut StatX8664, flags: u64) -> i64 {
-    unsafe {
-        syscall_4(
-            LinuxSysCalls::Newfstatat as u64,
-            dir_fd,
-            path as u64,
-            stat_buf as u64,
-            flags,
-        )
-    }
+pub fn mkdir(path: &str, mode: u64) -> HostResult {
+    let path = c_path(path)?;
+    check(unsafe { libc::mkdir(path.as_ptr(), (mode & 0o7777) as libc::mode_t) } as i64)
+}
+
+pub fn rmdir(path: &str) -> HostResult {
+    let path = c_path(path)?;
+    check(unsafe { libc::rmdir(path.as_ptr()) } as i64)
+}
+
+/// Open a host fd standing for an object of the emulator, like a pipe, to reserve its number.
+pub fn placeholder(cloexec: bool) -> HostResult {
+    let path = c_path("/dev/null")?;
+    let flags = if cloexec {
+        libc::O_RDWR | libc::O_CLOEXEC
+    } else {
+        libc::O_RDWR
+    };
+    check(unsafe { libc::open(path.as_ptr(), flags) } as i64)
+}
+
+/// poll events of the program, and their values on the host.
+const POLL_EVENTS: [(u64, libc::c_short); 6] = [
+    (0x1, libc::POLLIN),
+    (0x2, libc::POLLPRI),
+    (0x4, libc::POLLOUT),
+    (0x8, libc::POLLERR),
+    (0x10, libc::POLLHUP),
+    (0x20, libc::POLLNVAL),
+];
+
+/// Poll the host `fds` for the events of the program, return the ready events of each fd.
+/// `timeout` is in milliseconds, negative to wait forever.
+pub fn poll(fds: &[(u64, u64)], timeout: i32) -> HostResult<Vec<u64>> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|(fd, events)| libc::pollfd {
+            fd: host_fd(*fd),
+            events: POLL_EVENTS
+                .iter()
+                .filter(|(guest, _)| events & guest != 0)
+                .fold(0, |host, (_, h)| host | h),
+            revents: 0,
+        })
+        .collect();
+    let nfds = pollfds.len() as libc::nfds_t;
+    check(unsafe { libc::poll(pollfds.as_mut_ptr(), nfds, timeout) } as i64)?;
+    Ok(pollfds
+        .iter()
+        .map(|p| {
+            POLL_EVENTS
+                .iter()
+                .filter(|(_, host)| p.revents & host != 0)
+                .fold(0, |guest, (g, _)| guest | g)
+        })
+        .collect())
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn fstatat64(dir_fd: u64, path: *const u8, stat_buf: *mut StatX8664, flags: u64) -> i64 {
-    0 // TODO
+pub fn dup(fd: u64) -> HostResult {
+    check(unsafe { libc::dup(host_fd(fd)) } as i64)
 }
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-pub fn ioctl(fd: u64, cmd: u64, arg: u64) -> i64 {
-    unsafe { syscall_4(LinuxSysCalls::Ioctl as u64, fd, cmd, arg, 0) }
+pub fn dup2(old_fd: u64, new_fd: u64) -> HostResult {
+    check(unsafe { libc::dup2(host_fd(old_fd), host_fd(new_fd)) } as i64)
 }
 
-#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-pub fn ioctl(fd: u64, cmd: u64, arg: u64) -> i64 {
-    0 // TODO
+pub fn dup3(old_fd: u64, new_fd: u64, cloexec: bool) -> HostResult {
+    let flags = if cloexec { libc::O_CLOEXEC } else { 0 };
+    check(unsafe { libc::dup3(host_fd(old_fd), host_fd(new_fd), flags) } as i64)
 }
@@ -0,0 +1,361 @@
+//! Syscall handlers, and the registry which maps syscall numbers to them.
+//!
+//! Built-in syscalls are implemented by [`LinuxRunner`](super::LinuxRunner) itself, their arguments
+//! are decoded by [`FromSyscallArg`] as the parameter types of the handlers, like `int` fds.
+//! Library users can add syscalls, or override built-in ones, by registering a [`SyscallHandler`].
+//! Handlers see the machine through [`SyscallCore`], and return the raw result of the syscall,
+//! `-errno` on failures, which the runner reports following the calling convention of the arch.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc, str::FromStr};
+
+use goblin::container::Endian;
+use unicorn_engine::unicorn_const::{uc_error, Arch, MemRegion, Permission};
+
+use crate::{
+    arch::{ArchInfo, ArchT},
+    engine::Engine,
+    errors::EmulatorError,
+    memory::{Memory, PointerSizeT},
+    os::linux::syscall::{SysCalls, SYSCALL},
+    registers::{Registers, StackRegister},
+};
+
+/// max number of arguments a linux syscall takes.
+pub const SYSCALL_MAX_ARGS: usize = 6;
+
+/// Conversion of a raw syscall argument to the type a handler expects.
+pub trait FromSyscallArg: Sized {
+    fn from_arg(raw: u64) -> Self;
+}
+
+impl FromSyscallArg for u64 {
+    fn from_arg(raw: u64) -> Self {
+        raw
+    }
+}
+
+impl FromSyscallArg for u32 {
+    fn from_arg(raw: u64) -> Self {
+        raw as u32
+    }
+}
+
+/// `int` arguments like fds are 32 bits, even on 64 bits archs.
+impl FromSyscallArg for i32 {
+    fn from_arg(raw: u64) -> Self {
+        raw as i32
+    }
+}
+
+impl FromSyscallArg for i64 {
+    fn from_arg(raw: u64) -> Self {
+        raw as i64
+    }
+}
+
+impl FromSyscallArg for usize {
+    fn from_arg(raw: u64) -> Self {
+        raw as usize
+    }
+}
+
+impl FromSyscallArg for bool {
+    fn from_arg(raw: u64) -> Self {
+        raw != 0
+    }
+}
+
+/// Raw arguments of a syscall, as passed by the calling convention.
+#[derive(Default, Copy, Clone, Eq, PartialEq)]
+pub struct SyscallArgs([u64; SYSCALL_MAX_ARGS]);
+
+impl SyscallArgs {
+    pub fn new(raw: [u64; SYSCALL_MAX_ARGS]) -> Self {
+        Self(raw)
+    }
+
+    pub fn raw(&self) -> &[u64] {
+        &self.0
+    }
+
+    pub fn values(&self) -> [u64; SYSCALL_MAX_ARGS] {
+        self.0
+    }
+
+    /// The `i`th argument, decoded as `T`.
+    pub fn get<T: FromSyscallArg>(&self, i: usize) -> T {
+        T::from_arg(self.0[i])
+    }
+}
+
+impl fmt::Debug for SyscallArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self
+            .0
+            .iter()
+            .map(|a| format!("{:#x}", a))
+            .collect::<Vec<_>>();
+        write!(f, "{}", args.join(", "))
+    }
+}
+
+/// The machine as seen by syscall handlers.
+/// Unlike [`Mach`](crate::engine::Mach), it's object safe, so handlers don't depend on the arch type.
+pub trait SyscallCore {
+    fn arch(&self) -> Arch;
+    fn endian(&self) -> Endian;
+    fn pointer_size(&self) -> PointerSizeT;
+    fn pagesize(&self) -> u64;
+    /// number of steps executed so far.
+    fn steps(&self) -> u64;
+    fn pc(&self) -> Result<u64, uc_error>;
+    fn sp(&self) -> Result<u64, uc_error>;
+    fn reg_read(&self, reg: i32) -> Result<u64, uc_error>;
+    fn reg_write(&mut self, reg: i32, value: u64) -> Result<(), uc_error>;
+    fn mem_read(&self, addr: u64, size: usize) -> Result<Vec<u8>, uc_error>;
+    fn mem_write(&mut self, addr: u64, data: &[u8]) -> Result<(), uc_error>;
+    fn mem_map(&mut self, region: MemRegion, label: Option<String>) -> Result<(), uc_error>;
+    fn mem_unmap(&mut self, addr: u64, size: usize) -> Result<(), uc_error>;
+    fn mem_protect(&mut self, addr: u64, size: usize, perms: Permission) -> Result<(), uc_error>;
+    fn mapped_regions(&self) -> Result<Vec<MemRegion>, uc_error>;
+    /// Stop the emulation after the syscall returns.
+    fn emu_stop(&mut self) -> Result<(), uc_error>;
+}
+
+impl<'a, A: ArchT> SyscallCore for Engine<'a, A> {
+    fn arch(&self) -> Arch {
+        ArchInfo::arch(self)
+    }
+    fn endian(&self) -> Endian {
+        ArchInfo::endian(self)
+    }
+    fn pointer_size(&self) -> PointerSizeT {
+        ArchInfo::pointer_size(self)
+    }
+    fn pagesize(&self) -> u64 {
+        Memory::pagesize(self)
+    }
+    fn steps(&self) -> u64 {
+        self.get_data().state.steps
+    }
+    fn pc(&self) -> Result<u64, uc_error> {
+        Registers::pc(self)
+    }
+    fn sp(&self) -> Result<u64, uc_error> {
+        StackRegister::sp(self)
+    }
+    fn reg_read(&self, reg: i32) -> Result<u64, uc_error> {
+        Registers::read(self, reg)
+    }
+    fn reg_write(&mut self, reg: i32, value: u64) -> Result<(), uc_error> {
+        Registers::write(self, reg, value)
+    }
+    fn mem_read(&self, addr: u64, size: usize) -> Result<Vec<u8>, uc_error> {
+        Memory::read(self, addr, size)
+    }
+    fn mem_write(&mut self, addr: u64, data: &[u8]) -> Result<(), uc_error> {
+        Memory::write(self, addr, data)
+    }
+    fn mem_map(&mut self, region: MemRegion, label: Option<String>) -> Result<(), uc_error> {
+        Memory::mem_map(self, region, label)
+    }
+    fn mem_unmap(&mut self, addr: u64, size: usize) -> Result<(), uc_error> {
+        Memory::mem_unmap(self, addr, size)
+    }
+    fn mem_protect(&mut self, addr: u64, size: usize, perms: Permission) -> Result<(), uc_error> {
+        Memory::mprotect(self, addr, size, perms)
+    }
+    fn mapped_regions(&self) -> Result<Vec<MemRegion>, uc_error> {
+        Memory::mapped_regions(self)
+    }
+    fn emu_stop(&mut self) -> Result<(), uc_error> {
+        Engine::emu_stop(self)
+    }
+}
+
+/// A syscall implemented outside of the runner.
+pub trait SyscallHandler {
+    /// Run the syscall, return its raw result, `-errno` on failures.
+    fn handle(
+        &mut self,
+        core: &mut dyn SyscallCore,
+        args: &SyscallArgs,
+    ) -> Result<i64, EmulatorError>;
+}
+
+impl<F> SyscallHandler for F
+where
+    F: FnMut(&mut dyn SyscallCore, &SyscallArgs) -> Result<i64, EmulatorError>,
+{
+    fn handle(
+        &mut self,
+        core: &mut dyn SyscallCore,
+        args: &SyscallArgs,
+    ) -> Result<i64, EmulatorError> {
+        self(core, args)
+    }
+}
+
+#[derive(Clone)]
+pub enum SyscallEntry {
+    Builtin(SysCalls),
+    Custom {
+        name: String,
+        handler: Rc<RefCell<dyn SyscallHandler>>,
+    },
+}
+
+impl SyscallEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            SyscallEntry::Builtin(syscall) => syscall.into(),
+            SyscallEntry::Custom { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Debug for SyscallEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyscallEntry::Builtin(syscall) => write!(f, "Builtin({:?})", syscall),
+            SyscallEntry::Custom { name, .. } => write!(f, "Custom({})", name),
+        }
+    }
+}
+
+/// Handlers of syscalls, keyed by arch, pointer size and syscall number.
+#[derive(Debug, Clone)]
+pub struct SyscallRegistry {
+    entries: BTreeMap<(u8, PointerSizeT, u64), SyscallEntry>,
+}
+
+impl Default for SyscallRegistry {
+    /// The built-in syscalls, numbered after the syscall table of each arch.
+    fn default() -> Self {
+        let mut entries = BTreeMap::new();
+        for ((arch, pointer_size), table) in SYSCALL.iter() {
+            for (number, name) in table {
+                if let Ok(syscall) = SysCalls::from_str(name) {
+                    entries.insert(
+                        (*arch, *pointer_size, *number),
+                        SyscallEntry::Builtin(syscall),
+                    );
+                }
+            }
+        }
+        Self { entries }
+    }
+}
+
+impl SyscallRegistry {
+    /// Handler of syscall `number` of `arch` for 32 bits programs, like o32 mips.
+    pub fn get(&self, arch: Arch, number: u64) -> Option<&SyscallEntry> {
+        self.get_for(arch, 4, number)
+    }
+
+    /// Register `handler` as syscall `number` of `arch` for 32 bits programs,
+    /// replacing the built-in or registered one.
+    pub fn register(
+        &mut self,
+        arch: Arch,
+        number: u64,
+        name: impl Into<String>,
+        handler: impl SyscallHandler + 'static,
+    ) {
+        self.register_for(arch, 4, number, name, handler)
+    }
+
+    /// Remove syscall `number` of `arch` for 32 bits programs, calling it becomes an error.
+    pub fn unregister(&mut self, arch: Arch, number: u64) -> Option<SyscallEntry> {
+        self.unregister_for(arch, 4, number)
+    }
+
+    /// Same as `get`, for programs with `pointer_size` pointers.
+    pub fn get_for(
+        &self,
+        arch: Arch,
+        pointer_size: PointerSizeT,
+        number: u64,
+    ) -> Option<&SyscallEntry> {
+        self.entries.get(&(arch as u8, pointer_size, number))
+    }
+
+    /// Same as `register`, for programs with `pointer_size` pointers.
+    pub fn register_for(
+        &mut self,
+        arch: Arch,
+        pointer_size: PointerSizeT,
+        number: u64,
+        name: impl Into<String>,
+        handler: impl SyscallHandler + 'static,
+    ) {
+        self.entries.insert(
+            (arch as u8, pointer_size, number),
+            SyscallEntry::Custom {
+                name: name.into(),
+                handler: Rc::new(RefCell::new(handler)),
+            },
+        );
+    }
+
+    /// Same as `unregister`, for programs with `pointer_size` pointers.
+    pub fn unregister_for(
+        &mut self,
+        arch: Arch,
+        pointer_size: PointerSizeT,
+        number: u64,
+    ) -> Option<SyscallEntry> {
+        self.entries.remove(&(arch as u8, pointer_size, number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::{SyscallArgs, SyscallCore, SyscallEntry, SyscallRegistry};
+    use crate::{errors::EmulatorError, os::linux::syscall::SysCalls};
+
+    #[test]
+    fn test_registry() {
+        let mut registry = SyscallRegistry::default();
+        assert!(matches!(
+            registry.get(Arch::MIPS, 4004),
+            Some(SyscallEntry::Builtin(SysCalls::WRITE))
+        ));
+        // fork is in the syscall table, but not implemented.
+        assert!(registry.get(Arch::MIPS, 4002).is_none());
+        // riscv32 and riscv64 share numbers, for different syscalls sometimes.
+        assert!(matches!(
+            registry.get_for(Arch::RISCV, 8, 62),
+            Some(SyscallEntry::Builtin(SysCalls::LSEEK))
+        ));
+        assert!(matches!(
+            registry.get_for(Arch::RISCV, 4, 62),
+            Some(SyscallEntry::Builtin(SysCalls::_LLSEEK))
+        ));
+
+        registry.register(
+            Arch::MIPS,
+            4004,
+            "write",
+            |_core: &mut dyn SyscallCore, args: &SyscallArgs| -> Result<i64, EmulatorError> {
+                Ok(args.get::<u64>(2) as i64)
+            },
+        );
+        let entry = registry.get(Arch::MIPS, 4004).unwrap();
+        assert_eq!(entry.name(), "write");
+        assert!(matches!(entry, SyscallEntry::Custom { .. }));
+        // mips64 programs have their own table.
+        assert!(matches!(
+            registry.get_for(Arch::MIPS, 8, 5001),
+            Some(SyscallEntry::Builtin(SysCalls::WRITE))
+        ));
+        assert!(registry.unregister(Arch::MIPS, 4004).is_some());
+        assert!(registry.get(Arch::MIPS, 4004).is_none());
+
+        let args = SyscallArgs::new([u32::MAX as u64, 0, 0, 0, 0, 0]);
+        assert_eq!(args.get::<i32>(0), -1);
+        assert_eq!(format!("{:?}", args), "0xffffffff, 0x0, 0x0, 0x0, 0x0, 0x0");
+    }
+}
@@ -0,0 +1,314 @@
+//! Identity of the emulated machine, configured by the `[machine]` section.
+//! uname, sysinfo, getrlimit, the auxv and the files of `/proc` all report it,
+//! so the program sees the same machine whichever way it asks.
+
+use std::fmt::Write;
+
+use unicorn_engine::unicorn_const::Arch;
+
+use crate::{config::MachineConfig, memory::PointerSizeT, os::linux::structs::Sysinfo};
+
+/// resources of getrlimit, in the order of their numbers on most archs.
+const RLIMITS: [&str; 16] = [
+    "cpu",
+    "fsize",
+    "data",
+    "stack",
+    "core",
+    "rss",
+    "nproc",
+    "nofile",
+    "memlock",
+    "as",
+    "locks",
+    "sigpending",
+    "msgqueue",
+    "nice",
+    "rtprio",
+    "rttime",
+];
+
+/// resources of getrlimit on mips, which numbers a few of them differently.
+const MIPS_RLIMITS: [&str; 16] = [
+    "cpu",
+    "fsize",
+    "data",
+    "stack",
+    "core",
+    "nofile",
+    "as",
+    "rss",
+    "nproc",
+    "memlock",
+    "locks",
+    "sigpending",
+    "msgqueue",
+    "nice",
+    "rtprio",
+    "rttime",
+];
+
+/// `RLIM_INFINITY` of `struct rlimit`, 32 bits mips only reports 31 bits.
+pub fn rlim_infinity(arch: Arch, pointer_size: PointerSizeT) -> u64 {
+    match (arch, pointer_size) {
+        (Arch::MIPS, 4) => 0x7fff_ffff,
+        (_, 4) => u32::MAX as u64,
+        _ => u64::MAX,
+    }
+}
+
+/// `RLIM64_INFINITY` of `struct rlimit64`.
+pub const RLIM64_INFINITY: u64 = u64::MAX;
+
+/// `struct utsname` is 6 fields of 65 chars.
+pub const UTSNAME_FIELD_LEN: usize = 65;
+
+#[derive(Debug, Default)]
+pub struct Identity {
+    config: MachineConfig,
+}
+
+impl Identity {
+    pub fn new(config: MachineConfig) -> Self {
+        for name in config.rlimits.keys() {
+            if !RLIMITS.contains(&name.as_str()) {
+                log::warn!("unknown resource {} in machine rlimits", name);
+            }
+        }
+        Self { config }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.config.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.config.gid
+    }
+
+    /// total ram, which the mappings of the program can't exceed.
+    pub fn ram(&self) -> u64 {
+        self.config.ram
+    }
+
+    /// Name of the resource numbered `resource` on `arch`.
+    pub fn rlimit_name(arch: Arch, resource: u64) -> Option<&'static str> {
+        let names = match arch {
+            Arch::MIPS => &MIPS_RLIMITS,
+            _ => &RLIMITS,
+        };
+        names.get(resource as usize).copied()
+    }
+
+    /// Soft and hard limits of the resource named `name`, none if unlimited.
+    pub fn rlimit(&self, name: &str) -> (Option<u64>, Option<u64>) {
+        (self.config.rlimits.get(name).copied(), None)
+    }
+
+    /// `struct utsname` of the machine, `machine` is the name of the arch, like `mips`.
+    pub fn utsname(&self, machine: &str) -> Vec<u8> {
+        let fields = [
+            "Linux",
+            &self.config.hostname,
+            &self.config.kernel_release,
+            &self.config.kernel_version,
+            machine,
+            "(none)",
+        ];
+        let mut utsname = Vec::with_capacity(fields.len() * UTSNAME_FIELD_LEN);
+        for field in fields {
+            let mut f = field.as_bytes().to_vec();
+            // keep the terminating nul.
+            f.resize(UTSNAME_FIELD_LEN - 1, 0);
+            f.push(0);
+            utsname.extend(f);
+        }
+        utsname
+    }
+
+    /// `struct sysinfo` of the machine, `used_ram` bytes being mapped by the program.
+    /// The machine has been up for as long as the program ran, which is no time for the clocks.
+    pub fn sysinfo(&self, long_size: PointerSizeT, used_ram: u64) -> Sysinfo {
+        let mut unit = 1u64;
+        // 32 bits longs count ram in bigger units when bytes don't fit.
+        while self.config.ram / unit > long_max(long_size) {
+            unit <<= 1;
+        }
+        Sysinfo {
+            total_ram: self.config.ram / unit,
+            free_ram: self.config.ram.saturating_sub(used_ram) / unit,
+            procs: 1,
+            mem_unit: unit as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Content of the file at `path` of `/proc`, none if the machine doesn't have it.
+    /// `/proc/self` is process `pid`, with `threads` threads and `used_ram` bytes mapped.
+    pub fn proc_file(
+        &self,
+        path: &str,
+        arch: Arch,
+        pid: u64,
+        ppid: u64,
+        threads: usize,
+        used_ram: u64,
+    ) -> Option<String> {
+        let path = path.strip_prefix("/proc/")?;
+        let path = match path.split_once('/') {
+            Some((process, file)) if process == "self" || process == pid.to_string() => {
+                format!("self/{}", file)
+            }
+            _ => path.to_string(),
+        };
+        let config = &self.config;
+        let content = match path.as_str() {
+            "sys/kernel/hostname" => format!("{}\n", config.hostname),
+            "sys/kernel/osrelease" => format!("{}\n", config.kernel_release),
+            "sys/kernel/version" => format!("{}\n", config.kernel_version),
+            "version" => format!(
+                "Linux version {} (flexemu) {}\n",
+                config.kernel_release, config.kernel_version
+            ),
+            "meminfo" => {
+                let free = config.ram.saturating_sub(used_ram) >> 10;
+                format!(
+                    "MemTotal:       {:>8} kB\nMemFree:        {:>8} kB\nMemAvailable:   {:>8} kB\n",
+                    config.ram >> 10,
+                    free,
+                    free
+                )
+            }
+            "self/status" => format!(
+                "Pid:\t{pid}\nPPid:\t{ppid}\nUid:\t{uid}\t{uid}\t{uid}\t{uid}\nGid:\t{gid}\t{gid}\t{gid}\t{gid}\nThreads:\t{threads}\n",
+                pid = pid,
+                ppid = ppid,
+                uid = config.uid,
+                gid = config.gid,
+                threads = threads
+            ),
+            "self/limits" => self.limits(arch),
+            _ => return None,
+        };
+        Some(content)
+    }
+
+    /// `/proc/self/limits`, listed in the order of the resource numbers of `arch`.
+    fn limits(&self, arch: Arch) -> String {
+        let mut limits = format!(
+            "{:<25} {:<20} {:<20} {:<10}\n",
+            "Limit", "Soft Limit", "Hard Limit", "Units"
+        );
+        let names = match arch {
+            Arch::MIPS => &MIPS_RLIMITS,
+            _ => &RLIMITS,
+        };
+        for name in names {
+            let (label, unit) = rlimit_label(name);
+            let (soft, hard) = self.rlimit(name);
+            let show = |limit: Option<u64>| match limit {
+                Some(limit) => limit.to_string(),
+                None => "unlimited".to_string(),
+            };
+            let _ = writeln!(
+                limits,
+                "{:<25} {:<20} {:<20} {:<10}",
+                label,
+                show(soft),
+                show(hard),
+                unit
+            );
+        }
+        limits
+    }
+}
+
+/// Label and unit of a resource in `/proc/self/limits`.
+fn rlimit_label(name: &str) -> (&'static str, &'static str) {
+    match name {
+        "cpu" => ("Max cpu time", "seconds"),
+        "fsize" => ("Max file size", "bytes"),
+        "data" => ("Max data size", "bytes"),
+        "stack" => ("Max stack size", "bytes"),
+        "core" => ("Max core file size", "bytes"),
+        "rss" => ("Max resident set", "bytes"),
+        "nproc" => ("Max processes", "processes"),
+        "nofile" => ("Max open files", "files"),
+        "memlock" => ("Max locked memory", "bytes"),
+        "as" => ("Max address space", "bytes"),
+        "locks" => ("Max file locks", "locks"),
+        "sigpending" => ("Max pending signals", "signals"),
+        "msgqueue" => ("Max msgqueue size", "bytes"),
+        "nice" => ("Max nice priority", ""),
+        "rtprio" => ("Max realtime priority", ""),
+        _ => ("Max realtime timeout", "us"),
+    }
+}
+
+/// Name of the arch in `struct utsname`.
+pub fn machine_name(arch: Arch, pointer_size: PointerSizeT) -> &'static str {
+    match (arch, pointer_size) {
+        (Arch::MIPS, 8) => "mips64",
+        (Arch::MIPS, _) => "mips",
+        (Arch::RISCV, 8) => "riscv64",
+        (Arch::RISCV, _) => "riscv32",
+        (Arch::ARM64, _) => "aarch64",
+        (Arch::ARM, _) => "armv7l",
+        (Arch::X86, 8) => "x86_64",
+        _ => "i686",
+    }
+}
+
+fn long_max(pointer_size: PointerSizeT) -> u64 {
+    match pointer_size {
+        8 => u64::MAX,
+        _ => u32::MAX as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::Identity;
+    use crate::config::MachineConfig;
+
+    #[test]
+    fn test_identity() {
+        let identity = Identity::new(MachineConfig {
+            hostname: "judge".to_string(),
+            ram: 8 << 30,
+            ..Default::default()
+        });
+        let utsname = identity.utsname("mips");
+        assert_eq!(utsname.len(), 6 * 65);
+        assert_eq!(&utsname[65..71], b"judge\0");
+
+        // 8GiB doesn't fit in 32 bits longs, it's counted in units of 4 bytes.
+        let info = identity.sysinfo(4, 1 << 30);
+        assert_eq!((info.total_ram, info.free_ram), (2 << 30, 7 << 28));
+        assert_eq!(info.mem_unit, 4);
+        assert_eq!(identity.sysinfo(8, 0).mem_unit, 1);
+
+        assert_eq!(Identity::rlimit_name(Arch::MIPS, 5), Some("nofile"));
+        assert_eq!(identity.rlimit("nofile"), (Some(1024), None));
+        assert_eq!(identity.rlimit("cpu"), (None, None));
+
+        let status = identity
+            .proc_file("/proc/42/status", Arch::MIPS, 42, 1, 1, 0)
+            .unwrap();
+        assert!(status.contains("Uid:\t1000\t1000\t1000\t1000\n"));
+        let limits = identity
+            .proc_file("/proc/self/limits", Arch::MIPS, 42, 1, 1, 0)
+            .unwrap();
+        assert!(limits
+            .contains("Max open files            1024                 unlimited            files"));
+        assert_eq!(
+            identity.proc_file("/proc/sys/kernel/hostname", Arch::MIPS, 42, 1, 1, 0),
+            Some("judge\n".to_string())
+        );
+        assert!(identity
+            .proc_file("/proc/1/status", Arch::MIPS, 42, 1, 1, 0)
+            .is_none());
+    }
+}
@@ -0,0 +1,90 @@
+//! Placement of the mappings created by mmap2 and mremap.
+//! Flag values are those of all linux archs but `MAP_ANONYMOUS`, which differs on mips.
+
+use unicorn_engine::unicorn_const::{Arch, MemRegion};
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_FIXED: u64 = 0x10;
+
+/// `MAP_ANONYMOUS` of `arch`.
+pub fn map_anonymous(arch: Arch) -> u64 {
+    match arch {
+        Arch::MIPS => 0x800,
+        _ => 0x20,
+    }
+}
+
+pub const MREMAP_MAYMOVE: u64 = 1;
+pub const MREMAP_FIXED: u64 = 2;
+
+/// unit of the file offset of mmap2, whatever the page size is.
+pub const MMAP2_UNIT: u64 = 4096;
+
+/// First fit of `size` bytes in `[base, limit)`, between the `mapped` regions sorted by address.
+/// Ranges released by munmap or mremap are reused, unlike a bump allocator.
+pub fn find_free(mapped: &[MemRegion], base: u64, limit: u64, size: u64) -> Option<u64> {
+    let mut addr = base;
+    for region in mapped {
+        if region.end <= addr {
+            continue;
+        }
+        if region.begin >= addr.checked_add(size)? {
+            break;
+        }
+        addr = region.end;
+    }
+    (addr.checked_add(size)? <= limit).then_some(addr)
+}
+
+/// Whether nothing is mapped in `[begin, end)`.
+pub fn is_free(mapped: &[MemRegion], begin: u64, end: u64) -> bool {
+    mapped.iter().all(|r| r.end <= begin || r.begin >= end)
+}
+
+/// Parts of the `mapped` regions which overlap `[begin, end)`.
+pub fn overlaps(mapped: &[MemRegion], begin: u64, end: u64) -> Vec<MemRegion> {
+    mapped
+        .iter()
+        .filter(|r| r.end > begin && r.begin < end)
+        .map(|r| MemRegion {
+            begin: r.begin.max(begin),
+            end: r.end.min(end),
+            perms: r.perms,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use unicorn_engine::unicorn_const::{MemRegion, Permission};
+
+    use super::{find_free, is_free, overlaps};
+
+    fn region(begin: u64, end: u64) -> MemRegion {
+        MemRegion {
+            begin,
+            end,
+            perms: Permission::ALL,
+        }
+    }
+
+    #[test]
+    fn test_find_free() {
+        let mapped = vec![
+            region(0x1000, 0x3000),
+            region(0x4000, 0x5000),
+            region(0x7000, 0x8000),
+        ];
+        assert_eq!(find_free(&mapped, 0x1000, 0x10000, 0x1000), Some(0x3000));
+        assert_eq!(find_free(&mapped, 0x1000, 0x10000, 0x2000), Some(0x5000));
+        assert_eq!(find_free(&mapped, 0x1000, 0x10000, 0x3000), Some(0x8000));
+        assert_eq!(find_free(&mapped, 0x1000, 0x9000, 0x3000), None);
+        assert!(is_free(&mapped, 0x5000, 0x7000));
+        assert!(!is_free(&mapped, 0x5000, 0x7001));
+        let parts = overlaps(&mapped, 0x2000, 0x4800)
+            .into_iter()
+            .map(|r| (r.begin, r.end))
+            .collect::<Vec<_>>();
+        assert_eq!(parts, vec![(0x2000, 0x3000), (0x4000, 0x4800)]);
+    }
+}
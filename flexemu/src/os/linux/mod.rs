@@ -1,37 +1,88 @@
 use std::{
-    cell::RefCell, collections::HashMap, env, io::Write, mem, os::unix::ffi::OsStrExt, rc::Rc,
-    str::FromStr,
+    cell::RefCell, collections::BTreeMap, env, io::Write, os::unix::ffi::OsStrExt, path::PathBuf,
+    rc::Rc, str::FromStr,
 };
 
+use anyhow::anyhow;
 use log::info;
 use unicorn_engine::{
     unicorn_const::{uc_error, Arch, MemRegion, Permission},
     RegisterARM, RegisterARM64, RegisterMIPS, RegisterRISCV, RegisterX86,
 };
 
-use file::{open, read, write};
+use file::{
+    dup, dup2, dup3, fstatat, ftruncate, getdents64, host_open_flags, mkdir, open, openat, pread,
+    pwrite, read, rmdir, unlink, winsize, write,
+};
+use goblin::container::Endian;
 
 use crate::{
     arch::{ArchInfo, ArchT},
     cc::CallingConvention,
-    engine::Engine,
-    errors::{from_raw_syscall_ret, EmulatorError},
-    loader::LoadInfo,
-    memory::Memory,
+    config::{FlexEmuConfig, MachineConfig, SandboxConfig},
+    engine::{Engine, Mach},
+    errors::EmulatorError,
+    loader::{sysroot_entry_path, sysroot_path, LoadInfo},
+    memory::{Memory, PointerSizeT},
     os::{
         linux::{
-            file::{close, fcntl, fstat, fstatat64, ioctl, lseek, lstat, readlink, stat},
-            syscall::{Rlimit, Stat64MIPS, StatMIPS, StatX8664, SysCalls, SysInfoMIPS},
+            errno::{HostErrno, HostResult},
+            fdtable::{o_nonblock, FdTable, O_CLOEXEC},
+            file::{
+                close, fcntl, fstat, lseek, lstat, placeholder, poll, readlink, stat, FileFlags,
+                AT_SYMLINK_NOFOLLOW, F_DUPFD, F_DUPFD_CLOEXEC, F_GETFL, F_SETFL,
+            },
+            handler::{
+                SyscallArgs, SyscallEntry, SyscallHandler, SyscallRegistry, SYSCALL_MAX_ARGS,
+            },
+            identity::{machine_name, rlim_infinity, Identity, RLIM64_INFINITY},
+            mmap::{
+                find_free, is_free, map_anonymous, overlaps, MAP_FIXED, MAP_SHARED, MMAP2_UNIT,
+                MREMAP_FIXED, MREMAP_MAYMOVE,
+            },
+            preimage::{
+                DirPreimageProvider, PreimageKey, PreimageOracle, PreimageProvider, Preimages,
+                MIPS_PREIMAGE_SYSCALL,
+            },
+            sandbox::{path_arg, Sandbox},
+            signal::{
+                default_action, nsig, pack_sigset, read_sigaction, read_sigset, sigmask,
+                sigset_size, sigstop, write_sigaction, DefaultAction, SigAction, Signals,
+                RT_SIGFRAME_INFO, RT_SIGFRAME_SIZE, RT_SIGFRAME_UC, SA_NODEFER, SA_RESETHAND,
+                SC_MDHI, SC_MDLO, SC_PC, SC_REGS, SIGINFO_SIZE, SIGKILL, SIGPIPE, SIGSET_SIZE,
+                SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, SIG_UNBLOCK, SI_TKILL, UC_MCONTEXT,
+                UC_SIGMASK,
+            },
+            strace::{Strace, StraceFormat, SyscallRecord},
+            structs::{convert_dirents64, Iovec, Rlimit, Stat, Timespec},
+            syscall::SysCalls,
+            thread::{
+                Threads, CLONE_CHILD_CLEARTID, CLONE_CHILD_SETTID, CLONE_PARENT_SETTID,
+                CLONE_SETTLS, CLONE_THREAD, CLONE_VM, FUTEX_CLOCK_REALTIME, FUTEX_CMP_REQUEUE,
+                FUTEX_PRIVATE_FLAG, FUTEX_REQUEUE, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAKE,
+                FUTEX_WAKE_BITSET, IO_WAIT, MAIN_TID,
+            },
         },
-        Runner,
+        RunOutcome, Runner,
     },
     rand::{RAND_SOURCE, RAND_SOURCE_LEN},
-    registers::{Registers, StackRegister},
+    registers::{RegisterState, Registers, StackRegister},
     utils::{align, align_up, read_string, Packer},
 };
 
+pub mod errno;
+mod fdtable;
 mod file;
+pub mod handler;
+pub mod identity;
+pub mod mmap;
+pub mod preimage;
+pub mod sandbox;
+pub mod signal;
+pub mod strace;
+pub mod structs;
 pub mod syscall;
+pub mod thread;
 
 #[derive(Debug, Default)]
 pub struct LinuxRunner {
@@ -40,22 +91,152 @@ pub struct LinuxRunner {
 
 #[derive(Debug, Default)]
 struct Inner {
-    sigaction_act: HashMap<u64, Vec<u64>>,
+    syscalls: SyscallRegistry,
+    sandbox: Sandbox,
+    identity: Identity,
+    strace: Option<Strace>,
+    threads: Threads,
+    signals: Signals,
+    /// address of the trampoline which signal handlers return to.
+    sigreturn_trampoline: Option<u64>,
+    /// lowest address of the mappings placed by the runner.
     mmap_address: u64,
+    /// initial program break, the heap grows from there.
+    brk_start: u64,
     brk_address: u64,
+    /// largest size of the heap, unlimited if not set.
+    heap_size: Option<u64>,
+    /// host directory of the files of the program, like the libraries of dynamically linked ones.
+    sysroot: Option<PathBuf>,
+    /// error raised by a syscall handler, which stopped the emulation.
+    error: Option<EmulatorError>,
+    /// where to continue after the engine was stopped to redirect the control flow.
+    resume_address: Option<u64>,
+    outcome: Option<RunOutcome>,
+    /// pipes and eventfds.
+    fds: FdTable,
+    /// the running thread blocked in the current syscall, which runs again once it's woken.
+    restart_syscall: bool,
+    /// pre-images fetched through the preimage oracle, if any.
+    preimages: Option<Preimages>,
 }
 
 impl LinuxRunner {
     pub fn new(mmap_address: u64) -> Self {
         let inner = Inner {
-            sigaction_act: HashMap::default(),
+            syscalls: SyscallRegistry::default(),
+            sandbox: Sandbox::default(),
+            identity: Identity::default(),
+            strace: None,
+            threads: Threads::default(),
+            signals: Signals::default(),
+            sigreturn_trampoline: None,
             mmap_address,
+            brk_start: 0,
             brk_address: 0,
+            heap_size: None,
+            sysroot: None,
+            error: None,
+            resume_address: None,
+            outcome: None,
+            fds: FdTable::default(),
+            restart_syscall: false,
+            preimages: None,
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
         }
     }
+
+    /// Runner set up according to the `[os]`, `[sandbox]`, `[preimage]` and `[machine]` sections of `config`.
+    pub fn from_config(config: &FlexEmuConfig) -> Self {
+        let runner = Self::new(config.os.mmap_address)
+            .with_heap_size(config.os.heap_size)
+            .with_sysroot(config.os.sysroot.clone())
+            .with_sandbox(config.sandbox.clone())
+            .with_machine(config.machine_config());
+        match &config.preimage.dir {
+            Some(dir) => runner.with_preimage_oracle(DirPreimageProvider::new(dir)),
+            None => runner,
+        }
+    }
+
+    /// Restrict the syscalls of the program according to `config`.
+    pub fn with_sandbox(self, config: SandboxConfig) -> Self {
+        self.inner.borrow_mut().sandbox = Sandbox::new(config);
+        self
+    }
+
+    /// Limit the heap grown by brk to `size` bytes, brk fails beyond.
+    pub fn with_heap_size(self, size: Option<u64>) -> Self {
+        self.inner.borrow_mut().heap_size = size;
+        self
+    }
+
+    /// Look up absolute paths under `sysroot` rather than on the host, in open, stat, unlink
+    /// and the other path syscalls. Files missing from the sysroot are missing for the program.
+    pub fn with_sysroot(self, sysroot: Option<PathBuf>) -> Self {
+        self.inner.borrow_mut().sysroot = sysroot;
+        self
+    }
+
+    /// Report the machine described by `config` to the program.
+    pub fn with_machine(self, config: MachineConfig) -> Self {
+        self.inner.borrow_mut().identity = Identity::new(config);
+        self
+    }
+
+    /// Trace the syscalls of the program to `out`.
+    pub fn with_strace(self, format: StraceFormat, out: Box<dyn Write>) -> Self {
+        self.inner.borrow_mut().strace = Some(Strace::new(format, out));
+        self
+    }
+
+    /// Let the program fetch pre-images from `provider`, through the preimage syscall.
+    /// The syscall is only registered for mips o32, as [`MIPS_PREIMAGE_SYSCALL`].
+    pub fn with_preimage_oracle(mut self, provider: impl PreimageProvider + 'static) -> Self {
+        let oracle = PreimageOracle::new(provider);
+        self.inner.borrow_mut().preimages = Some(oracle.preimages());
+        self.register_syscall(Arch::MIPS, MIPS_PREIMAGE_SYSCALL, "preimage", oracle);
+        self
+    }
+
+    /// Pre-images the program fetched so far, keyed by their hash, to export along with the proofs.
+    pub fn preimages(&self) -> BTreeMap<PreimageKey, Vec<u8>> {
+        self.inner
+            .borrow()
+            .preimages
+            .as_ref()
+            .map(|preimages| preimages.borrow().clone())
+            .unwrap_or_default()
+    }
+
+    /// Handle syscall `number` of `arch` with `handler`, instead of the built-in implementation if any.
+    /// The sandbox checks the syscall under `name`. It's for 32 bits programs, like o32 mips.
+    pub fn register_syscall(
+        &mut self,
+        arch: Arch,
+        number: u64,
+        name: impl Into<String>,
+        handler: impl SyscallHandler + 'static,
+    ) {
+        self.register_syscall_for(arch, 4, number, name, handler)
+    }
+
+    /// Same as `register_syscall`, for programs with `pointer_size` pointers.
+    pub fn register_syscall_for(
+        &mut self,
+        arch: Arch,
+        pointer_size: PointerSizeT,
+        number: u64,
+        name: impl Into<String>,
+        handler: impl SyscallHandler + 'static,
+    ) {
+        self.inner
+            .borrow_mut()
+            .syscalls
+            .register_for(arch, pointer_size, number, name, handler);
+    }
 }
 
 impl Runner for LinuxRunner {
@@ -64,7 +245,22 @@ impl Runner for LinuxRunner {
         core: &mut Engine<'a, A>,
         load_info: LoadInfo,
     ) -> Result<(), EmulatorError> {
-        self.inner.borrow_mut().brk_address = load_info.brk_address;
+        {
+            // the heap starts on a page boundary, like it does on linux.
+            let mut inner = self.inner.borrow_mut();
+            inner.brk_start = align_up(load_info.brk_address, core.pagesize());
+            inner.brk_address = inner.brk_start;
+            inner.signals = Signals::new(core.get_arch());
+            // the loader may move it, like for the heap of Cannon states.
+            inner.mmap_address = load_info.mmap_address;
+        }
+
+        if core.get_arch() == Arch::RISCV {
+            // linux starts programs with the fpu on, unicorn with it off.
+            const MSTATUS_FS_INITIAL: u64 = 0x2000;
+            let mstatus = core.reg_read(RegisterRISCV::MSTATUS)?;
+            core.reg_write(RegisterRISCV::MSTATUS, mstatus | MSTATUS_FS_INITIAL)?;
+        }
 
         core.add_intr_hook({
             let inner = self.inner.clone();
@@ -74,6 +270,37 @@ impl Runner for LinuxRunner {
         })?;
         Ok(())
     }
+
+    fn take_error(&mut self) -> Option<EmulatorError> {
+        self.inner.borrow_mut().error.take()
+    }
+
+    fn take_resume_address(&mut self) -> Option<u64> {
+        self.inner.borrow_mut().resume_address.take()
+    }
+
+    fn outcome(&self) -> Option<RunOutcome> {
+        self.inner.borrow().outcome
+    }
+
+    fn time_slice(&self, steps: u64) -> Option<usize> {
+        self.inner
+            .borrow()
+            .threads
+            .time_slice(steps)
+            .map(|slice| slice as usize)
+    }
+
+    fn on_slice_end<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+    ) -> Result<(), EmulatorError> {
+        self.inner.borrow_mut().preempt(core)
+    }
+
+    fn multithreaded(&self) -> bool {
+        self.inner.borrow().threads.spawned()
+    }
 }
 
 impl Inner {
@@ -84,280 +311,571 @@ impl Inner {
             return;
         }
 
-        let syscall_no = get_syscall(arch, core).unwrap();
-        let call = syscall::SYSCALL
-            .get(&(core.get_arch() as u8))
-            .and_then(|v| v.get(&syscall_no));
-        match call {
-            None => {
-                unimplemented!("no such syscall {} for {:?}", syscall_no, arch);
+        if let Err(e) = self.dispatch_syscall(core) {
+            log::error!("{}", e);
+            self.error = Some(e);
+            // the error is already recorded, nothing more to do if the engine refuses to stop.
+            if let Err(e) = core.emu_stop() {
+                log::error!("failed to stop emulation: {:?}", e);
             }
-            Some(call) => match SysCalls::from_str(call.as_str()) {
-                Ok(c) => {
-                    self.handle_syscall(core, c).unwrap();
-                }
-                Err(_e) => {
-                    unimplemented!("syscall {} for {:?}", syscall_no, arch);
-                }
-            },
         }
     }
 
+    fn dispatch_syscall<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+    ) -> Result<(), EmulatorError> {
+        let arch = core.get_arch();
+        let syscall_no = get_syscall(arch, core)?;
+        let pc = core.pc()?;
+        let cc = core.get_data().env().cc();
+        let mut raw = [0; SYSCALL_MAX_ARGS];
+        for (i, arg) in raw.iter_mut().enumerate() {
+            *arg = cc.get_raw_param(core, i as u64, None)?;
+        }
+        let args = SyscallArgs::new(raw);
+
+        let pointer_size = core.pointer_size();
+        let table_name = syscall::SYSCALL
+            .get(&(arch as u8, pointer_size))
+            .and_then(|v| v.get(&syscall_no))
+            .cloned();
+        let entry = self
+            .syscalls
+            .get_for(arch, pointer_size, syscall_no)
+            .cloned();
+        let name = match &entry {
+            Some(entry) => entry.name().to_string(),
+            None => table_name.clone().unwrap_or_else(|| syscall_no.to_string()),
+        };
+        let result = match (entry, table_name) {
+            (Some(entry), _) => self.handle_syscall(core, entry, &args),
+            (None, None) => Err(anyhow!("no such syscall {} for {:?}", syscall_no, arch).into()),
+            (None, Some(_)) => {
+                Err(anyhow!("syscall {} not supported for {:?}", syscall_no, arch).into())
+            }
+        };
+        result.map_err(|e| EmulatorError::SyscallError {
+            name,
+            args: args.raw().to_vec(),
+            pc,
+            location: core.get_data().symbolizer().locate(pc).map(Box::new),
+            source: Box::new(e),
+        })
+    }
+
     fn handle_syscall<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
-        syscall: SysCalls,
+        entry: SyscallEntry,
+        args: &SyscallArgs,
     ) -> Result<(), EmulatorError> {
-        assert_eq!(core.get_arch(), Arch::MIPS, "only support mips for now");
-        info!("handle syscall, {:?}", syscall);
+        info!("handle syscall, {:?}", entry);
         let cc = core.get_data().env().cc();
+        let started = match self.strace {
+            Some(_) => Some((
+                core.get_data().state.steps,
+                self.threads.current_tid(),
+                core.pc()?,
+            )),
+            None => None,
+        };
+        let retvalue = match self.check_sandbox(core, entry.name(), args)? {
+            Some(errno) => -errno,
+            None => match &entry {
+                SyscallEntry::Custom { handler, .. } => handler.borrow_mut().handle(core, args)?,
+                SyscallEntry::Builtin(SysCalls::SIGRETURN | SysCalls::RT_SIGRETURN) => {
+                    // only mips o32 frames are ever set up, see `setup_signal_frame`.
+                    if core.get_arch() != Arch::MIPS {
+                        return Err(anyhow!("no signal frame to return from").into());
+                    }
+                    // registers are restored from the signal frame, there is no return value to set.
+                    let resume_address = self.sigreturn(core)?;
+                    self.trace_syscall(core, started, &entry, args, None)?;
+                    return self.deliver_signals(core, resume_address);
+                }
+                SyscallEntry::Builtin(syscall) => self.builtin_syscall(core, *syscall, args)?,
+            },
+        };
+        if std::mem::take(&mut self.restart_syscall) {
+            // registers are left as they are, the syscall instruction runs again once woken.
+            self.threads.take_switch_pending();
+            let pc = core.pc()?;
+            let next_pc = self.switch_thread(core, pc)?;
+            return self.deliver_signals(core, next_pc);
+        }
+        self.trace_syscall(core, started, &entry, args, Some(retvalue))?;
+
+        set_syscall_return(&cc, core, retvalue)?;
+
+        let mut next_pc = syscall_return_address(core)?;
+        if self.threads.take_switch_pending() && self.outcome.is_none() {
+            next_pc = self.switch_thread(core, next_pc)?;
+        } else if self.threads.take_slice_changed() {
+            // restart the engine, so that it stops at the end of the time slice.
+            self.resume_at(core, next_pc)?;
+        }
+        self.deliver_signals(core, next_pc)
+    }
+
+    /// Write the record of a syscall started at `(step, tid, pc)` to the strace output, if any.
+    fn trace_syscall<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        started: Option<(u64, u64, u64)>,
+        entry: &SyscallEntry,
+        args: &SyscallArgs,
+        ret: Option<i64>,
+    ) -> Result<(), EmulatorError> {
+        if let (Some(strace), Some(started)) = (&mut self.strace, started) {
+            let record = SyscallRecord::new(core, started, entry.name(), args, ret);
+            strace.write(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Run a syscall implemented by the runner, return its raw result.
+    fn builtin_syscall<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        syscall: SysCalls,
+        args: &SyscallArgs,
+    ) -> Result<i64, EmulatorError> {
+        if !matches!(core.get_arch(), Arch::MIPS | Arch::RISCV) {
+            return Err(anyhow!("only support mips and riscv for now").into());
+        }
+        // arguments are decoded as the parameter types of the handlers.
         let retvalue = match syscall {
-            SysCalls::SET_THREAD_AREA => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.set_thread_area(core, p0)?
-            }
-            SysCalls::SET_TID_ADDRESS => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.set_tid_address(core, p0)?
-            }
-            SysCalls::POLL => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.poll(core, p0, p1, p2)?
-            }
+            SysCalls::SET_THREAD_AREA => self.set_thread_area(core, args.get(0))?,
+            SysCalls::SET_TID_ADDRESS => self.set_tid_address(core, args.get(0))?,
+            SysCalls::POLL => self.poll(core, args.get(0), args.get(1), args.get(2))?,
             SysCalls::RT_SIGACTION => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.rt_sigaction(core, p0, p1, p2)?
+                self.rt_sigaction(core, args.get(0), args.get(1), args.get(2))?
             }
             SysCalls::RT_SIGPROCMASK => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                self.rt_sigprocmask(core, p0, p1, p2, p3)?
-            }
-            SysCalls::SIGALTSTACK => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.sigaltstack(core, p0, p1)?
-            }
-            SysCalls::SIGRETURN => self.sigreturn(core)?,
-            SysCalls::RT_SIGRETURN => self.sigreturn(core)?,
-            SysCalls::BRK => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.brk(core, p0)?
-            }
-            SysCalls::EXIT_GROUP => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.exit_group(core, p0)?
-            }
-            SysCalls::GETRANDOM => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.get_random(core, p0, p1)?
+                self.rt_sigprocmask(core, args.get(0), args.get(1), args.get(2), args.get(3))?
             }
+            SysCalls::SIGALTSTACK => self.sigaltstack(core, args.get(0), args.get(1))?,
+            SysCalls::BRK => self.brk(core, args.get(0))?,
+            SysCalls::EXIT_GROUP => self.exit_group(core, args.get(0))?,
+            SysCalls::GETRANDOM => self.get_random(core, args.get(0), args.get(1))?,
             SysCalls::SCHED_GETAFFINITY => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.sched_getaffinity(core, p0, p1, p2)?
+                self.sched_getaffinity(core, args.get(0), args.get(1), args.get(2))?
             }
             SysCalls::SCHED_YIELD => self.sched_yield(core)?,
-            SysCalls::TKILL => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.tkill(core, p0, p1, p2)?
-            }
-            SysCalls::FUTEX => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                let p4 = cc.get_raw_param(core, 4, None)?;
-                let p5 = cc.get_raw_param(core, 5, None)?;
-                self.futex(core, p0, p1, p2, p3, p4, p5)?
-            }
-            SysCalls::EXIT => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.exit(core, p0)?
-            }
+            SysCalls::TKILL => self.tkill(core, args.get(0), args.get(1))?,
+            SysCalls::FUTEX | SysCalls::FUTEX_TIME64 => self.futex(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                args.get(3),
+                args.get(4),
+                args.get(5),
+            )?,
+            SysCalls::EXIT => self.exit(core, args.get(0))?,
+            // mips passes tls before ctid, the other archs after it.
+            SysCalls::CLONE => match core.get_arch() {
+                Arch::MIPS => self.clone(
+                    core,
+                    args.get(0),
+                    args.get(1),
+                    args.get(2),
+                    args.get(3),
+                    args.get(4),
+                )?,
+                _ => self.clone(
+                    core,
+                    args.get(0),
+                    args.get(1),
+                    args.get(2),
+                    args.get(4),
+                    args.get(3),
+                )?,
+            },
             SysCalls::CLOCK_GETTIME => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.clock_gettime(core, p0, p1)?
-            }
-            SysCalls::MMAP2 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                let p4 = cc.get_raw_param(core, 4, None)?;
-                let p5 = cc.get_raw_param(core, 5, None)?;
-                self.mmap2(core, p0, p1, p2, p3, p4, p5, 2)?
-            }
-            SysCalls::MREMAP => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                let p4 = cc.get_raw_param(core, 4, None)?;
-                self.mremap(core, p0, p1, p2, p3, p4)?
-            }
-            SysCalls::MUNMAP => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.munmap(core, p0, p1)?
-            }
-            SysCalls::MPROTECT => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.mprotect(core, p0, p1, p2)?
-            }
-            SysCalls::MADVISE => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.madivse(core, p0, p1, p2)?
-            }
-            SysCalls::GETRLIMIT => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.getrlimit(core, p0, p1)?
-            }
-            SysCalls::SYSINFO => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.sysinfo(core, p0)?
-            }
-            SysCalls::SET_ROBUST_LIST => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.set_robust_list(core, p0, p1)?
+                self.clock_gettime(core, args.get(0), args.get(1), core.pointer_size())?
             }
+            SysCalls::CLOCK_GETTIME64 => self.clock_gettime(core, args.get(0), args.get(1), 8)?,
+            SysCalls::MMAP => self.mmap(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                args.get(3),
+                args.get(4),
+                args.get(5),
+            )?,
+            SysCalls::MMAP2 => self.mmap2(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                args.get(3),
+                args.get(4),
+                args.get(5),
+                2,
+            )?,
+            SysCalls::MREMAP => self.mremap(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                args.get(3),
+                args.get(4),
+            )?,
+            SysCalls::MUNMAP => self.munmap(core, args.get(0), args.get(1))?,
+            SysCalls::MPROTECT => self.mprotect(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::MADVISE => self.madivse(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::GETRLIMIT => self.getrlimit(core, args.get(0), args.get(1))?,
+            SysCalls::SYSINFO => self.sysinfo(core, args.get(0))?,
+            SysCalls::SET_ROBUST_LIST => self.set_robust_list(core, args.get(0), args.get(1))?,
             SysCalls::PRLIMIT64 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                self.prlimit64(core, p0, p1, p2, p3)?
-            }
-            SysCalls::OPEN => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.open(core, p0, p1, p2)?
-            }
-            SysCalls::READ => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.read(core, p0, p1, p2)?
-            }
-            SysCalls::WRITE => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.write(core, p0, p1, p2)?
-            }
-            SysCalls::WRITEV => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.writev(core, p0, p1, p2)?
-            }
-            SysCalls::CLOSE => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                self.close(core, p0)?
-            }
-            SysCalls::LSEEK => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.lseek(core, p0, p1, p2)?
-            }
-            SysCalls::_LLSEEK => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                let p4 = cc.get_raw_param(core, 4, None)?;
-                self._llseek(core, p0, p1, p2, p3, p4)?
-            }
-            SysCalls::FCNTL => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.fcntl(core, p0, p1, p2)?
-            }
-            SysCalls::FCNTL64 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.fcntl64(core, p0, p1, p2)?
-            }
-            SysCalls::READLINK => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.readlink(core, p0, p1, p2)?
-            }
-            SysCalls::STAT => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.stat(core, p0, p1)?
-            }
-            SysCalls::STAT64 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.stat64(core, p0, p1)?
-            }
-            SysCalls::FSTAT => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.fstat(core, p0, p1)?
-            }
-            SysCalls::FSTAT64 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.fstat64(core, p0, p1)?
-            }
-            SysCalls::LSTAT64 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.lstat64(core, p0, p1)?
+                self.prlimit64(core, args.get(0), args.get(1), args.get(2), args.get(3))?
             }
+            SysCalls::OPEN => self.open(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::READ => self.read(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::WRITE => self.write(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::WRITEV => self.writev(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::CLOSE => self.close(core, args.get(0))?,
+            SysCalls::LSEEK => self.lseek(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::_LLSEEK => self._llseek(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                args.get(3),
+                args.get(4),
+            )?,
+            SysCalls::FCNTL => self.fcntl(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::FCNTL64 => self.fcntl64(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::READLINK => self.readlink(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::STAT => self.stat(core, args.get(0), args.get(1))?,
+            SysCalls::LSTAT => self.lstat(core, args.get(0), args.get(1))?,
+            SysCalls::STAT64 => self.stat64(core, args.get(0), args.get(1))?,
+            SysCalls::FSTAT => self.fstat(core, args.get(0), args.get(1))?,
+            SysCalls::FSTAT64 => self.fstat64(core, args.get(0), args.get(1))?,
+            SysCalls::LSTAT64 => self.lstat64(core, args.get(0), args.get(1))?,
             SysCalls::FSTATAT64 => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                let p3 = cc.get_raw_param(core, 3, None)?;
-                self.fstatat64(core, p0, p1, p2, p3)?
+                self.fstatat64(core, args.get(0), args.get(1), args.get(2), args.get(3))?
+            }
+            SysCalls::NEWFSTATAT => {
+                self.newfstatat(core, args.get(0), args.get(1), args.get(2), args.get(3))?
+            }
+            SysCalls::GETCWD => self.getcwd(core, args.get(0), args.get(1))?,
+            SysCalls::IOCTL => self.ioctl(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::READV => self.readv(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::PREAD64 => self.pread64(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                arg_u64(core, args, 3),
+            )?,
+            SysCalls::PWRITE64 => self.pwrite64(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                arg_u64(core, args, 3),
+            )?,
+            SysCalls::OPENAT => {
+                self.openat(core, args.get(0), args.get(1), args.get(2), args.get(3))?
+            }
+            SysCalls::GETDENTS64 => self.getdents64(core, args.get(0), args.get(1), args.get(2))?,
+            SysCalls::STATX => self.statx(
+                core,
+                args.get(0),
+                args.get(1),
+                args.get(2),
+                args.get(3),
+                args.get(4),
+            )?,
+            SysCalls::FTRUNCATE => self.ftruncate(core, args.get(0), args.get(1))?,
+            SysCalls::FTRUNCATE64 => self.ftruncate(core, args.get(0), arg_u64(core, args, 1))?,
+            SysCalls::UNLINK => self.path_syscall(core, "unlink", args.get(0), unlink)?,
+            SysCalls::RMDIR => self.path_syscall(core, "rmdir", args.get(0), rmdir)?,
+            SysCalls::MKDIR => {
+                self.path_syscall(core, "mkdir", args.get(0), |path| mkdir(path, args.get(1)))?
             }
-            SysCalls::GETCWD => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                self.getcwd(core, p0, p1)?
+            SysCalls::PIPE2 => self.pipe2(core, args.get(0), args.get(1))?,
+            SysCalls::DUP => self.dup(core, args.get(0))?,
+            SysCalls::DUP2 => self.dup2(core, args.get(0), args.get(1), None)?,
+            SysCalls::DUP3 => self.dup2(core, args.get(0), args.get(1), Some(args.get(2)))?,
+            SysCalls::PPOLL => self.ppoll(core, args.get(0), args.get(1), args.get(2), false)?,
+            SysCalls::PPOLL_TIME64 => {
+                self.ppoll(core, args.get(0), args.get(1), args.get(2), true)?
             }
-            SysCalls::IOCTL => {
-                let p0 = cc.get_raw_param(core, 0, None)?;
-                let p1 = cc.get_raw_param(core, 1, None)?;
-                let p2 = cc.get_raw_param(core, 2, None)?;
-                self.ioctl(core, p0, p1, p2)?
+            SysCalls::EVENTFD => self.eventfd2(core, args.get(0), 0)?,
+            SysCalls::EVENTFD2 => self.eventfd2(core, args.get(0), args.get(1))?,
+            SysCalls::NANOSLEEP => self.nanosleep(core, args.get(0))?,
+            SysCalls::CLOCK_NANOSLEEP | SysCalls::CLOCK_NANOSLEEP_TIME64 => {
+                self.nanosleep(core, args.get(2))?
             }
+            SysCalls::GETPID => MAIN_TID as i64,
+            SysCalls::GETPPID => PARENT_PID as i64,
+            SysCalls::GETTID => self.threads.current_tid() as i64,
+            SysCalls::GETUID | SysCalls::GETEUID => self.identity.uid() as i64,
+            SysCalls::GETGID | SysCalls::GETEGID => self.identity.gid() as i64,
+            SysCalls::UNAME => self.uname(core, args.get(0))?,
 
             _ => {
-                panic!("please handle syscall: {:?}", syscall);
+                return Err(anyhow!("please handle syscall: {:?}", syscall).into());
             }
         };
+        Ok(retvalue)
+    }
+
+    /// Check the syscall against the sandbox policy, return the errno to fail it with if it's denied.
+    fn check_sandbox<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        name: &str,
+        args: &SyscallArgs,
+    ) -> Result<Option<i64>, EmulatorError> {
+        let allowed = self
+            .sandbox
+            .check_syscall(core.arch(), name, args.raw(), &self.fds);
+        let (errno, reason) = match allowed {
+            Err(reason) => (EPERM, reason),
+            Ok(()) => match SysCalls::from_str(name)
+                .ok()
+                .and_then(|s| path_arg(s).map(|i| (s, i)))
+            {
+                None => return Ok(None),
+                Some((syscall, i)) => {
+                    let path = read_string(core, args.get(i), b"\x00")?;
+                    let dirfd = (path_arg(syscall) == Some(1)).then_some(args.get(0));
+                    // the file the syscall accesses is checked, the one in the sysroot if any.
+                    let path = match self.host_path(path, follows_symlink(syscall, args)) {
+                        Some(path) => path,
+                        // the syscall fails without reaching the host.
+                        None => return Ok(None),
+                    };
+                    match self.sandbox.check_path(&path, dirfd) {
+                        Ok(()) => return Ok(None),
+                        Err(reason) => (EACCES, reason),
+                    }
+                }
+            },
+        };
+        log::warn!(
+            target: "flexemu::sandbox",
+            "denied {}({:?}) at pc {}, thread {}: {}",
+            name,
+            args,
+            core.get_data().symbolizer().describe(core.pc()?),
+            self.threads.current_tid(),
+            reason
+        );
+        Ok(Some(errno))
+    }
+
+    /// Switch to another thread at the end of the time slice of the running one.
+    fn preempt<'a, A: ArchT>(&mut self, core: &mut Engine<'a, A>) -> Result<(), EmulatorError> {
+        if !self.threads.should_preempt(core.get_data().state.steps) {
+            return Ok(());
+        }
+        let pc = core.pc()?;
+        // never split a branch from its delay slot, the switch is retried after the next step.
+        if in_delay_slot(core, pc)? {
+            return Ok(());
+        }
+        self.switch_thread(core, pc)?;
+        Ok(())
+    }
+
+    /// Save the context of the running thread which continues at `resume_address`,
+    /// and restore the context of the next thread to run. Return the address the next thread continues at.
+    fn switch_thread<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        resume_address: u64,
+    ) -> Result<u64, EmulatorError> {
+        let steps = core.get_data().state.steps;
+        let blocked = self.signals.blocked();
+        // the running thread is gone if it just exited.
+        if let Some(thread) = self.threads.current() {
+            let mut context = save_context(core)?;
+            context.insert(A::PC, resume_address);
+            thread.context = context;
+            thread.blocked = blocked;
+        }
+        let (tid, timed_out) = self.threads.switch(steps).ok_or_else(|| {
+            anyhow!("deadlock, all threads are waiting on futexes, pipes or eventfds")
+        })?;
+        let thread = self
+            .threads
+            .get_mut(tid)
+            .ok_or_else(|| anyhow!("no such thread {}", tid))?;
+        let mut pc = thread.context[&A::PC];
+        let blocked = thread.blocked;
+        restore_context(core, &thread.context)?;
+        let cc = core.get_data().env().cc();
+        match timed_out {
+            // a poll timed out without any ready fd, the thread is still at its syscall instruction.
+            Some(wait) if wait.addr == IO_WAIT => {
+                set_syscall_return(&cc, core, 0)?;
+                pc += 4;
+            }
+            Some(_) => {
+                let errno = HostErrno(libc::ETIMEDOUT).to_guest(core.arch());
+                cc.set_syscall_error(core, errno as u64)?
+            }
+            None => {}
+        }
+        self.signals.set_blocked(blocked);
+        log::debug!("switch to thread {} at {:#x}, step {}", tid, pc, steps);
+        self.resume_at(core, pc)?;
+        Ok(pc)
+    }
+
+    /// Stop the engine, and continue the emulation at `address`.
+    fn resume_at<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        address: u64,
+    ) -> Result<(), uc_error> {
+        self.resume_address = Some(address);
+        core.emu_stop()
+    }
+
+    /// Deliver pending signals before returning to the program at `resume_address`.
+    /// At most one handler is set up, the remaining signals are delivered when it returns.
+    fn deliver_signals<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        resume_address: u64,
+    ) -> Result<(), EmulatorError> {
+        if self.outcome.is_some() {
+            return Ok(());
+        }
+        while let Some(sig) = self.signals.take_deliverable() {
+            let action = self.signals.action(sig);
+            match action.handler {
+                SIG_IGN => {
+                    log::debug!("signal {} ignored", sig);
+                }
+                SIG_DFL => {
+                    if default_action(core.arch(), sig) == DefaultAction::Terminate {
+                        log::debug!("terminated by signal {}", sig);
+                        self.outcome = Some(RunOutcome::Signaled(sig));
+                        core.emu_stop()?;
+                        return Ok(());
+                    }
+                }
+                handler => {
+                    self.setup_signal_frame(core, sig, &action, resume_address)?;
+                    if action.flags & SA_RESETHAND != 0 {
+                        self.signals.set_action(sig, SigAction::default());
+                    }
+                    self.resume_at(core, handler)?;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Push a `struct rt_sigframe` saving the interrupted context, and set up registers to run the handler.
+    fn setup_signal_frame<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        sig: u64,
+        action: &SigAction,
+        resume_address: u64,
+    ) -> Result<(), EmulatorError> {
+        if core.get_arch() != Arch::MIPS || core.pointer_size() != 4 {
+            return Err(anyhow!("signal handlers are only supported for mips o32").into());
+        }
+        let trampoline = self.sigreturn_trampoline(core)?;
+        let frame = align(core.sp()? - RT_SIGFRAME_SIZE, 8u64);
+        let info = frame + RT_SIGFRAME_INFO;
+        let uc = frame + RT_SIGFRAME_UC;
+        let sc = uc + UC_MCONTEXT;
+
+        let packer = Packer::new(core.endian(), 4);
+        let mut siginfo = vec![0u8; SIGINFO_SIZE as usize];
+        siginfo[0..4].copy_from_slice(&packer.pack(sig));
+        siginfo[4..8].copy_from_slice(&packer.pack(SI_TKILL as u32 as u64));
+        siginfo[12..16].copy_from_slice(&packer.pack(MAIN_TID));
+        Memory::write(core, info, siginfo)?;
 
-        cc.set_return_value(core, retvalue as u64)?;
+        let reg_packer = Packer::new(core.endian(), 8);
+        Memory::write(core, uc, vec![0u8; (UC_MCONTEXT + SC_REGS) as usize])?;
+        Memory::write(core, sc + SC_PC, reg_packer.pack(resume_address))?;
+        let mut regs = Vec::with_capacity(32 * 8);
+        for i in 0..32 {
+            regs.extend(reg_packer.pack(Registers::read(core, RegisterMIPS::ZERO as i32 + i)?));
+        }
+        Memory::write(core, sc + SC_REGS, regs)?;
+        Memory::write(
+            core,
+            sc + SC_MDHI,
+            reg_packer.pack(Registers::read(core, RegisterMIPS::HI)?),
+        )?;
+        Memory::write(
+            core,
+            sc + SC_MDLO,
+            reg_packer.pack(Registers::read(core, RegisterMIPS::LO)?),
+        )?;
+        let mut saved_mask = pack_sigset(core, self.signals.blocked());
+        saved_mask.resize(SIGSET_SIZE as usize, 0);
+        Memory::write(core, uc + UC_SIGMASK, saved_mask)?;
+
+        let mut blocked = self.signals.blocked() | action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            blocked |= sigmask(sig);
+        }
+        self.signals.set_blocked(blocked);
 
+        log::debug!(
+            "deliver signal {} to handler {:#x}, frame: {:#x}, resume: {:#x}",
+            sig,
+            action.handler,
+            frame,
+            resume_address
+        );
+        core.set_sp(frame)?;
+        Registers::write(core, RegisterMIPS::A0, sig)?;
+        Registers::write(core, RegisterMIPS::A1, info)?;
+        Registers::write(core, RegisterMIPS::A2, uc)?;
+        Registers::write(core, RegisterMIPS::RA, trampoline)?;
+        // pic code expects t9 to hold the address of the called function.
+        Registers::write(core, RegisterMIPS::T9, action.handler)?;
         Ok(())
     }
+
+    /// Map the code handlers return to: `li v0, __NR_rt_sigreturn; syscall`.
+    /// There is no vdso, and mips libcs don't provide a sa_restorer.
+    fn sigreturn_trampoline<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+    ) -> Result<u64, EmulatorError> {
+        if let Some(addr) = self.sigreturn_trampoline {
+            return Ok(addr);
+        }
+        let page_size = core.pagesize();
+        let addr = self.find_free(core, page_size)?.ok_or(uc_error::NOMEM)?;
+        Memory::mem_map(
+            core,
+            MemRegion {
+                begin: addr,
+                end: addr + page_size,
+                perms: Permission::READ | Permission::EXEC,
+            },
+            Some("[sigreturn]".to_string()),
+        )?;
+
+        const LI_V0_RT_SIGRETURN: u64 = 0x24021061;
+        const SYSCALL: u64 = 0x0000000c;
+        let packer = Packer::new(core.endian(), 4);
+        let mut code = packer.pack(LI_V0_RT_SIGRETURN);
+        code.extend(packer.pack(SYSCALL));
+        Memory::write(core, addr, code)?;
+        self.sigreturn_trampoline = Some(addr);
+        Ok(addr)
+    }
 }
 
 // pub trait LinuxSyscallHandler {
@@ -415,9 +933,11 @@ impl Inner {
         _core: &mut Engine<'a, A>,
         tidptr: u64,
     ) -> Result<i64, uc_error> {
-        // TODO: implement thread management
         log::debug!("set_tid_address({})", tidptr);
-        Ok(42)
+        if let Some(thread) = self.threads.current() {
+            thread.clear_child_tid = tidptr;
+        }
+        Ok(self.threads.current_tid() as i64)
     }
 
     fn poll<'a, A: ArchT>(
@@ -425,17 +945,187 @@ impl Inner {
         core: &mut Engine<'a, A>,
         fds: u64,
         nfds: u64,
-        timeout: u64,
-    ) -> Result<i64, uc_error> {
-        log::debug!(
-            "poll({}, {}, {}), pc: {}, sp: {}",
-            fds,
-            nfds,
-            timeout,
-            core.pc()?,
-            core.sp()?
+        timeout: i32,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("poll({}, {}, {}) pc: {}", fds, nfds, timeout, core.pc()?);
+        // timeout is an int of milliseconds, negative to wait forever.
+        self.poll_fds(core, fds, nfds, timeout)
+    }
+
+    /// The signal mask of ppoll is ignored, signals are only delivered between syscalls anyway.
+    fn ppoll<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fds: u64,
+        nfds: u64,
+        tsp: u64,
+        time64: bool,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("ppoll({}, {}, {}) pc: {}", fds, nfds, tsp, core.pc()?);
+        let timeout = if tsp == 0 {
+            -1
+        } else {
+            let long_size = if time64 { 8 } else { core.pointer_size() };
+            let data = Memory::read(core, tsp, Timespec::size(long_size))?;
+            match Timespec::unpack(&data, core.endian(), long_size) {
+                Some(ts) if ts.sec >= 0 && (0..1_000_000_000).contains(&ts.nsec) => {
+                    let ms = ts.sec.saturating_mul(1000) + (ts.nsec + 999_999) / 1_000_000;
+                    ms.min(i32::MAX as i64) as i32
+                }
+                _ => return Ok(-EINVAL),
+            }
+        };
+        self.poll_fds(core, fds, nfds, timeout)
+    }
+
+    /// Poll the `nfds` pollfds at `fds`, for at most `timeout` milliseconds, or forever if negative.
+    /// Pipes and eventfds are polled in the emulator, a thread waiting on them is blocked until one
+    /// of them changes. Host fds are polled on the host. Pipes and eventfds only change when another
+    /// thread runs, so a thread waiting on both yields and polls again once scheduled back, and waits
+    /// on the host fds alone if no other thread can run.
+    fn poll_fds<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fds: u64,
+        nfds: u64,
+        timeout: i32,
+    ) -> Result<i64, EmulatorError> {
+        // struct pollfd is an int fd, and short events and revents.
+        const POLLFD_SIZE: u64 = 8;
+        if nfds > u16::MAX as u64 {
+            return Ok(-EINVAL);
+        }
+        let data = Memory::read(core, fds, (nfds * POLLFD_SIZE) as usize)?;
+        let packer = Packer::new(core.endian(), 4);
+        let short = Packer::new(core.endian(), 2);
+        let polled: Vec<(i32, u64)> = data
+            .chunks(POLLFD_SIZE as usize)
+            .map(|c| {
+                let fd = packer.unpack(c[..4].to_vec()) as i32;
+                (fd, short.unpack(c[4..6].to_vec()))
+            })
+            .collect();
+        let mut revents = vec![0; polled.len()];
+        let mut host_fds = vec![];
+        let mut objects = false;
+        for (i, (fd, events)) in polled.iter().enumerate() {
+            // negative fds are ignored.
+            if *fd < 0 {
+                continue;
+            }
+            match self.fds.poll(*fd as u64, *events) {
+                Some(ready) => {
+                    revents[i] = ready;
+                    objects = true;
+                }
+                None => host_fds.push((i, *fd as u64, *events)),
+            }
+        }
+        let objects_ready = revents.iter().any(|r| *r != 0);
+        let others_runnable = self.threads.others_runnable();
+        if !host_fds.is_empty() {
+            let host_timeout = if objects_ready || (objects && others_runnable) {
+                0
+            } else {
+                timeout
+            };
+            let list: Vec<(u64, u64)> = host_fds.iter().map(|(_, fd, ev)| (*fd, *ev)).collect();
+            match poll(&list, host_timeout) {
+                Ok(ready) => {
+                    for ((i, _, _), ready) in host_fds.iter().zip(ready) {
+                        revents[*i] = ready;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("failed to poll ({}, {}, {}): {}", fds, nfds, timeout, e);
+                    return Ok(e.guest_ret(core.arch()));
+                }
+            }
+        }
+        let ready = revents.iter().filter(|r| **r != 0).count() as i64;
+        if ready == 0 && objects && timeout != 0 {
+            if host_fds.is_empty() {
+                return Ok(self.block_io(timeout > 0));
+            }
+            if others_runnable {
+                // the host fds are polled again when the thread is scheduled back.
+                self.threads.yield_current();
+                self.restart_syscall = true;
+                return Ok(0);
+            }
+        }
+        for (i, r) in revents.iter().enumerate() {
+            Memory::write(core, fds + i as u64 * POLLFD_SIZE + 6, short.pack(*r))?;
+        }
+        Ok(ready)
+    }
+
+    /// Block the running thread until a pipe or an eventfd changes, its syscall runs again then.
+    /// A timed wait gives up when no other thread can run, the syscall returns 0.
+    fn block_io(&mut self, timed: bool) -> i64 {
+        self.threads.wait_io(timed);
+        self.restart_syscall = true;
+        0
+    }
+
+    /// Read at most `len` bytes of the pipe or eventfd `fd`, blocking if it's empty.
+    /// Err is the return of the syscall.
+    fn read_object(&mut self, steps: u64, fd: u64, len: usize) -> Result<Vec<u8>, i64> {
+        match self.fds.read(fd, len) {
+            Ok(data) => {
+                self.threads.wake_io(steps);
+                Ok(data)
+            }
+            Err(EAGAIN) if !self.fds.nonblock(fd) => Err(self.block_io(false)),
+            Err(errno) => Err(-errno),
+        }
+    }
+
+    /// Write `data` to the pipe or eventfd `fd`, blocking if it's full.
+    /// Err is the return of the syscall.
+    fn write_object(&mut self, steps: u64, fd: u64, data: &[u8]) -> Result<usize, i64> {
+        match self.fds.write(fd, data) {
+            Ok(n) => {
+                self.threads.wake_io(steps);
+                Ok(n)
+            }
+            Err(EAGAIN) if !self.fds.nonblock(fd) => Err(self.block_io(false)),
+            Err(EPIPE) => {
+                self.signals.raise(SIGPIPE);
+                Err(-EPIPE)
+            }
+            Err(errno) => Err(-errno),
+        }
+    }
+
+    /// eventfd is eventfd2 without flags.
+    fn eventfd2<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        initval: u64,
+        flags: u64,
+    ) -> Result<i64, EmulatorError> {
+        const EFD_SEMAPHORE: u64 = 1;
+        log::debug!("eventfd2({}, {:#x}) pc: {}", initval, flags, core.pc()?);
+        let o_nonblock = o_nonblock(core.arch());
+        if flags & !(EFD_SEMAPHORE | O_CLOEXEC | o_nonblock) != 0 {
+            return Ok(-EINVAL);
+        }
+        let fd = match placeholder(flags & O_CLOEXEC != 0) {
+            Ok(fd) => fd as u64,
+            Err(e) => {
+                log::warn!("failed to eventfd2 ({}, {:#x}): {}", initval, flags, e);
+                return Ok(e.guest_ret(core.arch()));
+            }
+        };
+        self.fds.eventfd(
+            fd,
+            initval & 0xffff_ffff,
+            flags & EFD_SEMAPHORE != 0,
+            flags & o_nonblock != 0,
+            core.endian(),
         );
-        Ok(0)
+        Ok(fd as i64)
     }
 
     fn rt_sigaction<'a, A: ArchT>(
@@ -445,31 +1135,6 @@ impl Inner {
         act: u64,
         oldact: u64,
     ) -> Result<i64, uc_error> {
-        if oldact != 0 {
-            let arr = self
-                .sigaction_act
-                .get(&signum)
-                .map(|s| s.as_slice())
-                .unwrap_or(&[0u64; 5]);
-
-            let data = {
-                let packer = Packer::new(core.endian(), 4);
-                arr.iter()
-                    .map(|v| packer.pack(*v as u64))
-                    .fold(vec![], |mut acc, mut v| {
-                        acc.append(&mut v);
-                        acc
-                    })
-            };
-            Memory::write(core, oldact, data.as_slice())?;
-        }
-        if act != 0 {
-            let data = (0..5)
-                .map(|i| Memory::read_ptr(core, act + i * 4, Some(4)))
-                .collect::<Result<Vec<_>, _>>()?;
-            self.sigaction_act.insert(signum, data);
-        }
-
         log::debug!(
             "rt_sigaction({}, {}, {}), pc: {}",
             signum,
@@ -477,6 +1142,19 @@ impl Inner {
             oldact,
             core.pc()?
         );
+        if signum == 0 || signum > nsig(core.arch()) {
+            return Ok(-EINVAL);
+        }
+        if oldact != 0 {
+            write_sigaction(core, oldact, &self.signals.action(signum))?;
+        }
+        if act != 0 {
+            if signum == SIGKILL || signum == sigstop(core.arch()) {
+                return Ok(-EINVAL);
+            }
+            let action = read_sigaction(core, act)?;
+            self.signals.set_action(signum, action);
+        }
         Ok(0)
     }
     fn rt_sigprocmask<'a, A: ArchT>(
@@ -495,6 +1173,24 @@ impl Inner {
             sigsetsize,
             core.pc()?
         );
+        if sigsetsize != sigset_size(core.arch()) {
+            return Ok(-EINVAL);
+        }
+        let blocked = self.signals.blocked();
+        if oset != 0 {
+            let data = pack_sigset(core, blocked);
+            Memory::write(core, oset, data)?;
+        }
+        if nset != 0 {
+            let set = read_sigset(core, nset)?;
+            let blocked = match how {
+                SIG_BLOCK => blocked | set,
+                SIG_UNBLOCK => blocked & !set,
+                SIG_SETMASK => set,
+                _ => return Ok(-EINVAL),
+            };
+            self.signals.set_blocked(blocked);
+        }
         Ok(0)
     }
 
@@ -514,35 +1210,100 @@ impl Inner {
         Ok(0)
     }
 
-    // TODO: not implemented .
-    fn sigreturn<'a, A: ArchT>(&mut self, core: &mut Engine<'a, A>) -> Result<i64, uc_error> {
-        log::warn!("not implemented, sigreturn pc: {}", core.pc()?);
-        Ok(0)
+    /// Restore the context saved in the signal frame at sp, return the address to resume at.
+    fn sigreturn<'a, A: ArchT>(&mut self, core: &mut Engine<'a, A>) -> Result<u64, uc_error> {
+        let frame = core.sp()?;
+        let uc = frame + RT_SIGFRAME_UC;
+        let sc = uc + UC_MCONTEXT;
+        let reg_packer = Packer::new(core.endian(), 8);
+
+        let regs = Memory::read(core, sc + SC_REGS, 32 * 8)?;
+        // skip $zero
+        for (i, v) in regs.chunks(8).enumerate().skip(1) {
+            Registers::write(
+                core,
+                RegisterMIPS::ZERO as i32 + i as i32,
+                reg_packer.unpack(v.to_vec()),
+            )?;
+        }
+        let hi = reg_packer.unpack(Memory::read(core, sc + SC_MDHI, 8)?);
+        Registers::write(core, RegisterMIPS::HI, hi)?;
+        let lo = reg_packer.unpack(Memory::read(core, sc + SC_MDLO, 8)?);
+        Registers::write(core, RegisterMIPS::LO, lo)?;
+        let blocked = read_sigset(core, uc + UC_SIGMASK)?;
+        self.signals.set_blocked(blocked);
+
+        let pc = reg_packer.unpack(Memory::read(core, sc + SC_PC, 8)?);
+        log::debug!("sigreturn, frame: {:#x}, resume: {:#x}", frame, pc);
+        self.resume_at(core, pc)?;
+        Ok(pc)
     }
 
     fn brk<'a, A: ArchT>(&mut self, core: &mut Engine<'a, A>, inp: u64) -> Result<i64, uc_error> {
         log::debug!("brk({}) pc: {}", inp, core.pc()?);
-        // current brk_address will be modified if inp is not NULL(zero)
-        // otherwise, just return current brk_address
-        if inp != 0 {
-            let cur_brk_addr = self.brk_address;
-            let new_brk_addr = align_up(inp, core.pagesize());
-            if inp > cur_brk_addr {
-                Memory::mem_map(
-                    core,
-                    MemRegion {
-                        begin: cur_brk_addr,
-                        end: new_brk_addr,
-                        perms: Permission::ALL,
-                    },
-                    Some("[brk]".to_string()),
-                )?;
-            } else if inp < cur_brk_addr {
-                Memory::mem_unmap(core, new_brk_addr, (cur_brk_addr - new_brk_addr) as usize)?;
+        // the break doesn't move when brk fails, the program finds the current one in the return.
+        let old_brk = self.brk_address as i64;
+        if inp == 0 || inp < self.brk_start {
+            return Ok(old_brk);
+        }
+        if self
+            .heap_size
+            .is_some_and(|size| inp - self.brk_start > size)
+        {
+            log::warn!("brk({:#x}) exceeds the heap size {:?}", inp, self.heap_size);
+            return Ok(old_brk);
+        }
+        let page_size = core.pagesize();
+        let cur_end = align_up(self.brk_address, page_size);
+        let new_end = align_up(inp, page_size);
+        if new_end > cur_end {
+            // the heap stays below the mappings placed by the runner.
+            let into_mmap = self.brk_start < self.mmap_address && new_end > self.mmap_address;
+            if into_mmap || !is_free(&Memory::mapped_regions(core)?, cur_end, new_end) {
+                log::warn!("brk({:#x}) collides with other mappings", inp);
+                return Ok(old_brk);
+            }
+            if !self.reserve_ram(core, new_end - cur_end)? {
+                return Ok(old_brk);
             }
-            self.brk_address = new_brk_addr;
+            Memory::mem_map(
+                core,
+                MemRegion {
+                    begin: cur_end,
+                    end: new_end,
+                    perms: Permission::ALL,
+                },
+                Some("[brk]".to_string()),
+            )?;
+        } else if new_end < cur_end {
+            self.unmap_range(core, new_end, cur_end)?;
+        }
+        self.brk_address = inp;
+        Ok(inp as i64)
+    }
+    /// Whether `size` more bytes can be mapped without exceeding the ram of the machine.
+    /// If not, the program is stopped as out of memory, like the oom killer of linux would do.
+    fn reserve_ram<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        size: u64,
+    ) -> Result<bool, uc_error> {
+        let used = used_ram(core)?;
+        if used.saturating_add(size) <= self.identity.ram() {
+            return Ok(true);
         }
-        Ok(self.brk_address as i64)
+        log::warn!(
+            "out of memory, {} bytes mapped and {} more requested, ram is {}",
+            used,
+            size,
+            self.identity.ram()
+        );
+        self.outcome = Some(RunOutcome::OutOfMemory {
+            used,
+            requested: size,
+        });
+        core.emu_stop()?;
+        Ok(false)
     }
     fn exit_group<'a, A: ArchT>(
         &mut self,
@@ -550,6 +1311,7 @@ impl Inner {
         code: u64,
     ) -> Result<i64, uc_error> {
         log::debug!("exit_group({}) pc: {}", code, core.pc()?);
+        self.outcome = Some(RunOutcome::Exited(code));
         core.emu_stop()?;
         Ok(0)
     }
@@ -589,48 +1351,188 @@ impl Inner {
         Ok(0)
     }
     fn sched_yield<'a, A: ArchT>(&mut self, core: &mut Engine<'a, A>) -> Result<i64, uc_error> {
-        log::warn!("not implemented, sched_yield pc: {}", core.pc()?);
+        log::debug!("sched_yield pc: {}", core.pc()?);
+        if self.threads.len() > 1 {
+            self.threads.yield_current();
+        }
         Ok(0)
     }
     fn tkill<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
-        _pid: u64,
-        _cpusetsize: u64,
-        _mask: u64,
+        tid: u64,
+        sig: u64,
     ) -> Result<i64, uc_error> {
-        log::warn!("not implemented, tkill pc: {}", core.pc()?);
+        log::debug!("tkill({}, {}) pc: {}", tid, sig, core.pc()?);
+        if !self.threads.contains(tid) {
+            return Ok(-ESRCH);
+        }
+        if sig > nsig(core.arch()) {
+            return Ok(-EINVAL);
+        }
+        // signal 0 only checks the existence of the thread.
+        if sig != 0 {
+            self.signals.raise(sig);
+        }
         Ok(0)
     }
     fn futex<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
-        _uaddr: u64,
-        _op: u64,
-        _val: u64,
-        _timeout: u64,
-        _uaddr2: u64,
-        _val3: u64,
+        uaddr: u64,
+        op: u64,
+        val: u64,
+        timeout: u64,
+        uaddr2: u64,
+        val3: u64,
     ) -> Result<i64, uc_error> {
-        log::warn!("not implemented, futex pc: {}", core.pc()?);
-        Ok(0)
+        log::debug!(
+            "futex({:#x}, {}, {}, {:#x}, {:#x}, {}) pc: {}",
+            uaddr,
+            op,
+            val,
+            timeout,
+            uaddr2,
+            val3,
+            core.pc()?
+        );
+        let steps = core.get_data().state.steps;
+        match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+            FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+                if Memory::read_ptr(core, uaddr, Some(4))? != val & 0xffff_ffff {
+                    return Ok(-EAGAIN);
+                }
+                let timed = timeout != 0;
+                // nobody else can change the value, waiting with a timeout would just time out.
+                if timed && self.threads.len() == 1 {
+                    return Ok(HostErrno(libc::ETIMEDOUT).guest_ret(core.arch()));
+                }
+                self.threads.wait_current(uaddr, timed);
+                Ok(0)
+            }
+            FUTEX_WAKE | FUTEX_WAKE_BITSET => Ok(self.threads.wake(uaddr, val, steps) as i64),
+            op @ (FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) => {
+                if op == FUTEX_CMP_REQUEUE
+                    && Memory::read_ptr(core, uaddr, Some(4))? != val3 & 0xffff_ffff
+                {
+                    return Ok(-EAGAIN);
+                }
+                // the timeout argument holds the max number of threads to requeue.
+                let woken = self.threads.wake(uaddr, val, steps);
+                let requeued = self.threads.requeue(uaddr, uaddr2, timeout);
+                Ok((woken + requeued) as i64)
+            }
+            op => {
+                log::warn!("not implemented, futex op {}", op);
+                Ok(HostErrno(libc::ENOSYS).guest_ret(core.arch()))
+            }
+        }
     }
     fn exit<'a, A: ArchT>(&mut self, core: &mut Engine<'a, A>, code: u64) -> Result<i64, uc_error> {
-        log::debug!("exit: {}", code);
-        core.emu_stop()?;
+        log::debug!("exit({}) thread: {}", code, self.threads.current_tid());
+        if self.threads.len() == 1 {
+            self.outcome = Some(RunOutcome::Exited(code));
+            core.emu_stop()?;
+            return Ok(0);
+        }
+        let thread = self.threads.exit_current();
+        if let Some(clear_child_tid) = thread.map(|t| t.clear_child_tid).filter(|a| *a != 0) {
+            Memory::write(core, clear_child_tid, vec![0u8; 4])?;
+            let steps = core.get_data().state.steps;
+            self.threads.wake(clear_child_tid, 1, steps);
+        }
         Ok(0)
     }
+    /// Only threads are supported, which share everything with the calling thread but the stack and tls.
+    fn clone<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        flags: u64,
+        newsp: u64,
+        ptid: u64,
+        tls: u64,
+        ctid: u64,
+    ) -> Result<i64, uc_error> {
+        log::debug!(
+            "clone({:#x}, {:#x}, {:#x}, {:#x}, {:#x}) pc: {}",
+            flags,
+            newsp,
+            ptid,
+            tls,
+            ctid,
+            core.pc()?
+        );
+        if flags & (CLONE_VM | CLONE_THREAD) != CLONE_VM | CLONE_THREAD {
+            log::warn!("not implemented, clone of a process");
+            return Ok(HostErrno(libc::ENOSYS).guest_ret(core.arch()));
+        }
+        let arch = core.get_arch();
+        let mut context = save_context(core)?;
+        context.insert(A::PC, syscall_return_address(core)?);
+        // the child returns 0.
+        match arch {
+            Arch::MIPS => {
+                context.insert(RegisterMIPS::V0 as i32, 0);
+                context.insert(RegisterMIPS::A3 as i32, 0);
+            }
+            _ => {
+                context.insert(RegisterRISCV::A0 as i32, 0);
+            }
+        }
+        if newsp != 0 {
+            context.insert(A::SP, newsp);
+        }
+        if flags & CLONE_SETTLS != 0 {
+            context.insert(tls_register(arch), tls);
+        }
+        let steps = core.get_data().state.steps;
+        let tid = self.threads.spawn(context, self.signals.blocked(), steps);
+
+        let packer = Packer::new(core.endian(), 4);
+        if flags & CLONE_PARENT_SETTID != 0 {
+            Memory::write(core, ptid, packer.pack(tid))?;
+        }
+        if flags & CLONE_CHILD_SETTID != 0 {
+            Memory::write(core, ctid, packer.pack(tid))?;
+        }
+        if flags & CLONE_CHILD_CLEARTID != 0 {
+            if let Some(thread) = self.threads.get_mut(tid) {
+                thread.clear_child_tid = ctid;
+            }
+        }
+        Ok(tid as i64)
+    }
     fn clock_gettime<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
         clock_id: u64,
         tp: u64,
+        long_size: PointerSizeT,
     ) -> Result<i64, uc_error> {
         log::debug!("clock_gettime: id {} tp: {}", clock_id, tp);
-        // on 32 bits platform, 32bits for sec, 32bits for nsec.
-        Memory::write(core, tp, vec![0u8; 8])?;
+        // time doesn't pass in the emulator, the seconds are longs of `long_size` bytes.
+        let ts = Timespec::default().pack(core.endian(), long_size);
+        Memory::write(core, tp, ts)?;
         Ok(0)
     }
+    /// mmap of 64 bits archs, the offset is in bytes rather than in pages.
+    #[allow(clippy::too_many_arguments)]
+    fn mmap<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        addr: u64,
+        length: u64,
+        prot: u64,
+        flags: u64,
+        fd: i32,
+        offset: u64,
+    ) -> Result<i64, uc_error> {
+        if offset & (MMAP2_UNIT - 1) != 0 {
+            return Ok(-EINVAL);
+        }
+        self.mmap2(core, addr, length, prot, flags, fd, offset / MMAP2_UNIT, 1)
+    }
+
     fn mmap2<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
@@ -638,12 +1540,10 @@ impl Inner {
         length: u64,
         prot: u64,
         flags: u64,
-        fd: u64,
-        pgoffset: u64, // TODO no fd supports yet
+        fd: i32,
+        pgoffset: u64,
         _ver: u8,
     ) -> Result<i64, uc_error> {
-        let fd = fd as i32;
-
         log::debug!(
             "[mmap2] {}, {}, {}, {}, {}, {}",
             addr,
@@ -653,81 +1553,88 @@ impl Inner {
             fd,
             pgoffset
         );
-        const MAP_FAILED: i64 = -1;
-
-        const MAP_SHARED: u64 = 0x01;
-        const MAP_FIXED: u64 = 0x10;
-        const MAP_ANONYMOUS: u64 = 0x20;
         let arch = core.get_arch();
         // mask off perms bits that are not supported by unicorn
         let perms = Permission::from_bits_truncate(prot as u32);
 
         let page_size = core.pagesize();
-
-        let mut mmap_base = align(addr as u32, page_size as u32) as u64;
-        if flags & MAP_FIXED != 0 && mmap_base != addr {
-            return Ok(MAP_FAILED);
+        if length == 0 || (flags & MAP_FIXED != 0 && addr & (page_size - 1) != 0) {
+            return Ok(-EINVAL);
         }
+        let mmap_size = align_up(length, page_size);
 
-        let mmap_size =
-            align_up((length - (addr & (page_size - 1))) as u32, page_size as u32) as u64;
-
-        let mut need_map = true;
-        if mmap_base != 0 {
-            // already mapped.
-            if Memory::is_mapped(core, mmap_base as u64, mmap_size as usize)? {
-                // if map fixed, we just protect mem
-                if flags & MAP_FIXED != 0 {
-                    log::debug!("mmap2 - MAP_FIXED, mapping not needed");
-
-                    Memory::mprotect(core, mmap_base as u64, mmap_size as usize, perms)?;
-                    need_map = false;
-                } else {
-                    // or else, we need to reallocate mem somewhere else.
-                    mmap_base = 0;
+        let mmap_base = if flags & MAP_FIXED != 0 {
+            // a fixed mapping replaces whatever was mapped there.
+            self.unmap_range(core, addr, addr + mmap_size)?;
+            addr
+        } else {
+            let hint = align(addr, page_size);
+            if hint != 0 && is_free(&Memory::mapped_regions(core)?, hint, hint + mmap_size) {
+                hint
+            } else {
+                match self.find_free(core, mmap_size)? {
+                    Some(addr) => addr,
+                    None => return Ok(-ENOMEM),
                 }
             }
+        };
+
+        if !self.reserve_ram(core, mmap_size)? {
+            return Ok(-ENOMEM);
         }
-        if need_map {
-            if mmap_base == 0 {
-                mmap_base = self.mmap_address;
-                self.mmap_address = mmap_base + mmap_size;
-            }
+        log::debug!(
+            "[mmap2] mapping for [{},{})",
+            mmap_base,
+            mmap_base + mmap_size
+        );
+        Memory::mem_map(
+            core,
+            MemRegion {
+                begin: mmap_base,
+                end: mmap_base + mmap_size,
+                perms,
+            },
+            Some("[syscall_mmap2]".to_string()),
+        )?;
 
-            log::debug!(
-                "[mmap2] mapping for [{},{})",
-                mmap_base,
-                mmap_base + mmap_size
-            );
-            Memory::mem_map(
-                core,
-                MemRegion {
-                    begin: mmap_base as u64,
-                    end: (mmap_base + mmap_size) as u64,
-                    perms,
-                },
-                Some("[syscall_mmap2]".to_string()),
-            )?;
+        // FIXME: MIPS32 Big Endian
+        if matches!(arch, Arch::MIPS | Arch::RISCV) {
+            Memory::write(core, mmap_base, vec![0u8; mmap_size as usize])?;
+        }
 
-            // FIXME: MIPS32 Big Endian
-            if arch == Arch::MIPS {
-                Memory::write(core, mmap_base as u64, vec![0u8; mmap_size as usize])?;
+        if flags & map_anonymous(arch) == 0 && fd != -1 {
+            // the file content is copied, writes to the mapping don't reach the file.
+            if flags & MAP_SHARED != 0 {
+                log::warn!("[mmap2] shared mapping of fd {} is mapped private", fd);
             }
-            log::debug!(
-                "[mmap2] mapped for [{},{})",
-                mmap_base,
-                mmap_base + mmap_size
-            );
-        }
-        // TODO: should handle fd?
-        if fd != -1 {
-            log::warn!("[mmap2] fd {} not handled", fd);
+            let mut data = vec![0u8; length as usize];
+            let size = match pread(fd as u64, &mut data, pgoffset * MMAP2_UNIT) {
+                Ok(size) => size,
+                Err(e) => {
+                    log::warn!(
+                        "[mmap2] failed to read fd {} at page {}: {}",
+                        fd,
+                        pgoffset,
+                        e
+                    );
+                    Memory::mem_unmap(core, mmap_base, mmap_size as usize)?;
+                    return Ok(e.guest_ret(arch));
+                }
+            };
+            // the part of the mapping beyond the end of file stays zero.
+            data.truncate(size as usize);
+            Memory::write(core, mmap_base, data)?;
         }
+        log::debug!(
+            "[mmap2] mapped for [{},{})",
+            mmap_base,
+            mmap_base + mmap_size
+        );
         Ok(mmap_base as i64)
     }
     fn mremap<'a, A: ArchT>(
         &mut self,
-        _core: &mut Engine<'a, A>,
+        core: &mut Engine<'a, A>,
         old_addr: u64,
         old_size: u64,
         new_size: u64,
@@ -742,7 +1649,60 @@ impl Inner {
             flags,
             new_addr
         );
-        Ok(-1)
+        let page_size = core.pagesize();
+        if old_addr & (page_size - 1) != 0
+            || new_size == 0
+            || (flags & MREMAP_FIXED != 0 && flags & MREMAP_MAYMOVE == 0)
+        {
+            return Ok(-EINVAL);
+        }
+        let old_size = align_up(old_size, page_size);
+        let new_size = align_up(new_size, page_size);
+        let old_end = old_addr + old_size;
+        if !Memory::is_mapped(core, old_addr, old_size as usize)? {
+            return Ok(-EFAULT);
+        }
+        let mapped = Memory::mapped_regions(core)?;
+        let perms = overlaps(&mapped, old_addr, old_addr + 1)[0].perms;
+        if new_size > old_size && !self.reserve_ram(core, new_size - old_size)? {
+            return Ok(-ENOMEM);
+        }
+
+        if flags & MREMAP_FIXED == 0 {
+            if new_size <= old_size {
+                self.unmap_range(core, old_addr + new_size, old_end)?;
+                return Ok(old_addr as i64);
+            }
+            // grow in place if the pages after the mapping are free.
+            if is_free(&mapped, old_end, old_addr + new_size) {
+                self.map_zeroed(core, old_end, old_addr + new_size, perms)?;
+                return Ok(old_addr as i64);
+            }
+            if flags & MREMAP_MAYMOVE == 0 {
+                return Ok(-ENOMEM);
+            }
+        }
+
+        let new_addr = if flags & MREMAP_FIXED != 0 {
+            if new_addr & (page_size - 1) != 0
+                || (new_addr < old_end && old_addr < new_addr + new_size)
+            {
+                return Ok(-EINVAL);
+            }
+            self.unmap_range(core, new_addr, new_addr + new_size)?;
+            new_addr
+        } else {
+            match self.find_free(core, new_size)? {
+                Some(addr) => addr,
+                None => return Ok(-ENOMEM),
+            }
+        };
+        let data = Memory::read(core, old_addr, old_size.min(new_size) as usize)?;
+        self.map_zeroed(core, new_addr, new_addr + new_size, perms)?;
+        Memory::write(core, new_addr, data)?;
+        self.unmap_range(core, old_addr, old_end)?;
+        log::debug!("[mremap] moved {:#x} to {:#x}", old_addr, new_addr);
+        Ok(new_addr as i64)
     }
     fn munmap<'a, A: ArchT>(
         &mut self,
@@ -751,10 +1711,57 @@ impl Inner {
         length: u64,
     ) -> Result<i64, uc_error> {
         log::debug!("[munmap] addr: {:#x}, length: {:#x}", addr, length);
-        let length = align_up(length as u32, core.pagesize() as u32);
-        Memory::mem_unmap(core, addr, length as usize)?;
+        let page_size = core.pagesize();
+        if addr & (page_size - 1) != 0 || length == 0 {
+            return Ok(-EINVAL);
+        }
+        let length = align_up(length, page_size);
+        self.unmap_range(core, addr, addr + length)?;
         Ok(0)
     }
+    /// Find a free range of `size` bytes for a new mapping, above `mmap_address`.
+    fn find_free<'a, A: ArchT>(
+        &self,
+        core: &Engine<'a, A>,
+        size: u64,
+    ) -> Result<Option<u64>, uc_error> {
+        let limit = match core.pointer_size() {
+            8 => u64::MAX,
+            size => 1 << (size as u64 * 8),
+        };
+        let mapped = Memory::mapped_regions(core)?;
+        Ok(find_free(&mapped, self.mmap_address, limit, size))
+    }
+    /// Unmap the pages in `[begin, end)`, holes in the range are skipped.
+    fn unmap_range<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        begin: u64,
+        end: u64,
+    ) -> Result<(), uc_error> {
+        for region in overlaps(&Memory::mapped_regions(core)?, begin, end) {
+            Memory::mem_unmap(core, region.begin, (region.end - region.begin) as usize)?;
+        }
+        Ok(())
+    }
+    fn map_zeroed<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        begin: u64,
+        end: u64,
+        perms: Permission,
+    ) -> Result<(), uc_error> {
+        Memory::mem_map(
+            core,
+            MemRegion { begin, end, perms },
+            Some("[syscall_mremap]".to_string()),
+        )?;
+        // FIXME: MIPS32 Big Endian
+        if matches!(core.get_arch(), Arch::MIPS | Arch::RISCV) {
+            Memory::write(core, begin, vec![0u8; (end - begin) as usize])?;
+        }
+        Ok(())
+    }
     fn mprotect<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
@@ -795,14 +1802,17 @@ impl Inner {
         rlim: u64,
     ) -> Result<i64, uc_error> {
         log::debug!("[getrlimit] res: {:#x}, rlim: {:#x}", res, rlim);
-
-        let rlimit = get_rlimit(res);
-        Memory::write_ptr(
-            core,
-            rlim,
-            (&rlimit as *const Rlimit) as u64,
-            Some(core.pointer_size()),
-        )?;
+        let name = match Identity::rlimit_name(core.arch(), res) {
+            Some(name) => name,
+            None => return Ok(-EINVAL),
+        };
+        let infinity = rlim_infinity(core.arch(), core.pointer_size());
+        let (cur, max) = self.identity.rlimit(name);
+        let rlimit = Rlimit {
+            cur: cur.map_or(infinity, |cur| cur.min(infinity)),
+            max: max.map_or(infinity, |max| max.min(infinity)),
+        };
+        Memory::write(core, rlim, rlimit.pack(core.endian(), core.pointer_size()))?;
         Ok(0)
     }
 
@@ -812,24 +1822,27 @@ impl Inner {
         info: u64,
     ) -> Result<i64, uc_error> {
         log::debug!("[sysinfo] info: {:#x}", info);
-
-        let i: SysInfoMIPS = Default::default();
-        Memory::write_ptr(
-            core,
-            info,
-            (&i as *const SysInfoMIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
+        let used_ram = used_ram(core)?;
+        let sysinfo = self.identity.sysinfo(core.pointer_size(), used_ram);
+        Memory::write(core, info, sysinfo.pack(core.endian(), core.pointer_size()))?;
         Ok(0)
     }
 
     fn set_robust_list<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
-        _head_ptr: u64,
-        _head_len: u64,
+        head_ptr: u64,
+        head_len: u64,
     ) -> Result<i64, uc_error> {
-        log::warn!("not implemented, set_robust_list pc: {}", core.pc()?);
+        log::debug!(
+            "set_robust_list({:#x}, {}) pc: {}",
+            head_ptr,
+            head_len,
+            core.pc()?
+        );
+        if let Some(thread) = self.threads.current() {
+            thread.robust_list = head_ptr;
+        }
         Ok(0)
     }
 
@@ -848,18 +1861,24 @@ impl Inner {
             new_limit,
             old_limit
         );
-        if pid == 0 && new_limit == 0 {
-            let rlimit = get_rlimit(res);
-            Memory::write_ptr(
-                core,
-                old_limit,
-                (&rlimit as *const Rlimit) as u64,
-                Some(core.pointer_size()),
-            )?;
-            return Ok(0);
+        let name = match Identity::rlimit_name(core.arch(), res) {
+            Some(name) => name,
+            None => return Ok(-EINVAL),
+        };
+        // limits of other processes can't be accessed, and limits can't be changed.
+        if (pid != 0 && pid != MAIN_TID) || new_limit != 0 {
+            return Ok(-EPERM);
         }
-
-        Ok(-1)
+        if old_limit != 0 {
+            let (cur, max) = self.identity.rlimit(name);
+            let rlimit = Rlimit {
+                cur: cur.unwrap_or(RLIM64_INFINITY),
+                max: max.unwrap_or(RLIM64_INFINITY),
+            };
+            // struct rlimit64
+            Memory::write(core, old_limit, rlimit.pack(core.endian(), 8))?;
+        }
+        Ok(0)
     }
     fn open<'a, A: ArchT>(
         &mut self,
@@ -869,42 +1888,86 @@ impl Inner {
         mode: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("open with flags: {}", flags);
-        let mut flags = flags;
-        flags &= !(0x80000); // trip O_CLOEXEC
-        flags &= !(0x2000); // trip O_ASYNC
-        if flags & 1 == 1 || flags & 2 == 2 {
-            // open with write
-            flags |= 1 << 6 // add O_CREAT
-        }
-        log::debug!("open with adjusted flags: {}", flags);
-
-        let mut path = read_string(core, filename, b"\x00")?;
+        let path = read_string(core, filename, b"\x00")?;
         if path.is_empty() {
             log::warn!("empty path to open ({}, {}, {})", filename, flags, mode);
-            return Ok(-1);
+            return Ok(-ENOENT);
         }
         log::debug!("open({}, {}, {}) pc: {}", path, flags, mode, core.pc()?);
-
-        let ret = open(&mut path, flags, mode);
-        if ret < 0 {
-            log::warn!(
-                "failed to open ({}, {}, {}): {:?}",
-                path,
-                flags,
-                mode,
-                from_raw_syscall_ret(ret)
-            );
-        } else {
-            log::debug!(
-                "succeed to open ({}, {}, {}) fd: {}",
-                path,
-                flags,
-                mode,
-                ret
-            );
+        if let Some(ret) = self.open_proc(core, &path, flags)? {
+            return Ok(ret);
         }
 
-        Ok(ret)
+        let path = match self.host_path(path, true) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        let host_flags = host_open_flags(core.arch(), flags);
+        log::debug!("open with host flags: {}", host_flags);
+        let ret = open(&path, host_flags, mode);
+        match ret {
+            Err(e) => log::warn!("failed to open ({}, {}, {}): {}", path, flags, mode, e),
+            Ok(fd) => log::debug!("succeed to open ({}, {}, {}) fd: {}", path, flags, mode, fd),
+        }
+        Ok(host_ret(core, ret))
+    }
+    /// Where the program finds the file at `path`, its counterpart in the sysroot for absolute paths.
+    /// A symlink at `path` is followed if `follow` is set. Files missing from the sysroot are missing
+    /// for the program, none if they can't be created there either.
+    fn host_path(&self, path: String, follow: bool) -> Option<String> {
+        let sysroot = match &self.sysroot {
+            Some(sysroot) if path.starts_with('/') => sysroot,
+            _ => return Some(path),
+        };
+        let host_path = match sysroot_path(sysroot, &path) {
+            Some(host_path) if follow => Some(host_path),
+            // a symlink which isn't followed, or a new file, in its directory of the sysroot.
+            // dangling symlinks would be followed to the host.
+            _ => sysroot_entry_path(sysroot, &path).filter(|p| !follow || !p.is_symlink()),
+        };
+        match host_path {
+            Some(host_path) => {
+                log::debug!("{} is in the sysroot at {}", path, host_path.display());
+                Some(host_path.to_string_lossy().into_owned())
+            }
+            None => {
+                log::debug!("{} is not in the sysroot", path);
+                None
+            }
+        }
+    }
+    /// Open the file at `path` of `/proc`, which is made up after the machine identity.
+    /// None if `path` is not under `/proc`.
+    fn open_proc<'a, A: ArchT>(
+        &mut self,
+        core: &Engine<'a, A>,
+        path: &str,
+        flags: u64,
+    ) -> Result<Option<i64>, uc_error> {
+        if !path.starts_with("/proc/") {
+            return Ok(None);
+        }
+        let content = self.identity.proc_file(
+            path,
+            core.arch(),
+            MAIN_TID,
+            PARENT_PID,
+            self.threads.len(),
+            used_ram(core)?,
+        );
+        let ret = match content {
+            None => -ENOENT,
+            Some(_) if flags & FileFlags::O_ACCMODE as u64 != FileFlags::O_RDONLY as u64 => -EACCES,
+            // backed by the fd table, the placeholder only reserves the fd number.
+            Some(content) => match placeholder(flags & O_CLOEXEC != 0) {
+                Ok(fd) => {
+                    self.fds.file(fd as u64, content.into_bytes());
+                    fd
+                }
+                Err(e) => e.guest_ret(core.arch()),
+            },
+        };
+        Ok(Some(ret))
     }
     fn write<'a, A: ArchT>(
         &mut self,
@@ -915,17 +1978,17 @@ impl Inner {
     ) -> Result<i64, EmulatorError> {
         log::debug!("write({}, {}, {}) pc: {}", fd, buf, count, core.pc()?);
         let data = Memory::read(core, buf, count as usize)?;
-        let size = write(fd, data, count);
-        if size < 0 {
-            log::warn!(
-                "failed to write ({}, {}, {}): {:?}",
-                fd,
-                buf,
-                count,
-                from_raw_syscall_ret(size)
-            );
+        if self.fds.contains(fd) {
+            let steps = core.get_data().state.steps;
+            return Ok(self
+                .write_object(steps, fd, &data)
+                .map_or_else(|ret| ret, |n| n as i64));
         }
-        Ok(size)
+        let ret = write(fd, &data);
+        if let Err(e) = ret {
+            log::warn!("failed to write ({}, {}, {}): {}", fd, buf, count, e);
+        }
+        Ok(host_ret(core, ret))
     }
 
     fn writev<'a, A: ArchT>(
@@ -936,32 +1999,25 @@ impl Inner {
         vlen: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("writev({}, {}, {}) pc: {}", fd, vec, vlen, core.pc()?);
+        if self.fds.contains(fd) {
+            // gathered into one write, so that it's as atomic as a write of the same size.
+            let mut data = vec![];
+            for Iovec { base, len } in read_iovecs(core, vec, vlen)? {
+                data.extend(Memory::read(core, base, len as usize)?);
+            }
+            let steps = core.get_data().state.steps;
+            return Ok(self
+                .write_object(steps, fd, &data)
+                .map_or_else(|ret| ret, |n| n as i64));
+        }
         let mut ret: i64 = 0;
-        let size_t_len = core.pointer_size() as u64;
-        let iov = Memory::read(core, vec, (vlen * size_t_len * 2) as usize)?;
-        let packer = Packer::new(core.endian(), core.pointer_size());
-        let mut i = 0;
-        while i < vlen {
-            let addr_origin =
-                &iov[(i * size_t_len * 2) as usize..(i * size_t_len * 2 + size_t_len) as usize];
-            let addr = packer.unpack(addr_origin.to_vec());
-            let l_origin = &iov[(i * size_t_len * 2 + size_t_len) as usize
-                ..(i * size_t_len * 2 + size_t_len * 2) as usize];
-            let l = packer.unpack(l_origin.to_vec());
-            ret += l as i64;
-            let buf = Memory::read(core, addr, l as usize)?;
-            let size = write(fd, buf, l);
-            if size < 0 {
-                log::warn!(
-                    "failed to writev ({}, {}, {}): {:?}",
-                    fd,
-                    vec,
-                    vlen,
-                    from_raw_syscall_ret(size)
-                );
-                return Ok(-1);
+        for Iovec { base, len } in read_iovecs(core, vec, vlen)? {
+            ret += len as i64;
+            let buf = Memory::read(core, base, len as usize)?;
+            if let Err(e) = write(fd, &buf) {
+                log::warn!("failed to writev ({}, {}, {}): {}", fd, vec, vlen, e);
+                return Ok(e.guest_ret(core.arch()));
             };
-            i += 1;
         }
         Ok(ret)
     }
@@ -973,20 +2029,25 @@ impl Inner {
         len: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("read({}, {}, {}) pc: {}", fd, buf, len, core.pc()?);
+        if self.fds.contains(fd) {
+            let steps = core.get_data().state.steps;
+            return match self.read_object(steps, fd, len as usize) {
+                Ok(data) => {
+                    Memory::write(core, buf, &data)?;
+                    Ok(data.len() as i64)
+                }
+                Err(ret) => Ok(ret),
+            };
+        }
         let mut host_buf = vec![0_u8; len as usize];
-        let size = read(fd, &mut host_buf, len);
-
-        if size < 0 {
-            log::warn!(
-                "failed to read ({}, {}, {}): {:?}",
-                fd,
-                buf,
-                len,
-                from_raw_syscall_ret(size)
-            );
-            return Ok(size);
+        let size = match read(fd, &mut host_buf) {
+            Ok(size) => size,
+            Err(e) => {
+                log::warn!("failed to read ({}, {}, {}): {}", fd, buf, len, e);
+                return Ok(e.guest_ret(core.arch()));
+            }
         };
-        Memory::write(core, buf, host_buf)?;
+        Memory::write(core, buf, &host_buf[..size as usize])?;
         Ok(size)
     }
     fn close<'a, A: ArchT>(
@@ -995,11 +2056,15 @@ impl Inner {
         fd: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("close({}) pc: {}", fd, core.pc()?);
+        if self.fds.close(fd) {
+            // readers of a pipe see its end, writers see it has no readers.
+            self.threads.wake_io(core.get_data().state.steps);
+        }
         let ret = close(fd);
-        if ret < 0 {
-            log::warn!("failed to close ({}): {:?}", fd, from_raw_syscall_ret(ret));
+        if let Err(e) = ret {
+            log::warn!("failed to close ({}): {}", fd, e);
         }
-        Ok(ret)
+        Ok(host_ret(core, ret))
     }
     fn lseek<'a, A: ArchT>(
         &mut self,
@@ -1009,17 +2074,22 @@ impl Inner {
         whence: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("lseek({}, {}, {}) pc: {}", fd, offset, whence, core.pc()?);
-        let off = lseek(fd, offset, whence);
-        if off < 0 {
-            log::warn!(
-                "failed to lseek ({} {} {}): {:?}",
-                fd,
-                offset,
-                whence,
-                from_raw_syscall_ret(off)
-            );
+        // off_t is a long of the program.
+        let offset = match core.pointer_size() {
+            4 => offset as i32 as i64,
+            _ => offset as i64,
+        };
+        if self.fds.contains(fd) {
+            return Ok(self
+                .fds
+                .seek(fd, offset, whence)
+                .map_or_else(|errno| -errno, |off| off as i64));
+        }
+        let ret = lseek(fd, offset, whence);
+        if let Err(e) = ret {
+            log::warn!("failed to lseek ({} {} {}): {}", fd, offset, whence, e);
         }
-        Ok(off)
+        Ok(host_ret(core, ret))
     }
     fn _llseek<'a, A: ArchT>(
         &mut self,
@@ -1039,28 +2109,31 @@ impl Inner {
             whence,
             core.pc()?
         );
-        let offset = offset_high << 32 | offset_low;
-        let origin = whence;
-        let ret = lseek(fd, offset, origin);
-        if ret < 0 {
-            log::warn!(
-                "failed to _llseek ({} {} {} {} {}): {:?}",
-                fd,
-                offset_high,
-                offset_low,
-                result,
-                whence,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(ret);
-        }
+        let offset = (offset_high << 32 | offset_low & 0xffff_ffff) as i64;
+        // errors are guest errno.
+        let ret = if self.fds.contains(fd) {
+            self.fds.seek(fd, offset, whence).map(|off| off as i64)
+        } else {
+            lseek(fd, offset, whence).map_err(|e| -e.guest_ret(core.arch()))
+        };
+        let off = match ret {
+            Ok(off) => off,
+            Err(errno) => {
+                log::warn!(
+                    "failed to _llseek ({} {} {} {} {}): {}",
+                    fd,
+                    offset_high,
+                    offset_low,
+                    result,
+                    whence,
+                    errno
+                );
+                return Ok(-errno);
+            }
+        };
 
-        Memory::write_ptr(
-            core,
-            result,
-            (&ret as *const i64) as u64,
-            Some(core.pointer_size()),
-        )?;
+        // loff_t result.
+        Memory::write(core, result, Packer::new(core.endian(), 8).pack(off as u64))?;
         Ok(0)
     }
     fn fcntl<'a, A: ArchT>(
@@ -1071,17 +2144,14 @@ impl Inner {
         arg: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("fcntl({}, {}, {}) pc: {}", fd, cmd, arg, core.pc()?);
-        let ret = fcntl(fd, cmd, arg);
-        if ret < 0 {
-            log::warn!(
-                "failed to fcntl ({} {} {}): {:?}",
-                fd,
-                cmd,
-                arg,
-                from_raw_syscall_ret(ret)
-            );
+        if self.fds.contains(fd) {
+            return Ok(self.fcntl_object(core, fd, cmd, arg));
         }
-        Ok(ret)
+        let ret = fcntl(core.arch(), fd, cmd, arg);
+        if let Err(e) = ret {
+            log::warn!("failed to fcntl ({} {} {}): {}", fd, cmd, arg, e);
+        }
+        Ok(host_ret(core, ret))
     }
     fn fcntl64<'a, A: ArchT>(
         &mut self,
@@ -1091,17 +2161,37 @@ impl Inner {
         arg: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("fcntl64({}, {}, {}) pc: {}", fd, cmd, arg, core.pc()?);
-        let ret = fcntl(fd, cmd, arg);
-        if ret < 0 {
-            log::warn!(
-                "failed to fcntl64 ({} {} {}): {:?}",
-                fd,
-                cmd,
-                arg,
-                from_raw_syscall_ret(ret)
-            );
+        if self.fds.contains(fd) {
+            return Ok(self.fcntl_object(core, fd, cmd, arg));
+        }
+        let ret = fcntl(core.arch(), fd, cmd, arg);
+        if let Err(e) = ret {
+            log::warn!("failed to fcntl64 ({} {} {}): {}", fd, cmd, arg, e);
+        }
+        Ok(host_ret(core, ret))
+    }
+    /// Status flags of pipes and eventfds are kept in the emulator, fd flags on their placeholder.
+    fn fcntl_object<'a, A: ArchT>(
+        &mut self,
+        core: &Engine<'a, A>,
+        fd: u64,
+        cmd: u64,
+        arg: u64,
+    ) -> i64 {
+        match cmd {
+            F_GETFL => self.fds.status_flags(fd, core.arch()).unwrap_or_default() as i64,
+            F_SETFL => {
+                self.fds.set_status_flags(fd, arg, core.arch());
+                0
+            }
+            _ => {
+                let ret = fcntl(core.arch(), fd, cmd, arg);
+                if let (F_DUPFD | F_DUPFD_CLOEXEC, Ok(new_fd)) = (cmd, ret) {
+                    self.fds.dup(fd, new_fd as u64);
+                }
+                host_ret(core, ret)
+            }
         }
-        Ok(ret)
     }
     fn readlink<'a, A: ArchT>(
         &mut self,
@@ -1118,7 +2208,7 @@ impl Inner {
                 buf,
                 buf_size
             );
-            return Ok(-1);
+            return Ok(-ENOENT);
         }
         log::debug!(
             "readlink({}, {}, {}) pc: {}",
@@ -1127,21 +2217,19 @@ impl Inner {
             buf_size,
             core.pc()?
         );
+        let path = match self.host_path(path, false) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
         let mut host_buf = vec![0_u8; buf_size as usize];
-        let mut c_path = path.as_bytes().to_vec();
-        c_path.extend_from_slice(b"\x00");
-        let size = readlink(c_path.as_ptr(), host_buf.as_mut_ptr(), buf_size);
-        if size < 0 {
-            log::debug!(
-                "failed to readlink({}, {}, {}): {:?}",
-                path,
-                buf,
-                buf_size,
-                from_raw_syscall_ret(size)
-            );
-            return Ok(size);
-        }
-        Memory::write(core, buf, host_buf)?;
+        let size = match readlink(&path, &mut host_buf) {
+            Ok(size) => size,
+            Err(e) => {
+                log::debug!("failed to readlink({}, {}, {}): {}", path, buf, buf_size, e);
+                return Ok(e.guest_ret(core.arch()));
+            }
+        };
+        Memory::write(core, buf, &host_buf[..size as usize])?;
         Ok(size)
     }
     fn stat<'a, A: ArchT>(
@@ -1153,31 +2241,20 @@ impl Inner {
         let path = read_string(core, path_name, b"\x00")?;
         if path.is_empty() {
             log::warn!("empty path to stat ({}, {})", path_name, stat_buf);
-            return Ok(-1);
+            return Ok(-ENOENT);
         }
         log::debug!("stat ({}, {}) pc: {}", path, stat_buf, core.pc()?);
-        let mut c_path = path.as_bytes().to_vec();
-        c_path.extend_from_slice(b"\x00");
-        let (host_buf, ret) = get_stat(c_path.as_ptr());
-        if ret < 0 {
-            log::debug!(
-                "failed to stat({}, {}): {:?}",
-                path,
-                stat_buf,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(ret);
+        let path = match self.host_path(path, true) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        match stat(&path) {
+            Ok(host) => Ok(write_stat(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to stat({}, {}): {}", path, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
         }
-        let mut stat = StatMIPS::default();
-        stat.st_size = host_buf.st_size as u32;
-        stat.st_mode = host_buf.st_mode;
-        Memory::write_ptr(
-            core,
-            stat_buf,
-            (&stat as *const StatMIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
-        Ok(0)
     }
     fn stat64<'a, A: ArchT>(
         &mut self,
@@ -1188,31 +2265,20 @@ impl Inner {
         let path = read_string(core, path_name, b"\x00")?;
         if path.is_empty() {
             log::warn!("empty path to stat64 ({}, {})", path_name, stat_buf);
-            return Ok(-1);
+            return Ok(-ENOENT);
         }
         log::debug!("stat64 ({}, {}) pc: {}", path, stat_buf, core.pc()?);
-        let mut c_path = path.as_bytes().to_vec();
-        c_path.extend_from_slice(b"\x00");
-        let (host_buf, ret) = get_stat(c_path.as_ptr());
-        if ret < 0 {
-            log::debug!(
-                "failed to stat64({}, {}): {:?}",
-                path,
-                stat_buf,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(ret);
+        let path = match self.host_path(path, true) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        match stat(&path) {
+            Ok(host) => Ok(write_stat64(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to stat64({}, {}): {}", path, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
         }
-        let mut stat = Stat64MIPS::default();
-        stat.st_size = host_buf.st_size as u64;
-        stat.st_mode = host_buf.st_mode;
-        Memory::write_ptr(
-            core,
-            stat_buf,
-            (&stat as *const Stat64MIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
-        Ok(0)
     }
     fn fstat<'a, A: ArchT>(
         &mut self,
@@ -1221,26 +2287,13 @@ impl Inner {
         stat_buf: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("fstat ({}, {}) pc: {}", fd, stat_buf, core.pc()?);
-        let (host_buf, ret) = get_fstat(fd);
-        if ret < 0 {
-            log::debug!(
-                "failed to fstat({}, {}): {:?}",
-                fd,
-                stat_buf,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(ret);
+        match fstat(fd) {
+            Ok(host) => Ok(write_stat(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to fstat({}, {}): {}", fd, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
         }
-        let mut stat = StatMIPS::default();
-        stat.st_size = host_buf.st_size as u32;
-        stat.st_mode = host_buf.st_mode;
-        Memory::write_ptr(
-            core,
-            stat_buf,
-            (&stat as *const StatMIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
-        Ok(0)
     }
     fn fstat64<'a, A: ArchT>(
         &mut self,
@@ -1249,26 +2302,37 @@ impl Inner {
         stat_buf: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("fstat64 ({}, {}) pc: {}", fd, stat_buf, core.pc()?);
-        let (host_buf, ret) = get_fstat(fd);
-        if ret < 0 {
-            log::debug!(
-                "failed to fstat64 ({}, {}): {:?}",
-                fd,
-                stat_buf,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(-1);
+        match fstat(fd) {
+            Ok(host) => Ok(write_stat64(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to fstat64 ({}, {}): {}", fd, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
+        }
+    }
+    fn lstat<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        path_name: u64,
+        stat_buf: u64,
+    ) -> Result<i64, EmulatorError> {
+        let path = read_string(core, path_name, b"\x00")?;
+        if path.is_empty() {
+            log::warn!("empty path to lstat ({}, {})", path_name, stat_buf);
+            return Ok(-ENOENT);
+        }
+        log::debug!("lstat ({}, {}) pc: {}", path, stat_buf, core.pc()?);
+        let path = match self.host_path(path, false) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        match lstat(&path) {
+            Ok(host) => Ok(write_stat(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to lstat ({}, {}): {}", path, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
         }
-        let mut stat = Stat64MIPS::default();
-        stat.st_size = host_buf.st_size as u64;
-        stat.st_mode = host_buf.st_mode;
-        Memory::write_ptr(
-            core,
-            stat_buf,
-            (&stat as *const Stat64MIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
-        Ok(0)
     }
     fn lstat64<'a, A: ArchT>(
         &mut self,
@@ -1279,31 +2343,20 @@ impl Inner {
         let path = read_string(core, path_name, b"\x00")?;
         if path.is_empty() {
             log::warn!("empty path to lstat64 ({}, {})", path_name, stat_buf);
-            return Ok(-1);
+            return Ok(-ENOENT);
         }
         log::debug!("lstat64 ({}, {}) pc: {}", path, stat_buf, core.pc()?);
-        let mut c_path = path.as_bytes().to_vec();
-        c_path.extend_from_slice(b"\x00");
-        let (host_buf, ret) = get_lstat(c_path.as_ptr());
-        if ret < 0 {
-            log::debug!(
-                "failed to lstat64 ({}, {}): {:?}",
-                path,
-                stat_buf,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(ret);
+        let path = match self.host_path(path, false) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        match lstat(&path) {
+            Ok(host) => Ok(write_stat64(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to lstat64 ({}, {}): {}", path, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
         }
-        let mut stat = Stat64MIPS::default();
-        stat.st_size = host_buf.st_size as u64;
-        stat.st_mode = host_buf.st_mode;
-        Memory::write_ptr(
-            core,
-            stat_buf,
-            (&stat as *const Stat64MIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
-        Ok(0)
     }
     fn fstatat64<'a, A: ArchT>(
         &mut self,
@@ -1314,39 +2367,42 @@ impl Inner {
         flags: u64,
     ) -> Result<i64, EmulatorError> {
         let path = read_string(core, path_name, b"\x00")?;
-        if path.is_empty() {
-            log::warn!(
-                "empty path to fstatat64 ({}, {}, {}, {})",
-                dir_fd,
-                path_name,
-                stat_buf,
-                flags
-            );
-            return Ok(-1);
-        }
         log::debug!("fstatat64 ({}, {}) pc: {}", path, stat_buf, core.pc()?);
-        let mut c_path = path.as_bytes().to_vec();
-        c_path.extend_from_slice(b"\x00");
-        let (host_buf, ret) = get_fstatat64(dir_fd, c_path.as_ptr(), flags);
-        if ret < 0 {
-            log::debug!(
-                "failed to fstatat64({}, {}): {:?}",
-                path,
-                stat_buf,
-                from_raw_syscall_ret(ret)
-            );
-            return Ok(ret);
+        let path = match self.host_path(path, flags & AT_SYMLINK_NOFOLLOW == 0) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        // an empty path is only valid with AT_EMPTY_PATH, which the host layer checks.
+        match fstatat(dir_fd, &path, flags) {
+            Ok(host) => Ok(write_stat64(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to fstatat64({}, {}): {}", path, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
+        }
+    }
+    /// fstatat of 64 bits archs, which fills a `struct stat`.
+    fn newfstatat<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        dir_fd: u64,
+        path_name: u64,
+        stat_buf: u64,
+        flags: u64,
+    ) -> Result<i64, EmulatorError> {
+        let path = read_string(core, path_name, b"\x00")?;
+        log::debug!("newfstatat ({}, {}) pc: {}", path, stat_buf, core.pc()?);
+        let path = match self.host_path(path, flags & AT_SYMLINK_NOFOLLOW == 0) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        match fstatat(dir_fd, &path, flags) {
+            Ok(host) => Ok(write_stat(core, stat_buf, &host)?),
+            Err(e) => {
+                log::debug!("failed to newfstatat({}, {}): {}", path, stat_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
         }
-        let mut stat = Stat64MIPS::default();
-        stat.st_size = host_buf.st_size as u64;
-        stat.st_mode = host_buf.st_mode;
-        Memory::write_ptr(
-            core,
-            stat_buf,
-            (&stat as *const Stat64MIPS) as u64,
-            Some(core.pointer_size()),
-        )?;
-        Ok(0)
     }
     fn getcwd<'a, A: ArchT>(
         &mut self,
@@ -1359,13 +2415,21 @@ impl Inner {
             // flexemu should run on same env.
             Err(e) => {
                 log::debug!("failed to getcwd ({}, {}): {:?}", buf, size, e);
-                return Ok(-1);
+                return Ok(HostErrno::from(e).guest_ret(core.arch()));
             }
             Ok(d) => d,
         };
-        Memory::write(core, buf, dir.as_os_str().as_bytes())?;
-        Ok(0)
+        let mut path = dir.as_os_str().as_bytes().to_vec();
+        path.push(0);
+        if path.len() as u64 > size {
+            return Ok(-ERANGE);
+        }
+        Memory::write(core, buf, &path)?;
+        // the length of the path, including the terminating null byte.
+        Ok(path.len() as i64)
     }
+    /// Only the terminal size can be queried, other requests are not passed to the host,
+    /// their arguments point to memory of the program.
     fn ioctl<'a, A: ArchT>(
         &mut self,
         core: &mut Engine<'a, A>,
@@ -1374,57 +2438,568 @@ impl Inner {
         arg: u64,
     ) -> Result<i64, EmulatorError> {
         log::debug!("ioctl ({}, {}, {}) pc: {}", fd, cmd, arg, core.pc()?);
-        let ret = ioctl(fd, cmd, arg);
-        if ret < 0 {
-            log::debug!(
-                "failed to ioctl ({}, {}, {}): {:?}",
+        if cmd != tiocgwinsz(core.arch()) {
+            log::debug!("unsupported ioctl ({}, {:#x}, {})", fd, cmd, arg);
+            return Ok(-ENOTTY);
+        }
+        match winsize(fd) {
+            Ok(ws) => {
+                let packer = Packer::new(core.endian(), 2);
+                let data: Vec<u8> = ws.iter().flat_map(|v| packer.pack(*v as u64)).collect();
+                Memory::write(core, arg, data)?;
+                Ok(0)
+            }
+            Err(e) => {
+                log::debug!("failed to ioctl ({}, {:#x}, {}): {}", fd, cmd, arg, e);
+                Ok(e.guest_ret(core.arch()))
+            }
+        }
+    }
+    fn readv<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fd: u64,
+        vec: u64,
+        vlen: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("readv({}, {}, {}) pc: {}", fd, vec, vlen, core.pc()?);
+        if self.fds.contains(fd) {
+            let iovecs = read_iovecs(core, vec, vlen)?;
+            let len = iovecs.iter().map(|v| v.len).sum::<u64>();
+            let steps = core.get_data().state.steps;
+            let data = match self.read_object(steps, fd, len as usize) {
+                Ok(data) => data,
+                Err(ret) => return Ok(ret),
+            };
+            // scattered over the buffers in order.
+            let mut rest = &data[..];
+            for Iovec { base, len } in iovecs {
+                let (chunk, tail) = rest.split_at(rest.len().min(len as usize));
+                Memory::write(core, base, chunk)?;
+                rest = tail;
+            }
+            return Ok(data.len() as i64);
+        }
+        let mut total = 0;
+        for Iovec { base, len } in read_iovecs(core, vec, vlen)? {
+            let mut host_buf = vec![0_u8; len as usize];
+            let size = match read(fd, &mut host_buf) {
+                Ok(size) => size,
+                Err(e) => {
+                    log::warn!("failed to readv ({}, {}, {}): {}", fd, vec, vlen, e);
+                    // data already read is returned, the error shows up on the next call.
+                    return Ok(if total > 0 {
+                        total
+                    } else {
+                        e.guest_ret(core.arch())
+                    });
+                }
+            };
+            Memory::write(core, base, &host_buf[..size as usize])?;
+            total += size;
+            // a short read ends the transfer, like the kernel does.
+            if (size as u64) < len {
+                break;
+            }
+        }
+        Ok(total)
+    }
+    fn pread64<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fd: u64,
+        buf: u64,
+        count: u64,
+        offset: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!(
+            "pread64({}, {}, {}, {}) pc: {}",
+            fd,
+            buf,
+            count,
+            offset,
+            core.pc()?
+        );
+        if self.fds.contains(fd) {
+            return match self.fds.pread(fd, count as usize, offset) {
+                Ok(data) => {
+                    Memory::write(core, buf, &data)?;
+                    Ok(data.len() as i64)
+                }
+                Err(errno) => Ok(-errno),
+            };
+        }
+        let mut host_buf = vec![0_u8; count as usize];
+        let size = match pread(fd, &mut host_buf, offset) {
+            Ok(size) => size,
+            Err(e) => {
+                log::warn!(
+                    "failed to pread64 ({}, {}, {}, {}): {}",
+                    fd,
+                    buf,
+                    count,
+                    offset,
+                    e
+                );
+                return Ok(e.guest_ret(core.arch()));
+            }
+        };
+        Memory::write(core, buf, &host_buf[..size as usize])?;
+        Ok(size)
+    }
+    fn pwrite64<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fd: u64,
+        buf: u64,
+        count: u64,
+        offset: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!(
+            "pwrite64({}, {}, {}, {}) pc: {}",
+            fd,
+            buf,
+            count,
+            offset,
+            core.pc()?
+        );
+        if self.fds.contains(fd) {
+            return Ok(-ESPIPE);
+        }
+        let data = Memory::read(core, buf, count as usize)?;
+        let ret = pwrite(fd, &data, offset);
+        if let Err(e) = ret {
+            log::warn!(
+                "failed to pwrite64 ({}, {}, {}, {}): {}",
                 fd,
-                cmd,
-                arg,
-                from_raw_syscall_ret(ret)
+                buf,
+                count,
+                offset,
+                e
             );
         }
-        Ok(ret)
+        Ok(host_ret(core, ret))
+    }
+    fn openat<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        dir_fd: u64,
+        path_name: u64,
+        flags: u64,
+        mode: u64,
+    ) -> Result<i64, EmulatorError> {
+        let path = read_string(core, path_name, b"\x00")?;
+        if path.is_empty() {
+            log::warn!(
+                "empty path to openat ({}, {}, {}, {})",
+                dir_fd,
+                path_name,
+                flags,
+                mode
+            );
+            return Ok(-ENOENT);
+        }
+        log::debug!(
+            "openat({}, {}, {}, {}) pc: {}",
+            dir_fd as i32,
+            path,
+            flags,
+            mode,
+            core.pc()?
+        );
+        if let Some(ret) = self.open_proc(core, &path, flags)? {
+            return Ok(ret);
+        }
+        let path = match self.host_path(path, true) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        let ret = openat(dir_fd, &path, host_open_flags(core.arch(), flags), mode);
+        if let Err(e) = ret {
+            log::warn!("failed to openat ({}, {}, {}): {}", path, flags, mode, e);
+        }
+        Ok(host_ret(core, ret))
     }
+    fn getdents64<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fd: u64,
+        dirp: u64,
+        count: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("getdents64({}, {}, {}) pc: {}", fd, dirp, count, core.pc()?);
+        let mut host_buf = vec![0_u8; count as usize];
+        let size = match getdents64(fd, &mut host_buf) {
+            Ok(size) => size,
+            Err(e) => {
+                log::warn!("failed to getdents64 ({}, {}, {}): {}", fd, dirp, count, e);
+                return Ok(e.guest_ret(core.arch()));
+            }
+        };
+        // records have the same size on the host and in the program, only the byte order differs.
+        let dirents = convert_dirents64(&host_buf[..size as usize], core.endian());
+        Memory::write(core, dirp, dirents)?;
+        Ok(size)
+    }
+    /// The host stat of the file is reported, whatever `mask` asks for.
+    fn statx<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        dir_fd: u64,
+        path_name: u64,
+        flags: u64,
+        mask: u64,
+        statx_buf: u64,
+    ) -> Result<i64, EmulatorError> {
+        let path = read_string(core, path_name, b"\x00")?;
+        log::debug!(
+            "statx({}, {}, {}, {}, {}) pc: {}",
+            dir_fd as i32,
+            path,
+            flags,
+            mask,
+            statx_buf,
+            core.pc()?
+        );
+        let path = match self.host_path(path, flags & AT_SYMLINK_NOFOLLOW == 0) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        match fstatat(dir_fd, &path, flags) {
+            Ok(host) => {
+                Memory::write(core, statx_buf, host.pack_statx(core.endian()))?;
+                Ok(0)
+            }
+            Err(e) => {
+                log::debug!("failed to statx ({}, {}): {}", path, statx_buf, e);
+                Ok(e.guest_ret(core.arch()))
+            }
+        }
+    }
+    fn ftruncate<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fd: u64,
+        length: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("ftruncate({}, {}) pc: {}", fd, length, core.pc()?);
+        let ret = ftruncate(fd, length as i64);
+        if let Err(e) = ret {
+            log::warn!("failed to ftruncate ({}, {}): {}", fd, length, e);
+        }
+        Ok(host_ret(core, ret))
+    }
+    /// Run a syscall which only takes a path, like unlink.
+    fn path_syscall<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        name: &str,
+        path_name: u64,
+        call: impl FnOnce(&str) -> HostResult,
+    ) -> Result<i64, EmulatorError> {
+        let path = read_string(core, path_name, b"\x00")?;
+        if path.is_empty() {
+            log::warn!("empty path to {} ({})", name, path_name);
+            return Ok(-ENOENT);
+        }
+        log::debug!("{}({}) pc: {}", name, path, core.pc()?);
+        // unlink, rmdir and mkdir act on the symlink itself.
+        let path = match self.host_path(path, false) {
+            Some(path) => path,
+            None => return Ok(-ENOENT),
+        };
+        let ret = call(&path);
+        if let Err(e) = ret {
+            log::warn!("failed to {} ({}): {}", name, path, e);
+        }
+        Ok(host_ret(core, ret))
+    }
+    fn pipe2<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        fds: u64,
+        flags: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("pipe2({}, {}) pc: {}", fds, flags, core.pc()?);
+        let o_nonblock = o_nonblock(core.arch());
+        if flags & !(O_CLOEXEC | o_nonblock) != 0 {
+            return Ok(-EINVAL);
+        }
+        let cloexec = flags & O_CLOEXEC != 0;
+        let ends = placeholder(cloexec).and_then(|read_fd| match placeholder(cloexec) {
+            Ok(write_fd) => Ok((read_fd as u64, write_fd as u64)),
+            Err(e) => {
+                let _ = close(read_fd as u64);
+                Err(e)
+            }
+        });
+        let (read_fd, write_fd) = match ends {
+            Ok(ends) => ends,
+            Err(e) => {
+                log::warn!("failed to pipe2 ({}, {}): {}", fds, flags, e);
+                return Ok(e.guest_ret(core.arch()));
+            }
+        };
+        self.fds.pipe(read_fd, write_fd, flags & o_nonblock != 0);
+        let packer = Packer::new(core.endian(), 4);
+        let mut data = packer.pack(read_fd);
+        data.extend(packer.pack(write_fd));
+        Memory::write(core, fds, data)?;
+        Ok(0)
+    }
+    fn dup<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        old_fd: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("dup({}) pc: {}", old_fd, core.pc()?);
+        let ret = dup(old_fd);
+        match ret {
+            Err(e) => log::warn!("failed to dup ({}): {}", old_fd, e),
+            Ok(new_fd) => self.fds.dup(old_fd, new_fd as u64),
+        }
+        Ok(host_ret(core, ret))
+    }
+    /// dup2, or dup3 with its `flags`, which only takes `O_CLOEXEC` and fails on the same fds.
+    fn dup2<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        old_fd: u64,
+        new_fd: u64,
+        flags: Option<u64>,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!(
+            "dup3({}, {}, {:?}) pc: {}",
+            old_fd,
+            new_fd,
+            flags,
+            core.pc()?
+        );
+        let ret = match flags {
+            None => dup2(old_fd, new_fd),
+            Some(flags) if flags & !O_CLOEXEC != 0 || old_fd == new_fd => return Ok(-EINVAL),
+            Some(flags) => dup3(old_fd, new_fd, flags & O_CLOEXEC != 0),
+        };
+        match ret {
+            Err(e) => log::warn!("failed to dup2 ({}, {}): {}", old_fd, new_fd, e),
+            // the host closed new_fd, and made it a copy of old_fd.
+            Ok(_) if old_fd != new_fd => {
+                if self.fds.close(new_fd) {
+                    self.threads.wake_io(core.get_data().state.steps);
+                }
+                self.fds.dup(old_fd, new_fd);
+            }
+            Ok(_) => {}
+        }
+        Ok(host_ret(core, ret))
+    }
+    /// Sleeps don't wait on the host, the emulation is deterministic.
+    /// The thread yields instead, so that the threads it waits for can make progress.
+    fn nanosleep<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        req: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("nanosleep({}) pc: {}", req, core.pc()?);
+        if req == 0 {
+            return Ok(-EFAULT);
+        }
+        self.threads.yield_current();
+        Ok(0)
+    }
+    fn uname<'a, A: ArchT>(
+        &mut self,
+        core: &mut Engine<'a, A>,
+        buf: u64,
+    ) -> Result<i64, EmulatorError> {
+        log::debug!("uname({}) pc: {}", buf, core.pc()?);
+        let machine = machine_name(core.arch(), core.pointer_size());
+        Memory::write(core, buf, self.identity.utsname(machine))?;
+        Ok(0)
+    }
+}
+
+/// Join a 64 bits argument passed in two registers on 32 bits archs, the first one is the high half on big endian.
+/// The 64 bits argument `index` of a syscall.
+/// o32 passes it in the aligned pair of registers from `index`, riscv32 in the pair at `index`,
+/// 64 bits archs in a single one.
+fn arg_u64(core: &impl ArchInfo, args: &SyscallArgs, index: usize) -> u64 {
+    if core.pointer_size() == 8 {
+        return args.get(index);
+    }
+    let first = match core.arch() {
+        Arch::MIPS => (index + 1) & !1,
+        _ => index,
+    };
+    join_u64(core, args.get(first), args.get(first + 1))
 }
 
-fn get_stat(path: *const u8) -> (StatX8664, i64) {
-    let mut host_buf: StatX8664 = unsafe { mem::zeroed() };
-    let ret = stat(path, &mut host_buf as *mut StatX8664);
-    (host_buf, ret)
+fn join_u64(core: &impl ArchInfo, first: u64, second: u64) -> u64 {
+    let (high, low) = match core.endian() {
+        Endian::Big => (first, second),
+        Endian::Little => (second, first),
+    };
+    (high & 0xffff_ffff) << 32 | (low & 0xffff_ffff)
 }
 
-fn get_fstat(fd: u64) -> (StatX8664, i64) {
-    let mut host_buf: StatX8664 = unsafe { mem::zeroed() };
-    let ret = fstat(fd, &mut host_buf as *mut StatX8664);
-    (host_buf, ret)
+/// Read the array of `count` iovecs at `addr`.
+fn read_iovecs(core: &impl Mach, addr: u64, count: u64) -> Result<Vec<Iovec>, uc_error> {
+    let data = Memory::read(
+        core,
+        addr,
+        count as usize * Iovec::size(core.pointer_size()),
+    )?;
+    Ok(Iovec::unpack_all(&data, core.endian(), core.pointer_size()))
 }
 
-fn get_lstat(path: *const u8) -> (StatX8664, i64) {
-    let mut host_buf: StatX8664 = unsafe { mem::zeroed() };
-    let ret = lstat(path, &mut host_buf as *mut StatX8664);
-    (host_buf, ret)
+/// Write the host stat `host` as the `struct stat` of the program at `addr`.
+fn write_stat(core: &mut impl Mach, addr: u64, host: &Stat) -> Result<i64, uc_error> {
+    match host.pack(core.arch(), core.endian(), core.pointer_size()) {
+        Some(stat) => {
+            Memory::write(core, addr, stat)?;
+            Ok(0)
+        }
+        None => Ok(HostErrno(libc::EOVERFLOW).guest_ret(core.arch())),
+    }
+}
+
+/// Write the host stat `host` as the `struct stat64` of the program at `addr`.
+fn write_stat64(core: &mut impl Mach, addr: u64, host: &Stat) -> Result<i64, uc_error> {
+    let stat = host.pack64(core.endian(), core.pointer_size());
+    Memory::write(core, addr, stat)?;
+    Ok(0)
 }
 
-fn get_fstatat64(dir_fd: u64, path: *const u8, flags: u64) -> (StatX8664, i64) {
-    let mut host_buf: StatX8664 = unsafe { mem::zeroed() };
-    let ret = fstatat64(dir_fd, path, &mut host_buf as *mut StatX8664, flags);
-    (host_buf, ret)
+/// Raw syscall return of the program for the result of a host call.
+fn host_ret(core: &impl ArchInfo, ret: HostResult) -> i64 {
+    ret.unwrap_or_else(|e| e.guest_ret(core.arch()))
 }
 
-fn get_rlimit(res: u64) -> Rlimit {
-    let mut r0: u32 = u32::MAX;
-    if res == 3 {
-        // RLIMIT_STACK
-        r0 = 196608 // 192KiB
+/// Whether the path syscall `syscall` follows a symlink at the end of its path.
+fn follows_symlink(syscall: SysCalls, args: &SyscallArgs) -> bool {
+    match syscall {
+        SysCalls::READLINK
+        | SysCalls::LSTAT
+        | SysCalls::LSTAT64
+        | SysCalls::UNLINK
+        | SysCalls::MKDIR
+        | SysCalls::RMDIR => false,
+        SysCalls::FSTATAT64 | SysCalls::NEWFSTATAT => args.get::<u64>(3) & AT_SYMLINK_NOFOLLOW == 0,
+        SysCalls::STATX => args.get::<u64>(2) & AT_SYMLINK_NOFOLLOW == 0,
+        _ => true,
     }
-    Rlimit {
-        cur: r0,
-        max: u32::MAX,
+}
+
+/// Bytes of memory mapped by the program.
+fn used_ram(core: &impl Memory) -> Result<u64, uc_error> {
+    Ok(core.mapped_regions()?.iter().map(|r| r.end - r.begin).sum())
+}
+
+/// `TIOCGWINSZ` ioctl of `arch`.
+fn tiocgwinsz(arch: Arch) -> u64 {
+    match arch {
+        Arch::MIPS => 0x4008_7468,
+        _ => 0x5413,
     }
 }
 
-const EBADF: u64 = 9;
+/// pid of the parent of the emulated process.
+const PARENT_PID: u64 = 1;
+
+const EPERM: i64 = 1;
+const ENOENT: i64 = 2;
+const ESRCH: i64 = 3;
+const EBADF: i64 = 9;
+const EAGAIN: i64 = 11;
+const ENOMEM: i64 = 12;
+const EACCES: i64 = 13;
+const EFAULT: i64 = 14;
+const EINVAL: i64 = 22;
+const ENOTTY: i64 = 25;
+const ESPIPE: i64 = 29;
+const EPIPE: i64 = 32;
+const ERANGE: i64 = 34;
+
+/// max errno value, raw syscall returns in `[-MAX_ERRNO, -1]` are errors.
+const MAX_ERRNO: i64 = 4095;
+
+/// Set the result of a syscall from the raw value returned by handlers, which is `-errno` on failures.
+fn set_syscall_return(
+    cc: &impl CallingConvention,
+    core: &mut impl Mach,
+    ret: i64,
+) -> Result<(), EmulatorError> {
+    if (-MAX_ERRNO..0).contains(&ret) {
+        cc.set_syscall_error(core, -ret as u64)
+    } else {
+        cc.set_return_value(core, ret as u64)
+    }
+}
+
+/// Address of the instruction after the syscall being handled, where the program continues.
+fn syscall_return_address(core: &impl Registers) -> Result<u64, uc_error> {
+    // pc still points to the `syscall` instruction while the interrupt hook runs.
+    Ok(core.pc()? + 4)
+}
+
+/// Whether `pc` is in the delay slot of a branch or jump, only mips has them.
+fn in_delay_slot<'a, A: ArchT>(core: &Engine<'a, A>, pc: u64) -> Result<bool, uc_error> {
+    if core.get_arch() != Arch::MIPS {
+        return Ok(false);
+    }
+    let packer = Packer::new(core.endian(), 4);
+    let insn = match Memory::read(core, pc.wrapping_sub(4), 4) {
+        Ok(insn) => packer.unpack(insn),
+        // nothing is mapped before pc, so no branch either.
+        Err(_) => return Ok(false),
+    };
+    let opcode = insn >> 26;
+    let rs = (insn >> 21) & 0x1f;
+    let funct = insn & 0x3f;
+    Ok(match opcode {
+        // jr, jalr
+        0 => funct == 8 || funct == 9,
+        // regimm branches, j, jal, beq, bne, blez, bgtz, and their likely versions
+        1..=7 | 20..=23 => true,
+        // bc1, bc2
+        17 | 18 => rs == 8,
+        _ => false,
+    })
+}
+
+/// Registers of a thread, including the tls pointer.
+fn save_context<'a, A: ArchT>(core: &Engine<'a, A>) -> Result<RegisterState, uc_error> {
+    let mut context = RegisterState::new();
+    for reg in core
+        .get_data()
+        .env()
+        .registers()
+        .iter()
+        .copied()
+        .chain([tls_register(core.get_arch())])
+    {
+        context.insert(reg, Registers::read(core, reg)?);
+    }
+    Ok(context)
+}
+
+fn restore_context<'a, A: ArchT>(
+    core: &mut Engine<'a, A>,
+    context: &RegisterState,
+) -> Result<(), uc_error> {
+    for (reg, value) in context {
+        Registers::write(core, *reg, *value)?;
+    }
+    Ok(())
+}
+
+/// Register holding the tls pointer of a thread.
+fn tls_register(arch: Arch) -> i32 {
+    match arch {
+        Arch::MIPS => RegisterMIPS::CP0_USERLOCAL as i32,
+        _ => RegisterRISCV::TP as i32,
+    }
+}
 
 #[inline]
 fn intr_signal(arch: Arch) -> u32 {
@@ -1466,7 +3041,477 @@ fn get_syscall(arch: Arch, registers: &impl Registers) -> Result<u64, uc_error>
 
 #[cfg(test)]
 mod tests {
-    use crate::os::linux::syscall::SysCalls;
+    use unicorn_engine::{
+        unicorn_const::{Arch, MemRegion, Permission},
+        RegisterMIPS, RegisterRISCV,
+    };
+
+    use goblin::container::Endian;
+
+    use super::{
+        HostErrno, Inner, Sandbox, EAGAIN, EBADF, EFAULT, EINVAL, ENOENT, EPERM, EPIPE, ERANGE,
+        ESPIPE, ESRCH, MAIN_TID, RT_SIGFRAME_SIZE,
+    };
+    use crate::{
+        arch::{
+            mips::{MipsProfile, MIPS},
+            riscv::{RiscvProfile, RISCV},
+            ArchT,
+        },
+        cc::CallingConvention,
+        config::SandboxConfig,
+        engine::{Engine, Machine},
+        memory::Memory,
+        os::linux::{
+            file::{guest_open_flags, host_open_flags},
+            structs::{convert_dirents64, Stat, STATX_SIZE},
+            syscall::SysCalls,
+        },
+        registers::{Registers, StackRegister},
+    };
+
+    const DATA: u64 = 0x10000;
+    const MMAP: u64 = 0x100000;
+
+    fn engine<'a>() -> Engine<'a, MIPS> {
+        let profile = MipsProfile::default();
+        let mut core = Machine::create(MIPS::new(profile.pointer_size()), profile.mode());
+        Memory::mem_map(
+            &mut core,
+            MemRegion {
+                begin: DATA,
+                end: DATA + 0x2000,
+                perms: Permission::ALL,
+            },
+            None,
+        )
+        .unwrap();
+        core.set_sp(DATA + 0x1000).unwrap();
+        core
+    }
+
+    fn runner() -> Inner {
+        Inner {
+            mmap_address: MMAP,
+            ..Default::default()
+        }
+    }
+
+    /// Run syscall `no` with `args`, return v0 and a3.
+    fn syscall(inner: &mut Inner, core: &mut Engine<MIPS>, no: u64, args: &[u64]) -> (u64, u64) {
+        let cc = core.get_data().env().cc();
+        Registers::write(core, RegisterMIPS::V0, no).unwrap();
+        for (i, arg) in args.iter().enumerate() {
+            cc.set_raw_param(core, i as u64, *arg, None).unwrap();
+        }
+        inner.dispatch_syscall(core).unwrap();
+        (
+            Registers::read(core, RegisterMIPS::V0).unwrap(),
+            Registers::read(core, RegisterMIPS::A3).unwrap(),
+        )
+    }
+
+    fn failed(errno: i64) -> (u64, u64) {
+        (errno as u64, 1)
+    }
+
+    #[test]
+    fn test_brk_return() {
+        let mut core = engine();
+        let mut inner = runner();
+        inner.brk_start = 0x20000;
+        inner.brk_address = 0x20000;
+        assert_eq!(syscall(&mut inner, &mut core, 4045, &[0]), (0x20000, 0));
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4045, &[0x21800]),
+            (0x21800, 0)
+        );
+        // failures leave the break where it was.
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4045, &[MMAP + 1]),
+            (0x21800, 0)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4045, &[0x1000]),
+            (0x21800, 0)
+        );
+        inner.heap_size = Some(0x3000);
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4045, &[0x24000]),
+            (0x21800, 0)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4045, &[0x20800]),
+            (0x20800, 0)
+        );
+    }
+
+    #[test]
+    fn test_open_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        Memory::write(&mut core, DATA, b"/nonexistent/flexemu\x00").unwrap();
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4005, &[DATA, 0, 0]),
+            failed(ENOENT)
+        );
+        Memory::write(&mut core, DATA, b"\x00").unwrap();
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4005, &[DATA, 0, 0]),
+            failed(ENOENT)
+        );
+    }
+
+    #[test]
+    fn test_fd_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        let bad_fd = 1000;
+        let ebadf = failed(EBADF);
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4003, &[bad_fd, DATA, 4]),
+            ebadf
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4004, &[bad_fd, DATA, 4]),
+            ebadf
+        );
+        assert_eq!(syscall(&mut inner, &mut core, 4006, &[bad_fd]), ebadf);
+    }
+
+    #[test]
+    fn test_pipe_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        // pipe2(DATA, O_NONBLOCK)
+        assert_eq!(syscall(&mut inner, &mut core, 4328, &[DATA, 0x80]), (0, 0));
+        let fds = Memory::read(&core, DATA, 8).unwrap();
+        let read_fd = u32::from_be_bytes(fds[..4].try_into().unwrap()) as u64;
+        let write_fd = u32::from_be_bytes(fds[4..].try_into().unwrap()) as u64;
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4003, &[read_fd, DATA, 4]),
+            failed(EAGAIN)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4019, &[read_fd, 0, 0]),
+            failed(ESPIPE)
+        );
+        // poll(DATA, 1, 0) on the read end, which becomes readable.
+        let pollfd = [(read_fd as u32).to_be_bytes(), [0, 1, 0, 0]].concat();
+        Memory::write(&mut core, DATA, &pollfd).unwrap();
+        assert_eq!(syscall(&mut inner, &mut core, 4188, &[DATA, 1, 0]), (0, 0));
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4004, &[write_fd, DATA, 4]),
+            (4, 0)
+        );
+        assert_eq!(syscall(&mut inner, &mut core, 4188, &[DATA, 1, 0]), (1, 0));
+        assert_eq!(syscall(&mut inner, &mut core, 4006, &[read_fd]), (0, 0));
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4004, &[write_fd, DATA, 4]),
+            failed(EPIPE)
+        );
+    }
+
+    #[test]
+    fn test_pipe_without_passthrough() {
+        let mut core = engine();
+        let mut inner = Inner {
+            sandbox: Sandbox::new(SandboxConfig {
+                host_passthrough: false,
+                ..Default::default()
+            }),
+            ..runner()
+        };
+        // pipe2(DATA, 0)
+        assert_eq!(syscall(&mut inner, &mut core, 4328, &[DATA, 0]), (0, 0));
+        let fds = Memory::read(&core, DATA, 8).unwrap();
+        let read_fd = u32::from_be_bytes(fds[..4].try_into().unwrap()) as u64;
+        let write_fd = u32::from_be_bytes(fds[4..].try_into().unwrap()) as u64;
+        Memory::write(&mut core, DATA, b"ping").unwrap();
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4004, &[write_fd, DATA, 4]),
+            (4, 0)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4003, &[read_fd, DATA + 4, 4]),
+            (4, 0)
+        );
+        assert_eq!(Memory::read(&core, DATA + 4, 4).unwrap(), b"ping");
+        // host fds stay out of reach.
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4003, &[write_fd + 1, DATA, 4]),
+            failed(EPERM)
+        );
+    }
+
+    fn pipe_fds(inner: &mut Inner, core: &mut Engine<MIPS>) -> (u64, u64) {
+        // pipe2(DATA, O_NONBLOCK)
+        assert_eq!(syscall(inner, core, 4328, &[DATA, 0x80]), (0, 0));
+        let fds = Memory::read(core, DATA, 8).unwrap();
+        (
+            u32::from_be_bytes(fds[..4].try_into().unwrap()) as u64,
+            u32::from_be_bytes(fds[4..].try_into().unwrap()) as u64,
+        )
+    }
+
+    #[test]
+    fn test_dup() {
+        let mut core = engine();
+        let mut inner = runner();
+        let (read_fd, write_fd) = pipe_fds(&mut inner, &mut core);
+        // dup(read_fd) twice, then dup3(write_fd, fd, O_CLOEXEC) over the second copy.
+        let (dup_read, _) = syscall(&mut inner, &mut core, 4041, &[read_fd]);
+        let (fd, _) = syscall(&mut inner, &mut core, 4041, &[read_fd]);
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4327, &[write_fd, fd, 0x80000]),
+            (fd, 0)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4327, &[fd, fd, 0]),
+            failed(EINVAL)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4327, &[write_fd, fd, 1]),
+            failed(EINVAL)
+        );
+
+        // the copies are the pipe, which outlives the fds it was created with.
+        assert_eq!(syscall(&mut inner, &mut core, 4006, &[read_fd]), (0, 0));
+        assert_eq!(syscall(&mut inner, &mut core, 4006, &[write_fd]), (0, 0));
+        Memory::write(&mut core, DATA, b"ping").unwrap();
+        assert_eq!(syscall(&mut inner, &mut core, 4004, &[fd, DATA, 4]), (4, 0));
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4003, &[dup_read, DATA + 4, 4]),
+            (4, 0)
+        );
+        assert_eq!(Memory::read(&core, DATA + 4, 4).unwrap(), b"ping");
+        for fd in [dup_read, fd] {
+            assert_eq!(syscall(&mut inner, &mut core, 4006, &[fd]), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_riscv_dup3() {
+        let profile = RiscvProfile::new(false);
+        let mut core = Machine::create(RISCV::new(profile.pointer_size()), profile.mode());
+        Memory::mem_map(
+            &mut core,
+            MemRegion {
+                begin: DATA,
+                end: DATA + 0x1000,
+                perms: Permission::ALL,
+            },
+            None,
+        )
+        .unwrap();
+        let mut inner = runner();
+        // riscv returns -errno in a0, the syscall number is in a7.
+        let mut syscall = |core: &mut Engine<RISCV>, no: u64, args: &[u64]| {
+            let cc = core.get_data().env().cc();
+            Registers::write(core, RegisterRISCV::A7, no).unwrap();
+            for (i, arg) in args.iter().enumerate() {
+                cc.set_raw_param(core, i as u64, *arg, None).unwrap();
+            }
+            inner.dispatch_syscall(core).unwrap();
+            Registers::read(core, RegisterRISCV::A0).unwrap() as i64
+        };
+
+        // pipe2(DATA, 0), then dup3(write_fd, fd, O_CLOEXEC) over a dup of the read end.
+        assert_eq!(syscall(&mut core, 59, &[DATA, 0]), 0);
+        let fds = Memory::read(&core, DATA, 8).unwrap();
+        let read_fd = u32::from_le_bytes(fds[..4].try_into().unwrap()) as u64;
+        let write_fd = u32::from_le_bytes(fds[4..].try_into().unwrap()) as u64;
+        let fd = syscall(&mut core, 23, &[read_fd]) as u64;
+        assert_eq!(syscall(&mut core, 24, &[write_fd, fd, 0x80000]), fd as i64);
+        assert_eq!(syscall(&mut core, 24, &[fd, fd, 0]), -EINVAL);
+
+        Memory::write(&mut core, DATA, b"ping").unwrap();
+        assert_eq!(syscall(&mut core, 64, &[fd, DATA, 4]), 4);
+        assert_eq!(syscall(&mut core, 63, &[read_fd, DATA + 4, 4]), 4);
+        assert_eq!(Memory::read(&core, DATA + 4, 4).unwrap(), b"ping");
+        for fd in [read_fd, write_fd, fd] {
+            assert_eq!(syscall(&mut core, 57, &[fd]), 0);
+        }
+    }
+
+    #[test]
+    fn test_poll_mixed_fds() {
+        let mut core = engine();
+        let mut inner = runner();
+        // eventfd2(0, 0), which nothing signals.
+        let (event_fd, _) = syscall(&mut inner, &mut core, 4325, &[0, 0]);
+        let mut host = [0; 2];
+        assert_eq!(unsafe { libc::pipe(host.as_mut_ptr()) }, 0);
+        let writer = host[1];
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            assert_eq!(unsafe { libc::write(writer, b"x".as_ptr().cast(), 1) }, 1);
+        });
+
+        // poll([event_fd, host pipe], 2, -1) waits on the host pipe, no other thread can signal the eventfd.
+        let pollfds = [
+            (event_fd as u32).to_be_bytes(),
+            [0, 1, 0, 0],
+            (host[0] as u32).to_be_bytes(),
+            [0, 1, 0, 0],
+        ]
+        .concat();
+        Memory::write(&mut core, DATA, &pollfds).unwrap();
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4188, &[DATA, 2, u32::MAX as u64]),
+            (1, 0)
+        );
+        assert!(!inner.restart_syscall);
+        assert_eq!(Memory::read(&core, DATA + 6, 2).unwrap(), [0, 0]);
+        assert_eq!(Memory::read(&core, DATA + 14, 2).unwrap(), [0, 1]);
+
+        t.join().unwrap();
+        assert_eq!(syscall(&mut inner, &mut core, 4006, &[event_fd]), (0, 0));
+        unsafe {
+            libc::close(host[0]);
+            libc::close(host[1]);
+        }
+    }
+
+    #[test]
+    fn test_mmap_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        // mmap2(NULL, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        let args = [0, 4096, 3, 0x802, u32::MAX as u64, 0];
+        assert_eq!(syscall(&mut inner, &mut core, 4210, &args), (MMAP, 0));
+        let args = [0, 0, 3, 0x802, u32::MAX as u64, 0];
+        assert_eq!(syscall(&mut inner, &mut core, 4210, &args), failed(EINVAL));
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4091, &[MMAP + 1, 4096]),
+            failed(EINVAL)
+        );
+        let args = [MMAP + 0x10000, 4096, 8192, 1, 0];
+        assert_eq!(syscall(&mut inner, &mut core, 4167, &args), failed(EFAULT));
+    }
+
+    #[test]
+    fn test_futex_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        Memory::write_ptr(&mut core, DATA, 1, Some(4)).unwrap();
+        // FUTEX_WAIT while the value is not the expected one.
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4238, &[DATA, 0, 0, 0, 0, 0]),
+            failed(EAGAIN)
+        );
+    }
+
+    #[test]
+    fn test_signal_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4194, &[0, 0, 0]),
+            failed(EINVAL)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4195, &[0, 0, 0, 8]),
+            failed(EINVAL)
+        );
+        assert_eq!(syscall(&mut inner, &mut core, 4236, &[7, 0]), failed(ESRCH));
+    }
+
+    #[test]
+    fn test_signal_frame() {
+        let mut core = engine();
+        let mut inner = runner();
+        // rt_sigaction(SIGUSR1, {flags: 0, handler: 0x400000, mask: 0}, NULL, 16)
+        let mut act = 0u32.to_be_bytes().to_vec();
+        act.extend(0x400000u32.to_be_bytes());
+        act.extend([0; 16]);
+        Memory::write(&mut core, DATA, act).unwrap();
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4194, &[16, DATA, 0, 16]),
+            (0, 0)
+        );
+
+        let sp = core.sp().unwrap();
+        Memory::write(&mut core, sp, vec![0xa5; (DATA + 0x2000 - sp) as usize]).unwrap();
+        // tkill(MAIN_TID, SIGUSR1)
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4236, &[MAIN_TID, 16]),
+            (0, 0)
+        );
+        let frame = core.sp().unwrap();
+        assert_eq!(frame, sp - RT_SIGFRAME_SIZE);
+        assert_eq!(inner.resume_address, Some(0x400000));
+        // the frame is below the interrupted stack, which is left as is.
+        assert_eq!(
+            Memory::read(&core, sp, (DATA + 0x2000 - sp) as usize).unwrap(),
+            vec![0xa5; (DATA + 0x2000 - sp) as usize]
+        );
+    }
+
+    #[test]
+    fn test_process_errno() {
+        let mut core = engine();
+        let mut inner = runner();
+        // fork-like clone without CLONE_VM | CLONE_THREAD.
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4120, &[17, 0, 0, 0, 0]),
+            failed(HostErrno(libc::ENOSYS).to_guest(Arch::MIPS))
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4203, &[DATA, 1]),
+            failed(ERANGE)
+        );
+        assert_eq!(
+            syscall(&mut inner, &mut core, 4338, &[1, 7, 0, DATA]),
+            failed(EPERM)
+        );
+    }
+
+    #[test]
+    fn test_host_layouts() {
+        // O_WRONLY|O_CREAT|O_TRUNC|O_LARGEFILE of mips.
+        assert_eq!(
+            host_open_flags(Arch::MIPS, 0x2301),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC
+        );
+        // O_RDWR|O_SYNC|O_CLOEXEC of mips.
+        assert_eq!(
+            guest_open_flags(Arch::MIPS, host_open_flags(Arch::MIPS, 0x84012)),
+            0x84012
+        );
+        // O_WRONLY|O_CREAT|O_TRUNC|O_NONBLOCK of riscv.
+        assert_eq!(
+            host_open_flags(Arch::RISCV, 0xa41),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_NONBLOCK
+        );
+        assert_eq!(
+            guest_open_flags(Arch::RISCV, host_open_flags(Arch::RISCV, 0x80a41)),
+            0x80a41
+        );
+
+        let mut host = vec![];
+        host.extend(7u64.to_ne_bytes());
+        host.extend(24u64.to_ne_bytes());
+        host.extend(24u16.to_ne_bytes());
+        host.extend([4, b'a', b'b', 0, 0, 0]);
+        let dirents = convert_dirents64(&host, Endian::Big);
+        assert_eq!(&dirents[..8], &7u64.to_be_bytes());
+        assert_eq!(&dirents[16..18], &24u16.to_be_bytes());
+        assert_eq!(&dirents[18..], &host[18..]);
+
+        let host = Stat {
+            dev: 0x801,
+            mode: 0o100644,
+            size: 5,
+            ..Default::default()
+        };
+        let stx = host.pack_statx(Endian::Big);
+        assert_eq!(stx.len(), STATX_SIZE);
+        assert_eq!(&stx[28..30], &0o100644u16.to_be_bytes());
+        assert_eq!(&stx[40..48], &5u64.to_be_bytes());
+        // dev major and minor.
+        assert_eq!(&stx[136..144], &[0, 0, 0, 8, 0, 0, 0, 1]);
+    }
 
     #[test]
     fn test_syscall_serde() {
@@ -0,0 +1,230 @@
+//! Pre-image oracle, which lets the program fetch its inputs by hash, like rollup blocks in fraud proofs.
+//!
+//! The oracle is a custom syscall, `preimage(op, ...)`, registered when a [`PreimageProvider`] is set.
+//! It's only available to mips o32 programs, as syscall [`MIPS_PREIMAGE_SYSCALL`].
+//! A key is the keccak256 hash of its pre-image. Fetched bytes are written to the program memory,
+//! so they become part of the proven state. The fetched pre-images are kept in [`Preimages`],
+//! which [`write_preimages`] exports next to the proofs. The judge, `preimage_oracle` of the contracts,
+//! is given them encoded by [`encode_preimages`], stores each one under its keccak256 hash, and steps
+//! the preimage syscall with them only, so a step reading oracle data is only accepted if it reads
+//! the pre-image of the key the program asked for.
+//!
+//! ops:
+//! - `PREIMAGE_HINT(ptr, len)`: pass a hint about the data the program is about to fetch.
+//! - `PREIMAGE_SIZE(key)`: size of the pre-image of the 32 bytes key at `key`.
+//! - `PREIMAGE_READ(key, buf, count, offset)`: copy at most `count` bytes of the pre-image from `offset`
+//!   to `buf`, return the number of copied bytes, 0 past the end.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::anyhow;
+use ethtrie_codec::KeccakHasher;
+use trie_db::Hasher;
+
+use crate::{
+    errors::EmulatorError,
+    os::linux::{
+        handler::{SyscallArgs, SyscallCore, SyscallHandler},
+        EFAULT, EINVAL, ENOENT,
+    },
+};
+
+/// number of the preimage syscall on mips o32, past the end of the linux syscall table.
+pub const MIPS_PREIMAGE_SYSCALL: u64 = 4990;
+
+pub const PREIMAGE_HINT: u64 = 0;
+pub const PREIMAGE_SIZE: u64 = 1;
+pub const PREIMAGE_READ: u64 = 2;
+
+pub const PREIMAGE_KEY_SIZE: usize = 32;
+
+pub type PreimageKey = [u8; PREIMAGE_KEY_SIZE];
+
+/// Pre-images fetched by the program, keyed by their hash, shared by the oracle and the runner.
+pub type Preimages = Rc<RefCell<BTreeMap<PreimageKey, Vec<u8>>>>;
+
+/// Source of the pre-images the program fetches.
+pub trait PreimageProvider {
+    /// Hint about the data the program is about to fetch, providers may use it to prepare pre-images.
+    fn hint(&mut self, _hint: &[u8]) {}
+    /// The pre-image of `key`, an error of kind `NotFound` if it's unknown.
+    fn get(&mut self, key: &PreimageKey) -> io::Result<Vec<u8>>;
+}
+
+/// Pre-images stored in a directory, in files named after the hex encoded key.
+#[derive(Debug, Clone)]
+pub struct DirPreimageProvider {
+    dir: PathBuf,
+}
+
+impl DirPreimageProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl PreimageProvider for DirPreimageProvider {
+    fn get(&mut self, key: &PreimageKey) -> io::Result<Vec<u8>> {
+        fs::read(self.dir.join(hex::encode(key)))
+    }
+}
+
+/// Write `preimages` to `dir`, in the layout [`DirPreimageProvider`] reads.
+pub fn write_preimages(
+    preimages: &BTreeMap<PreimageKey, Vec<u8>>,
+    dir: impl AsRef<Path>,
+) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    for (key, data) in preimages {
+        fs::write(dir.join(hex::encode(key)), data)?;
+    }
+    Ok(())
+}
+
+/// Encode `preimages` as a rlp list of their bytes, the way the contracts take them.
+pub fn encode_preimages(preimages: &BTreeMap<PreimageKey, Vec<u8>>) -> Vec<u8> {
+    let mut encoder = rlp::RlpStream::new_list(preimages.len());
+    for data in preimages.values() {
+        encoder.append(data);
+    }
+    encoder.out().to_vec()
+}
+
+/// Handler of the preimage syscall.
+pub struct PreimageOracle {
+    provider: Box<dyn PreimageProvider>,
+    /// pre-images fetched so far, checked against their key.
+    preimages: Preimages,
+}
+
+impl PreimageOracle {
+    pub fn new(provider: impl PreimageProvider + 'static) -> Self {
+        Self {
+            provider: Box::new(provider),
+            preimages: Preimages::default(),
+        }
+    }
+
+    /// Pre-images fetched by the program, keyed by their hash.
+    /// The handle stays valid once the oracle is registered, and sees later fetches.
+    pub fn preimages(&self) -> Preimages {
+        self.preimages.clone()
+    }
+
+    /// Fetch the pre-image of `key` into `preimages`, false if the provider doesn't know it.
+    fn fetch(&mut self, key: PreimageKey) -> Result<bool, EmulatorError> {
+        if self.preimages.borrow().contains_key(&key) {
+            return Ok(true);
+        }
+        let data = match self.provider.get(&key) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        // a pre-image which doesn't match its key could never be verified by the judge.
+        if KeccakHasher::hash(&data) != key {
+            return Err(anyhow!("pre-image of {} doesn't match its key", hex::encode(key)).into());
+        }
+        self.preimages.borrow_mut().insert(key, data);
+        Ok(true)
+    }
+}
+
+impl SyscallHandler for PreimageOracle {
+    fn handle(
+        &mut self,
+        core: &mut dyn SyscallCore,
+        args: &SyscallArgs,
+    ) -> Result<i64, EmulatorError> {
+        let op: u64 = args.get(0);
+        if op == PREIMAGE_HINT {
+            let hint = match core.mem_read(args.get(1), args.get(2)) {
+                Ok(hint) => hint,
+                Err(_) => return Ok(-EFAULT),
+            };
+            self.provider.hint(&hint);
+            return Ok(0);
+        }
+        if op != PREIMAGE_SIZE && op != PREIMAGE_READ {
+            return Ok(-EINVAL);
+        }
+
+        let key = match core.mem_read(args.get(1), PREIMAGE_KEY_SIZE) {
+            Ok(key) => PreimageKey::try_from(key.as_slice()).unwrap(),
+            Err(_) => return Ok(-EFAULT),
+        };
+        if !self.fetch(key)? {
+            return Ok(-ENOENT);
+        }
+        let preimages = self.preimages.borrow();
+        let data = &preimages[&key];
+        if op == PREIMAGE_SIZE {
+            return Ok(data.len() as i64);
+        }
+        let (buf, count, offset): (u64, usize, usize) = (args.get(2), args.get(3), args.get(4));
+        let chunk = &data[offset.min(data.len())..];
+        let chunk = &chunk[..count.min(chunk.len())];
+        match core.mem_write(buf, chunk) {
+            Ok(()) => Ok(chunk.len() as i64),
+            Err(_) => Ok(-EFAULT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io};
+
+    use ethtrie_codec::KeccakHasher;
+    use trie_db::Hasher;
+
+    use super::{
+        encode_preimages, write_preimages, DirPreimageProvider, PreimageKey, PreimageOracle,
+        PreimageProvider,
+    };
+
+    struct MemProvider(BTreeMap<PreimageKey, Vec<u8>>);
+
+    impl PreimageProvider for MemProvider {
+        fn get(&mut self, key: &PreimageKey) -> io::Result<Vec<u8>> {
+            self.0
+                .get(key)
+                .cloned()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+    }
+
+    #[test]
+    fn test_fetch_preimage() {
+        let block = b"l2 block".to_vec();
+        let key = KeccakHasher::hash(&block);
+        let forged = KeccakHasher::hash(b"forged");
+        let mut provider = BTreeMap::new();
+        provider.insert(key, block.clone());
+        provider.insert(forged, b"not the pre-image".to_vec());
+
+        let mut oracle = PreimageOracle::new(MemProvider(provider));
+        let preimages = oracle.preimages();
+        assert!(oracle.fetch(key).unwrap());
+        assert!(!oracle.fetch([0; 32]).unwrap());
+        assert!(oracle.fetch(forged).is_err());
+        assert_eq!(preimages.borrow().len(), 1);
+        assert_eq!(preimages.borrow()[&key], block);
+        assert_eq!(
+            hex::encode(encode_preimages(&preimages.borrow())),
+            "c9886c3220626c6f636b"
+        );
+
+        let dir = std::env::temp_dir().join(format!("flexemu-preimages-{}", std::process::id()));
+        write_preimages(&preimages.borrow(), &dir).unwrap();
+        assert_eq!(DirPreimageProvider::new(&dir).get(&key).unwrap(), block);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
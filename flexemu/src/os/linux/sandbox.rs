@@ -0,0 +1,247 @@
+//! Enforcement of the `[sandbox]` config on syscalls.
+//! Denied calls fail with an errno, and are reported to the `flexemu::sandbox` log target.
+
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use unicorn_engine::unicorn_const::Arch;
+
+use crate::{
+    config::SandboxConfig,
+    os::linux::{fdtable::FdTable, file::AT_FDCWD, mmap::map_anonymous, syscall::SysCalls},
+};
+
+/// fds of stdin, stdout and stderr, which stay usable without host passthrough,
+/// like the pipes, eventfds and synthetic files of the emulator.
+const STDIO_FDS: u64 = 3;
+
+#[derive(Debug, Default)]
+pub struct Sandbox {
+    config: SandboxConfig,
+    /// `path_prefixes` resolved to absolute paths.
+    path_prefixes: Option<Vec<PathBuf>>,
+}
+
+impl Sandbox {
+    pub fn new(config: SandboxConfig) -> Self {
+        for name in config.allow.iter().flatten().chain(config.deny.iter()) {
+            if SysCalls::from_str(name).is_err() {
+                log::warn!(target: "flexemu::sandbox", "unknown syscall {} in sandbox config", name);
+            }
+        }
+        let path_prefixes = config
+            .path_prefixes
+            .as_ref()
+            .map(|prefixes| prefixes.iter().map(|p| resolve(p)).collect());
+        Self {
+            config,
+            path_prefixes,
+        }
+    }
+
+    /// Check whether the syscall named `name` may be called with `args` on `arch`, return why not otherwise.
+    /// Syscalls registered by the embedder only go through the allow and deny lists.
+    /// fds in `fds` are emulated, they are not host fds.
+    pub fn check_syscall(
+        &self,
+        arch: Arch,
+        name: &str,
+        args: &[u64],
+        fds: &FdTable,
+    ) -> Result<(), String> {
+        if let Some(allow) = &self.config.allow {
+            if !allow.iter().any(|n| n == name) {
+                return Err("not in the allowed syscalls".to_string());
+            }
+        }
+        if self.config.deny.iter().any(|n| n == name) {
+            return Err("in the denied syscalls".to_string());
+        }
+        let syscall = match SysCalls::from_str(name) {
+            Ok(syscall) if !self.config.host_passthrough => syscall,
+            _ => return Ok(()),
+        };
+        if path_arg(syscall).is_some() || syscall == SysCalls::GETCWD {
+            return Err("host passthrough is disabled".to_string());
+        }
+        match host_fd(arch, syscall, args) {
+            Some(fd) if fd >= STDIO_FDS && !fds.contains(fd) => Err(format!(
+                "host passthrough is disabled, fd {} is not stdio",
+                fd
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check whether the program may access `path`, return why not otherwise.
+    /// `dirfd` is the directory fd relative paths are resolved against, if not the current directory.
+    pub fn check_path(&self, path: &str, dirfd: Option<u64>) -> Result<(), String> {
+        let prefixes = match &self.path_prefixes {
+            None => return Ok(()),
+            Some(prefixes) => prefixes,
+        };
+        let path = Path::new(path);
+        if path.is_relative() && dirfd.is_some_and(|fd| fd as i32 != AT_FDCWD) {
+            return Err(format!(
+                "{} is relative to a directory fd, which can't be checked",
+                path.display()
+            ));
+        }
+        let resolved = resolve(path);
+        if prefixes.iter().any(|p| resolved.starts_with(p)) {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} is not under the allowed path prefixes",
+                resolved.display()
+            ))
+        }
+    }
+}
+
+/// Index of the path argument of syscalls which access host paths.
+pub fn path_arg(syscall: SysCalls) -> Option<usize> {
+    match syscall {
+        SysCalls::OPEN
+        | SysCalls::READLINK
+        | SysCalls::STAT
+        | SysCalls::STAT64
+        | SysCalls::LSTAT
+        | SysCalls::LSTAT64
+        | SysCalls::UNLINK
+        | SysCalls::MKDIR
+        | SysCalls::RMDIR => Some(0),
+        SysCalls::FSTATAT64 | SysCalls::NEWFSTATAT | SysCalls::OPENAT | SysCalls::STATX => Some(1),
+        _ => None,
+    }
+}
+
+/// The host fd syscalls operate on, if any.
+fn host_fd(arch: Arch, syscall: SysCalls, args: &[u64]) -> Option<u64> {
+    match syscall {
+        SysCalls::READ
+        | SysCalls::WRITE
+        | SysCalls::WRITEV
+        | SysCalls::CLOSE
+        | SysCalls::LSEEK
+        | SysCalls::_LLSEEK
+        | SysCalls::FCNTL
+        | SysCalls::FCNTL64
+        | SysCalls::FSTAT
+        | SysCalls::FSTAT64
+        | SysCalls::IOCTL
+        | SysCalls::READV
+        | SysCalls::PREAD64
+        | SysCalls::PWRITE64
+        | SysCalls::GETDENTS64
+        | SysCalls::FTRUNCATE
+        | SysCalls::FTRUNCATE64
+        | SysCalls::DUP
+        | SysCalls::DUP2
+        | SysCalls::DUP3 => args.first().copied(),
+        SysCalls::MMAP | SysCalls::MMAP2 => {
+            let (flags, fd) = (args[3], args[4] as i32);
+            (flags & map_anonymous(arch) == 0 && fd != -1).then_some(fd as u64)
+        }
+        _ => None,
+    }
+}
+
+/// Make `path` absolute, without `.` or `..`, and with symlinks resolved as far as it exists.
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    // resolve symlinks of the longest existing ancestor, the rest doesn't exist yet.
+    let mut existing = normalized.as_path();
+    let mut rest = vec![];
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest.into_iter().rev().fold(canonical, |p, c| p.join(c));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return normalized,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::Sandbox;
+    use crate::{config::SandboxConfig, os::linux::fdtable::FdTable};
+
+    #[test]
+    fn test_sandbox_policy() {
+        let sandbox = Sandbox::new(SandboxConfig {
+            allow: Some(vec!["read".into(), "write".into(), "open".into()]),
+            deny: vec!["open".into()],
+            host_passthrough: false,
+            path_prefixes: Some(vec![PathBuf::from("/nonexistent/flexemu")]),
+        });
+        let fds = FdTable::default();
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "write", &[1, 0, 0], &fds)
+            .is_ok());
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "read", &[3, 0, 0], &fds)
+            .is_err());
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "open", &[0, 0, 0], &fds)
+            .is_err());
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "brk", &[0], &fds)
+            .is_err());
+
+        assert!(sandbox
+            .check_path("/nonexistent/flexemu/data/../input", None)
+            .is_ok());
+        assert!(sandbox
+            .check_path("/nonexistent/flexemu/../secret", None)
+            .is_err());
+        assert!(sandbox.check_path("input", Some(3)).is_err());
+    }
+
+    #[test]
+    fn test_emulated_fds_without_passthrough() {
+        let sandbox = Sandbox::new(SandboxConfig {
+            host_passthrough: false,
+            ..Default::default()
+        });
+        let mut fds = FdTable::default();
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "pipe2", &[0, 0], &fds)
+            .is_ok());
+        fds.pipe(3, 4, false);
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "write", &[4, 0, 4], &fds)
+            .is_ok());
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "read", &[3, 0, 4], &fds)
+            .is_ok());
+        assert!(sandbox
+            .check_syscall(Arch::MIPS, "read", &[5, 0, 4], &fds)
+            .is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Signal state of the emulated process.
+//! Signal frame layouts follow the mips o32 abi, signal numbers, flags and sigactions differ
+//! between mips and the other linux archs, like riscv.
+//! n64 shares the numbers and flags, its sigsets and sigactions are made of 8 bytes longs.
+
+use std::collections::HashMap;
+
+use unicorn_engine::unicorn_const::{uc_error, Arch};
+
+use crate::{engine::Mach, memory::Memory, os::linux::structs::StructWriter, utils::Packer};
+
+pub const SIGKILL: u64 = 9;
+pub const SIGPIPE: u64 = 13;
+
+/// signals whose default action is to be ignored or to stop, which is ignored too:
+/// SIGCHLD, SIGWINCH, SIGURG, SIGSTOP, SIGTSTP, SIGCONT, SIGTTIN and SIGTTOU.
+const MIPS_IGNORED: [u64; 8] = [18, 20, 21, 23, 24, 25, 26, 27];
+const GENERIC_IGNORED: [u64; 8] = [17, 28, 23, 19, 20, 18, 21, 22];
+
+/// `SIGSTOP` of `arch`.
+pub fn sigstop(arch: Arch) -> u64 {
+    match arch {
+        Arch::MIPS => 23,
+        _ => 19,
+    }
+}
+
+/// signals of `arch` are numbered from 1 to nsig.
+pub fn nsig(arch: Arch) -> u64 {
+    match arch {
+        Arch::MIPS => 128,
+        _ => 64,
+    }
+}
+
+/// size of kernel `sigset_t` of `arch` in bytes.
+pub fn sigset_size(arch: Arch) -> u64 {
+    nsig(arch) / 8
+}
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// `how` of rt_sigprocmask.
+pub const SIG_BLOCK: u64 = 1;
+pub const SIG_UNBLOCK: u64 = 2;
+pub const SIG_SETMASK: u64 = 3;
+
+/// si_code of signals sent by tkill.
+pub const SI_TKILL: i32 = -6;
+
+/// offset of `siginfo_t` in the frame, after the o32 argument save area.
+pub const RT_SIGFRAME_INFO: u64 = 24;
+/// offset of `struct ucontext` in the frame.
+pub const RT_SIGFRAME_UC: u64 = 152;
+pub const SIGINFO_SIZE: u64 = 128;
+/// offset of `struct sigcontext` in `struct ucontext`.
+pub const UC_MCONTEXT: u64 = 24;
+/// offset of `uc_sigmask` in `struct ucontext`.
+pub const UC_SIGMASK: u64 = 616;
+/// room of `uc_sigmask`, the 1024 bits `sigset_t` of libcs, which handlers may read and write
+/// through their `ucontext_t`. only the first `sigset_size` bytes are the mask of the kernel.
+pub const SIGSET_SIZE: u64 = 128;
+/// Layout of `struct rt_sigframe` which is pushed onto the stack before running a handler,
+/// `uc_sigmask` ends it.
+pub const RT_SIGFRAME_SIZE: u64 = RT_SIGFRAME_UC + UC_SIGMASK + SIGSET_SIZE;
+/// offsets in `struct sigcontext`.
+pub const SC_PC: u64 = 8;
+pub const SC_REGS: u64 = 16;
+pub const SC_MDHI: u64 = 552;
+pub const SC_MDLO: u64 = 560;
+
+pub type SigSet = u128;
+
+#[inline]
+pub fn sigmask(sig: u64) -> SigSet {
+    1 << (sig - 1)
+}
+
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SigAction {
+    pub flags: u64,
+    pub handler: u64,
+    pub mask: SigSet,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+}
+
+/// Default action of signals of `arch` whose handler is SIG_DFL.
+/// Stopping has no meaning for the single emulated process, stop signals are ignored.
+pub fn default_action(arch: Arch, sig: u64) -> DefaultAction {
+    let ignored = match arch {
+        Arch::MIPS => &MIPS_IGNORED,
+        _ => &GENERIC_IGNORED,
+    };
+    if ignored.contains(&sig) {
+        DefaultAction::Ignore
+    } else {
+        DefaultAction::Terminate
+    }
+}
+
+#[derive(Debug)]
+pub struct Signals {
+    actions: HashMap<u64, SigAction>,
+    blocked: SigSet,
+    pending: SigSet,
+    /// signals which can be neither blocked, caught nor ignored.
+    unblockable: SigSet,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new(Arch::MIPS)
+    }
+}
+
+impl Signals {
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            actions: HashMap::new(),
+            blocked: 0,
+            pending: 0,
+            unblockable: sigmask(SIGKILL) | sigmask(sigstop(arch)),
+        }
+    }
+
+    pub fn action(&self, sig: u64) -> SigAction {
+        self.actions.get(&sig).copied().unwrap_or_default()
+    }
+    pub fn set_action(&mut self, sig: u64, action: SigAction) {
+        self.actions.insert(sig, action);
+    }
+    pub fn blocked(&self) -> SigSet {
+        self.blocked
+    }
+    pub fn set_blocked(&mut self, mask: SigSet) {
+        self.blocked = mask & !self.unblockable;
+    }
+    pub fn raise(&mut self, sig: u64) {
+        self.pending |= sigmask(sig);
+    }
+
+    /// Take the lowest pending signal which is not blocked.
+    pub fn take_deliverable(&mut self) -> Option<u64> {
+        let deliverable = self.pending & !self.blocked;
+        if deliverable == 0 {
+            return None;
+        }
+        let sig = deliverable.trailing_zeros() as u64 + 1;
+        self.pending &= !sigmask(sig);
+        Some(sig)
+    }
+}
+
+/// sigsets are arrays of longs.
+pub fn read_sigset(core: &impl Mach, addr: u64) -> Result<SigSet, uc_error> {
+    let word_size = core.pointer_size() as usize;
+    let packer = Packer::new(core.endian(), core.pointer_size());
+    let data = Memory::read(core, addr, sigset_size(core.arch()) as usize)?;
+    Ok(data
+        .chunks(word_size)
+        .enumerate()
+        .fold(0, |set, (i, word)| {
+            set | ((packer.unpack(word.to_vec()) as SigSet) << (8 * word_size * i))
+        }))
+}
+
+pub fn pack_sigset(core: &impl Mach, set: SigSet) -> Vec<u8> {
+    let word_size = core.pointer_size() as u64;
+    let packer = Packer::new(core.endian(), core.pointer_size());
+    (0..sigset_size(core.arch()) / word_size)
+        .flat_map(|i| packer.pack((set >> (8 * word_size * i)) as u64))
+        .collect()
+}
+
+/// On mips, `sa_flags` is an int, followed by the handler and the mask, aligned as longs.
+/// On riscv, the handler is followed by `sa_flags` as a long and the mask, without `sa_restorer`.
+pub fn read_sigaction(core: &impl Mach, addr: u64) -> Result<SigAction, uc_error> {
+    let word_size = core.pointer_size() as u64;
+    let (flags, handler) = match core.arch() {
+        Arch::MIPS => (
+            Memory::read_ptr(core, addr, Some(4))?,
+            Memory::read_ptr(core, addr + word_size, None)?,
+        ),
+        _ => (
+            Memory::read_ptr(core, addr + word_size, None)?,
+            Memory::read_ptr(core, addr, None)?,
+        ),
+    };
+    Ok(SigAction {
+        flags,
+        handler,
+        mask: read_sigset(core, addr + 2 * word_size)?,
+    })
+}
+
+pub fn write_sigaction(
+    core: &mut impl Mach,
+    addr: u64,
+    action: &SigAction,
+) -> Result<(), uc_error> {
+    let mut writer = StructWriter::new(core.endian(), core.pointer_size());
+    let mut data = match core.arch() {
+        Arch::MIPS => writer.u32(action.flags).long(action.handler),
+        _ => writer.long(action.handler).long(action.flags),
+    }
+    .finish();
+    data.extend(pack_sigset(core, action.mask));
+    Memory::write(core, addr, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::{default_action, sigmask, sigstop, DefaultAction, Signals, SIGKILL};
+
+    #[test]
+    fn test_deliverable_signals() {
+        let mut signals = Signals::default();
+        signals.raise(6);
+        signals.raise(2);
+        signals.set_blocked(sigmask(2) | sigmask(SIGKILL));
+        assert_eq!(signals.blocked(), sigmask(2));
+        assert_eq!(signals.take_deliverable(), Some(6));
+        assert_eq!(signals.take_deliverable(), None);
+        signals.set_blocked(0);
+        assert_eq!(signals.take_deliverable(), Some(2));
+        assert_eq!(signals.take_deliverable(), None);
+
+        // SIGSTOP of riscv is SIGPWR of mips, which can be blocked there.
+        let mut signals = Signals::new(Arch::RISCV);
+        signals.set_blocked(sigmask(19) | sigmask(23));
+        assert_eq!(signals.blocked(), sigmask(23));
+        assert_eq!(sigstop(Arch::MIPS), 23);
+        // SIGCHLD
+        assert_eq!(default_action(Arch::RISCV, 17), DefaultAction::Ignore);
+        assert_eq!(default_action(Arch::MIPS, 17), DefaultAction::Terminate);
+    }
+}
@@ -0,0 +1,916 @@
+//! strace like trace of the syscalls of the program, as text or as json lines.
+//!
+//! Arguments are decoded after the syscall returns, so buffers filled by the syscall show their content.
+//! Records only depend on the emulated program, two runs of the same program can be diffed line by line.
+
+use std::{fmt, io, io::Write, str::FromStr};
+
+use goblin::container::Endian;
+use serde::Serialize;
+use unicorn_engine::unicorn_const::Arch;
+
+use crate::{
+    memory::PointerSizeT,
+    os::linux::{
+        errno::errno_name,
+        file::AT_FDCWD,
+        handler::{SyscallArgs, SyscallCore},
+        identity::UTSNAME_FIELD_LEN,
+        signal::{SIG_DFL, SIG_IGN},
+        structs::{Iovec, Rlimit, StructReader, Sysinfo, Timespec},
+        syscall::SysCalls,
+        MAX_ERRNO,
+    },
+};
+
+/// bytes of strings and buffers shown in the trace, longer ones are truncated.
+pub const STRACE_STRING_MAX: usize = 32;
+
+/// entries of arrays shown in the trace, like iovecs and pollfds.
+const STRACE_ARRAY_MAX: u64 = 8;
+
+/// bytes of `struct stat` and `struct stat64` up to `st_size`, in every layout.
+const STAT_SHOWN: usize = 64;
+/// bytes of `struct statx` up to `stx_size`.
+const STATX_SHOWN: usize = 48;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StraceFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for StraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(StraceFormat::Text),
+            "json" => Ok(StraceFormat::Json),
+            _ => Err(format!(
+                "unknown strace format {}, expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+/// How an argument of a syscall is decoded.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Arg {
+    Int,
+    Uint,
+    Hex,
+    Fd,
+    Mode,
+    /// NUL terminated string.
+    Str,
+    /// buffer read by the syscall, its size is the `n`th argument.
+    Buf(usize),
+    /// buffer filled by the syscall, its size is the return value.
+    OutBuf,
+    /// array of iovec, its length is the `n`th argument.
+    Iovec(usize),
+    /// array of pollfd, its length is the `n`th argument.
+    Pollfds(usize),
+    OpenFlags,
+    Prot,
+    MapFlags,
+    CloneFlags,
+    Signal,
+    /// pointer to a struct, decoded for the structs listed in `struct_size`.
+    Struct(&'static str),
+}
+
+fn signature(syscall: SysCalls) -> &'static [Arg] {
+    use Arg::*;
+    match syscall {
+        SysCalls::SET_THREAD_AREA | SysCalls::SET_TID_ADDRESS | SysCalls::BRK => &[Hex],
+        SysCalls::POLL => &[Pollfds(1), Uint, Int],
+        SysCalls::RT_SIGACTION => &[Signal, Struct("sigaction"), Struct("sigaction"), Uint],
+        SysCalls::RT_SIGPROCMASK => &[Int, Struct("sigset"), Struct("sigset"), Uint],
+        SysCalls::SYSCALL_SIGNAL => &[Signal, Hex],
+        SysCalls::SCHED_GETAFFINITY => &[Int, Uint, Hex],
+        SysCalls::SIGALTSTACK => &[Struct("stack_t"), Struct("stack_t")],
+        SysCalls::SIGRETURN | SysCalls::RT_SIGRETURN | SysCalls::SCHED_YIELD => &[],
+        SysCalls::EXIT_GROUP | SysCalls::EXIT => &[Int],
+        SysCalls::GETRANDOM => &[Hex, Uint, Hex],
+        SysCalls::FUTEX => &[Hex, Int, Uint, Struct("timespec"), Hex, Uint],
+        SysCalls::FUTEX_TIME64 => &[Hex, Int, Uint, Hex, Hex, Uint],
+        SysCalls::TKILL => &[Int, Signal],
+        SysCalls::GETTIMEOFDAY => &[Struct("timeval"), Hex],
+        SysCalls::CLOCK_GETTIME => &[Int, Struct("timespec")],
+        SysCalls::CLOCK_GETTIME64 => &[Int, Hex],
+        SysCalls::CLONE => &[CloneFlags, Hex, Hex, Hex, Hex],
+        SysCalls::MUNMAP => &[Hex, Uint],
+        SysCalls::MPROTECT => &[Hex, Uint, Prot],
+        SysCalls::MREMAP => &[Hex, Uint, Uint, Hex, Hex],
+        SysCalls::MMAP | SysCalls::MMAP2 => &[Hex, Uint, Prot, MapFlags, Fd, Hex],
+        SysCalls::MADVISE => &[Hex, Uint, Int],
+        SysCalls::GETRLIMIT => &[Int, Struct("rlimit")],
+        SysCalls::SYSINFO => &[Struct("sysinfo")],
+        SysCalls::SET_ROBUST_LIST => &[Hex, Uint],
+        SysCalls::PRLIMIT64 => &[Int, Int, Struct("rlimit64"), Struct("rlimit64")],
+        SysCalls::OPEN => &[Str, OpenFlags, Mode],
+        SysCalls::READ => &[Fd, OutBuf, Uint],
+        SysCalls::WRITE => &[Fd, Buf(2), Uint],
+        SysCalls::WRITEV => &[Fd, Iovec(2), Uint],
+        SysCalls::CLOSE => &[Fd],
+        SysCalls::LSEEK => &[Fd, Int, Int],
+        SysCalls::_LLSEEK => &[Fd, Uint, Uint, Hex, Int],
+        SysCalls::FCNTL | SysCalls::FCNTL64 => &[Fd, Int, Hex],
+        SysCalls::READLINK => &[Str, OutBuf, Uint],
+        SysCalls::STAT | SysCalls::LSTAT => &[Str, Struct("stat")],
+        SysCalls::STAT64 | SysCalls::LSTAT64 => &[Str, Struct("stat64")],
+        SysCalls::FSTAT => &[Fd, Struct("stat")],
+        SysCalls::FSTAT64 => &[Fd, Struct("stat64")],
+        SysCalls::FSTATAT64 => &[Fd, Str, Struct("stat64"), Hex],
+        SysCalls::NEWFSTATAT => &[Fd, Str, Struct("stat"), Hex],
+        SysCalls::GETCWD => &[OutBuf, Uint],
+        SysCalls::IOCTL => &[Fd, Hex, Hex],
+        SysCalls::READV => &[Fd, Iovec(2), Uint],
+        SysCalls::PREAD64 => &[Fd, OutBuf, Uint, Hex, Hex, Hex],
+        SysCalls::PWRITE64 => &[Fd, Buf(2), Uint, Hex, Hex, Hex],
+        SysCalls::OPENAT => &[Fd, Str, OpenFlags, Mode],
+        SysCalls::GETDENTS64 => &[Fd, Hex, Uint],
+        SysCalls::STATX => &[Fd, Str, Hex, Hex, Struct("statx")],
+        SysCalls::FTRUNCATE => &[Fd, Uint],
+        SysCalls::FTRUNCATE64 => &[Fd, Hex, Hex, Hex],
+        SysCalls::UNLINK | SysCalls::RMDIR => &[Str],
+        SysCalls::MKDIR => &[Str, Mode],
+        SysCalls::PIPE2 => &[Hex, OpenFlags],
+        SysCalls::DUP => &[Fd],
+        SysCalls::DUP2 => &[Fd, Fd],
+        SysCalls::DUP3 => &[Fd, Fd, OpenFlags],
+        SysCalls::PPOLL => &[Pollfds(1), Uint, Struct("timespec"), Struct("sigset"), Uint],
+        SysCalls::PPOLL_TIME64 => &[Pollfds(1), Uint, Hex, Struct("sigset"), Uint],
+        SysCalls::EVENTFD => &[Uint],
+        SysCalls::EVENTFD2 => &[Uint, OpenFlags],
+        SysCalls::NANOSLEEP => &[Struct("timespec"), Struct("timespec")],
+        SysCalls::CLOCK_NANOSLEEP => &[Int, Hex, Struct("timespec"), Struct("timespec")],
+        SysCalls::CLOCK_NANOSLEEP_TIME64 => &[Int, Hex, Hex, Hex],
+        SysCalls::GETPID
+        | SysCalls::GETPPID
+        | SysCalls::GETTID
+        | SysCalls::GETUID
+        | SysCalls::GETEUID
+        | SysCalls::GETGID
+        | SysCalls::GETEGID => &[],
+        SysCalls::UNAME => &[Struct("utsname")],
+    }
+}
+
+/// One syscall of the program.
+#[derive(Serialize, Clone, Debug)]
+pub struct SyscallRecord {
+    /// steps executed before the syscall.
+    pub step: u64,
+    pub tid: u64,
+    pub pc: u64,
+    pub name: String,
+    /// arguments decoded according to the syscall.
+    pub args: Vec<String>,
+    pub raw_args: Vec<u64>,
+    /// raw result, none for syscalls which don't return, like sigreturn.
+    pub ret: Option<i64>,
+    pub errno: Option<String>,
+}
+
+impl SyscallRecord {
+    pub fn new(
+        core: &mut dyn SyscallCore,
+        (step, tid, pc): (u64, u64, u64),
+        name: &str,
+        args: &SyscallArgs,
+        ret: Option<i64>,
+    ) -> Self {
+        let errno = ret
+            .filter(|r| (-MAX_ERRNO..0).contains(r))
+            .map(|r| errno_name(core.arch(), -r));
+        let decoded: Vec<String> = match SysCalls::from_str(name) {
+            Ok(syscall) => signature(syscall)
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| decode_arg(core, *arg, args.get(i), args, ret))
+                .collect(),
+            Err(_) => args.raw().iter().map(|a| format!("{:#x}", a)).collect(),
+        };
+        let n = decoded.len();
+        Self {
+            step,
+            tid,
+            pc,
+            name: name.to_string(),
+            args: decoded,
+            raw_args: args.raw()[..n].to_vec(),
+            ret,
+            errno,
+        }
+    }
+}
+
+impl fmt::Display for SyscallRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:>10}] [{}] {}({}) = ",
+            self.step,
+            self.tid,
+            self.name,
+            self.args.join(", ")
+        )?;
+        match (self.ret, &self.errno) {
+            (None, _) => write!(f, "?"),
+            (Some(_), Some(errno)) => write!(f, "-1 {}", errno),
+            (Some(ret), None) if ret > 0xffff => write!(f, "{:#x}", ret),
+            (Some(ret), None) => write!(f, "{}", ret),
+        }
+    }
+}
+
+pub struct Strace {
+    format: StraceFormat,
+    out: Box<dyn Write>,
+}
+
+impl fmt::Debug for Strace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Strace")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl Strace {
+    pub fn new(format: StraceFormat, out: Box<dyn Write>) -> Self {
+        Self { format, out }
+    }
+
+    pub fn write(&mut self, record: &SyscallRecord) -> io::Result<()> {
+        match self.format {
+            StraceFormat::Text => writeln!(self.out, "{}", record),
+            StraceFormat::Json => {
+                serde_json::to_writer(&mut self.out, record)?;
+                writeln!(self.out)
+            }
+        }
+    }
+}
+
+fn decode_arg(
+    core: &mut dyn SyscallCore,
+    arg: Arg,
+    value: u64,
+    args: &SyscallArgs,
+    ret: Option<i64>,
+) -> String {
+    if value == 0
+        && matches!(
+            arg,
+            Arg::Str | Arg::Buf(_) | Arg::OutBuf | Arg::Iovec(_) | Arg::Pollfds(_) | Arg::Struct(_)
+        )
+    {
+        return "NULL".to_string();
+    }
+    match arg {
+        Arg::Fd if value as i32 == AT_FDCWD => "AT_FDCWD".to_string(),
+        Arg::Int | Arg::Fd => (value as i32).to_string(),
+        Arg::Uint => value.to_string(),
+        Arg::Hex => format!("{:#x}", value),
+        Arg::Mode => format!("0{:o}", value),
+        Arg::Signal => signal_name(core.arch(), value),
+        Arg::Str => match read_cstring(core, value) {
+            Some(s) => quote(&s),
+            None => format!("{:#x}", value),
+        },
+        Arg::Buf(n) => read_buf(core, value, args.get(n)),
+        Arg::OutBuf => match ret {
+            Some(ret) if ret >= 0 => read_buf(core, value, ret as u64),
+            _ => format!("{:#x}", value),
+        },
+        Arg::Iovec(n) => decode_iovec(core, value, args.get(n)),
+        Arg::Pollfds(n) => decode_pollfds(core, value, args.get(n)),
+        Arg::OpenFlags => open_flags(core.arch(), value),
+        Arg::Prot => flags(value, PROT_FLAGS, "PROT_NONE"),
+        Arg::MapFlags => match core.arch() {
+            Arch::MIPS => flags(value, MIPS_MAP_FLAGS, "0"),
+            _ => flags(value, GENERIC_MAP_FLAGS, "0"),
+        },
+        Arg::CloneFlags => {
+            let sig = value & 0xff;
+            let f = flags(value & !0xff, CLONE_FLAGS, "0");
+            if sig == 0 {
+                f
+            } else {
+                format!("{}|{}", f, signal_name(core.arch(), sig))
+            }
+        }
+        Arg::Struct(name) => {
+            decode_struct(core, name, value).unwrap_or_else(|| format!("{:#x}", value))
+        }
+    }
+}
+
+/// Content of the structs which are worth showing, none for the others.
+fn decode_struct(core: &mut dyn SyscallCore, name: &str, addr: u64) -> Option<String> {
+    let (arch, endian, ptr) = (core.arch(), core.endian(), core.pointer_size());
+    let data = core.mem_read(addr, struct_size(name, ptr)?).ok()?;
+    format_struct(name, &data, arch, endian, ptr)
+}
+
+/// Bytes of the struct `name` which are shown.
+fn struct_size(name: &str, ptr: PointerSizeT) -> Option<usize> {
+    let long = ptr as usize;
+    let size = match name {
+        "timespec" | "timeval" => Timespec::size(ptr),
+        "rlimit" => 2 * long,
+        "rlimit64" => 16,
+        "sigset" => 8,
+        // flags, handler and the first 64 signals of the mask.
+        "sigaction" => 2 * long + 8,
+        "stack_t" => 3 * long,
+        "stat" | "stat64" => STAT_SHOWN,
+        "statx" => STATX_SHOWN,
+        "sysinfo" => Sysinfo::size(ptr),
+        // sysname and nodename.
+        "utsname" => 2 * UTSNAME_FIELD_LEN,
+        _ => return None,
+    };
+    Some(size)
+}
+
+fn format_struct(
+    name: &str,
+    data: &[u8],
+    arch: Arch,
+    endian: Endian,
+    ptr: PointerSizeT,
+) -> Option<String> {
+    let mut r = StructReader::new(data, endian, ptr);
+    let s = match name {
+        "timespec" => {
+            let ts = Timespec::unpack(data, endian, ptr)?;
+            format!("{{tv_sec={}, tv_nsec={}}}", ts.sec, ts.nsec)
+        }
+        "timeval" => {
+            // same layout as timespec, with microseconds.
+            let tv = Timespec::unpack(data, endian, ptr)?;
+            format!("{{tv_sec={}, tv_usec={}}}", tv.sec, tv.nsec)
+        }
+        "rlimit" | "rlimit64" => {
+            let size = if name == "rlimit" { ptr } else { 8 };
+            let rlimit = Rlimit::unpack(data, endian, size)?;
+            format!("{{rlim_cur={:#x}, rlim_max={:#x}}}", rlimit.cur, rlimit.max)
+        }
+        "sigset" => format!("[{:#x}]", read_sigset(&mut r, ptr)?),
+        "sigaction" => {
+            // the layouts of `read_sigaction`.
+            let (flags, handler) = match arch {
+                Arch::MIPS => {
+                    let flags = r.int(4)?;
+                    (flags, r.long()?)
+                }
+                _ => {
+                    let handler = r.long()?;
+                    (r.long()?, handler)
+                }
+            };
+            let handler = match handler {
+                SIG_DFL => "SIG_DFL".to_string(),
+                SIG_IGN => "SIG_IGN".to_string(),
+                handler => format!("{:#x}", handler),
+            };
+            format!(
+                "{{sa_handler={}, sa_mask=[{:#x}], sa_flags={:#x}}}",
+                handler,
+                read_sigset(&mut r, ptr)?,
+                flags
+            )
+        }
+        "stack_t" => {
+            // mips puts ss_size before ss_flags.
+            let sp = r.long()?;
+            let (flags, size) = match arch {
+                Arch::MIPS => {
+                    let size = r.long()?;
+                    (r.int(4)?, size)
+                }
+                _ => {
+                    let flags = r.int(4)?;
+                    (flags, r.long()?)
+                }
+            };
+            format!(
+                "{{ss_sp={:#x}, ss_flags={}, ss_size={}}}",
+                sp, flags as i32, size
+            )
+        }
+        "stat" | "stat64" | "statx" => {
+            let (mode, size) = read_stat(&mut r, name, arch, ptr)?;
+            let prefix = if name == "statx" { "stx" } else { "st" };
+            format!(
+                "{{{}_mode={}, {}_size={}, ...}}",
+                prefix,
+                file_mode(mode),
+                prefix,
+                size
+            )
+        }
+        "sysinfo" => {
+            let info = Sysinfo::unpack(data, endian, ptr)?;
+            format!(
+                "{{uptime={}, totalram={}, freeram={}, procs={}, mem_unit={}, ...}}",
+                info.uptime, info.total_ram, info.free_ram, info.procs, info.mem_unit
+            )
+        }
+        "utsname" => {
+            let field = |i: usize| {
+                let f = data.get(i * UTSNAME_FIELD_LEN..(i + 1) * UTSNAME_FIELD_LEN)?;
+                Some(quote(f.split(|b| *b == 0).next().unwrap()))
+            };
+            format!("{{sysname={}, nodename={}, ...}}", field(0)?, field(1)?)
+        }
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// The first 64 signals of a sigset, in one or two longs.
+fn read_sigset(r: &mut StructReader, ptr: PointerSizeT) -> Option<u64> {
+    match ptr {
+        4 => {
+            let low = r.long()?;
+            Some((r.long()? << 32) | low)
+        }
+        _ => r.long(),
+    }
+}
+
+/// `st_mode` and `st_size` in the layouts of `Stat`.
+fn read_stat(
+    r: &mut StructReader,
+    name: &str,
+    arch: Arch,
+    ptr: PointerSizeT,
+) -> Option<(u64, u64)> {
+    let long = ptr as usize;
+    let (mode, size) = match (name, arch, ptr) {
+        ("statx", _, _) => {
+            // mask, blksize, attributes, nlink, uid and gid, then the inode after the mode.
+            let mode = r.pad(28).int(2)?;
+            (mode, r.pad(10).int(8)?)
+        }
+        ("stat64", _, _) => {
+            r.long()?;
+            r.pad(3 * long).int(8)?;
+            let mode = r.int(4)?;
+            r.pad(12).long()?;
+            (mode, r.pad(3 * long).int(8)?)
+        }
+        ("stat", Arch::MIPS, 8) => {
+            let mode = r.pad(24).int(4)?;
+            (mode, r.pad(28).int(8)?)
+        }
+        ("stat", Arch::MIPS, _) => {
+            r.pad(4 + 3 * long).long()?;
+            let mode = r.int(4)?;
+            (mode, r.pad(16 + 2 * long).long()?)
+        }
+        _ => {
+            r.long()?;
+            r.long()?;
+            let mode = r.int(4)?;
+            r.pad(12).long()?;
+            (mode, r.pad(long).long()?)
+        }
+    };
+    Some((mode, size))
+}
+
+/// The type of the file and its permissions, like strace shows `st_mode`.
+fn file_mode(mode: u64) -> String {
+    let kind = match mode & 0o170000 {
+        0o140000 => "S_IFSOCK",
+        0o120000 => "S_IFLNK",
+        0o100000 => "S_IFREG",
+        0o060000 => "S_IFBLK",
+        0o040000 => "S_IFDIR",
+        0o020000 => "S_IFCHR",
+        0o010000 => "S_IFIFO",
+        _ => return format!("0{:o}", mode),
+    };
+    format!("{}|0{:o}", kind, mode & 0o7777)
+}
+
+fn decode_pollfds(core: &mut dyn SyscallCore, addr: u64, count: u64) -> String {
+    // struct pollfd is an int fd, and short events and revents.
+    let size = count.min(STRACE_ARRAY_MAX) as usize * 8;
+    let data = match core.mem_read(addr, size) {
+        Ok(data) => data,
+        Err(_) => return format!("{:#x}", addr),
+    };
+    let (endian, ptr) = (core.endian(), core.pointer_size());
+    let mut entries = data
+        .chunks_exact(8)
+        .map(|entry| {
+            let mut r = StructReader::new(entry, endian, ptr);
+            let (fd, events, revents) = (r.int(4).unwrap(), r.int(2).unwrap(), r.int(2).unwrap());
+            format!(
+                "{{fd={}, events={}, revents={}}}",
+                fd as i32,
+                flags(events, POLL_FLAGS, "0"),
+                flags(revents, POLL_FLAGS, "0")
+            )
+        })
+        .collect::<Vec<_>>();
+    if count > STRACE_ARRAY_MAX {
+        entries.push("...".to_string());
+    }
+    format!("[{}]", entries.join(", "))
+}
+
+fn decode_iovec(core: &mut dyn SyscallCore, addr: u64, count: u64) -> String {
+    let ptr = core.pointer_size();
+    let size = count.min(STRACE_ARRAY_MAX) as usize * Iovec::size(ptr);
+    let iovecs = match core.mem_read(addr, size) {
+        Ok(data) => Iovec::unpack_all(&data, core.endian(), ptr),
+        Err(_) => return format!("{:#x}", addr),
+    };
+    let mut entries = iovecs
+        .iter()
+        .map(|v| {
+            format!(
+                "{{iov_base={}, iov_len={}}}",
+                read_buf(core, v.base, v.len),
+                v.len
+            )
+        })
+        .collect::<Vec<_>>();
+    if count > STRACE_ARRAY_MAX {
+        entries.push("...".to_string());
+    }
+    format!("[{}]", entries.join(", "))
+}
+
+fn read_cstring(core: &mut dyn SyscallCore, addr: u64) -> Option<Vec<u8>> {
+    let mut s = vec![];
+    loop {
+        let b = core.mem_read(addr + s.len() as u64, 1).ok()?[0];
+        if b == 0 || s.len() > STRACE_STRING_MAX {
+            return Some(s);
+        }
+        s.push(b);
+    }
+}
+
+fn read_buf(core: &mut dyn SyscallCore, addr: u64, size: u64) -> String {
+    let shown = size.min(STRACE_STRING_MAX as u64 + 1) as usize;
+    match core.mem_read(addr, shown) {
+        Ok(data) => quote(&data),
+        Err(_) => format!("{:#x}", addr),
+    }
+}
+
+/// Quote and escape `data` like strace, with `...` if it's longer than shown.
+fn quote(data: &[u8]) -> String {
+    let shown = &data[..data.len().min(STRACE_STRING_MAX)];
+    let escaped = shown
+        .iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect::<String>();
+    if data.len() > STRACE_STRING_MAX {
+        format!("\"{}\"...", escaped)
+    } else {
+        format!("\"{}\"", escaped)
+    }
+}
+
+/// Names of the bits set in `value`, unknown bits are shown in hex.
+fn flags(value: u64, names: &[(u64, &str)], zero: &str) -> String {
+    if value == 0 {
+        return zero.to_string();
+    }
+    let mut rest = value;
+    let mut set = vec![];
+    for (bit, name) in names {
+        if value & bit != 0 {
+            set.push(name.to_string());
+            rest &= !bit;
+        }
+    }
+    if rest != 0 {
+        set.push(format!("{:#x}", rest));
+    }
+    set.join("|")
+}
+
+fn open_flags(arch: Arch, value: u64) -> String {
+    let access = match value & 3 {
+        0 => "O_RDONLY",
+        1 => "O_WRONLY",
+        2 => "O_RDWR",
+        _ => "O_ACCMODE",
+    };
+    match value & !3 {
+        0 => access.to_string(),
+        rest => {
+            let names = match arch {
+                Arch::MIPS => MIPS_OPEN_FLAGS,
+                _ => GENERIC_OPEN_FLAGS,
+            };
+            format!("{}|{}", access, flags(rest, names, "0"))
+        }
+    }
+}
+
+// flag values of the mips abi.
+const MIPS_OPEN_FLAGS: &[(u64, &str)] = &[
+    (0x8, "O_APPEND"),
+    (0x10, "O_SYNC"),
+    (0x80, "O_NONBLOCK"),
+    (0x100, "O_CREAT"),
+    (0x200, "O_TRUNC"),
+    (0x400, "O_EXCL"),
+    (0x800, "O_NOCTTY"),
+    (0x2000, "O_LARGEFILE"),
+    (0x10000, "O_DIRECTORY"),
+    (0x20000, "O_NOFOLLOW"),
+    (0x80000, "O_CLOEXEC"),
+];
+
+// flag values of the generic abi, used by riscv.
+const GENERIC_OPEN_FLAGS: &[(u64, &str)] = &[
+    (0x40, "O_CREAT"),
+    (0x80, "O_EXCL"),
+    (0x100, "O_NOCTTY"),
+    (0x200, "O_TRUNC"),
+    (0x400, "O_APPEND"),
+    (0x800, "O_NONBLOCK"),
+    (0x1000, "O_DSYNC"),
+    (0x8000, "O_LARGEFILE"),
+    (0x10000, "O_DIRECTORY"),
+    (0x20000, "O_NOFOLLOW"),
+    (0x80000, "O_CLOEXEC"),
+    (0x100000, "O_SYNC"),
+];
+
+const POLL_FLAGS: &[(u64, &str)] = &[
+    (0x1, "POLLIN"),
+    (0x2, "POLLPRI"),
+    (0x4, "POLLOUT"),
+    (0x8, "POLLERR"),
+    (0x10, "POLLHUP"),
+    (0x20, "POLLNVAL"),
+];
+
+const PROT_FLAGS: &[(u64, &str)] = &[(1, "PROT_READ"), (2, "PROT_WRITE"), (4, "PROT_EXEC")];
+
+const MIPS_MAP_FLAGS: &[(u64, &str)] = &[
+    (0x1, "MAP_SHARED"),
+    (0x2, "MAP_PRIVATE"),
+    (0x10, "MAP_FIXED"),
+    (0x400, "MAP_NORESERVE"),
+    (0x800, "MAP_ANONYMOUS"),
+    (0x1000, "MAP_GROWSDOWN"),
+    (0x40000, "MAP_STACK"),
+];
+
+const GENERIC_MAP_FLAGS: &[(u64, &str)] = &[
+    (0x1, "MAP_SHARED"),
+    (0x2, "MAP_PRIVATE"),
+    (0x10, "MAP_FIXED"),
+    (0x20, "MAP_ANONYMOUS"),
+    (0x100, "MAP_GROWSDOWN"),
+    (0x4000, "MAP_NORESERVE"),
+    (0x20000, "MAP_STACK"),
+];
+
+const CLONE_FLAGS: &[(u64, &str)] = &[
+    (0x100, "CLONE_VM"),
+    (0x200, "CLONE_FS"),
+    (0x400, "CLONE_FILES"),
+    (0x800, "CLONE_SIGHAND"),
+    (0x10000, "CLONE_THREAD"),
+    (0x40000, "CLONE_SYSVSEM"),
+    (0x80000, "CLONE_SETTLS"),
+    (0x100000, "CLONE_PARENT_SETTID"),
+    (0x200000, "CLONE_CHILD_CLEARTID"),
+    (0x1000000, "CLONE_CHILD_SETTID"),
+];
+
+const MIPS_SIGNAL_NAMES: [&str; 31] = [
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGEMT",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGBUS",
+    "SIGSEGV",
+    "SIGSYS",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGUSR1",
+    "SIGUSR2",
+    "SIGCHLD",
+    "SIGPWR",
+    "SIGWINCH",
+    "SIGURG",
+    "SIGIO",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGCONT",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGXCPU",
+    "SIGXFSZ",
+];
+
+const GENERIC_SIGNAL_NAMES: [&str; 31] = [
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+fn signal_name(arch: Arch, sig: u64) -> String {
+    let names = match arch {
+        Arch::MIPS => &MIPS_SIGNAL_NAMES,
+        _ => &GENERIC_SIGNAL_NAMES,
+    };
+    match sig {
+        1..=31 => names[sig as usize - 1].to_string(),
+        _ => sig.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::container::Endian;
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::{flags, format_struct, open_flags, quote, SyscallRecord, CLONE_FLAGS};
+    use crate::os::linux::structs::{Stat, StructWriter, Sysinfo};
+
+    #[test]
+    fn test_strace_format() {
+        assert_eq!(open_flags(Arch::MIPS, 0x301), "O_WRONLY|O_CREAT|O_TRUNC");
+        assert_eq!(open_flags(Arch::MIPS, 0), "O_RDONLY");
+        assert_eq!(open_flags(Arch::RISCV, 0x241), "O_WRONLY|O_CREAT|O_TRUNC");
+        assert_eq!(
+            flags(0x10100 | 0x4, CLONE_FLAGS, "0"),
+            "CLONE_VM|CLONE_THREAD|0x4"
+        );
+        assert_eq!(quote(b"hi\n"), "\"hi\\n\"");
+        assert_eq!(quote(&[b'a'; 40]), format!("\"{}\"...", "a".repeat(32)));
+
+        let record = SyscallRecord {
+            step: 12,
+            tid: 42,
+            pc: 0x400000,
+            name: "open".to_string(),
+            args: vec!["\"/etc/passwd\"".to_string(), "O_RDONLY".to_string()],
+            raw_args: vec![0x10000, 0],
+            ret: Some(-2),
+            errno: Some("ENOENT".to_string()),
+        };
+        assert_eq!(
+            record.to_string(),
+            "[        12] [42] open(\"/etc/passwd\", O_RDONLY) = -1 ENOENT"
+        );
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"step":12,"tid":42,"pc":4194304,"name":"open","args":["\"/etc/passwd\"","O_RDONLY"],"raw_args":[65536,0],"ret":-2,"errno":"ENOENT"}"#
+        );
+    }
+
+    #[test]
+    fn test_strace_structs() {
+        let stat = Stat {
+            ino: 0x1234,
+            mode: 0o100644,
+            size: 0x10000,
+            ..Default::default()
+        };
+        let shown = "{st_mode=S_IFREG|0644, st_size=65536, ...}";
+        let layouts = [
+            ("stat", Arch::MIPS, 4, stat.pack(Arch::MIPS, Endian::Big, 4)),
+            ("stat", Arch::MIPS, 8, stat.pack(Arch::MIPS, Endian::Big, 8)),
+            (
+                "stat",
+                Arch::RISCV,
+                8,
+                stat.pack(Arch::RISCV, Endian::Little, 8),
+            ),
+            ("stat64", Arch::MIPS, 4, Some(stat.pack64(Endian::Big, 4))),
+        ];
+        for (name, arch, ptr, data) in layouts {
+            let endian = match arch {
+                Arch::MIPS => Endian::Big,
+                _ => Endian::Little,
+            };
+            assert_eq!(
+                format_struct(name, &data.unwrap(), arch, endian, ptr).as_deref(),
+                Some(shown),
+                "{} of {:?} with {} bytes longs",
+                name,
+                arch,
+                ptr
+            );
+        }
+        assert_eq!(
+            format_struct(
+                "statx",
+                &stat.pack_statx(Endian::Big),
+                Arch::MIPS,
+                Endian::Big,
+                4
+            )
+            .unwrap(),
+            "{stx_mode=S_IFREG|0644, stx_size=65536, ...}"
+        );
+
+        // flags, handler and a mask of SIGINT, in two longs.
+        let sigaction = StructWriter::new(Endian::Big, 4)
+            .u32(0x8000_0000)
+            .long(0x400000)
+            .long(2)
+            .long(0)
+            .finish();
+        assert_eq!(
+            format_struct("sigaction", &sigaction, Arch::MIPS, Endian::Big, 4).unwrap(),
+            "{sa_handler=0x400000, sa_mask=[0x2], sa_flags=0x80000000}"
+        );
+        let stack = StructWriter::new(Endian::Little, 8)
+            .long(0x7000)
+            .u32(1)
+            .long(0x2000)
+            .finish();
+        assert_eq!(
+            format_struct("stack_t", &stack, Arch::RISCV, Endian::Little, 8).unwrap(),
+            "{ss_sp=0x7000, ss_flags=1, ss_size=8192}"
+        );
+
+        let info = Sysinfo {
+            uptime: 1,
+            total_ram: 2,
+            free_ram: 3,
+            procs: 4,
+            mem_unit: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_struct(
+                "sysinfo",
+                &info.pack(Endian::Big, 4),
+                Arch::MIPS,
+                Endian::Big,
+                4
+            )
+            .unwrap(),
+            "{uptime=1, totalram=2, freeram=3, procs=4, mem_unit=5, ...}"
+        );
+
+        let mut utsname = vec![0; 2 * 65];
+        utsname[..5].copy_from_slice(b"Linux");
+        utsname[65..70].copy_from_slice(b"judge");
+        assert_eq!(
+            format_struct("utsname", &utsname, Arch::MIPS, Endian::Big, 4).unwrap(),
+            "{sysname=\"Linux\", nodename=\"judge\", ...}"
+        );
+    }
+}
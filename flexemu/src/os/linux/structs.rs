@@ -0,0 +1,655 @@
+//! Kernel structs the program reads or writes, in its own layout:
+//! byte order, size of `long` and alignment of its arch.
+//!
+//! Host values are never copied as is, they go through [`StructWriter`] field by field,
+//! and structs passed by the program are decoded by [`StructReader`].
+//! Layouts which depend on the arch follow mips o32, but `struct stat` and `struct sigaction`
+//! which riscv lays out like the generic linux abi.
+
+use goblin::container::Endian;
+use unicorn_engine::unicorn_const::Arch;
+
+use crate::{
+    memory::PointerSizeT,
+    utils::{align_up, Packer},
+};
+
+/// Writer of a struct in the layout of the program.
+/// Fields are aligned to their size, and the struct to its largest field.
+pub struct StructWriter {
+    endian: Endian,
+    long: usize,
+    align: usize,
+    buf: Vec<u8>,
+}
+
+impl StructWriter {
+    pub fn new(endian: Endian, long_size: PointerSizeT) -> Self {
+        Self {
+            endian,
+            long: long_size as usize,
+            align: 1,
+            buf: vec![],
+        }
+    }
+
+    /// Append an integer of `size` bytes, values which don't fit are truncated like C casts do.
+    pub fn int(&mut self, size: usize, value: u64) -> &mut Self {
+        self.align_to(size);
+        self.buf
+            .extend(Packer::new(self.endian, size as PointerSizeT).pack(value));
+        self
+    }
+
+    pub fn u16(&mut self, value: u64) -> &mut Self {
+        self.int(2, value)
+    }
+
+    pub fn u32(&mut self, value: u64) -> &mut Self {
+        self.int(4, value)
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.int(8, value)
+    }
+
+    /// Append a `long` or a pointer.
+    pub fn long(&mut self, value: u64) -> &mut Self {
+        self.int(self.long, value)
+    }
+
+    /// Append `len` bytes of padding or reserved fields.
+    pub fn pad(&mut self, len: usize) -> &mut Self {
+        self.buf.resize(self.buf.len() + len, 0);
+        self
+    }
+
+    /// Append raw bytes, like char arrays.
+    pub fn bytes(&mut self, data: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(data);
+        self
+    }
+
+    /// The struct, with the trailing padding of arrays of it.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let len = align_up(self.buf.len(), self.align);
+        self.buf.resize(len, 0);
+        std::mem::take(&mut self.buf)
+    }
+
+    fn align_to(&mut self, alignment: usize) {
+        self.align = self.align.max(alignment);
+        let len = align_up(self.buf.len(), alignment);
+        self.buf.resize(len, 0);
+    }
+}
+
+/// Reader of a struct in the layout of the program, the counterpart of [`StructWriter`].
+pub struct StructReader<'a> {
+    endian: Endian,
+    long: usize,
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StructReader<'a> {
+    pub fn new(data: &'a [u8], endian: Endian, long_size: PointerSizeT) -> Self {
+        Self {
+            endian,
+            long: long_size as usize,
+            data,
+            offset: 0,
+        }
+    }
+
+    /// Next integer of `size` bytes, none past the end of the data.
+    pub fn int(&mut self, size: usize) -> Option<u64> {
+        let begin = align_up(self.offset, size);
+        let field = self.data.get(begin..begin + size)?;
+        self.offset = begin + size;
+        Some(Packer::new(self.endian, size as PointerSizeT).unpack(field.to_vec()))
+    }
+
+    pub fn long(&mut self) -> Option<u64> {
+        self.int(self.long)
+    }
+
+    /// Skip `len` bytes of padding or fields which aren't read.
+    pub fn pad(&mut self, len: usize) -> &mut Self {
+        self.offset += len;
+        self
+    }
+}
+
+/// `struct timespec`, `struct __kernel_timespec` being the one with 8 bytes longs.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: i64,
+}
+
+impl Timespec {
+    pub fn size(long_size: PointerSizeT) -> usize {
+        2 * long_size as usize
+    }
+
+    pub fn pack(&self, endian: Endian, long_size: PointerSizeT) -> Vec<u8> {
+        StructWriter::new(endian, long_size)
+            .long(self.sec as u64)
+            .long(self.nsec as u64)
+            .finish()
+    }
+
+    pub fn unpack(data: &[u8], endian: Endian, long_size: PointerSizeT) -> Option<Self> {
+        let mut r = StructReader::new(data, endian, long_size);
+        let sign = |v: u64| match long_size {
+            4 => v as i32 as i64,
+            _ => v as i64,
+        };
+        Some(Self {
+            sec: sign(r.long()?),
+            nsec: sign(r.long()?),
+        })
+    }
+}
+
+/// `struct rlimit`, `struct rlimit64` being the one with 8 bytes longs.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Rlimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+impl Rlimit {
+    pub fn pack(&self, endian: Endian, long_size: PointerSizeT) -> Vec<u8> {
+        StructWriter::new(endian, long_size)
+            .long(self.cur)
+            .long(self.max)
+            .finish()
+    }
+
+    pub fn unpack(data: &[u8], endian: Endian, long_size: PointerSizeT) -> Option<Self> {
+        let mut r = StructReader::new(data, endian, long_size);
+        Some(Self {
+            cur: r.long()?,
+            max: r.long()?,
+        })
+    }
+}
+
+/// `struct iovec`.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Iovec {
+    pub base: u64,
+    pub len: u64,
+}
+
+impl Iovec {
+    pub fn size(long_size: PointerSizeT) -> usize {
+        2 * long_size as usize
+    }
+
+    pub fn pack(&self, endian: Endian, long_size: PointerSizeT) -> Vec<u8> {
+        StructWriter::new(endian, long_size)
+            .long(self.base)
+            .long(self.len)
+            .finish()
+    }
+
+    /// Decode an array of iovecs, up to the last complete one.
+    pub fn unpack_all(data: &[u8], endian: Endian, long_size: PointerSizeT) -> Vec<Self> {
+        data.chunks_exact(Self::size(long_size))
+            .map(|entry| {
+                let mut r = StructReader::new(entry, endian, long_size);
+                Self {
+                    base: r.long().unwrap(),
+                    len: r.long().unwrap(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// `struct sysinfo`.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Sysinfo {
+    pub uptime: i64,
+    pub loads: [u64; 3],
+    pub total_ram: u64,
+    pub free_ram: u64,
+    pub shared_ram: u64,
+    pub buffer_ram: u64,
+    pub total_swap: u64,
+    pub free_swap: u64,
+    pub procs: u16,
+    pub total_high: u64,
+    pub free_high: u64,
+    pub mem_unit: u32,
+}
+
+impl Sysinfo {
+    pub fn pack(&self, endian: Endian, long_size: PointerSizeT) -> Vec<u8> {
+        let mut w = StructWriter::new(endian, long_size);
+        w.long(self.uptime as u64);
+        for load in self.loads {
+            w.long(load);
+        }
+        w.long(self.total_ram)
+            .long(self.free_ram)
+            .long(self.shared_ram)
+            .long(self.buffer_ram)
+            .long(self.total_swap)
+            .long(self.free_swap)
+            .u16(self.procs as u64)
+            .pad(2)
+            .long(self.total_high)
+            .long(self.free_high)
+            .u32(self.mem_unit as u64)
+            // char _f[20 - 2 * sizeof(long) - sizeof(int)]
+            .pad(20usize.saturating_sub(2 * long_size as usize + 4))
+            .finish()
+    }
+
+    pub fn size(long_size: PointerSizeT) -> usize {
+        Self::default().pack(Endian::Little, long_size).len()
+    }
+
+    pub fn unpack(data: &[u8], endian: Endian, long_size: PointerSizeT) -> Option<Self> {
+        let mut r = StructReader::new(data, endian, long_size);
+        Some(Self {
+            uptime: match long_size {
+                4 => r.long()? as i32 as i64,
+                _ => r.long()? as i64,
+            },
+            loads: [r.long()?, r.long()?, r.long()?],
+            total_ram: r.long()?,
+            free_ram: r.long()?,
+            shared_ram: r.long()?,
+            buffer_ram: r.long()?,
+            total_swap: r.long()?,
+            free_swap: r.long()?,
+            procs: r.int(2)? as u16,
+            total_high: r.long()?,
+            free_high: r.long()?,
+            mem_unit: r.int(4)? as u32,
+        })
+    }
+}
+
+/// `struct stat` as reported by the host, to be written in the layout of the program.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pub size: i64,
+    pub blksize: i64,
+    pub blocks: i64,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+}
+
+impl From<&libc::stat> for Stat {
+    // the types of the fields differ between hosts.
+    #[allow(clippy::unnecessary_cast)]
+    fn from(host: &libc::stat) -> Self {
+        Self {
+            dev: host.st_dev as u64,
+            ino: host.st_ino as u64,
+            mode: host.st_mode as u32,
+            nlink: host.st_nlink as u64,
+            uid: host.st_uid,
+            gid: host.st_gid,
+            rdev: host.st_rdev as u64,
+            size: host.st_size as i64,
+            blksize: host.st_blksize as i64,
+            blocks: host.st_blocks as i64,
+            atime: Timespec {
+                sec: host.st_atime as i64,
+                nsec: host.st_atime_nsec as i64,
+            },
+            mtime: Timespec {
+                sec: host.st_mtime as i64,
+                nsec: host.st_mtime_nsec as i64,
+            },
+            ctime: Timespec {
+                sec: host.st_ctime as i64,
+                nsec: host.st_ctime_nsec as i64,
+            },
+        }
+    }
+}
+
+impl Stat {
+    /// `struct stat` of `arch`, mips o32 or n64 with 8 bytes longs, or the generic one of riscv.
+    /// None if the inode or the size don't fit, which is `EOVERFLOW`.
+    pub fn pack(&self, arch: Arch, endian: Endian, long_size: PointerSizeT) -> Option<Vec<u8>> {
+        if arch == Arch::MIPS && long_size == 8 {
+            return Some(self.pack_n64(endian));
+        }
+        let long_max = u64::MAX >> (64 - 8 * long_size as u32);
+        if self.ino > long_max || self.size as u64 > long_max >> 1 {
+            return None;
+        }
+        if arch != Arch::MIPS {
+            return Some(self.pack_generic(endian, long_size));
+        }
+        let mut w = StructWriter::new(endian, long_size);
+        w.u32(self.dev)
+            .pad(3 * long_size as usize)
+            .long(self.ino)
+            .u32(self.mode as u64)
+            .u32(self.nlink)
+            .u32(self.uid as u64)
+            .u32(self.gid as u64)
+            .u32(self.rdev)
+            .pad(2 * long_size as usize)
+            .long(self.size as u64)
+            .pad(long_size as usize);
+        for time in [self.atime, self.mtime, self.ctime] {
+            w.long(time.sec as u64).long(time.nsec as u64);
+        }
+        w.long(self.blksize as u64)
+            .long(self.blocks as u64)
+            .pad(14 * long_size as usize);
+        Some(w.finish())
+    }
+
+    /// `struct stat` of asm-generic, with the times and the padding after them.
+    fn pack_generic(&self, endian: Endian, long_size: PointerSizeT) -> Vec<u8> {
+        let mut w = StructWriter::new(endian, long_size);
+        w.long(self.dev)
+            .long(self.ino)
+            .u32(self.mode as u64)
+            .u32(self.nlink)
+            .u32(self.uid as u64)
+            .u32(self.gid as u64)
+            .long(self.rdev)
+            .pad(long_size as usize)
+            .long(self.size as u64)
+            .u32(self.blksize as u64)
+            .pad(4)
+            .long(self.blocks as u64);
+        for time in [self.atime, self.mtime, self.ctime] {
+            w.long(time.sec as u64).long(time.nsec as u64);
+        }
+        w.pad(8).finish()
+    }
+
+    /// n64 keeps the 32 bits dev and times of o32, and widens the inode, the size and the blocks.
+    fn pack_n64(&self, endian: Endian) -> Vec<u8> {
+        let mut w = StructWriter::new(endian, 8);
+        w.u32(self.dev)
+            .pad(12)
+            .u64(self.ino)
+            .u32(self.mode as u64)
+            .u32(self.nlink)
+            .u32(self.uid as u64)
+            .u32(self.gid as u64)
+            .u32(self.rdev)
+            .pad(12)
+            .u64(self.size as u64);
+        for time in [self.atime, self.mtime, self.ctime] {
+            w.u32(time.sec as u64).u32(time.nsec as u64);
+        }
+        w.u32(self.blksize as u64)
+            .pad(4)
+            .u64(self.blocks as u64)
+            .finish()
+    }
+
+    /// `struct stat64` of mips o32.
+    pub fn pack64(&self, endian: Endian, long_size: PointerSizeT) -> Vec<u8> {
+        let mut w = StructWriter::new(endian, long_size);
+        w.long(self.dev)
+            .pad(3 * long_size as usize)
+            .u64(self.ino)
+            .u32(self.mode as u64)
+            .u32(self.nlink)
+            .u32(self.uid as u64)
+            .u32(self.gid as u64)
+            .long(self.rdev)
+            .pad(3 * long_size as usize)
+            .u64(self.size as u64);
+        for time in [self.atime, self.mtime, self.ctime] {
+            w.long(time.sec as u64).long(time.nsec as u64);
+        }
+        w.long(self.blksize as u64)
+            .pad(long_size as usize)
+            .u64(self.blocks as u64)
+            .finish()
+    }
+
+    /// `struct statx`, which has the same layout on every arch.
+    /// Only the basic stats are reported, the birth time is not.
+    pub fn pack_statx(&self, endian: Endian) -> Vec<u8> {
+        let mut w = StructWriter::new(endian, 8);
+        w.u32(STATX_BASIC_STATS)
+            .u32(self.blksize as u64)
+            .u64(0)
+            .u32(self.nlink)
+            .u32(self.uid as u64)
+            .u32(self.gid as u64)
+            .u16(self.mode as u64)
+            .u64(self.ino)
+            .u64(self.size as u64)
+            .u64(self.blocks as u64)
+            .u64(0);
+        for time in [self.atime, Timespec::default(), self.ctime, self.mtime] {
+            w.u64(time.sec as u64).u32(time.nsec as u64).pad(4);
+        }
+        let (rdev_major, rdev_minor) = split_dev(self.rdev);
+        let (dev_major, dev_minor) = split_dev(self.dev);
+        w.u32(rdev_major)
+            .u32(rdev_minor)
+            .u32(dev_major)
+            .u32(dev_minor)
+            .pad(STATX_SIZE - 0x90);
+        w.finish()
+    }
+}
+
+pub const STATX_SIZE: usize = 256;
+
+/// `stx_mask` of everything but the birth time.
+const STATX_BASIC_STATS: u64 = 0x7ff;
+
+/// Major and minor numbers of a linux `dev_t`.
+fn split_dev(dev: u64) -> (u64, u64) {
+    let major = (dev >> 8) & 0xfff | (dev >> 32) & !0xfff;
+    let minor = dev & 0xff | (dev >> 12) & !0xff;
+    (major & 0xffff_ffff, minor & 0xffff_ffff)
+}
+
+/// Re-encode the fields of a host struct in the byte order of the program.
+/// `fields` are the sizes of the fields, which must be laid out without padding.
+/// Bytes after the last field are copied as is.
+fn convert_fields(host: &[u8], fields: &[usize], endian: Endian) -> Vec<u8> {
+    let mut w = StructWriter::new(endian, 4);
+    let mut offset = 0;
+    for size in fields {
+        let mut field = [0u8; 8];
+        field[..*size].copy_from_slice(&host[offset..offset + size]);
+        if cfg!(target_endian = "big") {
+            field[..*size].reverse();
+        }
+        // the field is little endian here.
+        w.int(*size, u64::from_le_bytes(field));
+        offset += size;
+    }
+    w.bytes(&host[offset..]);
+    w.finish()
+}
+
+/// Convert host `linux_dirent64` records to the layout of the program.
+/// `d_ino`, `d_off` and `d_reclen` change byte order, `d_type` and `d_name` are copied.
+pub fn convert_dirents64(host: &[u8], endian: Endian) -> Vec<u8> {
+    let mut out = Vec::with_capacity(host.len());
+    let mut rest = host;
+    while rest.len() >= 19 {
+        let reclen = u16::from_ne_bytes([rest[16], rest[17]]) as usize;
+        if reclen < 19 || reclen > rest.len() {
+            break;
+        }
+        out.extend(convert_fields(&rest[..reclen], &[8, 8, 2], endian));
+        rest = &rest[reclen..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::container::Endian;
+    use unicorn_engine::unicorn_const::Arch;
+
+    use super::{Iovec, Rlimit, Stat, Sysinfo, Timespec};
+
+    #[test]
+    fn test_mips_layouts() {
+        let stat = Stat {
+            dev: 0x801,
+            ino: 0x1234,
+            mode: 0o100644,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            size: 0x10000,
+            blksize: 4096,
+            blocks: 128,
+            atime: Timespec { sec: 1, nsec: 2 },
+            mtime: Timespec { sec: 3, nsec: 4 },
+            ctime: Timespec { sec: 5, nsec: 6 },
+        };
+        let golden = hex::decode(concat!(
+            "00000801000000000000000000000000",                 // dev, pad
+            "00001234000081a400000001",                         // ino, mode, nlink
+            "000003e80000006400000000",                         // uid, gid, rdev
+            "00000000000000000001000000000000",                 // pad, size, pad
+            "000000010000000200000003000000040000000500000006", // times
+            "0000100000000080",                                 // blksize, blocks
+        ))
+        .unwrap();
+        let packed = stat.pack(Arch::MIPS, Endian::Big, 4).unwrap();
+        assert_eq!(packed.len(), 144);
+        assert_eq!(&packed[..golden.len()], golden.as_slice());
+        assert!(packed[golden.len()..].iter().all(|b| *b == 0));
+        let big = Stat {
+            size: 1 << 31,
+            ..stat
+        };
+        assert!(big.pack(Arch::MIPS, Endian::Big, 4).is_none());
+
+        let golden64 = hex::decode(concat!(
+            "00000801000000000000000000000000",                 // dev, pad
+            "0000000000001234000081a400000001",                 // ino, mode, nlink
+            "000003e80000006400000000",                         // uid, gid, rdev
+            "000000000000000000000000",                         // pad
+            "0000000080000000",                                 // size
+            "000000010000000200000003000000040000000500000006", // times
+            "0000100000000000",                                 // blksize, pad
+            "0000000000000080",                                 // blocks
+        ))
+        .unwrap();
+        assert_eq!(big.pack64(Endian::Big, 4), golden64);
+
+        let golden_n64 = hex::decode(concat!(
+            "00000801000000000000000000000000",                 // dev, pad
+            "0000000000001234000081a400000001",                 // ino, mode, nlink
+            "000003e80000006400000000",                         // uid, gid, rdev
+            "000000000000000000000000",                         // pad
+            "0000000000010000",                                 // size
+            "000000010000000200000003000000040000000500000006", // times
+            "0000100000000000",                                 // blksize, pad
+            "0000000000000080",                                 // blocks
+        ))
+        .unwrap();
+        assert_eq!(stat.pack(Arch::MIPS, Endian::Big, 8), Some(golden_n64));
+
+        let golden_riscv64 = hex::decode(concat!(
+            "01080000000000003412000000000000", // dev, ino
+            "a481000001000000e803000064000000", // mode, nlink, uid, gid
+            "00000000000000000000000000000000", // rdev, pad
+            "0000010000000000",                 // size
+            "0010000000000000",                 // blksize, pad
+            "8000000000000000",                 // blocks
+            "01000000000000000200000000000000", // atime
+            "03000000000000000400000000000000", // mtime
+            "05000000000000000600000000000000", // ctime
+            "0000000000000000",                 // unused
+        ))
+        .unwrap();
+        assert_eq!(
+            stat.pack(Arch::RISCV, Endian::Little, 8),
+            Some(golden_riscv64)
+        );
+
+        let ts = Timespec { sec: -1, nsec: 5 };
+        assert_eq!(
+            ts.pack(Endian::Little, 4),
+            hex::decode("ffffffff05000000").unwrap()
+        );
+        assert_eq!(
+            Timespec::unpack(&ts.pack(Endian::Big, 4), Endian::Big, 4),
+            Some(ts)
+        );
+
+        let rlimit = Rlimit {
+            cur: 0x30000,
+            max: u64::MAX,
+        };
+        assert_eq!(
+            rlimit.pack(Endian::Big, 8),
+            hex::decode("0000000000030000ffffffffffffffff").unwrap()
+        );
+
+        let iovecs = [
+            Iovec {
+                base: 0x1000,
+                len: 3,
+            },
+            Iovec {
+                base: 0x2000,
+                len: 4,
+            },
+        ];
+        let packed: Vec<u8> = iovecs.iter().flat_map(|v| v.pack(Endian::Big, 4)).collect();
+        assert_eq!(
+            packed,
+            hex::decode("00001000000000030000200000000004").unwrap()
+        );
+        // the incomplete trailing entry is ignored.
+        assert_eq!(
+            Iovec::unpack_all(&packed[..12], Endian::Big, 4),
+            iovecs[..1]
+        );
+
+        let info = Sysinfo {
+            uptime: 1,
+            total_ram: 2,
+            free_ram: 3,
+            procs: 4,
+            mem_unit: 5,
+            ..Default::default()
+        };
+        let golden = hex::decode(concat!(
+            "00000001000000000000000000000000", // uptime, loads
+            "00000002000000030000000000000000", // total, free, shared and buffer ram
+            "00000000000000000004000000000000", // swap, procs, pad, total high
+            "00000000000000050000000000000000", // free high, mem_unit, _f
+        ))
+        .unwrap();
+        assert_eq!(info.pack(Endian::Big, 4), golden);
+        assert_eq!(info.pack(Endian::Little, 8).len(), 112);
+        assert_eq!(Sysinfo::size(4), 64);
+        assert_eq!(
+            Sysinfo::unpack(&info.pack(Endian::Little, 8), Endian::Little, 8),
+            Some(info)
+        );
+    }
+}
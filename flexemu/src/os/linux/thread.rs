@@ -0,0 +1,341 @@
+//! Deterministic green threads of the emulated process.
+//!
+//! Only one thread runs on the engine at a time, the others keep their registers in a saved context.
+//! Threads are switched when the running one blocks, yields or exits, or is preempted after
+//! [`TIME_SLICE`] steps. Preemption points are absolute step counts, so any run of the same
+//! program reproduces the same interleaving.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{os::linux::signal::SigSet, registers::RegisterState};
+
+/// steps a thread runs before it's preempted, if other threads are runnable.
+pub const TIME_SLICE: u64 = 10_000;
+
+/// tid of the main thread, which is also the pid of the emulated process.
+pub const MAIN_TID: u64 = 42;
+
+pub const CLONE_VM: u64 = 0x0000_0100;
+pub const CLONE_THREAD: u64 = 0x0001_0000;
+pub const CLONE_SETTLS: u64 = 0x0008_0000;
+pub const CLONE_PARENT_SETTID: u64 = 0x0010_0000;
+pub const CLONE_CHILD_CLEARTID: u64 = 0x0020_0000;
+pub const CLONE_CHILD_SETTID: u64 = 0x0100_0000;
+
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+pub const FUTEX_REQUEUE: u64 = 3;
+pub const FUTEX_CMP_REQUEUE: u64 = 4;
+pub const FUTEX_WAIT_BITSET: u64 = 9;
+pub const FUTEX_WAKE_BITSET: u64 = 10;
+pub const FUTEX_PRIVATE_FLAG: u64 = 128;
+pub const FUTEX_CLOCK_REALTIME: u64 = 256;
+
+/// what threads blocked on pipes and eventfds wait on, futexes are aligned so it's no futex address.
+pub const IO_WAIT: u64 = u64::MAX;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FutexWait {
+    pub addr: u64,
+    /// a timed wait times out when no other thread can run.
+    pub timed: bool,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct Thread {
+    pub tid: u64,
+    /// registers saved when the thread was switched out, stale while it's running.
+    pub context: RegisterState,
+    /// cleared and woken when the thread exits, see CLONE_CHILD_CLEARTID.
+    pub clear_child_tid: u64,
+    pub robust_list: u64,
+    /// signal mask saved when the thread was switched out.
+    pub blocked: SigSet,
+    pub wait: Option<FutexWait>,
+}
+
+#[derive(Debug)]
+pub struct Threads {
+    threads: BTreeMap<u64, Thread>,
+    current: u64,
+    next_tid: u64,
+    /// waiting threads of each futex, in the order they started waiting.
+    futex_queues: BTreeMap<u64, VecDeque<u64>>,
+    /// step count at which the running thread is preempted.
+    next_switch: u64,
+    /// the running thread gives up the cpu when the current syscall returns.
+    switch_pending: bool,
+    /// a second thread became runnable, the running one has a time slice from now on.
+    slice_changed: bool,
+    /// a thread besides the main one was created.
+    spawned: bool,
+}
+
+impl Default for Threads {
+    fn default() -> Self {
+        Self::new(MAIN_TID)
+    }
+}
+
+impl Threads {
+    pub fn new(main_tid: u64) -> Self {
+        let mut threads = BTreeMap::new();
+        threads.insert(
+            main_tid,
+            Thread {
+                tid: main_tid,
+                ..Default::default()
+            },
+        );
+        Self {
+            threads,
+            current: main_tid,
+            next_tid: main_tid + 1,
+            futex_queues: BTreeMap::new(),
+            next_switch: 0,
+            switch_pending: false,
+            slice_changed: false,
+            spawned: false,
+        }
+    }
+
+    pub fn current_tid(&self) -> u64 {
+        self.current
+    }
+    pub fn current(&mut self) -> Option<&mut Thread> {
+        self.threads.get_mut(&self.current)
+    }
+    pub fn get_mut(&mut self, tid: u64) -> Option<&mut Thread> {
+        self.threads.get_mut(&tid)
+    }
+    pub fn contains(&self, tid: u64) -> bool {
+        self.threads.contains_key(&tid)
+    }
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+    /// Whether the program created a second thread at some point, even if it exited since.
+    pub fn spawned(&self) -> bool {
+        self.spawned
+    }
+
+    /// Whether a thread besides the running one can run.
+    pub fn others_runnable(&self) -> bool {
+        self.runnable_count() > 1
+    }
+
+    fn runnable_count(&self) -> usize {
+        self.threads.values().filter(|t| t.wait.is_none()).count()
+    }
+
+    /// Create a thread with the given context, it starts running when scheduled.
+    pub fn spawn(&mut self, context: RegisterState, blocked: SigSet, steps: u64) -> u64 {
+        let tid = self.next_tid;
+        self.next_tid += 1;
+        self.spawned = true;
+        self.threads.insert(
+            tid,
+            Thread {
+                tid,
+                context,
+                blocked,
+                ..Default::default()
+            },
+        );
+        self.became_runnable(steps);
+        tid
+    }
+
+    fn became_runnable(&mut self, steps: u64) {
+        if self.runnable_count() == 2 {
+            self.next_switch = steps + TIME_SLICE;
+            self.slice_changed = true;
+        }
+    }
+
+    /// Remove the running thread, another one is scheduled when the syscall returns.
+    pub fn exit_current(&mut self) -> Option<Thread> {
+        let thread = self.threads.remove(&self.current);
+        self.switch_pending = true;
+        thread
+    }
+
+    pub fn yield_current(&mut self) {
+        self.switch_pending = true;
+    }
+
+    /// Block the running thread on the futex at `addr`.
+    pub fn wait_current(&mut self, addr: u64, timed: bool) {
+        if let Some(t) = self.threads.get_mut(&self.current) {
+            t.wait = Some(FutexWait { addr, timed });
+            self.futex_queues.entry(addr).or_default().push_back(t.tid);
+        }
+        self.switch_pending = true;
+    }
+
+    /// Block the running thread until a pipe or an eventfd changes.
+    pub fn wait_io(&mut self, timed: bool) {
+        self.wait_current(IO_WAIT, timed);
+    }
+
+    /// Wake all threads blocked on pipes and eventfds, they check again whether they can go on.
+    pub fn wake_io(&mut self, steps: u64) -> u64 {
+        self.wake(IO_WAIT, u64::MAX, steps)
+    }
+
+    /// Wake at most `n` threads waiting on `addr`, return the number of woken threads.
+    pub fn wake(&mut self, addr: u64, n: u64, steps: u64) -> u64 {
+        let mut woken = 0;
+        while woken < n {
+            let tid = match self.futex_queues.get_mut(&addr).and_then(|q| q.pop_front()) {
+                Some(tid) => tid,
+                None => break,
+            };
+            if let Some(t) = self.threads.get_mut(&tid) {
+                t.wait = None;
+            }
+            self.became_runnable(steps);
+            woken += 1;
+        }
+        if self.futex_queues.get(&addr).is_some_and(|q| q.is_empty()) {
+            self.futex_queues.remove(&addr);
+        }
+        woken
+    }
+
+    /// Move at most `n` threads waiting on `from` to wait on `to`, return the number of moved threads.
+    pub fn requeue(&mut self, from: u64, to: u64, n: u64) -> u64 {
+        let mut moved = 0;
+        while moved < n {
+            let tid = match self.futex_queues.get_mut(&from).and_then(|q| q.pop_front()) {
+                Some(tid) => tid,
+                None => break,
+            };
+            if let Some(FutexWait { addr, .. }) =
+                self.threads.get_mut(&tid).and_then(|t| t.wait.as_mut())
+            {
+                *addr = to;
+            }
+            self.futex_queues.entry(to).or_default().push_back(tid);
+            moved += 1;
+        }
+        if self.futex_queues.get(&from).is_some_and(|q| q.is_empty()) {
+            self.futex_queues.remove(&from);
+        }
+        moved
+    }
+
+    /// Steps the running thread may still run before it's preempted, none if no other thread can run.
+    pub fn time_slice(&self, steps: u64) -> Option<u64> {
+        if self.runnable_count() > 1 {
+            Some(self.next_switch.saturating_sub(steps).max(1))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the running thread should be preempted at `steps`.
+    pub fn should_preempt(&self, steps: u64) -> bool {
+        self.runnable_count() > 1 && steps >= self.next_switch
+    }
+
+    pub fn take_slice_changed(&mut self) -> bool {
+        std::mem::take(&mut self.slice_changed)
+    }
+
+    pub fn take_switch_pending(&mut self) -> bool {
+        std::mem::take(&mut self.switch_pending)
+    }
+
+    /// Pick the thread to run next, in round-robin order of tids, and make it the current one.
+    /// When all threads are waiting, the first timed waiter times out and is returned with its wait.
+    pub fn switch(&mut self, steps: u64) -> Option<(u64, Option<FutexWait>)> {
+        let next = self
+            .threads
+            .range(self.current + 1..)
+            .chain(self.threads.range(..=self.current))
+            .find(|(_, t)| t.wait.is_none())
+            .map(|(tid, _)| *tid);
+        let (next, timed_out) = match next {
+            Some(tid) => (tid, None),
+            None => {
+                let (tid, wait) = self.threads.values().find_map(|t| match t.wait {
+                    Some(wait) if wait.timed => Some((t.tid, wait)),
+                    _ => None,
+                })?;
+                if let Some(q) = self.futex_queues.get_mut(&wait.addr) {
+                    q.retain(|t| *t != tid);
+                }
+                if let Some(t) = self.threads.get_mut(&tid) {
+                    t.wait = None;
+                }
+                (tid, Some(wait))
+            }
+        };
+        self.current = next;
+        self.next_switch = steps + TIME_SLICE;
+        self.slice_changed = false;
+        Some((next, timed_out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FutexWait, Threads, IO_WAIT, TIME_SLICE};
+
+    #[test]
+    fn test_schedule_threads() {
+        let mut threads = Threads::new(42);
+        assert_eq!(threads.time_slice(0), None);
+        assert!(!threads.spawned());
+        let t1 = threads.spawn(Default::default(), 0, 100);
+        assert!(threads.spawned());
+        let t2 = threads.spawn(Default::default(), 0, 100);
+        assert!(threads.take_slice_changed());
+        assert_eq!(threads.time_slice(100), Some(TIME_SLICE));
+        assert!(threads.should_preempt(100 + TIME_SLICE));
+
+        // main thread waits, t1 and t2 run in turn.
+        threads.wait_current(0x1000, false);
+        assert!(threads.take_switch_pending());
+        assert_eq!(threads.switch(200), Some((t1, None)));
+        threads.yield_current();
+        assert_eq!(threads.switch(300), Some((t2, None)));
+        threads.yield_current();
+        assert_eq!(threads.switch(400), Some((t1, None)));
+
+        assert_eq!(threads.wake(0x1000, 2, 400), 1);
+        threads.exit_current();
+        assert_eq!(threads.switch(500), Some((t2, None)));
+        threads.wait_current(0x2000, true);
+        assert_eq!(threads.switch(600), Some((42, None)));
+        threads.wait_current(0x3000, false);
+        // only t2 waits with a timeout.
+        let timed_out = FutexWait {
+            addr: 0x2000,
+            timed: true,
+        };
+        assert_eq!(threads.switch(700), Some((t2, Some(timed_out))));
+
+        // t2 blocks on a pipe, and is woken by the main thread.
+        threads.wait_io(false);
+        assert_eq!(threads.wake(0x3000, 1, 800), 1);
+        assert_eq!(threads.switch(800), Some((42, None)));
+        assert_eq!(threads.wake_io(900), 1);
+        threads.yield_current();
+        assert_eq!(threads.switch(900), Some((t2, None)));
+        threads.wait_io(true);
+        assert_eq!(threads.switch(1000), Some((42, None)));
+        threads.wait_current(0x3000, false);
+        let timed_out = FutexWait {
+            addr: IO_WAIT,
+            timed: true,
+        };
+        assert_eq!(threads.switch(1100), Some((t2, Some(timed_out))));
+        threads.wait_current(0x2000, false);
+        assert_eq!(threads.switch(1200), None);
+    }
+}
@@ -1,9 +1,23 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{arch::ArchT, engine::Engine};
 
 use crate::{errors::EmulatorError, loader::LoadInfo};
 
 pub mod linux;
 
+/// How the emulated program finished.
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RunOutcome {
+    /// exited by itself with the exit code.
+    Exited(u64),
+    /// terminated by the default action of the signal.
+    Signaled(u64),
+    /// stopped when it asked for more memory than the ram of the machine,
+    /// with the bytes it had mapped and the bytes it asked for.
+    OutOfMemory { used: u64, requested: u64 },
+}
+
 pub trait Runner {
     fn on_load<'a, A: ArchT>(
         &mut self,
@@ -14,4 +28,42 @@ pub trait Runner {
     fn run<'a, A: ArchT>(&mut self, _core: &mut Engine<'a, A>) -> Result<(), EmulatorError> {
         Ok(())
     }
+
+    /// Take the error which made the runner stop the emulation, if any.
+    /// Hooks cannot return errors to unicorn, so runners record them and stop the engine instead.
+    fn take_error(&mut self) -> Option<EmulatorError> {
+        None
+    }
+
+    /// Take the address where the emulation should continue, if the runner redirected the control flow.
+    /// Unicorn resets pc when an interrupt hook returns, so runners stop the engine and let the emulator restart from here.
+    fn take_resume_address(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// How the program finished, none if it is still running.
+    fn outcome(&self) -> Option<RunOutcome> {
+        None
+    }
+
+    /// Steps the running program may run before the runner needs to reschedule, none if unlimited.
+    /// `steps` is the number of steps executed so far.
+    fn time_slice(&self, _steps: u64) -> Option<usize> {
+        None
+    }
+
+    /// Called when the engine was stopped at the end of a time slice.
+    /// Runners may switch to another context here, and redirect the control flow with a resume address.
+    fn on_slice_end<'a, A: ArchT>(
+        &mut self,
+        _core: &mut Engine<'a, A>,
+    ) -> Result<(), EmulatorError> {
+        Ok(())
+    }
+
+    /// Whether the program ran more than one thread. The scheduler is runner state, which isn't
+    /// part of the saved state, so steps of such programs can't be proven.
+    fn multithreaded(&self) -> bool {
+        false
+    }
 }
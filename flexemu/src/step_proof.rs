@@ -1,5 +1,11 @@
-use crate::emulator::StateChange;
+use crate::{
+    emulator::{EmulatorState, StateChange},
+    memory::PointerSizeT,
+    registers::RegisterState,
+    utils::align_up,
+};
 use ethtrie_codec::{EthTrieLayout, KeccakHasher, RlpNodeCodec};
+use goblin::container::Endian;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use trie_db::{NodeCodec, TrieMut};
@@ -16,6 +22,55 @@ pub struct StepProof {
     pub root_after: [u8; 32],
     #[serde(with = "HexForm")]
     pub access_nodes: Vec<u8>,
+    /// function and source line of the proven instruction, if the program has symbols.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+/// Key of the leaf of the 4 bytes word at `addr`, as wide as the addresses.
+pub fn memory_key(addr: u64, pointer_size: PointerSizeT) -> Vec<u8> {
+    match pointer_size {
+        8 => (addr >> 2).to_be_bytes().to_vec(),
+        _ => ((addr >> 2) as u32).to_be_bytes().to_vec(),
+    }
+}
+
+/// Key of the leaf of the registers, all zeros.
+pub fn registers_key(pointer_size: PointerSizeT) -> Vec<u8> {
+    memory_key(0, pointer_size)
+}
+
+/// Registers as a rlp list of `id << 32 | value`, or of the id in 4 bytes followed by the value
+/// in 8 bytes when registers are 64 bits.
+pub fn encode_registers(regs: &RegisterState, pointer_size: PointerSizeT) -> Vec<u8> {
+    let mut encoder = rlp::RlpStream::new_list(regs.len());
+    for (reg_id, v) in regs {
+        if pointer_size == 8 {
+            let mut encoded_register = (*reg_id as u32).to_be_bytes().to_vec();
+            encoded_register.extend(v.to_be_bytes());
+            encoder.append_iter(encoded_register);
+        } else {
+            let encoded_register = ((*reg_id as u64) << 32) + v;
+            encoder.append_iter(encoded_register.to_be_bytes());
+        }
+    }
+    encoder.out().to_vec()
+}
+
+/// Insert the memory and the registers of `state` into `trie`.
+pub fn insert_state(trie: &mut impl TrieMut<EthTrieLayout>, state: &EmulatorState) {
+    let mem: BTreeMap<_, _> = state.memories.clone().into();
+    for (addr, v) in mem {
+        trie.insert(&memory_key(addr, state.pointer_size), v.as_slice())
+            .unwrap();
+    }
+
+    // insert registers as a leaf with key [0,0,0,0]
+    trie.insert(
+        &registers_key(state.pointer_size),
+        &encode_registers(&state.regs, state.pointer_size),
+    )
+    .unwrap();
 }
 
 pub fn generate_step_proof(change: StateChange) -> StepProof {
@@ -24,33 +79,22 @@ pub fn generate_step_proof(change: StateChange) -> StepProof {
         state_after,
         step: _,
         access,
+        location,
     } = change;
+    let endian = if state_before.little_endian {
+        Endian::Little
+    } else {
+        Endian::Big
+    };
+    let pointer_size = state_before.pointer_size;
     let mut root = Default::default();
     let mut db = memory_db::MemoryDB::<KeccakHasher, memory_db::HashKey<KeccakHasher>, _>::new(
         RlpNodeCodec::empty_node(),
     );
 
     {
-        let mem: BTreeMap<_, _> = state_before.memories.into();
-
         let mut trie = trie_db::TrieDBMutBuilder::<EthTrieLayout>::new(&mut db, &mut root).build();
-        for (addr, v) in mem {
-            let shortend_addr = (addr >> 2) as u32;
-            trie.insert(&shortend_addr.to_be_bytes(), v.as_slice())
-                .unwrap();
-        }
-
-        // insert registers as a leaf with key [0,0,0,0]
-        let regs_before = {
-            let mut encoder = rlp::RlpStream::new_list(state_before.regs.len());
-            for (reg_id, v) in state_before.regs.clone() {
-                let encoded_register = ((reg_id as u64) << 32) + v;
-                encoder.append_iter(encoded_register.to_be_bytes());
-            }
-            encoder.out().to_vec()
-        };
-        trie.insert(&0u32.to_be_bytes(), &regs_before).unwrap();
-
+        insert_state(&mut trie, &state_before);
         trie.commit();
 
         // for (reg_id, v) in state_before.regs {
@@ -70,35 +114,30 @@ pub fn generate_step_proof(change: StateChange) -> StepProof {
             trie_db::TrieDBMutBuilder::<EthTrieLayout>::from_existing(&mut db, &mut root)
                 .with_recorder(&mut recorder)
                 .build();
+        // leaves hold the bytes of the aligned 4 bytes words in memory order, an access covers
+        // every word it touches, like the 2 bytes fetch of a compressed riscv instruction.
+        let mut memory = state_before.memories.clone();
         for acc in &access {
-            assert_eq!(acc.addr & 3, 0, "addr {:#x} not 4byte aligned", acc.addr);
-            assert_eq!(acc.size, 4, "mem size {} not 4", acc.size);
-
-            // FIXME: if read/or write is not 4bytes, this will be error.
+            let end = align_up(acc.addr + acc.size as u64, 4u8);
             if acc.write {
-                let _old_value = trie
-                    .insert(
-                        ((acc.addr >> 2) as u32).to_be_bytes().as_slice(),
-                        (acc.value as u32).to_be_bytes().as_slice(),
-                    )
-                    .unwrap();
-            } else {
-                let read_result = trie
-                    .get(((acc.addr >> 2) as u32).to_be_bytes().as_slice())
-                    .unwrap();
-                assert!(read_result.is_some());
+                memory.write_value(acc.addr, acc.size, acc.value, endian);
             }
-        }
-        trie.get(0u32.to_be_bytes().as_slice()).unwrap();
-        let regs_after = {
-            let mut encoder = rlp::RlpStream::new_list(state_after.regs.len());
-            for (reg_id, v) in state_after.regs.clone() {
-                let encoded_register = ((reg_id as u64) << 32) + v;
-                encoder.append_iter(encoded_register.to_be_bytes());
+            for word in (acc.addr & !3..end).step_by(4) {
+                let key = memory_key(word, pointer_size);
+                if acc.write {
+                    let _old_value = trie.insert(&key, &memory.read_bytes(word, 4)).unwrap();
+                } else {
+                    // zero words aren't in the trie, the path to where they'd be proves it.
+                    trie.get(&key).unwrap();
+                }
             }
-            encoder.out().to_vec()
-        };
-        trie.insert(&0u32.to_be_bytes(), &regs_after).unwrap();
+        }
+        trie.get(&registers_key(pointer_size)).unwrap();
+        trie.insert(
+            &registers_key(pointer_size),
+            &encode_registers(&state_after.regs, pointer_size),
+        )
+        .unwrap();
         trie.commit();
         drop(trie);
         recorder.drain()
@@ -130,5 +169,35 @@ pub fn generate_step_proof(change: StateChange) -> StepProof {
         root_before,
         root_after,
         access_nodes: encoded_nodes.out().to_vec(),
+        location,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_registers, memory_key, registers_key};
+    use crate::registers::RegisterState;
+
+    #[test]
+    fn test_keys_and_registers() {
+        assert_eq!(memory_key(0x7ff3cf04, 4), hex::decode("1ffcf3c1").unwrap());
+        assert_eq!(
+            memory_key(0x120000004, 8),
+            hex::decode("0000000048000001").unwrap()
+        );
+        assert_eq!(registers_key(8), vec![0; 8]);
+
+        let regs = RegisterState::from([(1, 0x400180), (4, 0x7ff3cf00)]);
+        // a list of two 8 bytes strings.
+        assert_eq!(
+            hex::encode(encode_registers(&regs, 4)),
+            "d288000000010040018088000000047ff3cf00"
+        );
+        let regs = RegisterState::from([(1, 0x400180), (4, 0x1_2000_0000)]);
+        // a list of two 12 bytes strings.
+        assert_eq!(
+            hex::encode(encode_registers(&regs, 8)),
+            "da8c0000000100000000004001808c000000040000000120000000"
+        );
     }
 }
@@ -0,0 +1,240 @@
+//! Names of guest addresses, from the symbol table and the DWARF line tables of the program.
+//!
+//! Logs, step traces and fault reports show addresses as `function+offset (file:line)`
+//! when the program isn't stripped.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+};
+
+use gimli::{EndianSlice, RunTimeEndian};
+use goblin::elf::{sym::STT_FUNC, Elf};
+use log::debug;
+
+/// Where an address is in the source of the program.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Location {
+    pub function: Option<String>,
+    /// offset of the address from the start of `function`.
+    pub offset: u64,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(function) = &self.function {
+            write!(f, "{}+{:#x}", function, self.offset)?;
+        }
+        if let (Some(file), Some(line)) = (&self.file, self.line) {
+            if self.function.is_some() {
+                write!(f, " ")?;
+            }
+            write!(f, "({}:{})", file, line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Symbol {
+    name: String,
+    size: u64,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Symbolizer {
+    /// functions by start address.
+    symbols: BTreeMap<u64, Symbol>,
+    /// rows of the line tables, by start address, as index in `files` and line.
+    /// `None` at the end of a sequence.
+    lines: BTreeMap<u64, Option<(usize, u64)>>,
+    files: Vec<String>,
+}
+
+impl Symbolizer {
+    /// Symbols of `elf`, loaded at `load_address`, and its line tables if it has debug info.
+    pub fn from_elf(binary: &[u8], elf: &Elf, load_address: u64) -> Self {
+        let mut symbolizer = Self::default();
+        for sym in elf.syms.iter() {
+            if sym.st_type() != STT_FUNC || sym.st_value == 0 {
+                continue;
+            }
+            if let Some(name) = elf.strtab.get_at(sym.st_name) {
+                symbolizer.add_symbol(load_address + sym.st_value, sym.st_size, name);
+            }
+        }
+        if let Err(e) = symbolizer.load_lines(binary, elf, load_address) {
+            debug!("ignore line tables: {}", e);
+        }
+        debug!(
+            "symbolizer: {} functions, {} line rows",
+            symbolizer.symbols.len(),
+            symbolizer.lines.len()
+        );
+        symbolizer
+    }
+
+    /// Add the function `name` at `address`, rust names are demangled.
+    pub fn add_symbol(&mut self, address: u64, size: u64, name: &str) {
+        let name = format!("{:#}", rustc_demangle::demangle(name));
+        self.symbols.insert(address, Symbol { name, size });
+    }
+
+    /// Add a row of a line table, the addresses from `address` up to the next row are at `file:line`.
+    /// `None` ends the sequence.
+    pub fn add_line(&mut self, address: u64, line: Option<(&str, u64)>) {
+        match line {
+            Some((file, line)) => {
+                let file = match self.files.iter().position(|f| f == file) {
+                    Some(i) => i,
+                    None => {
+                        self.files.push(file.to_string());
+                        self.files.len() - 1
+                    }
+                };
+                self.lines.insert(address, Some((file, line)));
+            }
+            // sequences may start where another one ends.
+            None => {
+                self.lines.entry(address).or_insert(None);
+            }
+        }
+    }
+
+    fn load_lines(
+        &mut self,
+        binary: &[u8],
+        elf: &Elf,
+        load_address: u64,
+    ) -> Result<(), gimli::Error> {
+        let endian = if elf.little_endian {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let sections = elf
+            .section_headers
+            .iter()
+            .filter_map(|sh| {
+                let name = elf.shdr_strtab.get_at(sh.sh_name)?;
+                let data = binary.get(sh.file_range()?)?;
+                Some((name, data))
+            })
+            .collect::<HashMap<_, _>>();
+        if !sections.contains_key(".debug_line") {
+            return Ok(());
+        }
+        let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+            let data = sections.get(id.name()).copied().unwrap_or(&[]);
+            Ok(EndianSlice::new(data, endian))
+        })?;
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match unit.line_program.clone() {
+                Some(program) => program,
+                None => continue,
+            };
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let address = load_address + row.address();
+                if row.end_sequence() {
+                    self.add_line(address, None);
+                    continue;
+                }
+                let file = match row.file(header) {
+                    Some(file) => file,
+                    None => continue,
+                };
+                let mut path = dwarf
+                    .attr_string(&unit, file.path_name())?
+                    .to_string_lossy()
+                    .into_owned();
+                if let Some(dir) = file.directory(header) {
+                    let dir = dwarf.attr_string(&unit, dir)?.to_string_lossy();
+                    if !dir.is_empty() && !path.starts_with('/') {
+                        path = format!("{}/{}", dir.trim_end_matches('/'), path);
+                    }
+                }
+                let line = row.line().map_or(0, |l| l.get());
+                self.add_line(address, Some((&path, line)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty() && self.lines.is_empty()
+    }
+
+    /// Function and source line of `address`, if known.
+    pub fn locate(&self, address: u64) -> Option<Location> {
+        let symbol = self
+            .symbols
+            .range(..=address)
+            .next_back()
+            .filter(|(start, s)| s.size == 0 || address < *start + s.size);
+        let line = self
+            .lines
+            .range(..=address)
+            .next_back()
+            .and_then(|(_, row)| *row);
+        if symbol.is_none() && line.is_none() {
+            return None;
+        }
+        Some(Location {
+            function: symbol.map(|(_, s)| s.name.clone()),
+            offset: symbol.map_or(0, |(start, _)| address - start),
+            file: line.map(|(file, _)| self.files[file].clone()),
+            line: line.map(|(_, line)| line),
+        })
+    }
+
+    /// `address` in hex, followed by its location if known.
+    pub fn describe(&self, address: u64) -> String {
+        match self.locate(address) {
+            Some(location) => format!("{:#x} {}", address, location),
+            None => format!("{:#x}", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Location, Symbolizer};
+
+    #[test]
+    fn test_locate() {
+        let mut symbolizer = Symbolizer::default();
+        symbolizer.add_symbol(0x400100, 0x20, "main");
+        symbolizer.add_symbol(0x400200, 0, "_ZN4core9panicking5panic17h0123456789abcdefE");
+        symbolizer.add_line(0x400100, Some(("src/main.c", 3)));
+        symbolizer.add_line(0x400108, Some(("src/main.c", 4)));
+        symbolizer.add_line(0x400120, None);
+
+        assert_eq!(
+            symbolizer.locate(0x40010c),
+            Some(Location {
+                function: Some("main".to_string()),
+                offset: 0xc,
+                file: Some("src/main.c".to_string()),
+                line: Some(4),
+            })
+        );
+        assert_eq!(
+            symbolizer.describe(0x400104),
+            "0x400104 main+0x4 (src/main.c:3)"
+        );
+        // past the end of main and its line table.
+        assert_eq!(symbolizer.describe(0x400180), "0x400180");
+        // functions without size extend to the next one.
+        assert_eq!(
+            symbolizer.describe(0x400210),
+            "0x400210 core::panicking::panic+0x10"
+        );
+        assert_eq!(symbolizer.locate(0x4000ff), None);
+    }
+}
@@ -0,0 +1,48 @@
+use std::{
+    env::args,
+    fs::{self, File, OpenOptions},
+    io::{IoSliceMut, Read, Write},
+    os::unix::fs::FileExt,
+};
+
+/// Exercise the file syscalls std uses, in a scratch directory:
+/// mkdir, openat, pwrite64, pread64, readv, ftruncate64, statx, getdents64, unlink and rmdir.
+fn main() {
+    let mut args = args();
+    let _ = args.next().unwrap(); // first is binary name
+    let dir = args.next().unwrap_or_else(|| "fs-example.tmp".to_string());
+    fs::create_dir(&dir).unwrap();
+
+    let path = format!("{}/data", dir);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"hello flexemu").unwrap();
+    file.write_at(b"HELLO", 0).unwrap();
+    let mut buf = [0u8; 7];
+    file.read_at(&mut buf, 6).unwrap();
+    assert_eq!(&buf, b"flexemu");
+    file.set_len(5).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), 5);
+
+    let mut file = File::open(&path).unwrap();
+    let (mut head, mut tail) = ([0u8; 2], [0u8; 3]);
+    let n = file
+        .read_vectored(&mut [IoSliceMut::new(&mut head), IoSliceMut::new(&mut tail)])
+        .unwrap();
+    assert_eq!(n, 5);
+    assert_eq!((&head, &tail), (b"HE", b"LLO"));
+
+    let names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["data"]);
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&dir).unwrap();
+    println!("fs ok");
+}
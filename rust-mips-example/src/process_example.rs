@@ -0,0 +1,65 @@
+use std::{
+    ffi::CStr,
+    fs::{self, File},
+    io::{Read, Write},
+    os::{
+        raw::{c_char, c_int, c_long},
+        unix::io::FromRawFd,
+    },
+    process, thread,
+    time::Duration,
+};
+
+/// `struct utsname` of linux.
+#[repr(C)]
+struct UtsName {
+    fields: [[c_char; 65]; 6],
+}
+
+const O_CLOEXEC: c_int = 0x80000;
+/// gettid on mips o32.
+const SYS_GETTID: c_long = 4222;
+
+// provided by musl, which the binary is linked with.
+extern "C" {
+    fn uname(buf: *mut UtsName) -> c_int;
+    fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+    fn dup2(old: c_int, new: c_int) -> c_int;
+    fn getuid() -> u32;
+    fn getgid() -> u32;
+    fn syscall(number: c_long, ...) -> c_long;
+}
+
+/// Exercise the process syscalls: getpid, gettid, getuid, getgid, uname, nanosleep, pipe2 and dup2,
+/// and the synthetic files of `/proc`.
+fn main() {
+    let pid = process::id();
+    let tid = unsafe { syscall(SYS_GETTID) };
+    assert_eq!(pid as c_long, tid);
+    println!("pid={} uid={} gid={}", pid, unsafe { getuid() }, unsafe {
+        getgid()
+    });
+
+    let mut uts = UtsName {
+        fields: [[0; 65]; 6],
+    };
+    assert_eq!(unsafe { uname(&mut uts) }, 0);
+    let field = |i: usize| unsafe { CStr::from_ptr(uts.fields[i].as_ptr()) }.to_string_lossy();
+    println!("{} {} {}", field(0), field(1), field(2));
+    // /proc reports the same machine.
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    assert_eq!(hostname.trim_end(), field(1));
+
+    thread::sleep(Duration::from_millis(10));
+
+    let mut fds = [0 as c_int; 2];
+    assert_eq!(unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) }, 0);
+    let copy = fds[1] + 1;
+    assert_eq!(unsafe { dup2(fds[1], copy) }, copy);
+    let (mut reader, mut writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(copy)) };
+    writer.write_all(b"ping").unwrap();
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+    println!("process ok");
+}
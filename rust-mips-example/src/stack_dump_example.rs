@@ -0,0 +1,49 @@
+use std::{ffi::CStr, fs, mem::size_of, os::raw::c_char, slice};
+
+/// types of the auxv.
+const AT_NULL: usize = 0;
+const AT_EXECFN: usize = 31;
+
+/// Dump the stack the program is started with, from the stack pointer at its entry to the null
+/// pointer after the `AT_EXECFN` string at the top, as `sp=<address>` then the hex of the bytes.
+/// Run under linux or qemu, it's the reference flexemu lays out its initial stack against.
+fn main() {
+    // the 28th field of /proc/self/stat is the stack pointer at the entry, fields are counted
+    // from the state, the 3rd one, since the command may have spaces.
+    let stat = fs::read_to_string("/proc/self/stat").unwrap();
+    let fields = stat[stat.rfind(')').unwrap() + 2..]
+        .split(' ')
+        .collect::<Vec<_>>();
+    let sp: usize = fields[25].parse().unwrap();
+
+    // the stack above sp is only read by the startup code of musl and std.
+    let word = |addr: usize| unsafe { *(addr as *const usize) };
+    let ptr_size = size_of::<usize>();
+    // skip argc, argv and envp.
+    let mut p = sp + (word(sp) + 2) * ptr_size;
+    while word(p) != 0 {
+        p += ptr_size;
+    }
+    p += ptr_size;
+    let mut execfn = None;
+    while word(p) != AT_NULL {
+        if word(p) == AT_EXECFN {
+            execfn = Some(word(p + ptr_size));
+        }
+        p += 2 * ptr_size;
+    }
+    let execfn = execfn.expect("no AT_EXECFN in the auxv");
+    let len = unsafe { CStr::from_ptr(execfn as *const c_char) }
+        .to_bytes_with_nul()
+        .len();
+    let top = execfn + len + ptr_size;
+
+    let data = unsafe { slice::from_raw_parts(sp as *const u8, top - sp) };
+    println!("sp={:#x}", sp);
+    println!(
+        "{}",
+        data.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    );
+}